@@ -6,6 +6,8 @@ fn main() {
     // Use system `protoc` instead of bloated `protoc-bin-vendored` crate.
 
     // All inputs and imports from the inputs must reside in `includes` directories.
+    // The protobuf definitions are a submodule inside this crate, so
+    // they're packaged along with it.
         .includes(["flipperzero-protobuf"])
     // Inputs must reside in some of include paths.
         .input("flipperzero-protobuf/application.proto")
@@ -0,0 +1,8 @@
+// The Flipper's RPC messages, and building and parsing them. This has
+// no Bluetooth or serial in it, so anything that can move bytes to
+// and from a Flipper can use it. flipwire is built on it.
+
+#[macro_use] extern crate tracing;
+
+pub mod flipper_pb;
+pub mod protobuf_codec;
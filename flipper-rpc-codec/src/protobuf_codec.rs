@@ -0,0 +1,871 @@
+use std::error::Error;
+
+use protobuf::{Message, MessageField, CodedInputStream};
+use chrono::Datelike;
+use chrono::Timelike;
+
+use crate::flipper_pb;
+
+// This file is long and grows a decent amount each time we add a new
+// command. There's probably a better way to do this but I don't know
+// what it is.
+
+// The flipperzero_protobuf_py example uses a chunk size of 512, which
+// absolutely doesn't work for us, because you can only write up to
+// 512 bytes to a characteristic at a time! (The max BLE MTU is 512
+// bytes). To leave room for protobuf data, we cut that down to 350
+// bytes, so our transmission unit size (..._TU_SIZE) is 350.
+//
+// This number also affects things like lag, and 350 is a good number
+// that seems to just work. It can be changed with set_tu_size() for
+// adapters that need something else.
+pub const PROTOBUF_BLE_TU_SIZE: usize = 350;
+//const PROTOBUF_BLE_MTU_SIZE: usize = 25;
+
+// Number of file bytes to write per cycle. Making this larger makes
+// it *seem* as though the upload is going faster, because each block
+// gets sent faster, but then you have to wait much longer at the
+// end. This happens because we're basically overrunning the Flipper
+// serial/RPC service, which means that it has to catch up later. The
+// numbers here and in upload_file() in flipper_ble.rs are carefully
+// tuned. set_write_chunk_size() changes it.
+pub const PROTOBUF_FILE_WRITE_CHUNK_SIZE: usize = 512;
+
+pub struct ProtobufCodec {
+    // command_id is uint32 in protobuf definition
+    command_id: u32,
+    tu_size: usize,
+    write_chunk_size: usize,
+}
+
+impl Default for ProtobufCodec {
+    fn default() -> Self {
+        ProtobufCodec::new()
+    }
+}
+
+/// Encapsulated representation of a chunk of StorageWriteRequest data
+pub struct ProtobufWriteRequestChunk {
+    /// Number of bytes *from the file* in this chunk
+    pub file_byte_count: usize,
+    /// Actual encoded protobuf packets (split up by
+    /// PROTOBUF_CHUNK_SIZE as needed) to send over the wire
+    pub packets: Vec<Vec<u8>>,
+}
+
+#[allow(dead_code)]
+impl ProtobufCodec {
+    pub fn new() -> ProtobufCodec {
+        ProtobufCodec {
+            command_id: 0,
+            tu_size: PROTOBUF_BLE_TU_SIZE,
+            write_chunk_size: PROTOBUF_FILE_WRITE_CHUNK_SIZE,
+        }
+    }
+
+    /// Set the largest piece encoded packets are split into for a
+    /// single characteristic write (PROTOBUF_BLE_TU_SIZE by default).
+    /// It has to fit in the connection's MTU.
+    ///
+    /// # Arguments
+    ///
+    /// * `tu_size`: Transmission unit size in bytes, at least 1
+    pub fn set_tu_size(&mut self, tu_size: usize) {
+        self.tu_size = tu_size.max(1);
+    }
+
+    /// Set how many bytes of a file go in each StorageWriteRequest
+    /// (PROTOBUF_FILE_WRITE_CHUNK_SIZE by default).
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk_size`: File bytes per write request, at least 1
+    pub fn set_write_chunk_size(&mut self, chunk_size: usize) {
+        self.write_chunk_size = chunk_size.max(1);
+    }
+
+    fn new_blank_packet(&mut self, increment: bool) -> flipper_pb::flipper::Main {
+        let final_msg = flipper_pb::flipper::Main {
+            command_id: self.command_id,
+            command_status: flipper_pb::flipper::CommandStatus::OK.into(),
+            // give default values to the rest of the fields
+            ..Default::default()
+        };
+
+        if increment {
+            self.command_id += 1;
+        }
+
+        final_msg
+    }
+
+    /// Increment the global command ID. Call this after the Flipper
+    /// sends a successful command.
+    pub fn inc_command_id(&mut self) {
+        self.command_id += 1;
+    }
+
+    /// Returns the command ID the next request will be sent with, so
+    /// the response can be waited for before the request goes out.
+    pub fn next_command_id(&self) -> u32 {
+        self.command_id
+    }
+
+    /// Returns a Vec<u8> containing an encoded Empty packet with
+    /// command status OK, used for responses to the Flipper after an
+    /// operation.
+    pub fn create_ok_packet(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut final_vec = Vec::new();
+        
+        let packet = self.new_blank_packet(true);
+
+        packet.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+    /// Returns a Vec<Vec<u8>> containing an encoded AppStartRequest
+    /// protobuf packet to send to the Flipper, or an error if
+    /// encoding failed (very unlikely). Send all nested Vecs
+    /// consecutively.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Full Flipper path or builtin app name to launch.
+    //pub fn create_launch_request_packet(&mut self, path: &str, args: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    pub fn create_launch_request_packet(&mut self, path: &str, args: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let launch_request = flipper_pb::application::StartRequest {
+            // builtin apps can be launched by name, external ones need a full path
+            name: path.to_string(),
+            args: args.to_string(),
+            
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(flipper_pb::flipper::main::Content::AppStartRequest(launch_request));
+
+        // append the length to the start of the packet
+        let mut final_vec = Vec::new();
+        
+        // this function will encode the length of the packet as a
+        // varint at the start of the Vec
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        // if there's just one chunk, .chunks() will make just one chunk.
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+        
+        Ok(vecs)
+
+    }
+
+    /// Returns a Vec<Vec<u8>> containing an encoded
+    /// StorageListRequest protobuf packet for a specific path to send
+    /// to the Flipper, or an error if encoding failed. Send all
+    /// nested Vecs consecutively.
+    ///
+    /// # Arguments
+    ///
+    /// `path`: File to get stats about
+    /// `include_md5`: Have the Flipper include each file's MD5 (older
+    /// firmware leaves it empty)
+    /// `filter_max_size`: Leave out files bigger than this many bytes,
+    /// or 0 to list everything
+    pub fn create_list_request_packet(&mut self, path: &str, include_md5: bool, filter_max_size: u32) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let list_request = flipper_pb::storage::ListRequest {
+            path: path.to_string(),
+            include_md5,
+            filter_max_size,
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(flipper_pb::flipper::main::Content::StorageListRequest(list_request));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+        
+        Ok(vecs)
+    }
+    
+    /// Returns a Vec<Vec<u8>> of encoded StorageWriteRequest packets
+    /// containing the content of the file at `file` and the
+    /// destination Flipper path `destpath`, or an error if file
+    /// reading or encoding occurred.
+    ///
+    /// # Arguments
+    ///
+    /// * `file`: Input file to send to the Flipper
+    /// * `destpath`: Destination Flipper path, must be a complete path including filename
+    ///
+    /// # Returns
+    ///
+    /// * Vec<ProtobufWriteRequestChunk> of file chunks.
+    pub fn create_write_request_packets(
+        &mut self,
+        file_data: &[u8],
+        dest_path: &str) -> Result<Vec<ProtobufWriteRequestChunk>, Box<dyn Error>> {
+
+        let mut packet_stream = Vec::new();
+        let chunk_size = self.write_chunk_size;
+
+        // Workaround: an empty file will cause the loop to never
+        // run. There's no easy "always iterate at least once" wrapper
+        // for an iterator, so we do this instead.
+        // TODO: there's gotta be a better way to do this.
+        if file_data.is_empty() {
+            debug!("creating packets for empty file");
+            let write_request = flipper_pb::storage::WriteRequest {
+                path: dest_path.to_string(),
+
+                ..Default::default()
+            };
+            // no data to write here
+            
+            let mut packet = self.new_blank_packet(false);
+            packet.content = Some(flipper_pb::flipper::main::Content::StorageWriteRequest(write_request));
+            packet.has_next = false;
+
+            let mut packet_vec = Vec::new();
+            packet.write_length_delimited_to_vec(&mut packet_vec)?;
+
+            let vecs = packet_vec.chunks(self.tu_size)
+                    .map(|x| x.to_vec())
+                    .collect();
+
+            packet_stream.push(ProtobufWriteRequestChunk {
+                file_byte_count: 0,
+                packets: vecs,
+            });
+            
+        } else {
+            // Every packet is the same, a WriteRequest, and the Flipper knows
+            // if we have more data to send via the has_next flag.
+            for index in (0..file_data.len()).step_by(chunk_size) {
+                let file_chunk = if index + chunk_size < file_data.len() {
+                    &file_data[index..index+chunk_size]
+                } else {
+                    &file_data[index..]
+                };
+                
+                // make a write request packet
+
+                let mut write_request = flipper_pb::storage::WriteRequest {
+                    path: dest_path.to_string(),
+
+                    ..Default::default()
+                };
+                // You have to use MessageField::some() to write to the `file` field.
+                // There are other fields in the File struct but we don't
+                // need to worry about them.
+                let mut f = flipper_pb::storage::File::new();
+                f.data = file_chunk.to_vec();
+                write_request.file = MessageField::some(f);
+
+                // only increment the packet when we finish the full command
+                let mut packet = self.new_blank_packet(false);
+                packet.content = Some(flipper_pb::flipper::main::Content::StorageWriteRequest(write_request));
+                
+                if index + chunk_size < file_data.len() {
+                    // has_next = true because we still have more data
+                    packet.has_next = true;
+                } else {
+                    packet.has_next = false;
+                }
+                
+                let mut packet_vec = Vec::new();
+                packet.write_length_delimited_to_vec(&mut packet_vec)?;
+
+                // now split into multiple Vec<u8>s for the ProtobufWriteRequestChunk
+                let vecs = packet_vec.chunks(self.tu_size)
+                    .map(|x| x.to_vec())
+                    .collect();
+
+                packet_stream.push(ProtobufWriteRequestChunk {
+                    file_byte_count: file_chunk.len(),
+                    packets: vecs,
+                });
+            }
+        }
+        // The command ID only increments after every complete
+        // command. The packet stream is a series of protobuf commands
+        // that represent a single command, so we increment it after
+        // we make all the packets.
+        self.command_id += 1;
+        Ok(packet_stream)
+    }
+
+    /// Returns a Vec<Vec<u8>> of an encoded StorageReadRequest for
+    /// the file at `path`. Send all nested Vecs consecutively.
+    pub fn create_read_request_packet(&mut self, path: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let read_request = flipper_pb::storage::ReadRequest {
+            path: path.to_string(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(flipper_pb::flipper::main::Content::StorageReadRequest(read_request));
+        debug!("read request: {:?}", final_msg);
+        let mut final_vec = Vec::new();
+        
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+        
+        Ok(vecs)
+    }
+
+    /// Returns a Vec<u8> of an encoded StorageStatRequest for the
+    /// file at `path`. Send all nested Vecs consecutively.
+    pub fn create_stat_request_packet(&mut self, path: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let stat_request = flipper_pb::storage::StatRequest {
+            path: path.to_string(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(flipper_pb::flipper::main::Content::StorageStatRequest(stat_request));
+        debug!("stat request: {:?}", final_msg);
+        let mut final_vec = Vec::new();
+        
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+        
+        Ok(vecs)
+    }
+
+    /// Returns a Vec<u8> of an encoded StorageTimestampRequest for
+    /// the file at `path`. Send all nested Vecs consecutively.
+    pub fn create_timestamp_request_packet(&mut self, path: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let timestamp_request = flipper_pb::storage::TimestampRequest {
+            path: path.to_string(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(flipper_pb::flipper::main::Content::StorageTimestampRequest(timestamp_request));
+        debug!("timestamp request: {:?}", final_msg);
+        let mut final_vec = Vec::new();
+
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+
+        Ok(vecs)
+    }
+
+    /// Returns a Vec<Vec<u8>> of an encoded StorageInfoRequest for
+    /// the filesystem at `path` (`/int` or `/ext`). Send all nested
+    /// Vecs consecutively.
+    pub fn create_storage_info_request_packet(&mut self, path: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let info_request = flipper_pb::storage::InfoRequest {
+            path: path.to_string(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(flipper_pb::flipper::main::Content::StorageInfoRequest(info_request));
+        debug!("storage info request: {:?}", final_msg);
+        let mut final_vec = Vec::new();
+
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+
+        Ok(vecs)
+    }
+
+    /// Returns a Vec<Vec<u8>> of an encoded StorageMd5sumRequest for
+    /// the file at `path`. Send all nested Vecs consecutively.
+    pub fn create_md5sum_request_packet(&mut self, path: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let md5sum_request = flipper_pb::storage::Md5sumRequest {
+            path: path.to_string(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(flipper_pb::flipper::main::Content::StorageMd5sumRequest(md5sum_request));
+        debug!("md5sum request: {:?}", final_msg);
+        let mut final_vec = Vec::new();
+
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+
+        Ok(vecs)
+    }
+
+    /// Returns a Vec<Vec<u8>> of an encoded StorageMkdirRequest for
+    /// the directory at `path`. Send all nested Vecs consecutively.
+    pub fn create_mkdir_request_packet(&mut self, path: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let mkdir_request = flipper_pb::storage::MkdirRequest {
+            path: path.to_string(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(flipper_pb::flipper::main::Content::StorageMkdirRequest(mkdir_request));
+        debug!("mkdir request: {:?}", final_msg);
+        let mut final_vec = Vec::new();
+
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+
+        Ok(vecs)
+    }
+
+    /// Returns a Vec<Vec<u8>> of an encoded StorageRenameRequest that
+    /// moves `old_path` to `new_path`. Both have to be on the same
+    /// storage. Send all nested Vecs consecutively.
+    pub fn create_rename_request_packet(&mut self, old_path: &str, new_path: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let rename_request = flipper_pb::storage::RenameRequest {
+            old_path: old_path.to_string(),
+            new_path: new_path.to_string(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(flipper_pb::flipper::main::Content::StorageRenameRequest(rename_request));
+        debug!("rename request: {:?}", final_msg);
+        let mut final_vec = Vec::new();
+
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+
+        Ok(vecs)
+    }
+
+    /// Returns a Vec<u8> of an encoded StorageDeleteRequest for the
+    /// file at `path`. `recursive` specifies that the directory (if
+    /// `path` is one) should be deleted recursively. Send all nested
+    /// Vecs consecutively.
+    pub fn create_delete_request_packet(&mut self, path: &str, recursive: bool) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let delete_request = flipper_pb::storage::DeleteRequest {
+            path: path.to_string(),
+            recursive,
+
+            ..Default::default()
+        };
+        
+        let mut final_msg = self.new_blank_packet(true);
+
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::StorageDeleteRequest(delete_request));
+        debug!("delete request: {:?}", final_msg);
+        let mut final_vec = Vec::new();
+
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+        
+        Ok(vecs)
+
+    }
+    
+    /// Returns a Vec<u8> of an encoded PingRequest. The Flipper sends
+    /// `data` back in its PingResponse. No need for chunking as long
+    /// as `data` is short.
+    pub fn create_ping_request_packet(&mut self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let ping_request = flipper_pb::system::PingRequest {
+            data: data.to_vec(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::SystemPingRequest(ping_request));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+
+    /// Returns a Vec<u8> of `msg` encoded with the next command ID,
+    /// for sending messages there's no other function for. Send all
+    /// nested Vecs consecutively.
+    pub fn create_raw_request_packet(&mut self, mut msg: flipper_pb::flipper::Main) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        msg.command_id = self.new_blank_packet(true).command_id;
+        debug!("raw request: {:?}", msg);
+
+        let mut final_vec = Vec::new();
+        msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+
+        Ok(vecs)
+    }
+
+    /// Returns a Vec<u8> of an encoded PlayAudiovisualAlertRequest.
+    /// No need for chunking, because there's no arguments.
+    pub fn create_alert_request_packet(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut final_msg = self.new_blank_packet(true);
+        // we can combine this because PlayAudiovisualAlertRequest has no fields
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::SystemPlayAudiovisualAlertRequest(
+                flipper_pb::system::PlayAudiovisualAlertRequest::default()));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+
+    /// Returns a Vec<u8> of an encoded desktop IsLockedRequest. No need
+    /// for chunking, because there's no arguments.
+    pub fn create_desktop_is_locked_request_packet(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::DesktopIsLockedRequest(
+                flipper_pb::desktop::IsLockedRequest::default()));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+
+    /// Returns a Vec<u8> of an encoded gpio SetPinMode, to make `pin`
+    /// an input or output. No need for chunking.
+    pub fn create_gpio_set_pin_mode_request_packet(
+        &mut self,
+        pin: flipper_pb::gpio::GpioPin,
+        mode: flipper_pb::gpio::GpioPinMode) -> Result<Vec<u8>, Box<dyn Error>> {
+        let set_pin_mode = flipper_pb::gpio::SetPinMode {
+            pin: pin.into(),
+            mode: mode.into(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::GpioSetPinMode(set_pin_mode));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+
+    /// Returns a Vec<u8> of an encoded gpio ReadPin for `pin`. No
+    /// need for chunking.
+    pub fn create_gpio_read_pin_request_packet(&mut self, pin: flipper_pb::gpio::GpioPin) -> Result<Vec<u8>, Box<dyn Error>> {
+        let read_pin = flipper_pb::gpio::ReadPin {
+            pin: pin.into(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::GpioReadPin(read_pin));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+
+    /// Returns a Vec<u8> of an encoded gpio WritePin, to set `pin` to
+    /// `value` (0 or 1). No need for chunking.
+    pub fn create_gpio_write_pin_request_packet(&mut self, pin: flipper_pb::gpio::GpioPin, value: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        let write_pin = flipper_pb::gpio::WritePin {
+            pin: pin.into(),
+            value,
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::GpioWritePin(write_pin));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+
+    /// Returns a Vec<u8> of an encoded SetDatetimeRequest with the
+    /// datetime arguments set to the fields in `datetime`. No need
+    /// for chunking, this command is always the same size.
+    pub fn create_set_datetime_request_packet(
+        &mut self,
+        datetime: chrono::DateTime<chrono::FixedOffset>) -> Result<Vec<u8>, Box<dyn Error>> {
+
+        // SetDatetimeRequest is a thin wrapper around
+        // FuriHalRtcDateTime which itself is a thin wrapper around
+        // the STM32 LL RTC driver. This driver defines Monday as day
+        // 1 and Sunday as day 7, hence number_from_monday() below.
+        let datetime_pb = flipper_pb::system::DateTime {
+            hour: datetime.hour(),
+            minute: datetime.minute(),
+            second: datetime.second(),
+
+            day: datetime.day(),
+            month: datetime.month(),
+            year: datetime.year() as u32,
+
+            weekday: datetime.weekday().number_from_monday(),
+
+            ..Default::default()
+        };
+
+        let set_datetime_request = flipper_pb::system::SetDateTimeRequest {
+            datetime: MessageField::some(datetime_pb),
+
+            ..Default::default()
+        };
+        
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::SystemSetDatetimeRequest(
+                set_datetime_request));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+        
+        Ok(final_vec)
+    }
+
+    /// Returns a Vec<u8> of an encoded GetDatetimeRequest packet. No
+    /// chunking, because there's no arguments.
+    pub fn create_get_datetime_request_packet(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut final_msg = self.new_blank_packet(true);
+
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::SystemGetDatetimeRequest(
+                flipper_pb::system::GetDateTimeRequest::default()));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+    
+    /// Returns a Vec<u8> of an encoded DeviceInfoRequest packet. No
+    /// chunking, because there's no arguments.
+    pub fn create_device_info_request_packet(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut final_msg = self.new_blank_packet(true);
+
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::SystemDeviceInfoRequest(
+                flipper_pb::system::DeviceInfoRequest::default()));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+
+    /// Parse a &[u8] straight from the Flipper into a Main protobuf
+    /// struct. This expects the bytes to start with a varint
+    /// indicating the length of the following data.
+    pub fn parse_response(data: &[u8]) -> Result<(u32, flipper_pb::flipper::Main), Box<dyn Error>> {
+        let mut stream = CodedInputStream::from_bytes(data);
+        let length = stream.read_raw_varint32()?;
+        let s = flipper_pb::flipper::Main::parse_from_reader(&mut stream)?;
+        Ok((length, s))
+    }
+
+    /// Returns a Vec<Vec<u8>> of an encoded PropertyGetRequest for
+    /// `key`. Send all nested Vecs consecutively.
+    pub fn create_property_get_request_packet(&mut self, key: &str) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let property_request = flipper_pb::property::GetRequest {
+            key: key.to_string(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::PropertyGetRequest(property_request));
+        debug!("property get request: {:?}", final_msg);
+        let mut final_vec = Vec::new();
+
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+
+        Ok(vecs)
+    }
+
+    /// Returns a Vec<u8> of an encoded SendInputEventRequest for one
+    /// event on one button. A full button press is several events
+    /// (press, short or long, release). No chunking, this command is
+    /// always the same size.
+    pub fn create_input_event_request_packet(
+        &mut self,
+        key: flipper_pb::gui::InputKey,
+        input_type: flipper_pb::gui::InputType) -> Result<Vec<u8>, Box<dyn Error>> {
+
+        let input_request = flipper_pb::gui::SendInputEventRequest {
+            key: key.into(),
+            type_: input_type.into(),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::GuiSendInputEventRequest(input_request));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+
+    /// Returns a Vec<u8> of an encoded StartScreenStreamRequest. The
+    /// Flipper sends a ScreenFrame every time the screen changes
+    /// after this. No chunking, because there's no arguments.
+    pub fn create_start_screen_stream_request_packet(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut final_msg = self.new_blank_packet(true);
+
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::GuiStartScreenStreamRequest(
+                flipper_pb::gui::StartScreenStreamRequest::default()));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+
+    /// Returns a Vec<u8> of an encoded StopScreenStreamRequest. No
+    /// chunking, because there's no arguments.
+    pub fn create_stop_screen_stream_request_packet(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut final_msg = self.new_blank_packet(true);
+
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::GuiStopScreenStreamRequest(
+                flipper_pb::gui::StopScreenStreamRequest::default()));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+
+    /// Returns a Vec<Vec<u8>> of an encoded StartVirtualDisplayRequest,
+    /// which replaces the Flipper's screen with `frame` until the
+    /// virtual display is stopped. Send all nested Vecs consecutively.
+    pub fn create_start_virtual_display_request_packet(
+        &mut self,
+        frame: flipper_pb::gui::ScreenFrame) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+
+        let display_request = flipper_pb::gui::StartVirtualDisplayRequest {
+            first_frame: MessageField::some(frame),
+
+            ..Default::default()
+        };
+
+        let mut final_msg = self.new_blank_packet(true);
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::GuiStartVirtualDisplayRequest(display_request));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        // a frame is 1024 bytes, so this always needs chunking
+        let vecs: Vec<Vec<u8>> = final_vec
+            .chunks(self.tu_size)
+            .map(|x| x.to_vec())
+            .collect();
+
+        Ok(vecs)
+    }
+
+    /// Returns a Vec<u8> of an encoded StopVirtualDisplayRequest. No
+    /// chunking, because there's no arguments.
+    pub fn create_stop_virtual_display_request_packet(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut final_msg = self.new_blank_packet(true);
+
+        final_msg.content = Some(
+            flipper_pb::flipper::main::Content::GuiStopVirtualDisplayRequest(
+                flipper_pb::gui::StopVirtualDisplayRequest::default()));
+
+        let mut final_vec = Vec::new();
+        final_msg.write_length_delimited_to_vec(&mut final_vec)?;
+
+        Ok(final_vec)
+    }
+
+    /// Pull the first complete message off the front of `buffer`,
+    /// leaving any bytes after it for the next call. Returns None if
+    /// `buffer` doesn't hold a whole message yet.
+    ///
+    /// Use this instead of parse_response() for commands that answer
+    /// with lots of small messages, because the Flipper can pack
+    /// several of them into one notification.
+    pub fn take_message(buffer: &mut Vec<u8>) -> Result<Option<flipper_pb::flipper::Main>, Box<dyn Error>> {
+        let (header_len, length) = {
+            let mut stream = CodedInputStream::from_bytes(buffer);
+            match stream.read_raw_varint32() {
+                Ok(l) => (stream.pos() as usize, l as usize),
+                // A length varint is at most 5 bytes, so if we have
+                // more than that and still can't read it, the data
+                // is bad rather than incomplete.
+                Err(e) if buffer.len() > 5 => return Err(e.into()),
+                Err(_) => return Ok(None),
+            }
+        };
+
+        if buffer.len() < header_len + length {
+            return Ok(None);
+        }
+
+        let msg = flipper_pb::flipper::Main::parse_from_bytes(&buffer[header_len..header_len + length])?;
+        buffer.drain(..header_len + length);
+        Ok(Some(msg))
+    }
+}
@@ -0,0 +1,791 @@
+mod protobuf_codec; // make accessible
+
+// Function (unit?) tests! These are in a separate file to keep
+// protobuf_codec.rs from getting too big.
+
+use protobuf_codec::ProtobufCodec;
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn protobuf_codec_launch_request_test() {
+        // check that data can be loaded in and out, from protobuf form to byte data
+        let mut p = ProtobufCodec::new();
+        // include command id increment in all tests
+        p.inc_command_id();
+        let path = "/ext/app.fap";
+        let args = "/ext/chungus_app.fap";
+        let mut launch_chunks = p.create_launch_request_packet(path, args).unwrap();
+        
+        let mut launch_packet = Vec::new();
+        launch_chunks.iter_mut().for_each(|x| launch_packet.append(&mut *x));
+        
+        match ProtobufCodec::parse_response(&launch_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::AppStartRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(path, r.name);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    fn protobuf_codec_list_packet_test() {
+        let mut p = ProtobufCodec::new();
+        let path = "/ext/apps";
+        p.inc_command_id();
+        let mut list_chunks = p.create_list_request_packet(path, false, 0).unwrap();
+
+        let mut list_packet = Vec::new();
+        list_chunks.iter_mut().for_each(|x| list_packet.append(&mut *x));
+            
+        match ProtobufCodec::parse_response(&list_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::StorageListRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(path, r.path);
+                    assert!(!r.include_md5);
+                    assert_eq!(0, r.filter_max_size);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+        
+    }
+
+    #[test]
+    fn protobuf_codec_list_options_packet_test() {
+        let mut p = ProtobufCodec::new();
+        let path = "/ext/nfc";
+        p.inc_command_id();
+        let list_packet = p.create_list_request_packet(path, true, 4096).unwrap().concat();
+
+        match ProtobufCodec::parse_response(&list_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::StorageListRequest(r)) = m.1.content {
+                    assert_eq!(path, r.path);
+                    assert!(r.include_md5);
+                    assert_eq!(4096, r.filter_max_size);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    fn protobuf_codec_write_request_test() {
+        // generate some data, package it up, then check to see if the
+        // data chunks match the original data
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        
+        let mut data = Vec::new();
+        for i in 0..1023 {
+            data.push(i as u8);
+        }
+
+        let write_request_chunks =
+            p.create_write_request_packets(&data, "/ext/data.dat").unwrap();
+
+        let mut index = 0;
+        for mut chunk in write_request_chunks {
+            // This test function takes a different approach than the
+            // others. We stitch up the separate Vecs for each
+            // ProtobufWriteRequestChunk and pass that to the parser,
+            // so that we can get all the data in that chunk at once
+            // but still keep file_byte_count available.
+            let mut stitched_vec = Vec::new();
+            chunk.packets.iter_mut()
+                .for_each(|x| stitched_vec.append(x));
+            
+            match ProtobufCodec::parse_response(&stitched_vec) {
+                Ok(m) => {
+                    if let Some(flipper_pb::flipper::main::Content::StorageWriteRequest(r)) = m.1.content {
+                        assert_eq!(1, m.1.command_id);
+                        assert_eq!(r.file.data, data[index..index+chunk.file_byte_count]);
+                        index += chunk.file_byte_count;
+                    } else {
+                        panic!("wrong type of protobuf message");
+                    }
+                },
+                Err(e) => {
+                    panic!("error {:?}", e);
+                }
+            };
+        }
+    }
+
+    #[test]
+    fn protobuf_codec_tuning_test() {
+        // smaller write chunks mean more write requests, and a smaller
+        // TU size means each one is split into more pieces
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        p.set_write_chunk_size(100);
+        p.set_tu_size(20);
+
+        let data = vec![0x55u8; 250];
+        let write_request_chunks =
+            p.create_write_request_packets(&data, "/ext/data.dat").unwrap();
+        assert_eq!(3, write_request_chunks.len());
+
+        let mut total = 0;
+        for mut chunk in write_request_chunks {
+            assert!(chunk.packets.iter().all(|x| x.len() <= 20));
+            assert!(chunk.packets.len() > 1);
+
+            let mut stitched_vec = Vec::new();
+            chunk.packets.iter_mut()
+                .for_each(|x| stitched_vec.append(x));
+            match ProtobufCodec::parse_response(&stitched_vec) {
+                Ok(m) => {
+                    if let Some(flipper_pb::flipper::main::Content::StorageWriteRequest(r)) = m.1.content {
+                        assert_eq!(chunk.file_byte_count, r.file.data.len());
+                        total += r.file.data.len();
+                    } else {
+                        panic!("wrong type of protobuf message");
+                    }
+                },
+                Err(e) => {
+                    panic!("error {:?}", e);
+                }
+            };
+        }
+        assert_eq!(data.len(), total);
+    }
+
+    #[test]
+    fn protobuf_codec_read_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let path = "/ext/apps/GPIO/ublox.fap";
+        let mut read_chunks = p.create_read_request_packet(path).unwrap();
+        
+        let mut read_packet = Vec::new();
+        read_chunks.iter_mut().for_each(|x| read_packet.append(&mut *x));
+        
+        match ProtobufCodec::parse_response(&read_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::StorageReadRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(path, r.path);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_stat_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let path = "/ext/apps/GPIO/ublox.fap";
+        let mut stat_chunks = p.create_stat_request_packet(path).unwrap();
+
+        let mut stat_packet = Vec::new();
+        stat_chunks.iter_mut().for_each(|x| stat_packet.append(&mut *x));
+        
+        match ProtobufCodec::parse_response(&stat_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::StorageStatRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(path, r.path);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_timestamp_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let path = "/ext/subghz/garage.sub";
+        let timestamp_chunks = p.create_timestamp_request_packet(path).unwrap();
+
+        let timestamp_packet: Vec<u8> = timestamp_chunks.concat();
+        match ProtobufCodec::parse_response(&timestamp_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::StorageTimestampRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(path, r.path);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_storage_info_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let path = "/ext";
+        let mut info_chunks = p.create_storage_info_request_packet(path).unwrap();
+
+        let mut info_packet = Vec::new();
+        info_chunks.iter_mut().for_each(|x| info_packet.append(&mut *x));
+
+        match ProtobufCodec::parse_response(&info_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::StorageInfoRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(path, r.path);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_md5sum_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let path = "/ext/badusb/payload.txt";
+        let mut md5sum_chunks = p.create_md5sum_request_packet(path).unwrap();
+
+        let mut md5sum_packet = Vec::new();
+        md5sum_chunks.iter_mut().for_each(|x| md5sum_packet.append(&mut *x));
+
+        match ProtobufCodec::parse_response(&md5sum_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::StorageMd5sumRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(path, r.path);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_mkdir_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let path = "/ext/apps_data/flipwire";
+        let mut mkdir_chunks = p.create_mkdir_request_packet(path).unwrap();
+
+        let mut mkdir_packet = Vec::new();
+        mkdir_chunks.iter_mut().for_each(|x| mkdir_packet.append(&mut *x));
+
+        match ProtobufCodec::parse_response(&mkdir_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::StorageMkdirRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(path, r.path);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_rename_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let old_path = "/ext/subghz/garage.sub";
+        let new_path = "/ext/subghz/archive/garage.sub";
+        let mut rename_chunks = p.create_rename_request_packet(old_path, new_path).unwrap();
+
+        let mut rename_packet = Vec::new();
+        rename_chunks.iter_mut().for_each(|x| rename_packet.append(&mut *x));
+
+        match ProtobufCodec::parse_response(&rename_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::StorageRenameRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(old_path, r.old_path);
+                    assert_eq!(new_path, r.new_path);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_delete_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let path = "/ext/apps/GPIO/ublox.fap";
+        let mut delete_chunks = p.create_delete_request_packet(path, true).unwrap();
+
+        let mut delete_packet = Vec::new();
+        delete_chunks.iter_mut().for_each(|x| delete_packet.append(&mut *x));
+        
+        match ProtobufCodec::parse_response(&delete_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::StorageDeleteRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(path, r.path);
+                    assert_eq!(true, r.recursive);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_alert_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let alert_packet = p.create_alert_request_packet().unwrap();
+        
+        match ProtobufCodec::parse_response(&alert_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::SystemPlayAudiovisualAlertRequest(_)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                } else {
+                    panic!("unexpected type decoded!");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+
+    #[test]
+    pub fn protobuf_codec_desktop_is_locked_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let is_locked_packet = p.create_desktop_is_locked_request_packet().unwrap();
+
+        match ProtobufCodec::parse_response(&is_locked_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::DesktopIsLockedRequest(_)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                } else {
+                    panic!("unexpected type decoded!");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_ping_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let data = b"flipwire";
+        let ping_packet = p.create_ping_request_packet(data).unwrap();
+
+        match ProtobufCodec::parse_response(&ping_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::SystemPingRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(data.to_vec(), r.data);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_raw_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let msg = flipper_pb::flipper::Main {
+            // should be replaced with the codec's
+            command_id: 99,
+            content: Some(flipper_pb::flipper::main::Content::StorageListRequest(
+                flipper_pb::storage::ListRequest {
+                    path: "/ext".to_string(),
+                    ..Default::default()
+                })),
+            ..Default::default()
+        };
+        let raw_chunks = p.create_raw_request_packet(msg).unwrap();
+
+        let raw_packet: Vec<u8> = raw_chunks.concat();
+        match ProtobufCodec::parse_response(&raw_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::StorageListRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!("/ext", r.path);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+        assert_eq!(2, p.next_command_id());
+    }
+
+    #[test]
+    pub fn protobuf_codec_gpio_set_pin_mode_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let packet = p.create_gpio_set_pin_mode_request_packet(
+            flipper_pb::gpio::GpioPin::PA7, flipper_pb::gpio::GpioPinMode::OUTPUT).unwrap();
+
+        match ProtobufCodec::parse_response(&packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::GpioSetPinMode(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(flipper_pb::gpio::GpioPin::PA7, r.pin.enum_value().unwrap());
+                    assert_eq!(flipper_pb::gpio::GpioPinMode::OUTPUT, r.mode.enum_value().unwrap());
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_gpio_read_pin_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let packet = p.create_gpio_read_pin_request_packet(flipper_pb::gpio::GpioPin::PC3).unwrap();
+
+        match ProtobufCodec::parse_response(&packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::GpioReadPin(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(flipper_pb::gpio::GpioPin::PC3, r.pin.enum_value().unwrap());
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_gpio_write_pin_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let packet = p.create_gpio_write_pin_request_packet(flipper_pb::gpio::GpioPin::PB2, 1).unwrap();
+
+        match ProtobufCodec::parse_response(&packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::GpioWritePin(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(flipper_pb::gpio::GpioPin::PB2, r.pin.enum_value().unwrap());
+                    assert_eq!(1, r.value);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_next_command_id_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let id = p.next_command_id();
+        let stat_chunks = p.create_stat_request_packet("/ext").unwrap();
+
+        let stat_packet: Vec<u8> = stat_chunks.concat();
+        let m = ProtobufCodec::parse_response(&stat_packet).unwrap();
+        assert_eq!(id, m.1.command_id);
+        assert_eq!(id + 1, p.next_command_id());
+    }
+
+    #[test]
+    pub fn protobuf_codec_set_datetime_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let datetime = chrono::DateTime::parse_from_rfc2822("Mon, 29 Jan 2024 10:39:45 -0700").unwrap();
+        let datetime_packet = p.create_set_datetime_request_packet(datetime).unwrap();
+
+        match ProtobufCodec::parse_response(&datetime_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::SystemSetDatetimeRequest(r)) = m.1.content {
+                    assert_eq!(r.datetime.hour, datetime.hour());
+                    assert_eq!(r.datetime.minute, datetime.minute());
+                    assert_eq!(r.datetime.second, datetime.second());
+                    assert_eq!(r.datetime.day, datetime.day());
+                    assert_eq!(r.datetime.month, datetime.month());
+                    assert_eq!(r.datetime.year, datetime.year() as u32);
+                    assert_eq!(r.datetime.weekday, datetime.weekday().number_from_monday());
+                    assert_eq!(1, m.1.command_id);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_get_datetime_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let datetime_packet = p.create_get_datetime_request_packet().unwrap();
+
+        match ProtobufCodec::parse_response(&datetime_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::SystemGetDatetimeRequest(_)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+    
+    #[test]
+    pub fn protobuf_codec_device_info_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let device_info_packet = p.create_device_info_request_packet().unwrap();
+
+        match ProtobufCodec::parse_response(&device_info_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::SystemDeviceInfoRequest(_)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_property_get_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let key = "devinfo.";
+        let mut property_chunks = p.create_property_get_request_packet(key).unwrap();
+
+        let mut property_packet = Vec::new();
+        property_chunks.iter_mut().for_each(|x| property_packet.append(&mut *x));
+
+        match ProtobufCodec::parse_response(&property_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::PropertyGetRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(key, r.key);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_input_event_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let input_packet = p.create_input_event_request_packet(
+            flipper_pb::gui::InputKey::OK, flipper_pb::gui::InputType::LONG).unwrap();
+
+        match ProtobufCodec::parse_response(&input_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::GuiSendInputEventRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(flipper_pb::gui::InputKey::OK, r.key.unwrap());
+                    assert_eq!(flipper_pb::gui::InputType::LONG, r.type_.unwrap());
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_screen_stream_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let start_packet = p.create_start_screen_stream_request_packet().unwrap();
+        let stop_packet = p.create_stop_screen_stream_request_packet().unwrap();
+
+        match ProtobufCodec::parse_response(&start_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::GuiStartScreenStreamRequest(_)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+
+        match ProtobufCodec::parse_response(&stop_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::GuiStopScreenStreamRequest(_)) = m.1.content {
+                    assert_eq!(2, m.1.command_id);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn protobuf_codec_virtual_display_request_test() {
+        let mut p = ProtobufCodec::new();
+        p.inc_command_id();
+        let mut frame = flipper_pb::gui::ScreenFrame::new();
+        frame.data = vec![0xa5; 1024];
+        let start_chunks = p.create_start_virtual_display_request_packet(frame).unwrap();
+        let stop_packet = p.create_stop_virtual_display_request_packet().unwrap();
+
+        let start_packet: Vec<u8> = start_chunks.concat();
+        match ProtobufCodec::parse_response(&start_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::GuiStartVirtualDisplayRequest(r)) = m.1.content {
+                    assert_eq!(1, m.1.command_id);
+                    assert_eq!(vec![0xa5; 1024], r.first_frame.data);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+
+        match ProtobufCodec::parse_response(&stop_packet) {
+            Ok(m) => {
+                if let Some(flipper_pb::flipper::main::Content::GuiStopVirtualDisplayRequest(_)) = m.1.content {
+                    assert_eq!(2, m.1.command_id);
+                } else {
+                    panic!("wrong type of protobuf message");
+                }
+            },
+            Err(e) => {
+                panic!("error {:?}", e);
+            }
+        };
+    }
+
+    #[test]
+    pub fn take_message_test() {
+        // two complete messages back to back, plus half of a third,
+        // like we'd get from a couple of notifications
+        let mut p = ProtobufCodec::new();
+        let mut buffer = p.create_device_info_request_packet().unwrap();
+        buffer.extend(p.create_alert_request_packet().unwrap());
+        let third = p.create_get_datetime_request_packet().unwrap();
+        buffer.extend(&third[..third.len() / 2]);
+
+        match ProtobufCodec::take_message(&mut buffer) {
+            Ok(Some(m)) => {
+                assert_eq!(0, m.command_id);
+                assert!(matches!(m.content,
+                                 Some(flipper_pb::flipper::main::Content::SystemDeviceInfoRequest(_))));
+            },
+            other => {
+                panic!("expected first message, got {:?}", other);
+            }
+        };
+
+        match ProtobufCodec::take_message(&mut buffer) {
+            Ok(Some(m)) => {
+                assert_eq!(1, m.command_id);
+                assert!(matches!(m.content,
+                                 Some(flipper_pb::flipper::main::Content::SystemPlayAudiovisualAlertRequest(_))));
+            },
+            other => {
+                panic!("expected second message, got {:?}", other);
+            }
+        };
+
+        // the third one isn't complete yet
+        assert!(matches!(ProtobufCodec::take_message(&mut buffer), Ok(None)));
+        buffer.extend(&third[third.len() / 2..]);
+        assert!(matches!(ProtobufCodec::take_message(&mut buffer), Ok(Some(_))));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn bad_data_test() {
+        // force the whole thing to u8
+        // the original data is from a StorageListRequest of "/ext/apps/NFC/"
+        //let dat = [18u8, 58, 16, 10, 14, 47, 101, 120, 116, 47, 97, 112, 112, 115, 47, 78, 70, 67, 47];
+        let dat = [18u8, 0, 16, 10, 14, 47, 101, 120, 116, 47, 97, 112, 112, 115, 47, 78, 70, 67, 47];
+
+        match ProtobufCodec::parse_response(&dat) {
+            Ok(_) => {
+                panic!("parse of bad data succeeded!");
+            },
+            Err(_) => {
+                // nothing to do here
+            }
+        };
+    }
+
+}
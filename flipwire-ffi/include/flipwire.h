@@ -0,0 +1,68 @@
+/* C API for flipwire. Link against libflipwire_ffi.
+ *
+ * Functions that can fail return 0 on success and -1 on failure, and
+ * flipwire_last_error() describes the failure. All calls block until
+ * they finish, so keep them off your UI thread.
+ *
+ * Handles: you own the handle flipwire_connect() returns until you
+ * pass it to flipwire_disconnect(), which frees it; don't use it after
+ * that. Every function taking a handle returns -1 for a NULL handle
+ * (flipwire_disconnect() ignores it), but a freed handle can't be
+ * detected. Don't use one handle from two threads at once.
+ *
+ * Strings: every `const char *` argument must be a NUL-terminated
+ * UTF-8 string. NULL is an error, not an empty string. flipwire keeps
+ * no pointers to your strings after a call returns.
+ */
+
+#ifndef FLIPWIRE_H
+#define FLIPWIRE_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct FlipwireHandle FlipwireHandle;
+
+/* Called with the bytes done so far and the total bytes. */
+typedef void (*FlipwireProgressCallback)(uint64_t done, uint64_t total, void *user_data);
+
+/* Called once for each entry in a directory. `name` is only valid
+ * during the call. Directories have no size. */
+typedef void (*FlipwireListCallback)(const char *name, bool is_dir, uint32_t size, void *user_data);
+
+/* Description of the last error on this thread, or NULL. Valid until
+ * the next failing call. */
+const char *flipwire_last_error(void);
+
+/* Connect to a paired Flipper by name (like "Uwu2"). NULL on failure.
+ * The handle is yours until flipwire_disconnect(). */
+FlipwireHandle *flipwire_connect(const char *name);
+
+/* Disconnect and free the handle, even if disconnecting fails. NULL is
+ * ignored and returns 0. */
+int flipwire_disconnect(FlipwireHandle *handle);
+
+/* Upload a local file to a full path on the Flipper. `progress` can be NULL. */
+int flipwire_upload(FlipwireHandle *handle, const char *file, const char *dest,
+                    FlipwireProgressCallback progress, void *user_data);
+
+/* Download a Flipper file to a local path. `progress` can be NULL. */
+int flipwire_download(FlipwireHandle *handle, const char *file, const char *dest,
+                      FlipwireProgressCallback progress, void *user_data);
+
+/* List a directory on the Flipper. `callback` can't be NULL. */
+int flipwire_list(FlipwireHandle *handle, const char *path,
+                  FlipwireListCallback callback, void *user_data);
+
+/* Launch an app by path or built-in name, with arguments (can be ""). */
+int flipwire_launch(FlipwireHandle *handle, const char *app, const char *args);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* FLIPWIRE_H */
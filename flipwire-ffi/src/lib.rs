@@ -0,0 +1,284 @@
+// C API for flipwire, so GUI frontends (or anything else that can
+// call C) can use the same BLE and RPC code as the command line
+// tool. The declarations are in include/flipwire.h.
+//
+// Every function that can fail returns 0 on success and -1 on
+// failure, and flipwire_last_error() says what went wrong. Calls
+// block until they're done, so don't make them from a UI thread.
+//
+// The caller owns a handle from flipwire_connect() until it's given
+// to flipwire_disconnect(), which frees it. Functions that take a
+// handle fail on NULL instead of crashing, but a handle that's been
+// disconnected can't be checked for, and one handle can't be used
+// from two threads at once.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::path::Path;
+use std::ptr;
+
+use flipwire::flipper_ble::{ConnectOptions, FlipperBle};
+use flipwire::flipper_pb;
+use tokio::runtime::Runtime;
+
+/// A connected Flipper. Get one from flipwire_connect() and give it
+/// back to flipwire_disconnect() when you're done.
+pub struct FlipwireHandle {
+    runtime: Runtime,
+    flipper: FlipperBle,
+}
+
+/// Called with the bytes done so far and the total bytes
+pub type FlipwireProgressCallback = Option<extern "C" fn(done: u64, total: u64, user_data: *mut c_void)>;
+
+/// Called once for each entry in a directory
+pub type FlipwireListCallback = Option<extern "C" fn(name: *const c_char, is_dir: bool, size: u32, user_data: *mut c_void)>;
+
+// The caller's user_data pointer, which we only hand back to them.
+struct UserData(*mut c_void);
+// Safety: we never dereference it, and the callback runs on the
+// thread that made the call.
+unsafe impl Send for UserData {}
+
+impl UserData {
+    // (a method, so closures capture all of UserData and not just
+    // the pointer inside it)
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(e: &dyn Error) {
+    // interior NULs can't go in a C string
+    let msg = e.to_string().replace('\0', " ");
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(msg).ok());
+}
+
+/// Turn a Result into the 0/-1 return value, saving the error.
+fn status(result: Result<(), Box<dyn Error>>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(e.as_ref());
+            -1
+        },
+    }
+}
+
+/// Borrow a C string argument as UTF-8.
+///
+/// # Safety
+///
+/// `s` must be NULL or a valid NUL-terminated string.
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str, Box<dyn Error>> {
+    if s.is_null() {
+        return Err("unexpected NULL string".into());
+    }
+    Ok(CStr::from_ptr(s).to_str()?)
+}
+
+/// Borrow the handle argument.
+///
+/// # Safety
+///
+/// `handle` must be NULL or come from flipwire_connect() and not have
+/// been disconnected, and nothing else can be using it.
+unsafe fn handle_arg<'a>(handle: *mut FlipwireHandle) -> Result<&'a mut FlipwireHandle, Box<dyn Error>> {
+    if handle.is_null() {
+        return Err("NULL handle".into());
+    }
+    Ok(&mut *handle)
+}
+
+fn progress_callback(callback: FlipwireProgressCallback, user_data: *mut c_void) -> Option<flipwire::flipper_ble::ProgressCallback> {
+    let callback = callback?;
+    let user_data = UserData(user_data);
+    Some(Box::new(move |done, total| {
+        callback(done, total, user_data.get());
+    }))
+}
+
+/// Returns a description of the last error on this thread, or NULL
+/// if nothing has failed yet. The string is valid until the next
+/// call that fails.
+#[no_mangle]
+pub extern "C" fn flipwire_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(msg) => msg.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Connect to a paired Flipper by name (like "Uwu2"). Returns NULL on
+/// failure.
+///
+/// # Safety
+///
+/// `name` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn flipwire_connect(name: *const c_char) -> *mut FlipwireHandle {
+    let connect = || -> Result<FlipwireHandle, Box<dyn Error>> {
+        let name = str_arg(name)?;
+        let runtime = Runtime::new()?;
+        let flipper = runtime.block_on(FlipperBle::connect_with(name, &ConnectOptions::default()))?;
+        Ok(FlipwireHandle {
+            runtime,
+            flipper,
+        })
+    };
+
+    match connect() {
+        Ok(handle) => Box::into_raw(Box::new(handle)),
+        Err(e) => {
+            set_last_error(e.as_ref());
+            ptr::null_mut()
+        },
+    }
+}
+
+/// Disconnect from the Flipper and free the handle, which can't be
+/// used after this. NULL is ignored.
+///
+/// # Safety
+///
+/// `handle` must be NULL or come from flipwire_connect(), and not
+/// have been disconnected already.
+#[no_mangle]
+pub unsafe extern "C" fn flipwire_disconnect(handle: *mut FlipwireHandle) -> c_int {
+    if handle.is_null() {
+        return 0;
+    }
+    let handle = Box::from_raw(handle);
+    status(handle.runtime.block_on(handle.flipper.disconnect()))
+}
+
+/// Upload a local file to a full path on the Flipper.
+///
+/// # Safety
+///
+/// `handle` must be NULL (which fails) or a handle from
+/// flipwire_connect() that hasn't been disconnected, and `file` and
+/// `dest` must be valid NUL-terminated strings. `progress` can be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn flipwire_upload(
+    handle: *mut FlipwireHandle,
+    file: *const c_char,
+    dest: *const c_char,
+    progress: FlipwireProgressCallback,
+    user_data: *mut c_void) -> c_int {
+
+    status((|| {
+        let handle = handle_arg(handle)?;
+        let file = str_arg(file)?;
+        let dest = str_arg(dest)?;
+        handle.flipper.set_progress_callback(progress_callback(progress, user_data));
+        let result = handle.runtime.block_on(handle.flipper.upload_file(Path::new(file), dest));
+        handle.flipper.set_progress_callback(None);
+        result
+    })())
+}
+
+/// Download a file from the Flipper to a local path.
+///
+/// # Safety
+///
+/// `handle` must be NULL (which fails) or a handle from
+/// flipwire_connect() that hasn't been disconnected, and `file` and
+/// `dest` must be valid NUL-terminated strings. `progress` can be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn flipwire_download(
+    handle: *mut FlipwireHandle,
+    file: *const c_char,
+    dest: *const c_char,
+    progress: FlipwireProgressCallback,
+    user_data: *mut c_void) -> c_int {
+
+    status((|| {
+        let handle = handle_arg(handle)?;
+        let file = str_arg(file)?;
+        let dest = str_arg(dest)?;
+        handle.flipper.set_progress_callback(progress_callback(progress, user_data));
+        let result = handle.runtime.block_on(handle.flipper.download_file(file, Path::new(dest)));
+        handle.flipper.set_progress_callback(None);
+        result
+    })())
+}
+
+/// List a directory on the Flipper, calling `callback` for each
+/// entry. The name is only valid during the callback.
+///
+/// # Safety
+///
+/// `handle` must be NULL (which fails) or a handle from
+/// flipwire_connect() that hasn't been disconnected, and `path` must
+/// be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn flipwire_list(
+    handle: *mut FlipwireHandle,
+    path: *const c_char,
+    callback: FlipwireListCallback,
+    user_data: *mut c_void) -> c_int {
+
+    status((|| {
+        let handle = handle_arg(handle)?;
+        let path = str_arg(path)?;
+        let callback = callback.ok_or("list callback can't be NULL")?;
+        let entries = handle.runtime.block_on(handle.flipper.list_entries(path))?;
+        for f in entries {
+            let name = CString::new(f.name.replace('\0', " "))?;
+            let is_dir = f.type_ == flipper_pb::storage::file::FileType::DIR.into();
+            callback(name.as_ptr(), is_dir, f.size, user_data);
+        }
+        Ok(())
+    })())
+}
+
+/// Launch an app: a full path ("/ext/apps/...") or the name of a
+/// built-in app (like "NFC"), with arguments, which can be empty.
+///
+/// # Safety
+///
+/// `handle` must be NULL (which fails) or a handle from
+/// flipwire_connect() that hasn't been disconnected, and `app` and
+/// `args` must be valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn flipwire_launch(
+    handle: *mut FlipwireHandle,
+    app: *const c_char,
+    args: *const c_char) -> c_int {
+
+    status((|| {
+        let handle = handle_arg(handle)?;
+        let app = str_arg(app)?;
+        let args = str_arg(args)?;
+        handle.runtime.block_on(handle.flipper.launch(app, args))
+    })())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(flipwire_last_error()) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn null_handle_test() {
+        let path = c"/ext";
+        unsafe {
+            assert_eq!(flipwire_upload(ptr::null_mut(), path.as_ptr(), path.as_ptr(), None, ptr::null_mut()), -1);
+            assert_eq!(last_error(), "NULL handle");
+            assert_eq!(flipwire_download(ptr::null_mut(), path.as_ptr(), path.as_ptr(), None, ptr::null_mut()), -1);
+            assert_eq!(flipwire_list(ptr::null_mut(), path.as_ptr(), None, ptr::null_mut()), -1);
+            assert_eq!(flipwire_launch(ptr::null_mut(), path.as_ptr(), path.as_ptr()), -1);
+            assert_eq!(last_error(), "NULL handle");
+            assert_eq!(flipwire_disconnect(ptr::null_mut()), 0);
+        }
+    }
+}
@@ -0,0 +1,428 @@
+// Talking to a Flipper from a web page over Web Bluetooth, with the
+// same RPC codec flipwire uses, so a browser-based client (something
+// like lab.flipper.net) doesn't have to reimplement it. Build it for
+// wasm32-unknown-unknown with wasm-pack:
+//
+//     wasm-pack build flipwire-web --target web
+//
+// and then from JavaScript:
+//
+//     const flipper = await Flipper.connect(); // from a click handler
+//     for (const f of await flipper.list("/ext")) {
+//         console.log(f.name, f.isDir, f.size);
+//     }
+//
+// Browsers only let a page ask for a Bluetooth device in response to
+// a click or key press, and only over https (or on localhost).
+//
+// Requests go through flipwire's own Session, with a Transport that
+// writes over Web Bluetooth, so they're paced by the Flipper's flow
+// control the same way the command line's are, and several can be in
+// flight at once.
+//
+// The Web Bluetooth bindings are declared here instead of coming from
+// web-sys, which only has them with --cfg=web_sys_unstable_apis.
+
+#[macro_use] extern crate tracing;
+
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use flipwire::flipper_pb;
+use flipwire::flow_control::FlowControl;
+use flipwire::protobuf_codec::ProtobufCodec;
+use flipwire::rpc_reader::RpcReader;
+use flipwire::session::Session;
+use flipwire::transport::Transport;
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use js_sys::{Array, DataView, Function, Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+// The Flipper's serial service, which carries the RPC
+const SERIAL_SERVICE_UUID: &str = "8fe5b3d5-2e7f-4a98-2a48-7acc60fe0000";
+// the characteristic we write to
+const RX_CHR_UUID: &str = "19ed82ae-ed21-4c9d-4145-228e62fe0000";
+// the characteristic we read from
+const TX_CHR_UUID: &str = "19ed82ae-ed21-4c9d-4145-228e61fe0000";
+// flow control
+const FLOW_CTRL_CHR_UUID: &str = "19ed82ae-ed21-4c9d-4145-228e63fe0000";
+
+#[wasm_bindgen]
+extern "C" {
+    type Bluetooth;
+    #[wasm_bindgen(method, js_name = requestDevice)]
+    fn request_device(this: &Bluetooth, options: &Object) -> Promise;
+
+    type BluetoothDevice;
+    #[wasm_bindgen(method, getter)]
+    fn name(this: &BluetoothDevice) -> Option<String>;
+    #[wasm_bindgen(method, getter)]
+    fn gatt(this: &BluetoothDevice) -> GattServer;
+    #[wasm_bindgen(method, js_name = addEventListener)]
+    fn add_event_listener(this: &BluetoothDevice, kind: &str, listener: &Function);
+
+    // BluetoothRemoteGATTServer
+    type GattServer;
+    #[wasm_bindgen(method)]
+    fn connect(this: &GattServer) -> Promise;
+    #[wasm_bindgen(method)]
+    fn disconnect(this: &GattServer);
+    #[wasm_bindgen(method, js_name = getPrimaryService)]
+    fn get_primary_service(this: &GattServer, uuid: &str) -> Promise;
+
+    // BluetoothRemoteGATTService
+    type GattService;
+    #[wasm_bindgen(method, js_name = getCharacteristic)]
+    fn get_characteristic(this: &GattService, uuid: &str) -> Promise;
+
+    // BluetoothRemoteGATTCharacteristic
+    #[derive(Clone)]
+    type Characteristic;
+    #[wasm_bindgen(method, getter)]
+    fn value(this: &Characteristic) -> Option<DataView>;
+    #[wasm_bindgen(method, js_name = readValue)]
+    fn read_value(this: &Characteristic) -> Promise;
+    #[wasm_bindgen(method, js_name = writeValueWithResponse)]
+    fn write_value_with_response(this: &Characteristic, value: &Uint8Array) -> Promise;
+    #[wasm_bindgen(method, js_name = startNotifications)]
+    fn start_notifications(this: &Characteristic) -> Promise;
+    #[wasm_bindgen(method, js_name = addEventListener)]
+    fn add_event_listener(this: &Characteristic, kind: &str, listener: &Function);
+
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &Function, ms: i32) -> JsValue;
+}
+
+/// A connected Flipper. Every method that talks to it returns a
+/// Promise, which rejects with an Error saying what went wrong.
+#[wasm_bindgen]
+pub struct Flipper {
+    connection: Rc<Connection>,
+}
+
+struct Connection {
+    device: BluetoothDevice,
+    session: Session,
+    // the event listeners, which have to live as long as the
+    // connection
+    _listeners: Vec<Closure<dyn FnMut(JsValue)>>,
+}
+
+/// Writes requests to the Flipper's RX characteristic
+struct WebTransport {
+    rx: OneThread<Characteristic>,
+}
+
+impl Transport for WebTransport {
+    fn write<'a>(&'a self, piece: &'a [u8]) -> BoxFuture<'a, Result<(), Box<dyn Error>>> {
+        let written = wait(self.rx.0.write_value_with_response(&Uint8Array::from(piece)));
+        Box::pin(OneThread(async move {
+            written.await?;
+            Ok(())
+        }))
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let done = sleep(i32::try_from(duration.as_millis()).unwrap_or(i32::MAX));
+        Box::pin(OneThread(async move {
+            let _ = done.await;
+        }))
+    }
+}
+
+// Transport has to be Send and Sync, since natively a session is
+// shared between tokio tasks, but nothing from JavaScript is. A page
+// only has the one thread, so this wraps them up to say they are.
+struct OneThread<T>(T);
+
+// Safety: wasm32-unknown-unknown has no threads, so there's nowhere
+// to send it to or share it with. (Built natively, there's no Web
+// Bluetooth to connect with, so none are ever made.)
+unsafe impl<T> Send for OneThread<T> {}
+unsafe impl<T> Sync for OneThread<T> {}
+
+impl<F: Future> Future for OneThread<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        // Safety: the future inside is never moved out
+        unsafe { self.map_unchecked_mut(|t| &mut t.0) }.poll(cx)
+    }
+}
+
+#[wasm_bindgen]
+impl Flipper {
+    /// Ask the user to pick a Flipper, connect to it, and start
+    /// listening to it. Call this from a click or key press handler.
+    pub async fn connect() -> Result<Flipper, JsError> {
+        Ok(Flipper {
+            connection: Rc::new(Connection::open().await.map_err(js_error)?),
+        })
+    }
+
+    /// The Flipper's name, like "Uwu2"
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> Option<String> {
+        self.connection.device.name()
+    }
+
+    /// Disconnect from the Flipper. Anything still waiting on it fails.
+    pub fn disconnect(&self) {
+        self.connection.device.gatt().disconnect();
+    }
+
+    /// List a directory. Resolves to an array of `{name, isDir, size}`.
+    pub fn list(&self, path: String) -> Promise {
+        self.run(|c| async move {
+            let entries = Array::new();
+            for f in c.list(&path).await? {
+                let entry = Object::new();
+                set(&entry, "name", &f.name.into())?;
+                set(&entry, "isDir", &(f.type_ == flipper_pb::storage::file::FileType::DIR.into()).into())?;
+                set(&entry, "size", &f.size.into())?;
+                entries.push(&entry);
+            }
+            Ok(entries.into())
+        })
+    }
+
+    /// Read a whole file. Resolves to a Uint8Array.
+    pub fn read(&self, path: String) -> Promise {
+        self.run(|c| async move {
+            Ok(Uint8Array::from(c.read(&path).await?.as_slice()).into())
+        })
+    }
+
+    /// Write `data` to a file, replacing it if it exists.
+    pub fn write(&self, path: String, data: Vec<u8>) -> Promise {
+        self.run(|c| async move {
+            c.write(&path, &data).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Make a directory.
+    pub fn mkdir(&self, path: String) -> Promise {
+        self.run(|c| async move {
+            c.request(|p| p.create_mkdir_request_packet(&path)).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Delete a file or directory, and everything in it if
+    /// `recursive` is set.
+    pub fn delete(&self, path: String, recursive: bool) -> Promise {
+        self.run(|c| async move {
+            c.request(|p| p.create_delete_request_packet(&path, recursive)).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Launch an app: a full path ("/ext/apps/...") or the name of a
+    /// built-in app (like "NFC"), with arguments, which can be empty.
+    pub fn launch(&self, app: String, args: String) -> Promise {
+        self.run(|c| async move {
+            c.request(|p| p.create_launch_request_packet(&app, &args)).await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+}
+
+impl Flipper {
+    /// Returns a Promise for what `f` does with the connection.
+    fn run<F, Fut>(&self, f: F) -> Promise
+    where F: FnOnce(Rc<Connection>) -> Fut,
+          Fut: Future<Output = Result<JsValue, Box<dyn Error>>> + 'static {
+        let result = f(self.connection.clone());
+        wasm_bindgen_futures::future_to_promise(async move {
+            result.await.map_err(|e| js_error(e).into())
+        })
+    }
+}
+
+impl Connection {
+    async fn open() -> Result<Connection, Box<dyn Error>> {
+        let navigator = Reflect::get(&js_sys::global(), &"navigator".into()).map_err(js_message)?;
+        let bluetooth = Reflect::get(&navigator, &"bluetooth".into()).map_err(js_message)?;
+        if bluetooth.is_undefined() {
+            return Err("this browser doesn't have Web Bluetooth".into());
+        }
+        let bluetooth: Bluetooth = bluetooth.unchecked_into();
+
+        // {filters: [{namePrefix: "Flipper"}], optionalServices: [...]}
+        let filter = Object::new();
+        set(&filter, "namePrefix", &"Flipper".into())?;
+        let options = Object::new();
+        set(&options, "filters", &Array::of1(&filter))?;
+        set(&options, "optionalServices", &Array::of1(&SERIAL_SERVICE_UUID.into()))?;
+        let device: BluetoothDevice = wait(bluetooth.request_device(&options)).await?.unchecked_into();
+        info!("connecting to {:?}", device.name());
+
+        let server: GattServer = wait(device.gatt().connect()).await?.unchecked_into();
+        let service: GattService = wait(server.get_primary_service(SERIAL_SERVICE_UUID)).await?.unchecked_into();
+        let rx: Characteristic = wait(service.get_characteristic(RX_CHR_UUID)).await?.unchecked_into();
+        let tx: Characteristic = wait(service.get_characteristic(TX_CHR_UUID)).await?.unchecked_into();
+
+        let (data_tx, notifications) = mpsc::unbounded();
+        let (flow_tx, flow_values) = mpsc::unbounded();
+        let mut listeners = Vec::new();
+
+        let on_data = Closure::<dyn FnMut(JsValue)>::new({
+            let tx = tx.clone();
+            let data_tx = data_tx.clone();
+            move |_| {
+                if let Some(value) = tx.value() {
+                    let _ = data_tx.unbounded_send(bytes(&value));
+                }
+            }
+        });
+        tx.add_event_listener("characteristicvaluechanged", on_data.as_ref().unchecked_ref());
+        wait(tx.start_notifications()).await?;
+        listeners.push(on_data);
+
+        let (reader, reading) = RpcReader::new(notifications);
+        wasm_bindgen_futures::spawn_local(reading);
+        let session = Session::new(Arc::new(WebTransport { rx: OneThread(rx) }), reader);
+
+        // Older firmware doesn't have flow control, and then writes
+        // just go out as fast as they can.
+        match wait(service.get_characteristic(FLOW_CTRL_CHR_UUID)).await {
+            Ok(flow) => {
+                let flow: Characteristic = flow.unchecked_into();
+                let initial = match wait(flow.read_value()).await {
+                    Ok(value) => Some(bytes(value.unchecked_ref())),
+                    Err(e) => {
+                        debug!("couldn't read flow control characteristic: {}", e);
+                        None
+                    },
+                };
+                let on_flow = Closure::<dyn FnMut(JsValue)>::new({
+                    let flow = flow.clone();
+                    let flow_tx = flow_tx.clone();
+                    move |_| {
+                        if let Some(value) = flow.value() {
+                            let _ = flow_tx.unbounded_send(bytes(&value));
+                        }
+                    }
+                });
+                flow.add_event_listener("characteristicvaluechanged", on_flow.as_ref().unchecked_ref());
+                wait(flow.start_notifications()).await?;
+                listeners.push(on_flow);
+
+                let (flow_control, following) = FlowControl::new(initial, flow_values);
+                wasm_bindgen_futures::spawn_local(following);
+                session.set_flow_control(Some(flow_control));
+            },
+            Err(e) => debug!("no flow control: {}", e),
+        }
+
+        // closing the channels ends the reader, which wakes up
+        // anything waiting on the Flipper
+        let mut channels = Some((data_tx, flow_tx));
+        let on_disconnect = Closure::<dyn FnMut(JsValue)>::new(move |_| {
+            info!("Flipper disconnected");
+            channels.take();
+        });
+        device.add_event_listener("gattserverdisconnected", on_disconnect.as_ref().unchecked_ref());
+        listeners.push(on_disconnect);
+
+        Ok(Connection {
+            device,
+            session,
+            _listeners: listeners,
+        })
+    }
+
+    /// Send the request `build` makes and return every message of its
+    /// response, failing if the Flipper says it didn't work.
+    async fn request<F>(&self, build: F) -> Result<Vec<flipper_pb::flipper::Main>, Box<dyn Error>>
+    where F: FnOnce(&mut ProtobufCodec) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let mut responses = self.session.request(build).await?;
+        let mut messages = Vec::new();
+        loop {
+            let m = responses.next().await?;
+            if m.command_status != flipper_pb::flipper::CommandStatus::OK.into() {
+                return Err(format!("the Flipper said {:?}", m.command_status).into());
+            }
+            let more = m.has_next;
+            messages.push(m);
+            if !more {
+                return Ok(messages);
+            }
+        }
+    }
+
+    async fn list(&self, path: &str) -> Result<Vec<flipper_pb::storage::File>, Box<dyn Error>> {
+        let mut files = Vec::new();
+        for m in self.request(|p| p.create_list_request_packet(path, false, 0)).await? {
+            if let Some(flipper_pb::flipper::main::Content::StorageListResponse(r)) = m.content {
+                files.extend(r.file);
+            }
+        }
+        Ok(files)
+    }
+
+    async fn read(&self, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = Vec::new();
+        for m in self.request(|p| p.create_read_request_packet(path)).await? {
+            if let Some(flipper_pb::flipper::main::Content::StorageReadResponse(r)) = m.content {
+                if let Some(f) = r.file.into_option() {
+                    data.extend(f.data);
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    async fn write(&self, path: &str, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.request(|p| {
+            Ok(p.create_write_request_packets(data, path)?
+               .into_iter()
+               .flat_map(|chunk| chunk.packets)
+               .collect())
+        }).await?;
+        Ok(())
+    }
+}
+
+/// Wait for a Promise, with a rejection turned into an error.
+async fn wait(promise: Promise) -> Result<JsValue, Box<dyn Error>> {
+    JsFuture::from(promise).await.map_err(js_message)
+}
+
+/// Returns a future that finishes after `ms` milliseconds.
+fn sleep(ms: i32) -> JsFuture {
+    JsFuture::from(Promise::new(&mut |resolve, _| {
+        set_timeout(&resolve, ms);
+    }))
+}
+
+fn set(object: &Object, key: &str, value: &JsValue) -> Result<(), Box<dyn Error>> {
+    Reflect::set(object, &key.into(), value).map_err(js_message)?;
+    Ok(())
+}
+
+/// Returns the bytes a DataView looks at.
+fn bytes(view: &DataView) -> Vec<u8> {
+    Uint8Array::new_with_byte_offset_and_length(&view.buffer(), view.byte_offset() as u32, view.byte_length() as u32)
+        .to_vec()
+}
+
+/// Returns what a JavaScript exception says.
+fn js_message(e: JsValue) -> Box<dyn Error> {
+    match e.dyn_ref::<js_sys::Error>() {
+        Some(e) => String::from(e.message()).into(),
+        None => format!("{:?}", e).into(),
+    }
+}
+
+fn js_error(e: Box<dyn Error>) -> JsError {
+    JsError::new(&e.to_string())
+}
@@ -0,0 +1,12 @@
+// Whatever the Flipper (or something pretending to be one) sends,
+// parsing it should fail cleanly instead of panicking.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use flipper_rpc_codec::protobuf_codec::ProtobufCodec;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = ProtobufCodec::parse_response(data);
+});
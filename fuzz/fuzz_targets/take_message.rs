@@ -0,0 +1,35 @@
+// Feed data to take_message() a piece at a time, the same way the
+// RPC reader does with notifications. It has to either take a message
+// off the front, wait for more, or fail, and never get stuck.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use flipper_rpc_codec::protobuf_codec::ProtobufCodec;
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte picks how big each piece is
+    let Some((&size, data)) = data.split_first() else {
+        return;
+    };
+    let size = usize::from(size).max(1);
+
+    let mut buffer = Vec::new();
+    for piece in data.chunks(size) {
+        buffer.extend(piece);
+        loop {
+            let before = buffer.len();
+            match ProtobufCodec::take_message(&mut buffer) {
+                Ok(Some(_)) => assert!(buffer.len() < before, "took a message without using any data"),
+                Ok(None) => break,
+                // the reader throws the buffer away when it can't
+                // make sense of it
+                Err(_) => {
+                    buffer.clear();
+                    break;
+                },
+            }
+        }
+    }
+});
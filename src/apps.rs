@@ -0,0 +1,66 @@
+// Where the Flipper keeps external apps (.fap files) and what they
+// leave behind. Each app's data and assets go in directories named
+// after its appid, which for nearly every app is the .fap's file name
+// without ".fap".
+
+#[cfg(feature = "ble")]
+use std::error::Error;
+
+#[cfg(feature = "ble")]
+use crate::flipper_ble;
+
+/// Where .fap files are installed, one directory per category
+pub const APPS_DIR: &str = "/ext/apps";
+/// Where apps save their own files
+pub const APPS_DATA_DIR: &str = "/ext/apps_data";
+/// Where apps' bundled assets are unpacked
+pub const APPS_ASSETS_DIR: &str = "/ext/apps_assets";
+
+/// Returns the appid of the app at `fap_path`, going by its file name.
+pub fn appid(fap_path: &str) -> &str {
+    let name = fap_path.rsplit('/').next().unwrap_or(fap_path);
+    name.strip_suffix(".fap").unwrap_or(name)
+}
+
+/// Returns the directories the app `appid` keeps its data and assets
+/// in. They don't always exist.
+pub fn data_dirs(appid: &str) -> [String; 2] {
+    [
+        format!("{}/{}", APPS_DATA_DIR, appid),
+        format!("{}/{}", APPS_ASSETS_DIR, appid),
+    ]
+}
+
+/// Returns the .fap for the app `name` and whichever of its data and
+/// assets directories exist, each with how many things are inside (None
+/// for the .fap).
+#[cfg(feature = "ble")]
+pub async fn files(flipper: &mut flipper_ble::FlipperBle, name: &str) -> Result<Vec<(String, Option<usize>)>, Box<dyn Error>> {
+    let fap = if name.contains('/') {
+        if flipper.stat(name).await?.is_none() {
+            return Err(format!("{} doesn't exist", name).into());
+        }
+        name.to_string()
+    } else {
+        let wanted = name.strip_suffix(".fap").unwrap_or(name);
+        let mut matches: Vec<String> = flipper.walk(APPS_DIR).await?
+            .into_iter()
+            .map(|(path, _)| path)
+            .filter(|path| path.ends_with(".fap") && appid(path).eq_ignore_ascii_case(wanted))
+            .collect();
+        match matches.len() {
+            0 => return Err(format!("no app named {:?} in {}", wanted, APPS_DIR).into()),
+            1 => matches.remove(0),
+            _ => return Err(format!("more than one app is named {:?}: {}, give the full path", wanted, matches.join(", ")).into()),
+        }
+    };
+
+    let mut targets = vec![(fap.clone(), None)];
+    for dir in data_dirs(appid(&fap)) {
+        if flipper.stat(&dir).await?.is_some() {
+            let inside = flipper.walk(&dir).await?.len();
+            targets.push((dir, Some(inside)));
+        }
+    }
+    Ok(targets)
+}
@@ -0,0 +1,594 @@
+// Backups of the Flipper's storage, as tar archives. Files are written
+// into the archive as they come in over Bluetooth, so nothing is kept
+// in memory or on disk twice. A backup has the Bluetooth pairing keys
+// from /int and whatever's been captured to the SD card, so it can be
+// encrypted with age (https://age-encryption.org), with a passphrase
+// or to someone's public key. `age -d backup.tar.age | tar x` gets it
+// back.
+//
+// It can be compressed too, with gzip or zstd, as it's written rather
+// than afterwards, so a few hundred MB off the SD card only takes up
+// the compressed size on disk.
+//
+// The tar writer is just enough of the ustar format for a backup:
+// files and directories, with GNU long name entries for the rare path
+// that doesn't fit in the header.
+
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+#[cfg(feature = "cli")]
+use std::env;
+
+#[cfg(feature = "cli")]
+use flate2::write::GzEncoder;
+
+#[cfg(all(feature = "ble", feature = "cli"))]
+use crate::flipper_ble;
+
+// tar works in blocks of this many bytes
+const BLOCK_SIZE: usize = 512;
+// longest name and prefix a ustar header has room for
+const NAME_SIZE: usize = 100;
+const PREFIX_SIZE: usize = 155;
+
+/// How to encrypt a backup
+#[derive(Debug, Clone)]
+pub enum Encryption {
+    /// With a passphrase, which age stretches with scrypt
+    Passphrase(String),
+    /// To age public keys, like "age1..."
+    Recipients(Vec<String>),
+}
+
+/// How to compress a backup
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// gzip, which anything can open (.tar.gz)
+    Gzip,
+    /// zstd, which is smaller and faster (.tar.zst)
+    Zstd,
+}
+
+/// Compresses what's written to it on the way to `W`
+#[cfg(feature = "cli")]
+pub enum Compressor<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+#[cfg(feature = "cli")]
+impl<W: Write> Compressor<W> {
+    pub fn new(inner: W, compression: Compression) -> Result<Compressor<W>, Box<dyn Error>> {
+        match compression {
+            Compression::None => Ok(Compressor::None(inner)),
+            Compression::Gzip => Ok(Compressor::Gzip(GzEncoder::new(inner, flate2::Compression::default()))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Compressor::Zstd(zstd::Encoder::new(inner, 0)?)),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => Err("flipwire was built without the \"zstd\" feature".into()),
+        }
+    }
+
+    /// Write out whatever's still being compressed and return what
+    /// it was written to.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Compressor::None(w) => Ok(w),
+            Compressor::Gzip(w) => w.finish(),
+            #[cfg(feature = "zstd")]
+            Compressor::Zstd(w) => w.finish(),
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Compressor::None(w) => w.write(buf),
+            Compressor::Gzip(w) => w.write(buf),
+            #[cfg(feature = "zstd")]
+            Compressor::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Compressor::None(w) => w.flush(),
+            Compressor::Gzip(w) => w.flush(),
+            #[cfg(feature = "zstd")]
+            Compressor::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+/// Where a backup gets written: the file itself, or age encrypting
+/// it on the way
+pub enum BackupWriter {
+    Plain(BufWriter<fs::File>),
+    #[cfg(feature = "encrypt")]
+    Encrypted(age::stream::StreamWriter<BufWriter<fs::File>>),
+}
+
+impl BackupWriter {
+    /// Write to `file`, encrypting with `encryption` if it's given.
+    pub fn new(file: fs::File, encryption: Option<&Encryption>) -> Result<BackupWriter, Box<dyn Error>> {
+        let file = BufWriter::new(file);
+        match encryption {
+            None => Ok(BackupWriter::Plain(file)),
+            #[cfg(feature = "encrypt")]
+            Some(e) => Ok(BackupWriter::Encrypted(encryptor(e)?.wrap_output(file)?)),
+            #[cfg(not(feature = "encrypt"))]
+            Some(_) => Err("flipwire was built without the \"encrypt\" feature".into()),
+        }
+    }
+
+    /// Finish writing, including the end of the encryption, and
+    /// flush everything to the file.
+    // there's only one kind without the "encrypt" feature
+    #[cfg_attr(not(feature = "encrypt"), allow(clippy::infallible_destructuring_match))]
+    pub fn finish(self) -> io::Result<fs::File> {
+        let file = match self {
+            BackupWriter::Plain(f) => f,
+            #[cfg(feature = "encrypt")]
+            BackupWriter::Encrypted(w) => w.finish()?,
+        };
+        file.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl Write for BackupWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            BackupWriter::Plain(f) => f.write(buf),
+            #[cfg(feature = "encrypt")]
+            BackupWriter::Encrypted(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            BackupWriter::Plain(f) => f.flush(),
+            #[cfg(feature = "encrypt")]
+            BackupWriter::Encrypted(w) => w.flush(),
+        }
+    }
+}
+
+#[cfg(feature = "encrypt")]
+fn encryptor(encryption: &Encryption) -> Result<age::Encryptor, Box<dyn Error>> {
+    match encryption {
+        Encryption::Passphrase(p) => Ok(age::Encryptor::with_user_passphrase(p.clone().into())),
+        Encryption::Recipients(keys) => {
+            let recipients = keys.iter()
+                .map(|k| k.parse::<age::x25519::Recipient>()
+                     .map_err(|e| format!("bad age public key {:?}: {}", k, e)))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))?)
+        },
+    }
+}
+
+/// Writes a tar archive, one entry at a time
+pub struct TarWriter<W: Write> {
+    inner: W,
+    // bytes of the current file still to come
+    remaining: u64,
+    // bytes of the current file written so far, for padding
+    written: u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(inner: W) -> TarWriter<W> {
+        TarWriter {
+            inner,
+            remaining: 0,
+            written: 0,
+        }
+    }
+
+    /// Add a directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Path in the archive, like "ext/nfc"
+    /// * `mtime`: When it was last changed, in seconds since 1970
+    pub fn add_dir(&mut self, path: &str, mtime: u64) -> io::Result<()> {
+        self.check_no_file()?;
+        self.write_header(&format!("{}/", path.trim_end_matches('/')), 0, mtime, b'5', 0o755)
+    }
+
+    /// Start a file, whose data comes from write_data() and then
+    /// finish_file().
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Path in the archive, like "ext/nfc/card.nfc"
+    /// * `size`: How many bytes of data it has
+    /// * `mtime`: When it was last changed, in seconds since 1970
+    pub fn start_file(&mut self, path: &str, size: u64, mtime: u64) -> io::Result<()> {
+        self.check_no_file()?;
+        self.write_header(path, size, mtime, b'0', 0o644)?;
+        self.remaining = size;
+        self.written = 0;
+        Ok(())
+    }
+
+    /// Add the next piece of the current file.
+    pub fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() as u64 > self.remaining {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "file is bigger than it was when the backup started"));
+        }
+        self.inner.write_all(data)?;
+        self.remaining -= data.len() as u64;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    /// Finish the current file, which has to have all its data.
+    pub fn finish_file(&mut self) -> io::Result<()> {
+        if self.remaining > 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      format!("file is {} bytes smaller than it was when the backup started", self.remaining)));
+        }
+        self.pad(self.written)?;
+        self.written = 0;
+        Ok(())
+    }
+
+    /// Write the end of the archive and return what it was written
+    /// to.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.check_no_file()?;
+        self.inner.write_all(&[0; BLOCK_SIZE * 2])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn check_no_file(&self) -> io::Result<()> {
+        if self.remaining > 0 {
+            return Err(io::Error::other("the last file isn't finished"));
+        }
+        Ok(())
+    }
+
+    /// Fill out the block after `len` bytes of data with zeros.
+    fn pad(&mut self, len: u64) -> io::Result<()> {
+        let rest = (BLOCK_SIZE - (len % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE;
+        self.inner.write_all(&[0; BLOCK_SIZE][..rest])
+    }
+
+    fn write_header(&mut self, path: &str, size: u64, mtime: u64, kind: u8, mode: u32) -> io::Result<()> {
+        let (prefix, name) = match split_path(path) {
+            Some(split) => split,
+            None => {
+                // GNU tar's way of saying the next entry's whole name
+                let mut long_name = path.as_bytes().to_vec();
+                long_name.push(0);
+                self.inner.write_all(&header("", "././@LongLink", long_name.len() as u64, 0, b'L', 0o644))?;
+                self.inner.write_all(&long_name)?;
+                self.pad(long_name.len() as u64)?;
+                ("", truncate(path, NAME_SIZE))
+            },
+        };
+        self.inner.write_all(&header(prefix, name, size, mtime, kind, mode))
+    }
+}
+
+/// Returns `s` cut down to at most `len` bytes, on a character
+/// boundary.
+fn truncate(s: &str, len: usize) -> &str {
+    let mut end = s.len().min(len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Returns `path` split into the prefix and name fields of a ustar
+/// header, or None if it's too long for them.
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= NAME_SIZE {
+        return Some(("", path));
+    }
+    // the split has to be at a slash, which isn't stored
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= PREFIX_SIZE && name.len() <= NAME_SIZE && !name.is_empty())
+}
+
+/// Returns a ustar header block.
+fn header(prefix: &str, name: &str, size: u64, mtime: u64, kind: u8, mode: u32) -> [u8; BLOCK_SIZE] {
+    let mut h = [0u8; BLOCK_SIZE];
+    let mut field = |offset: usize, len: usize, value: &[u8]| {
+        let n = value.len().min(len);
+        h[offset..offset + n].copy_from_slice(&value[..n]);
+    };
+    // numbers are octal, with a NUL after
+    let octal = |value: u64, len: usize| format!("{:0width$o}\0", value, width = len - 1).into_bytes();
+
+    field(0, 100, name.as_bytes());
+    field(100, 8, &octal(u64::from(mode), 8));
+    // uid and gid
+    field(108, 8, &octal(0, 8));
+    field(116, 8, &octal(0, 8));
+    field(124, 12, &octal(size, 12));
+    field(136, 12, &octal(mtime, 12));
+    // the checksum is worked out with spaces here
+    field(148, 8, b"        ");
+    field(156, 1, &[kind]);
+    field(257, 8, b"ustar\x0000");
+    field(345, 155, prefix.as_bytes());
+
+    let checksum: u32 = h.iter().map(|&b| u32::from(b)).sum();
+    h[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    h
+}
+
+/// Returns how `backup create` should encrypt, if at all. Without
+/// recipients, the passphrase comes from $FLIPWIRE_BACKUP_PASSPHRASE,
+/// or it's asked for twice on the terminal.
+#[cfg(feature = "cli")]
+pub fn encryption(encrypt: bool, recipients: &[String]) -> Result<Option<Encryption>, Box<dyn Error>> {
+    if !recipients.is_empty() {
+        return Ok(Some(Encryption::Recipients(recipients.to_vec())));
+    }
+    if !encrypt {
+        return Ok(None);
+    }
+    if let Ok(passphrase) = env::var("FLIPWIRE_BACKUP_PASSPHRASE") {
+        return Ok(Some(Encryption::Passphrase(passphrase)));
+    }
+
+    let term = console::Term::stderr();
+    if !term.is_term() {
+        return Err("no terminal to ask for a passphrase, set FLIPWIRE_BACKUP_PASSPHRASE".into());
+    }
+    term.write_str("Passphrase: ")?;
+    let passphrase = term.read_secure_line()?;
+    term.write_str("Passphrase again: ")?;
+    if term.read_secure_line()? != passphrase {
+        return Err("passphrases don't match".into());
+    }
+    if passphrase.is_empty() {
+        return Err("passphrase can't be empty".into());
+    }
+    Ok(Some(Encryption::Passphrase(passphrase)))
+}
+
+/// Back up `paths` to the tar archive `file`, compressed with
+/// `compression` and then encrypted with `encryption` if it's
+/// given. It's written to a temporary file next
+/// to `file` first, so a failed backup doesn't leave half an archive
+/// (or clobber an older one). Returns how many files were backed up.
+#[cfg(all(feature = "ble", feature = "cli"))]
+pub async fn create(flipper: &mut flipper_ble::FlipperBle, paths: &[String], file: &std::path::Path, compression: Compression, encryption: Option<&Encryption>) -> Result<usize, Box<dyn Error>> {
+    let dir = match file.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => std::path::Path::new("."),
+    };
+    let temp = tempfile::Builder::new()
+        .prefix(".flipwire-backup-")
+        .tempfile_in(dir)?;
+    let (temp_file, temp_path) = temp.into_parts();
+
+    let out = BackupWriter::new(temp_file, encryption)?;
+    let mut archive = TarWriter::new(Compressor::new(out, compression)?);
+    let mtime = u64::try_from(chrono::Utc::now().timestamp())?;
+    let count = flipper.backup(paths, &mut archive, mtime).await?;
+    archive.finish()?.finish()?.finish()?.sync_all()?;
+
+    temp_path.persist(file)?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An entry read back out of an archive
+    #[derive(Debug, PartialEq)]
+    struct Entry {
+        path: String,
+        kind: u8,
+        data: Vec<u8>,
+    }
+
+    fn octal_field(field: &[u8]) -> u64 {
+        let s = std::str::from_utf8(field).unwrap().trim_matches(['\0', ' ']);
+        u64::from_str_radix(s, 8).unwrap()
+    }
+
+    fn text_field(field: &[u8]) -> String {
+        let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+        String::from_utf8(field[..end].to_vec()).unwrap()
+    }
+
+    /// Returns the entries in `tar`, checking every header's checksum
+    /// and magic, and that the archive ends with two empty blocks.
+    fn read_archive(tar: &[u8]) -> Vec<Entry> {
+        assert_eq!(tar.len() % BLOCK_SIZE, 0);
+        assert!(tar.len() >= BLOCK_SIZE * 2 && tar[tar.len() - BLOCK_SIZE * 2..].iter().all(|&b| b == 0));
+
+        let mut entries = Vec::new();
+        let mut long_name = None;
+        let mut blocks = tar.chunks(BLOCK_SIZE);
+        while let Some(h) = blocks.next() {
+            if h.iter().all(|&b| b == 0) {
+                break;
+            }
+            let checksum: u64 = h.iter().enumerate()
+                .map(|(i, &b)| if (148..156).contains(&i) { u64::from(b' ') } else { u64::from(b) })
+                .sum();
+            assert_eq!(octal_field(&h[148..156]), checksum);
+            assert_eq!(&h[257..265], b"ustar\x0000");
+
+            let size = octal_field(&h[124..136]) as usize;
+            let mut data = Vec::new();
+            for _ in 0..size.div_ceil(BLOCK_SIZE) {
+                data.extend_from_slice(blocks.next().unwrap());
+            }
+            data.truncate(size);
+
+            if h[156] == b'L' {
+                assert_eq!(text_field(&h[..100]), "././@LongLink");
+                long_name = Some(text_field(&data));
+                continue;
+            }
+            let path = long_name.take().unwrap_or_else(|| {
+                let (prefix, name) = (text_field(&h[345..500]), text_field(&h[..100]));
+                if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) }
+            });
+            entries.push(Entry { path, kind: h[156], data });
+        }
+        entries
+    }
+
+    /// Returns an archive with a directory, a file written in two
+    /// pieces, and an empty file.
+    fn write_sample<W: Write>(inner: W) -> W {
+        let mut tar = TarWriter::new(inner);
+        tar.add_dir("ext/nfc", 1700000000).unwrap();
+        tar.start_file("ext/nfc/card.nfc", 700, 1700000000).unwrap();
+        tar.write_data(&[b'a'; 300]).unwrap();
+        tar.write_data(&[b'b'; 400]).unwrap();
+        tar.finish_file().unwrap();
+        tar.start_file("ext/empty.txt", 0, 1700000000).unwrap();
+        tar.finish_file().unwrap();
+        tar.finish().unwrap()
+    }
+
+    fn sample_entries() -> Vec<Entry> {
+        let mut card = vec![b'a'; 300];
+        card.extend([b'b'; 400]);
+        vec![
+            Entry { path: "ext/nfc/".to_string(), kind: b'5', data: Vec::new() },
+            Entry { path: "ext/nfc/card.nfc".to_string(), kind: b'0', data: card },
+            Entry { path: "ext/empty.txt".to_string(), kind: b'0', data: Vec::new() },
+        ]
+    }
+
+    #[test]
+    fn header_bytes_test() {
+        let h = header("", "ext/a.txt", 5, 0o17, b'0', 0o644);
+        assert_eq!(&h[..10], b"ext/a.txt\0");
+        assert_eq!(&h[100..108], b"0000644\0");
+        assert_eq!(&h[108..124], b"0000000\x000000000\0");
+        assert_eq!(&h[124..136], b"00000000005\0");
+        assert_eq!(&h[136..148], b"00000000017\0");
+        assert_eq!(h[156], b'0');
+        assert_eq!(&h[257..265], b"ustar\x0000");
+        // six octal digits, a NUL, and a space
+        assert_eq!(h[154..156], *b"\0 ");
+        let sum: u32 = h.iter().enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { u32::from(b' ') } else { u32::from(b) })
+            .sum();
+        assert_eq!(&h[148..154], format!("{:06o}", sum).as_bytes());
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let tar = write_sample(Vec::new());
+        // a header for each entry, two blocks of card.nfc, and the end
+        assert_eq!(tar.len(), BLOCK_SIZE * (3 + 2 + 2));
+        assert_eq!(read_archive(&tar), sample_entries());
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn compression_none_test() {
+        let tar = write_sample(Compressor::new(Vec::new(), Compression::None).unwrap()).finish().unwrap();
+        assert_eq!(tar, write_sample(Vec::new()));
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn compression_gzip_test() {
+        use std::io::Read;
+
+        let gz = write_sample(Compressor::new(Vec::new(), Compression::Gzip).unwrap()).finish().unwrap();
+        assert_eq!(&gz[..2], b"\x1f\x8b");
+        let mut tar = Vec::new();
+        flate2::read::GzDecoder::new(gz.as_slice()).read_to_end(&mut tar).unwrap();
+        assert_eq!(read_archive(&tar), sample_entries());
+    }
+
+    #[cfg(all(feature = "cli", feature = "zstd"))]
+    #[test]
+    fn compression_zstd_test() {
+        let zst = write_sample(Compressor::new(Vec::new(), Compression::Zstd).unwrap()).finish().unwrap();
+        assert_eq!(&zst[..4], b"\x28\xb5\x2f\xfd");
+        let tar = zstd::decode_all(zst.as_slice()).unwrap();
+        assert_eq!(read_archive(&tar), sample_entries());
+    }
+
+    #[cfg(all(feature = "cli", not(feature = "zstd")))]
+    #[test]
+    fn compression_zstd_missing_test() {
+        assert!(Compressor::new(Vec::new(), Compression::Zstd).is_err());
+    }
+
+    #[test]
+    fn prefix_split_test() {
+        let dir = format!("ext/{}", "d".repeat(90));
+        let path = format!("{}/{}", dir, "f".repeat(90));
+        assert_eq!(split_path(&path), Some((dir.as_str(), "f".repeat(90).as_str())));
+
+        let mut tar = TarWriter::new(Vec::new());
+        tar.start_file(&path, 3, 0).unwrap();
+        tar.write_data(b"abc").unwrap();
+        tar.finish_file().unwrap();
+        let tar = tar.finish().unwrap();
+
+        // no long name entry, just the file
+        assert_eq!(tar[156], b'0');
+        assert_eq!(text_field(&tar[345..500]), dir);
+        assert_eq!(read_archive(&tar), vec![Entry { path, kind: b'0', data: b"abc".to_vec() }]);
+    }
+
+    #[test]
+    fn long_link_test() {
+        // a name too long for the name field can't be split
+        let path = format!("ext/{}", "n".repeat(120));
+        assert_eq!(split_path(&path), None);
+        // and neither can a prefix too long for its field
+        let deep = format!("{}/card.nfc", "d".repeat(200));
+        assert_eq!(split_path(&deep), None);
+
+        for path in [path, deep] {
+            let mut tar = TarWriter::new(Vec::new());
+            tar.start_file(&path, 2, 0).unwrap();
+            tar.write_data(b"hi").unwrap();
+            tar.finish_file().unwrap();
+            let tar = tar.finish().unwrap();
+
+            assert_eq!(tar[156], b'L');
+            assert_eq!(read_archive(&tar), vec![Entry { path, kind: b'0', data: b"hi".to_vec() }]);
+        }
+    }
+
+    #[test]
+    fn file_size_mismatch_test() {
+        let mut tar = TarWriter::new(Vec::new());
+        tar.start_file("ext/long.txt", 4, 0).unwrap();
+        assert_eq!(tar.write_data(b"12345").unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut tar = TarWriter::new(Vec::new());
+        tar.start_file("ext/short.txt", 4, 0).unwrap();
+        tar.write_data(b"12").unwrap();
+        assert_eq!(tar.finish_file().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        // nothing else can go in while a file is missing data
+        let mut tar = TarWriter::new(Vec::new());
+        tar.start_file("ext/short.txt", 4, 0).unwrap();
+        assert!(tar.add_dir("ext/nfc", 0).is_err());
+        assert!(tar.start_file("ext/other.txt", 0, 0).is_err());
+        assert!(tar.finish().is_err());
+    }
+}
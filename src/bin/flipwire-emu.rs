@@ -0,0 +1,185 @@
+// A fake Flipper on a TCP socket, for trying out tools that speak the
+// Flipper RPC without having a Flipper (or Bluetooth) around. Each
+// connection is a separate RPC session, with the same length-prefixed
+// messages the Flipper sends over BLE. Each read from the socket goes
+// over a SimulatedLink as one frame, so a bad link can be tried out
+// too. With --flow-control, reads are cut into BLE-sized frames that
+// wait for room in the buffer, the way flipwire writes to a real
+// Flipper.
+
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+use clap::Parser;
+use futures::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use flipwire::duration::parse_duration;
+use flipwire::emu::EmulatedFlipper;
+use flipwire::flow_control::FlowControl;
+use flipwire::protobuf_codec;
+use flipwire::sim_link::{LinkConditions, SimulatedLink};
+
+#[macro_use] extern crate tracing;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Pretend to be a Flipper Zero over TCP", long_about = None)]
+struct Cli {
+    /// Directory to keep the Flipper's files in. /ext and /int are
+    /// the `ext` and `int` directories inside it.
+    root: PathBuf,
+
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8090")]
+    listen: String,
+
+    /// Name to report in device info
+    #[arg(long, default_value = "Emu")]
+    name: String,
+
+    /// Delay every frame by this long (like "30ms")
+    #[arg(long, value_parser = parse_duration, default_value = "0")]
+    latency: Duration,
+
+    /// Delay every frame by up to this much more, at random
+    #[arg(long, value_parser = parse_duration, default_value = "0")]
+    jitter: Duration,
+
+    /// Chance from 0 to 1 that a frame is lost
+    #[arg(long, value_parser = parse_drop_rate, default_value = "0")]
+    drop_rate: f64,
+
+    /// Carry only this many bytes per second, like a slow radio link
+    #[arg(long, value_name = "BYTES_PER_SEC", value_parser = clap::value_parser!(u64).range(1..))]
+    bandwidth: Option<u64>,
+
+    /// Throw away frames that don't fit in a serial buffer this big,
+    /// like a real Flipper (which has 1024 bytes). TCP clients can't
+    /// see how much room is left, so this is off by default.
+    #[arg(long, value_name = "BYTES")]
+    buffer_size: Option<usize>,
+
+    /// Hold each frame until the buffer has room, the way flipwire
+    /// paces its writes, instead of throwing away what doesn't fit
+    #[arg(long, requires = "buffer_size")]
+    flow_control: bool,
+
+    /// Seed for the jitter and drops, to repeat a run exactly
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+}
+
+fn parse_drop_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("{:?} isn't a number from 0 to 1", s)),
+    }
+}
+
+/// Answer requests on `socket` until the client hangs up.
+///
+/// # Arguments
+///
+/// * `flow_control` - Pace writes to the link by its flow control
+///    values, since the client can't see them over TCP
+async fn serve(socket: TcpStream, flipper: EmulatedFlipper, conditions: LinkConditions, flow_control: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut reader, mut writer) = socket.into_split();
+    let (link, mut responses, flow_values) = SimulatedLink::new(flipper, conditions);
+    let flow = flow_control.then(|| FlowControl::spawn(link.flow_value(), flow_values));
+    let forward = tokio::spawn(async move {
+        while let Some(data) = responses.next().await {
+            if writer.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut data = [0u8; 4096];
+    let result = loop {
+        let n = match reader.read(&mut data).await {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(e) => break Err(e.into()),
+        };
+        let written = match &flow {
+            Some(flow) => write_paced(&link, flow, &data[..n]).await,
+            None => link.write(&data[..n]).await,
+        };
+        if let Err(e) = written {
+            break Err(e);
+        }
+    };
+    forward.abort();
+    let stats = link.stats();
+    if stats.dropped > 0 || stats.overflows > 0 {
+        info!("{} of {} frames dropped, {} overflowed the buffer", stats.dropped, stats.frames, stats.overflows);
+    }
+    result
+}
+
+/// Write `data` to `link` in frames no bigger than a BLE write, each
+/// once `flow` says there's room for it.
+async fn write_paced(link: &SimulatedLink, flow: &FlowControl, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    for frame in data.chunks(protobuf_codec::PROTOBUF_BLE_TU_SIZE) {
+        flow.reserve(frame.len(), link).await;
+        link.write(frame).await?;
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let listener = match TcpListener::bind(&cli.listen).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("couldn't listen on {}: {}", cli.listen, e);
+            process::exit(1);
+        },
+    };
+    info!("emulating Flipper {} from {:?} on {}", cli.name, cli.root, cli.listen);
+
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("failed to accept connection: {}", e);
+                continue;
+            },
+        };
+
+        let flipper = match EmulatedFlipper::new(&cli.root, &cli.name) {
+            Ok(f) => f,
+            Err(e) => {
+                error!("can't use {:?}: {}", cli.root, e);
+                process::exit(1);
+            },
+        };
+        info!("{} connected", addr);
+        let conditions = LinkConditions {
+            latency: cli.latency,
+            jitter: cli.jitter,
+            drop_rate: cli.drop_rate,
+            bandwidth: cli.bandwidth,
+            buffer_size: cli.buffer_size,
+            seed: cli.seed,
+        };
+        let flow_control = cli.flow_control;
+        tokio::spawn(async move {
+            match serve(socket, flipper, conditions, flow_control).await {
+                Ok(()) => info!("{} disconnected", addr),
+                Err(e) => warn!("{} disconnected: {}", addr, e),
+            }
+        });
+    }
+}
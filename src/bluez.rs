@@ -0,0 +1,244 @@
+// Linux-only extras that go to BlueZ (or the kernel under it)
+// directly, for things btleplug doesn't cover.
+//
+// Connection parameters and the PHY are picked by the central, and
+// BlueZ doesn't let applications ask for them over D-Bus. The kernel
+// does have defaults for new connections in debugfs, which root can
+// change, so that's what --fast-connection uses. There's nothing like
+// that for the PHY: `btmgmt phy` sets it for the whole adapter.
+//
+// Pairing goes through BlueZ too, with flipwire registered as the
+// agent that answers BlueZ's questions (like the PIN the Flipper
+// shows), the way bluetoothctl does it.
+
+use std::error::Error;
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bluez_async::BluetoothSession;
+use btleplug::api::BDAddr;
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::MatchRule;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::strings::ErrorName;
+use dbus::Message;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::output;
+
+// where our agent lives on the bus
+const AGENT_PATH: &str = "/org/flipwire/agent";
+// How long the whole pairing can take, including typing in the PIN.
+const PAIR_TIMEOUT: Duration = Duration::from_secs(90);
+
+// 7.5 ms to 15 ms, in units of 1.25 ms. 7.5 ms is the shortest BLE
+// allows, and what phones tend to get.
+const FAST_MIN_INTERVAL: u16 = 6;
+const FAST_MAX_INTERVAL: u16 = 12;
+
+/// Puts the adapter's default connection interval back when dropped,
+/// so other devices don't get it too.
+pub struct IntervalOverride {
+    dir: PathBuf,
+    min: String,
+    max: String,
+}
+
+/// Have the kernel ask for a short connection interval on the next
+/// connection `adapter` makes, until the returned override is
+/// dropped. Needs root and debugfs mounted.
+///
+/// # Arguments
+///
+/// * `adapter`: Adapter info from btleplug, starting with its name
+///   (like "hci0 (usb:v1D6Bp0246d0540)")
+pub fn request_fast_interval(adapter: &str) -> Result<IntervalOverride, Box<dyn Error>> {
+    let name = adapter.split_whitespace().next().ok_or("adapter has no name")?;
+    let dir = PathBuf::from("/sys/kernel/debug/bluetooth").join(name);
+    let read = |file: &str| fs::read_to_string(dir.join(file))
+        .map(|s| s.trim().to_string())
+        .map_err(|e| format!("can't read {:?} ({}), this needs root and debugfs", dir.join(file), e));
+    let old = IntervalOverride {
+        min: read("conn_min_interval")?,
+        max: read("conn_max_interval")?,
+        dir: dir.clone(),
+    };
+
+    // the kernel refuses a minimum over the maximum, so lower the
+    // minimum first
+    fs::write(dir.join("conn_min_interval"), FAST_MIN_INTERVAL.to_string())?;
+    fs::write(dir.join("conn_max_interval"), FAST_MAX_INTERVAL.to_string())?;
+    debug!("asking for a {}-{} ms connection interval on {}",
+           f64::from(FAST_MIN_INTERVAL) * 1.25, f64::from(FAST_MAX_INTERVAL) * 1.25, name);
+    Ok(old)
+}
+
+impl Drop for IntervalOverride {
+    fn drop(&mut self) {
+        // the other way around from setting it
+        let result = fs::write(self.dir.join("conn_max_interval"), &self.max)
+            .and_then(|_| fs::write(self.dir.join("conn_min_interval"), &self.min));
+        if let Err(e) = result {
+            warn!("couldn't put the default connection interval back in {:?}: {}", self.dir, e);
+        }
+    }
+}
+
+/// Returns the ATT MTU BlueZ negotiated for the characteristic
+/// `chr_uuid` on the connected device `address`, or None if it doesn't
+/// say.
+pub async fn att_mtu(address: BDAddr, chr_uuid: Uuid) -> Result<Option<u16>, Box<dyn Error>> {
+    let (dbus, session) = BluetoothSession::new().await?;
+    // the D-Bus connection runs until this returns
+    let dbus = tokio::spawn(dbus);
+    let result = async {
+        let address = address.to_string();
+        for device in session.get_devices().await? {
+            if !device.mac_address.to_string().eq_ignore_ascii_case(&address) {
+                continue;
+            }
+            for service in session.get_services(&device.id).await? {
+                for chr in session.get_characteristics(&service.id).await? {
+                    if chr.uuid == chr_uuid {
+                        return Ok(chr.mtu);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }.await;
+    dbus.abort();
+    result
+}
+
+/// Pair with the device `address` through BlueZ, asking for the PIN
+/// (or to confirm a passkey) on the terminal, and trust it so it can
+/// connect again later without asking. A device that's already
+/// paired is left alone.
+///
+/// # Arguments
+///
+/// * `adapter`: Adapter info from btleplug, starting with its name
+/// * `address`: Address of the device, which BlueZ has to have seen
+///   in a scan
+pub async fn pair(adapter: &str, address: BDAddr) -> Result<(), Box<dyn Error>> {
+    let name = adapter.split_whitespace().next().ok_or("adapter has no name")?;
+    let device_path = format!("/org/bluez/{}/dev_{}", name, address.to_string().replace(':', "_"));
+
+    let (resource, conn) = dbus_tokio::connection::new_system_sync()?;
+    let dbus = tokio::spawn(resource);
+    let result = pair_on(&conn, &device_path).await;
+    dbus.abort();
+    result
+}
+
+async fn pair_on(conn: &std::sync::Arc<SyncConnection>, device_path: &str) -> Result<(), Box<dyn Error>> {
+    // BlueZ asks the agent of whoever called Pair(), so ours only
+    // answers for this pairing and the desktop's agent is left alone.
+    let (tx, mut requests) = mpsc::unbounded_channel();
+    conn.start_receive(MatchRule::new_method_call().with_path(AGENT_PATH), Box::new(move |m, _| {
+        let _ = tx.send(m);
+        true
+    }));
+    let bluez = Proxy::new("org.bluez", "/org/bluez", PAIR_TIMEOUT, conn.clone());
+    bluez.method_call::<(), _, _, _>("org.bluez.AgentManager1", "RegisterAgent",
+                                     (dbus::Path::from(AGENT_PATH), "KeyboardDisplay")).await?;
+
+    let device = Proxy::new("org.bluez", device_path, PAIR_TIMEOUT, conn.clone());
+    let pairing = device.method_call::<(), _, _, _>("org.bluez.Device1", "Pair", ());
+    tokio::pin!(pairing);
+    let paired = loop {
+        tokio::select! {
+            result = &mut pairing => break result,
+            Some(request) = requests.recv() => {
+                let reply = answer_agent_request(&request).await;
+                if conn.send(reply).is_err() {
+                    warn!("couldn't answer BlueZ");
+                }
+            },
+        }
+    };
+
+    let _ = bluez.method_call::<(), _, _, _>("org.bluez.AgentManager1", "UnregisterAgent",
+                                             (dbus::Path::from(AGENT_PATH),)).await;
+    match paired {
+        Ok(()) => {},
+        Err(e) if e.name() == Some("org.bluez.Error.AlreadyExists") => {
+            info!("already paired");
+        },
+        Err(e) if e.name() == Some("org.freedesktop.DBus.Error.UnknownObject") => {
+            return Err("BlueZ hasn't seen the Flipper, try a longer --scan-time".into());
+        },
+        Err(e) => return Err(e.message().unwrap_or("pairing failed").into()),
+    }
+
+    // so BlueZ lets it connect again later without asking
+    device.method_call::<(), _, _, _>("org.freedesktop.DBus.Properties", "Set",
+                                      ("org.bluez.Device1", "Trusted", dbus::arg::Variant(true))).await?;
+    Ok(())
+}
+
+/// Returns the reply to one of BlueZ's calls to the agent, asking on
+/// the terminal when it needs an answer.
+async fn answer_agent_request(request: &Message) -> Message {
+    let member = request.member().map(|m| m.to_string()).unwrap_or_default();
+    debug!("BlueZ agent request: {}", member);
+    let rejected = |why: &str| request.error(&ErrorName::from("org.bluez.Error.Rejected"),
+                                             &CString::new(why).unwrap_or_default());
+
+    match member.as_str() {
+        // the Flipper shows a PIN, and BlueZ needs us to type it in
+        "RequestPasskey" => match prompt("Enter the PIN shown on the Flipper: ").await {
+            Ok(pin) => match pin.trim().parse::<u32>() {
+                Ok(passkey) => request.return_with_args((passkey,)),
+                Err(_) => rejected("not a number"),
+            },
+            Err(e) => rejected(&e.to_string()),
+        },
+        "RequestPinCode" => match prompt("Enter the PIN shown on the Flipper: ").await {
+            Ok(pin) => request.return_with_args((pin.trim().to_string(),)),
+            Err(e) => rejected(&e.to_string()),
+        },
+        "RequestConfirmation" => {
+            let passkey = request.read2::<dbus::Path, u32>().map(|(_, p)| p).unwrap_or(0);
+            match prompt(&format!("Does the Flipper show {:06}? [y/N] ", passkey)).await {
+                Ok(answer) if answer.trim().eq_ignore_ascii_case("y") => request.method_return(),
+                _ => rejected("passkey doesn't match"),
+            }
+        },
+        "DisplayPasskey" => {
+            if let Ok((_, passkey)) = request.read2::<dbus::Path, u32>() {
+                output::status(format!("enter {:06} on the Flipper", passkey));
+            }
+            request.method_return()
+        },
+        "DisplayPinCode" => {
+            if let Ok((_, pin)) = request.read2::<dbus::Path, String>() {
+                output::status(format!("enter {} on the Flipper", pin));
+            }
+            request.method_return()
+        },
+        "Cancel" => {
+            output::status("the Flipper cancelled pairing");
+            request.method_return()
+        },
+        // RequestAuthorization, AuthorizeService, and Release: we
+        // started this, so yes
+        _ => request.method_return(),
+    }
+}
+
+/// Print `question` and return the line typed in answer.
+async fn prompt(question: &str) -> io::Result<String> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    tokio::task::spawn_blocking(|| {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        Ok(line)
+    }).await?
+}
@@ -0,0 +1,9 @@
+// Reading the desktop clipboard, for `upload --from-clipboard`.
+
+use std::error::Error;
+
+/// Returns the text on the clipboard.
+pub fn read_text() -> Result<String, Box<dyn Error>> {
+    let mut clipboard = arboard::Clipboard::new()?;
+    Ok(clipboard.get_text()?)
+}
@@ -0,0 +1,134 @@
+// Settings that apply to every command, from config.toml in the
+// config directory (like ~/.config/flipwire/config.toml on Linux):
+//
+//     # set the Flipper's clock every time flipwire connects
+//     autosync = true
+//     # lock the Flipper when flipwire is done with it
+//     lock_on_exit = true
+//
+//     # picked with --profile lab, or always with default_profile
+//     [profile.lab]
+//     flipper = "Uwu2"
+//     adapter = "hci1"
+//     reliable = true
+//
+//     # `flipwire launch @spi` for apps with long paths
+//     [launch]
+//     spi = { path = "/ext/apps/GPIO/spi_mem_manager.fap", args = "" }
+//
+// Every setting has a command line flag too, which works even when
+// the config file doesn't turn it on. Flags given on the command line
+// win over the profile.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Sync the date and time whenever flipwire connects
+    pub autosync: bool,
+    /// Lock the Flipper's desktop before disconnecting
+    pub lock_on_exit: bool,
+    /// Profile to use when --profile isn't given
+    pub default_profile: Option<String>,
+    /// Named profiles, from `[profile.NAME]` tables
+    #[serde(rename = "profile")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Apps to launch by name, from the `[launch]` table
+    pub launch: BTreeMap<String, LaunchTarget>,
+}
+
+/// An app `launch @NAME` runs
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LaunchTarget {
+    /// Full path to the .fap, or the name of a built-in app
+    pub path: String,
+    /// Arguments to launch it with, unless others are given
+    pub args: String,
+}
+
+/// Settings for one Flipper, or one machine. Each one is the same as
+/// the flag with its name, and anything left out is up to the flags
+/// and their defaults.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Flipper name, like -f
+    pub flipper: Option<String>,
+    /// Bluetooth adapter, like "hci1"
+    pub adapter: Option<String>,
+    /// Serial port for USB commands
+    pub port: Option<String>,
+    /// Duration, like "10s"
+    pub scan_time: Option<String>,
+    /// Duration, like "1m"
+    pub connect_timeout: Option<String>,
+    pub retries: Option<u32>,
+    pub fast_connection: Option<bool>,
+    pub reliable: Option<bool>,
+    pub chunk_size: Option<usize>,
+    pub tu_size: Option<usize>,
+    pub chunk_delay_ms: Option<u64>,
+    /// Bytes per second, like "20K"
+    pub download_rate: Option<String>,
+    pub autosync: Option<bool>,
+    pub lock_on_exit: Option<bool>,
+    pub cwd: Option<String>,
+}
+
+/// Returns where the config file is kept.
+pub fn config_path() -> io::Result<PathBuf> {
+    match dirs::config_dir() {
+        Some(d) => Ok(d.join("flipwire").join("config.toml")),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "couldn't find a config directory")),
+    }
+}
+
+impl Config {
+    /// Read the config file, or return the defaults if there isn't
+    /// one.
+    pub fn load() -> Result<Config, Box<dyn Error>> {
+        let path = config_path()?;
+        match fs::read_to_string(&path) {
+            Ok(s) => toml::from_str(&s).map_err(|e| format!("{}: {}", path.display(), e).into()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e).into()),
+        }
+    }
+
+    /// Returns the profile called `name`, or the default profile if
+    /// `name` is None. Returns None if there's no default either.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<&Profile>, Box<dyn Error>> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(n) => match self.profiles.get(n) {
+                Some(p) => Ok(Some(p)),
+                None if self.profiles.is_empty() => Err(format!("no profile named {:?}, the config file doesn't have any", n).into()),
+                None => Err(format!("no profile named {:?} (there's {})", n, self.profile_names().join(", ")).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the launch target called `name` (without the "@").
+    pub fn launch_target(&self, name: &str) -> Result<&LaunchTarget, Box<dyn Error>> {
+        match self.launch.get(name) {
+            Some(t) if t.path.is_empty() => Err(format!("launch target {:?} doesn't have a path", name).into()),
+            Some(t) => Ok(t),
+            None if self.launch.is_empty() => Err(format!("no launch target named {:?}, the config file doesn't have any", name).into()),
+            None => Err(format!("no launch target named {:?} (there's {})",
+                                name, self.launch.keys().cloned().collect::<Vec<_>>().join(", ")).into()),
+        }
+    }
+
+    /// Returns the names of all the profiles.
+    pub fn profile_names(&self) -> Vec<String> {
+        self.profiles.keys().cloned().collect()
+    }
+}
@@ -0,0 +1,262 @@
+// `flipwire daemon`: staying connected to report how the Flipper is
+// doing, to stdout or MQTT, and serving the web page.
+
+use std::error::Error;
+use std::time::Duration;
+
+use crate::duration::format_duration;
+use crate::flipper_ble;
+#[cfg(any(feature = "mqtt", feature = "web"))]
+use crate::output;
+use crate::status;
+#[cfg(feature = "web")]
+use crate::web_ui::{self, Job};
+use crate::hook;
+
+// Without the web page, there are never any requests to answer
+#[cfg(not(feature = "web"))]
+enum Job {}
+
+/// How the daemon runs, from its flags
+#[derive(Debug, Default)]
+pub struct DaemonOptions<'a> {
+    /// Broker to publish to
+    pub mqtt: Option<&'a str>,
+    /// Topic to publish under
+    pub mqtt_topic: Option<&'a str>,
+    /// Home Assistant discovery prefix, if the Flipper should be
+    /// announced to it
+    pub home_assistant: Option<&'a str>,
+    /// Seconds between reports
+    pub interval: u64,
+    /// Address to serve the web page on
+    pub web: Option<&'a str>,
+    /// Token the web page needs, instead of a random one
+    pub web_token: Option<&'a str>,
+    /// Percent to warn below, and a command to run then
+    pub low_battery: Option<(u8, Option<&'a str>)>,
+    /// Take the web page's socket from systemd and tell it how the
+    /// daemon is doing
+    pub systemd: bool,
+    /// Stop after this long without a web request
+    pub idle_exit: Option<Duration>,
+}
+
+/// Report the Flipper's status every so often until Ctrl+C (or
+/// SIGTERM), to an MQTT broker if `mqtt` is given or to stdout if not.
+pub async fn run(flipper: &mut flipper_ble::FlipperBle, options: &DaemonOptions<'_>) -> Result<(), Box<dyn Error>> {
+    let DaemonOptions { mqtt, mqtt_topic, home_assistant, interval, web, web_token, low_battery, systemd, idle_exit } = *options;
+    #[cfg(feature = "mqtt")]
+    let publisher = match mqtt {
+        Some(url) => {
+            let broker = crate::mqtt::parse_broker(url)?;
+            let prefix = mqtt_topic.map_or_else(|| format!("flipwire/{}", flipper.name()), str::to_string);
+            output::status(format!("publishing to {}:{} under {}", broker.host, broker.port, prefix));
+            let publisher = crate::mqtt::Publisher::connect(&broker, &prefix);
+            if let Some(discovery_prefix) = home_assistant {
+                publisher.publish_discovery(discovery_prefix, flipper.name()).await?;
+            }
+            Some(publisher)
+        },
+        None => None,
+    };
+    #[cfg(not(feature = "mqtt"))]
+    if mqtt.is_some() || mqtt_topic.is_some() || home_assistant.is_some() {
+        return Err("flipwire was built without the \"mqtt\" feature".into());
+    }
+
+    // the link's state for the web page, which it can get even while
+    // we're busy reconnecting
+    let mut link_events = flipper.subscribe_events();
+    let mut link_state = "connected";
+    #[cfg(feature = "web")]
+    let (link, link_receiver) = tokio::sync::watch::channel(web_ui::link_json(link_state, flipper.mtu()));
+
+    // keep the server around until the daemon stops
+    #[cfg(feature = "web")]
+    let (server, mut jobs) = match (listener(systemd)?, web) {
+        (Some(listener), _) => {
+            let (server, jobs) = web_ui::WebServer::from_listener(listener, web_token, link_receiver)?;
+            output::status(format!("serving the web page at {} (from systemd)", server.url()));
+            (Some(server), Some(jobs))
+        },
+        (None, Some(listen)) => {
+            let (server, jobs) = web_ui::WebServer::bind(listen, web_token, link_receiver).await?;
+            output::status(format!("serving the web page at {}", server.url()));
+            (Some(server), Some(jobs))
+        },
+        (None, None) => (None, None),
+    };
+    #[cfg(not(feature = "web"))]
+    let (server, mut jobs) = match (listener(systemd)?, web) {
+        (None, None) if web_token.is_none() => (None::<()>, None::<tokio::sync::mpsc::Receiver<Job>>),
+        _ => return Err("flipwire was built without the \"web\" feature".into()),
+    };
+    if idle_exit.is_some() && server.is_none() {
+        return Err("--idle-exit needs the web page, from --web or a socket from systemd".into());
+    }
+    #[cfg(unix)]
+    if systemd {
+        crate::systemd::notify(&format!("READY=1\nSTATUS=connected to Flipper {}", flipper.name()))?;
+    }
+    #[cfg(unix)]
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    let mut last_request = tokio::time::Instant::now();
+
+    let mut alarm = low_battery.map(|(percent, _)| status::LowBatteryAlarm::new(percent));
+    // web requests handled, for /api/stats
+    let mut commands = 0;
+
+    let mut ticks = tokio::time::interval(Duration::from_secs(interval));
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        let next_job = async {
+            match &mut jobs {
+                Some(j) => j.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        let idle = async {
+            match idle_exit {
+                Some(d) => tokio::time::sleep_until(last_request + d).await,
+                None => std::future::pending().await,
+            }
+        };
+        let terminated = async {
+            #[cfg(unix)]
+            sigterm.recv().await;
+            #[cfg(not(unix))]
+            std::future::pending::<()>().await;
+        };
+        tokio::select! {
+            _ = ticks.tick() => {},
+            Some(job) = next_job => {
+                commands += 1;
+                last_request = tokio::time::Instant::now();
+                answer(flipper, job, commands).await;
+                continue;
+            },
+            Ok(event) = link_events.recv() => {
+                debug!("link event: {:?}", event);
+                let was_connected = link_state == "connected";
+                link_state = match event {
+                    flipper_ble::ConnectionEvent::Connected => "connected",
+                    flipper_ble::ConnectionEvent::Disconnected => "disconnected",
+                    flipper_ble::ConnectionEvent::Reconnecting => "reconnecting",
+                    flipper_ble::ConnectionEvent::MtuChanged(_) => link_state,
+                };
+                #[cfg(feature = "web")]
+                link.send_replace(web_ui::link_json(link_state, flipper.mtu()));
+                // Reconnect right away instead of at the next report,
+                // but only once: a failed reconnect says Disconnected
+                // too, and the next report tries again.
+                if was_connected && event == flipper_ble::ConnectionEvent::Disconnected {
+                    if let Err(e) = flipper.keep_alive().await {
+                        warn!("couldn't reconnect: {}", e);
+                    }
+                }
+                continue;
+            },
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminated => {
+                info!("got SIGTERM, stopping");
+                break;
+            },
+            _ = idle => {
+                info!("no web requests for {}, stopping", format_duration(idle_exit.unwrap_or_default()));
+                break;
+            },
+        }
+        let s = status::fetch(flipper).await;
+        if alarm.as_mut().is_some_and(|a| a.check(&s)) {
+            warn_low_battery(flipper, s.battery.unwrap_or_default(), low_battery.and_then(|(_, hook)| hook)).await;
+        }
+
+        #[cfg(feature = "mqtt")]
+        if let Some(p) = &publisher {
+            if let Err(e) = p.publish_status(&s).await {
+                warn!("couldn't publish status: {}", e);
+            }
+            continue;
+        }
+        println!("{}  {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"), s);
+    }
+
+    // Hand the Flipper over cleanly for whatever runs next (like the
+    // daemon again, when systemd sees the next connection): stop
+    // taking requests, answer the ones already waiting, and
+    // disconnect.
+    #[cfg(unix)]
+    if systemd {
+        crate::systemd::notify("STOPPING=1")?;
+    }
+    #[cfg(feature = "web")]
+    drop(server);
+    if let Some(j) = &mut jobs {
+        j.close();
+        while let Some(job) = j.recv().await {
+            commands += 1;
+            answer(flipper, job, commands).await;
+        }
+    }
+
+    #[cfg(feature = "mqtt")]
+    if let Some(p) = &publisher {
+        p.close().await?;
+    }
+    if systemd {
+        flipper.disconnect().await?;
+        info!("disconnected from Flipper {}", flipper.name());
+    }
+    Ok(())
+}
+
+/// Answer a request from the web page.
+#[cfg(feature = "web")]
+async fn answer(flipper: &mut flipper_ble::FlipperBle, job: Job, commands: u64) {
+    let response = web_ui::handle_request(flipper, job.request, commands).await;
+    // the browser may have given up already
+    let _ = job.reply.send(response);
+}
+
+#[cfg(not(feature = "web"))]
+async fn answer(_flipper: &mut flipper_ble::FlipperBle, job: Job, _commands: u64) {
+    match job {}
+}
+
+/// Returns the web page's socket from systemd, if `systemd` is set and
+/// flipwire was started by socket activation.
+pub fn listener(systemd: bool) -> Result<Option<std::net::TcpListener>, Box<dyn Error>> {
+    if !systemd {
+        return Ok(None);
+    }
+    #[cfg(unix)]
+    {
+        let mut listeners = crate::systemd::listeners()?;
+        if listeners.len() > 1 {
+            warn!("systemd passed {} sockets, only using the first", listeners.len());
+        }
+        Ok((!listeners.is_empty()).then(|| listeners.swap_remove(0)))
+    }
+    #[cfg(not(unix))]
+    Err("--systemd only works on Linux".into())
+}
+
+/// Let everyone know the battery is at `battery` percent: play the
+/// alert on the Flipper, show a notification, and run `hook`.
+pub async fn warn_low_battery(flipper: &mut flipper_ble::FlipperBle, battery: u8, hook: Option<&str>) {
+    warn!("{}'s battery is low ({}%)", flipper.name(), battery);
+    if let Err(e) = flipper.alert().await {
+        warn!("couldn't play the alert: {}", e);
+    }
+    #[cfg(feature = "notify")]
+    if let Err(e) = crate::notify::send(&format!("{}'s battery is low", flipper.name()),
+                                        &format!("{}% left, charge it soon", battery)) {
+        warn!("couldn't show notification: {}", e);
+    }
+    if let Some(command) = hook {
+        if let Err(e) = hook::command(command).env("FLIPWIRE_BATTERY", battery.to_string()).spawn() {
+            warn!("failed to run {:?}: {}", command, e);
+        }
+    }
+}
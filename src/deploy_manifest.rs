@@ -0,0 +1,104 @@
+// Deploying from a manifest, usually flipwire.toml, that lists what to
+// push where and what to launch afterwards. Keeping it in a file makes
+// provisioning an SD card or shipping a build to testers the same
+// every time:
+//
+//     [[files]]
+//     src = "dist/my_app.fap"
+//     dest = "/ext/apps/Tools/"
+//
+//     [[files]]
+//     src = "assets"
+//     dest = "/ext/apps_data/my_app"
+//
+//     [launch]
+//     app = "/ext/apps/Tools/my_app.fap"
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+#[cfg(feature = "ble")]
+use crate::flipper_ble;
+#[cfg(feature = "ble")]
+use crate::path_filter::PathFilter;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DeployManifest {
+    /// What to upload, in order
+    #[serde(default)]
+    pub files: Vec<DeployFile>,
+    /// App to open once everything's uploaded
+    pub launch: Option<Launch>,
+}
+
+/// A local file or directory and where it goes
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DeployFile {
+    /// Local file or directory, relative to the manifest
+    pub src: PathBuf,
+    /// Full Flipper path. For a file, ending it in "/" puts the file
+    /// inside that directory under its own name; a directory's
+    /// contents always go inside `dest`.
+    pub dest: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Launch {
+    /// Full path to a .fap, or the name of a built-in app
+    pub app: String,
+    #[serde(default)]
+    pub args: String,
+}
+
+impl DeployManifest {
+    /// Read the manifest at `path`, with every `src` made relative to
+    /// where the manifest is rather than the current directory.
+    pub fn load(path: &Path) -> Result<DeployManifest, Box<dyn Error>> {
+        let mut manifest: DeployManifest = toml::from_str(&fs::read_to_string(path)?)?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        for f in &mut manifest.files {
+            // the manifest has to mean the same thing whatever the
+            // shell's cwd was
+            if !f.dest.starts_with('/') {
+                return Err(format!("destination {:?} isn't a full Flipper path", f.dest).into());
+            }
+            f.src = base.join(&f.src);
+            if !f.src.exists() {
+                return Err(format!("{:?} doesn't exist", f.src).into());
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+/// Upload everything the deploy manifest at `path` lists, then launch
+/// its app if it has one and `launch` is set. Returns how many entries
+/// were uploaded.
+#[cfg(feature = "ble")]
+pub async fn deploy(flipper: &mut flipper_ble::FlipperBle, path: &Path, launch: bool) -> Result<usize, Box<dyn Error>> {
+    let manifest = DeployManifest::load(path)?;
+
+    for f in &manifest.files {
+        if f.src.is_dir() {
+            info!("uploading {:?} into {}", f.src, f.dest);
+            flipper.upload_dir(&f.src, &f.dest, &PathFilter::default()).await?;
+        } else {
+            let dest = flipper.upload_destination(&f.src, &f.dest).await?;
+            info!("uploading {:?} to {}", f.src, dest);
+            flipper.upload_file(&f.src, &dest).await?;
+        }
+    }
+    if let (Some(l), true) = (&manifest.launch, launch) {
+        info!("launching {}", l.app);
+        flipper.launch(&l.app, &l.args).await?;
+    }
+
+    Ok(manifest.files.len())
+}
@@ -0,0 +1,151 @@
+// Keeping track of how far the Flipper's clock has drifted each time
+// synctime sets it. The skew at each sync is how much the clock
+// drifted since the sync before, so a healthy RTC shows a small,
+// steady rate and a failing one jumps around or grows.
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local, TimeZone};
+
+use crate::output;
+
+/// One synctime measurement
+#[derive(Debug, Clone)]
+pub struct DriftSample {
+    /// When the sync happened
+    pub time: DateTime<Local>,
+    /// Name of the Flipper that was synced
+    pub flipper: String,
+    /// Computer time minus Flipper time, in milliseconds
+    pub skew_ms: i64,
+}
+
+impl DriftSample {
+    /// Returns how fast the clock drifted between `previous` and this
+    /// sample, in seconds per day. Positive means the Flipper's clock
+    /// runs slow.
+    pub fn rate_since(&self, previous: &DriftSample) -> Option<f64> {
+        let elapsed = (self.time - previous.time).num_milliseconds();
+        if elapsed <= 0 {
+            return None;
+        }
+        // skew is in ms, elapsed in ms, so this is seconds per second
+        Some(self.skew_ms as f64 / elapsed as f64 * 86400.0)
+    }
+}
+
+/// Returns where the drift log is kept, like
+/// `~/.local/share/flipwire/drift.log` on Linux.
+pub fn log_path() -> io::Result<PathBuf> {
+    match dirs::data_local_dir() {
+        Some(d) => Ok(d.join("flipwire").join("drift.log")),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "couldn't find a data directory")),
+    }
+}
+
+/// Add a measurement to the end of the drift log.
+///
+/// # Arguments
+///
+/// * `flipper`: Name of the Flipper that was synced
+/// * `skew_ms`: Computer time minus Flipper time, in milliseconds
+pub fn record(flipper: &str, skew_ms: i64) -> io::Result<()> {
+    let path = log_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut log = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+    // one tab-separated line per sync: unix time, name, skew
+    writeln!(log, "{}\t{}\t{}", Local::now().timestamp(), flipper, skew_ms)
+}
+
+/// Remove every measurement of `flipper` from the drift log. Returns
+/// how many were removed.
+pub fn forget(flipper: &str) -> io::Result<usize> {
+    let path = log_path()?;
+    let log = match fs::read_to_string(&path) {
+        Ok(l) => l,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    // bad lines are kept, they're not ours to throw away
+    let (removed, kept): (Vec<&str>, Vec<&str>) = log.lines()
+        .partition(|l| l.split('\t').nth(1) == Some(flipper));
+    if !removed.is_empty() {
+        let mut rest = kept.join("\n");
+        if !rest.is_empty() {
+            rest.push('\n');
+        }
+        fs::write(&path, rest)?;
+    }
+    Ok(removed.len())
+}
+
+/// Returns every measurement in the drift log, oldest first.
+///
+/// # Arguments
+///
+/// * `flipper`: Only return measurements for this Flipper, or all of
+///   them if None
+pub fn history(flipper: Option<&str>) -> io::Result<Vec<DriftSample>> {
+    let file = match fs::File::open(log_path()?) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut samples = Vec::new();
+    for line in io::BufReader::new(file).lines() {
+        let line = line?;
+        let mut fields = line.split('\t');
+        let parsed = match (fields.next(), fields.next(), fields.next()) {
+            (Some(t), Some(name), Some(skew)) => t.parse().ok()
+                .and_then(|t| Local.timestamp_opt(t, 0).single())
+                .zip(skew.parse().ok())
+                .map(|(time, skew_ms)| DriftSample { time, flipper: name.to_string(), skew_ms }),
+            _ => None,
+        };
+        match parsed {
+            Some(s) if flipper.is_none_or(|f| f == s.flipper) => samples.push(s),
+            Some(_) => {},
+            None => warn!("skipping bad line in drift log: {:?}", line),
+        }
+    }
+    Ok(samples)
+}
+
+/// Print every clock drift measurement, with how fast the clock
+/// drifted since the one before.
+///
+/// # Arguments
+///
+/// * `flipper`: Only show this Flipper's measurements, or everyone's
+///   if None
+pub fn print_history(flipper: Option<&str>) {
+    let samples = match history(flipper) {
+        Ok(s) => s,
+        Err(e) => {
+            output::error(format!("failed to read drift log: {}", e));
+            return;
+        },
+    };
+    if samples.is_empty() {
+        output::status("no clock drift measured yet, run synctime to take one");
+        return;
+    }
+
+    println!("{:<19}  {:<8}  {:>11}  {:>12}", "synced", "flipper", "skew", "drift");
+    for (i, s) in samples.iter().enumerate() {
+        // drift is only meaningful since the same Flipper's last sync
+        let previous = samples[..i].iter().rev().find(|p| p.flipper == s.flipper);
+        let rate = match previous.and_then(|p| s.rate_since(p)) {
+            Some(r) => format!("{:+.3} s/day", r),
+            None => String::new(),
+        };
+        println!("{:<19}  {:<8}  {:>+10.3}s  {:>12}",
+                 s.time.format("%Y-%m-%d %H:%M:%S"), s.flipper, s.skew_ms as f64 / 1000.0, rate);
+    }
+}
@@ -0,0 +1,35 @@
+// Durations on the command line and in scripts, like "200ms" or
+// "5s".
+
+use std::time::Duration;
+
+/// Parse a duration like "200ms", "5s", "10m", or "1h". A plain
+/// number is milliseconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse()
+        .map_err(|_| format!("invalid duration {:?}", s))?;
+
+    match unit.trim() {
+        "" | "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        "h" => Ok(Duration::from_secs(number * 60 * 60)),
+        _ => Err(format!("unknown unit in duration {:?} (use ms, s, m, or h)", s)),
+    }
+}
+
+/// Returns `d` to the second, like "1h 2m 3s", "2m 3s", or "3s".
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{}h {}m {}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m {}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
@@ -0,0 +1,587 @@
+// A pretend Flipper for testing without hardware. It answers the
+// storage and system RPCs out of a local directory, with `ext/` and
+// `int/` inside it standing in for the SD card and internal storage.
+//
+// It only deals in messages, so it can sit behind any transport:
+// flipwire-emu serves it over TCP.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, Timelike};
+use protobuf::{Message, MessageField};
+
+use crate::flipper_pb;
+use crate::flipper_pb::flipper::main::Content;
+use crate::flipper_pb::flipper::CommandStatus;
+use crate::protobuf_codec::ProtobufCodec;
+
+// Same as the real Flipper sends
+const READ_CHUNK_SIZE: usize = 512;
+const LIST_CHUNK_SIZE: usize = 8;
+
+// Sizes to report for each filesystem. Free space is whatever these
+// leave after the files in the directory.
+const EXT_TOTAL_SPACE: u64 = 4 * 1024 * 1024 * 1024;
+const INT_TOTAL_SPACE: u64 = 256 * 1024;
+
+pub struct EmulatedFlipper {
+    root: PathBuf,
+    name: String,
+    // data of writes still coming in, by command ID
+    writes: HashMap<u32, Vec<u8>>,
+}
+
+/// Returns a response to request `id`.
+fn reply(id: u32, status: CommandStatus, content: Option<Content>) -> flipper_pb::flipper::Main {
+    flipper_pb::flipper::Main {
+        command_id: id,
+        command_status: status.into(),
+        content: Some(content.unwrap_or(Content::Empty(flipper_pb::flipper::Empty::default()))),
+
+        ..Default::default()
+    }
+}
+
+/// Returns the CommandStatus the Flipper would send for a storage error.
+fn storage_status(e: &io::Error) -> CommandStatus {
+    match e.kind() {
+        io::ErrorKind::NotFound => CommandStatus::ERROR_STORAGE_NOT_EXIST,
+        io::ErrorKind::AlreadyExists => CommandStatus::ERROR_STORAGE_EXIST,
+        io::ErrorKind::PermissionDenied => CommandStatus::ERROR_STORAGE_DENIED,
+        _ => CommandStatus::ERROR_STORAGE_INTERNAL,
+    }
+}
+
+/// Returns the total size of the files under `dir`.
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries.flatten().map(|e| match e.metadata() {
+        Ok(m) if m.is_dir() => dir_size(&e.path()),
+        Ok(m) => m.len(),
+        Err(_) => 0,
+    }).sum()
+}
+
+/// Returns the storage File entry for a local file.
+fn file_entry(name: &str, meta: &fs::Metadata) -> flipper_pb::storage::File {
+    let mut f = flipper_pb::storage::File::new();
+    f.name = name.to_string();
+    if meta.is_dir() {
+        f.type_ = flipper_pb::storage::file::FileType::DIR.into();
+    } else {
+        f.type_ = flipper_pb::storage::file::FileType::FILE.into();
+        f.size = u32::try_from(meta.len()).unwrap_or(u32::MAX);
+    }
+    f
+}
+
+impl EmulatedFlipper {
+    /// Returns an emulated Flipper keeping its files in `root`, which
+    /// gets `ext` and `int` directories made in it if they're missing.
+    ///
+    /// # Arguments
+    ///
+    /// * `root`: Local directory to keep files in
+    /// * `name`: Name the Flipper reports in its device info
+    pub fn new(root: &Path, name: &str) -> io::Result<EmulatedFlipper> {
+        fs::create_dir_all(root.join("ext"))?;
+        fs::create_dir_all(root.join("int"))?;
+
+        Ok(EmulatedFlipper {
+            root: root.to_path_buf(),
+            name: name.to_string(),
+            writes: HashMap::new(),
+        })
+    }
+
+    /// Answer every complete message at the front of `buffer`,
+    /// leaving any partial one for next time. Returns the encoded
+    /// responses, ready to send.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer`: Bytes received from the client so far
+    pub fn handle_bytes(&mut self, buffer: &mut Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut out = Vec::new();
+        while let Some(m) = ProtobufCodec::take_message(buffer)? {
+            for r in self.handle(m) {
+                r.write_length_delimited_to_vec(&mut out)?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Returns the messages the Flipper would send back for `m`,
+    /// which is empty for requests it doesn't answer (like all but
+    /// the last chunk of a write).
+    pub fn handle(&mut self, m: flipper_pb::flipper::Main) -> Vec<flipper_pb::flipper::Main> {
+        let id = m.command_id;
+        debug!("request {}: {:?}", id, m.content);
+
+        match m.content {
+            Some(Content::SystemPingRequest(r)) => {
+                let response = flipper_pb::system::PingResponse {
+                    data: r.data,
+
+                    ..Default::default()
+                };
+                vec![reply(id, CommandStatus::OK, Some(Content::SystemPingResponse(response)))]
+            },
+            Some(Content::SystemDeviceInfoRequest(_)) => self.device_info(id),
+            Some(Content::SystemGetDatetimeRequest(_)) => {
+                let now = chrono::Local::now();
+                let datetime = flipper_pb::system::DateTime {
+                    hour: now.hour(),
+                    minute: now.minute(),
+                    second: now.second(),
+                    day: now.day(),
+                    month: now.month(),
+                    year: now.year() as u32,
+                    weekday: now.weekday().number_from_monday(),
+
+                    ..Default::default()
+                };
+                let response = flipper_pb::system::GetDateTimeResponse {
+                    datetime: MessageField::some(datetime),
+
+                    ..Default::default()
+                };
+                vec![reply(id, CommandStatus::OK, Some(Content::SystemGetDatetimeResponse(response)))]
+            },
+            // nothing to actually do for these
+            Some(Content::SystemSetDatetimeRequest(_))
+                | Some(Content::SystemPlayAudiovisualAlertRequest(_)) => {
+                vec![reply(id, CommandStatus::OK, None)]
+            },
+            Some(Content::StorageInfoRequest(r)) => vec![self.storage_info(id, &r.path)],
+            Some(Content::StorageStatRequest(r)) => vec![self.stat(id, &r.path)],
+            Some(Content::StorageListRequest(r)) => self.list(id, &r),
+            Some(Content::StorageReadRequest(r)) => self.read(id, &r.path),
+            Some(Content::StorageWriteRequest(r)) => {
+                self.writes.entry(id).or_default().extend(&r.file.data);
+                if m.has_next {
+                    return Vec::new();
+                }
+                let data = self.writes.remove(&id).unwrap_or_default();
+                vec![self.write(id, &r.path, &data)]
+            },
+            Some(Content::StorageDeleteRequest(r)) => vec![self.delete(id, &r.path, r.recursive)],
+            Some(Content::StorageMkdirRequest(r)) => vec![self.mkdir(id, &r.path)],
+            Some(Content::StorageMd5sumRequest(r)) => vec![self.md5sum(id, &r.path)],
+            Some(Content::StorageRenameRequest(r)) => vec![self.rename(id, &r.old_path, &r.new_path)],
+            // the client's OK after a read
+            Some(Content::Empty(_)) | None => Vec::new(),
+            Some(other) => {
+                debug!("not implemented: {:?}", other);
+                vec![reply(id, CommandStatus::ERROR_NOT_IMPLEMENTED, None)]
+            },
+        }
+    }
+
+    /// Returns the local path for a Flipper path, or an error status
+    /// if it isn't on /ext, /int, or /any, or tries to leave them.
+    fn local_path(&self, path: &str) -> Result<PathBuf, CommandStatus> {
+        let mut parts = path.split('/').filter(|p| !p.is_empty());
+        let mut local = match parts.next() {
+            // /any is the SD card when there is one
+            Some("ext") | Some("any") => self.root.join("ext"),
+            Some("int") => self.root.join("int"),
+            _ => return Err(CommandStatus::ERROR_STORAGE_INVALID_NAME),
+        };
+        for p in parts {
+            if p == "." || p == ".." {
+                return Err(CommandStatus::ERROR_STORAGE_INVALID_NAME);
+            }
+            local.push(p);
+        }
+        Ok(local)
+    }
+
+    fn device_info(&self, id: u32) -> Vec<flipper_pb::flipper::Main> {
+        let info = [
+            ("hardware_name", self.name.as_str()),
+            ("hardware_model", "Emulator"),
+            ("firmware_version", env!("CARGO_PKG_VERSION")),
+            ("firmware_origin_fork", "flipwire-emu"),
+        ];
+
+        let mut responses: Vec<_> = info.iter().map(|(key, value)| {
+            let response = flipper_pb::system::DeviceInfoResponse {
+                key: key.to_string(),
+                value: value.to_string(),
+
+                ..Default::default()
+            };
+            let mut m = reply(id, CommandStatus::OK, Some(Content::SystemDeviceInfoResponse(response)));
+            m.has_next = true;
+            m
+        }).collect();
+        if let Some(last) = responses.last_mut() {
+            last.has_next = false;
+        }
+        responses
+    }
+
+    fn storage_info(&self, id: u32, path: &str) -> flipper_pb::flipper::Main {
+        let (dir, total) = match self.local_path(path) {
+            Ok(p) if p.starts_with(self.root.join("int")) => (self.root.join("int"), INT_TOTAL_SPACE),
+            Ok(_) => (self.root.join("ext"), EXT_TOTAL_SPACE),
+            Err(status) => return reply(id, status, None),
+        };
+
+        let response = flipper_pb::storage::InfoResponse {
+            total_space: total,
+            free_space: total.saturating_sub(dir_size(&dir)),
+
+            ..Default::default()
+        };
+        reply(id, CommandStatus::OK, Some(Content::StorageInfoResponse(response)))
+    }
+
+    fn stat(&self, id: u32, path: &str) -> flipper_pb::flipper::Main {
+        let local = match self.local_path(path) {
+            Ok(p) => p,
+            Err(status) => return reply(id, status, None),
+        };
+
+        match fs::metadata(&local) {
+            Ok(meta) => {
+                let name = local.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                let response = flipper_pb::storage::StatResponse {
+                    file: MessageField::some(file_entry(name, &meta)),
+
+                    ..Default::default()
+                };
+                reply(id, CommandStatus::OK, Some(Content::StorageStatResponse(response)))
+            },
+            Err(e) => reply(id, storage_status(&e), None),
+        }
+    }
+
+    fn list(&self, id: u32, request: &flipper_pb::storage::ListRequest) -> Vec<flipper_pb::flipper::Main> {
+        let path = request.path.as_str();
+        let mut files = Vec::new();
+        if path.trim_end_matches('/').is_empty() {
+            // the root just has the filesystems in it
+            for name in ["int", "ext", "any"] {
+                let mut f = flipper_pb::storage::File::new();
+                f.name = name.to_string();
+                f.type_ = flipper_pb::storage::file::FileType::DIR.into();
+                files.push(f);
+            }
+        } else {
+            let local = match self.local_path(path) {
+                Ok(p) => p,
+                Err(status) => return vec![reply(id, status, None)],
+            };
+            let entries = match fs::read_dir(&local) {
+                Ok(e) => e,
+                Err(e) => return vec![reply(id, storage_status(&e), None)],
+            };
+            for e in entries.flatten() {
+                if let (Some(name), Ok(meta)) = (e.file_name().to_str(), e.metadata()) {
+                    let mut f = file_entry(name, &meta);
+                    // like the firmware, 0 means no limit
+                    if request.filter_max_size != 0 && f.size > request.filter_max_size {
+                        continue;
+                    }
+                    if request.include_md5 && meta.is_file() {
+                        if let Ok(data) = fs::read(e.path()) {
+                            f.md5sum = format!("{:x}", md5::compute(data));
+                        }
+                    }
+                    files.push(f);
+                }
+            }
+        }
+
+        // An empty directory still gets one (empty) response.
+        let chunks: Vec<_> = files.chunks(LIST_CHUNK_SIZE).collect();
+        let count = chunks.len().max(1);
+        (0..count).map(|i| {
+            let response = flipper_pb::storage::ListResponse {
+                file: chunks.get(i).map_or(Vec::new(), |c| c.to_vec()),
+
+                ..Default::default()
+            };
+            let mut m = reply(id, CommandStatus::OK, Some(Content::StorageListResponse(response)));
+            m.has_next = i + 1 < count;
+            m
+        }).collect()
+    }
+
+    fn read(&self, id: u32, path: &str) -> Vec<flipper_pb::flipper::Main> {
+        let data = match self.local_path(path) {
+            Ok(local) if local.is_dir() => {
+                return vec![reply(id, CommandStatus::ERROR_STORAGE_INVALID_PARAMETER, None)];
+            },
+            Ok(local) => match fs::read(local) {
+                Ok(d) => d,
+                Err(e) => return vec![reply(id, storage_status(&e), None)],
+            },
+            Err(status) => return vec![reply(id, status, None)],
+        };
+
+        // An empty file still gets one (empty) response.
+        let chunks: Vec<_> = data.chunks(READ_CHUNK_SIZE).collect();
+        let count = chunks.len().max(1);
+        (0..count).map(|i| {
+            let mut f = flipper_pb::storage::File::new();
+            f.data = chunks.get(i).map_or(Vec::new(), |c| c.to_vec());
+            let response = flipper_pb::storage::ReadResponse {
+                file: MessageField::some(f),
+
+                ..Default::default()
+            };
+            let mut m = reply(id, CommandStatus::OK, Some(Content::StorageReadResponse(response)));
+            m.has_next = i + 1 < count;
+            m
+        }).collect()
+    }
+
+    fn write(&self, id: u32, path: &str, data: &[u8]) -> flipper_pb::flipper::Main {
+        let result = self.local_path(path)
+            .and_then(|local| fs::write(local, data).map_err(|e| storage_status(&e)));
+        match result {
+            Ok(()) => reply(id, CommandStatus::OK, None),
+            Err(status) => reply(id, status, None),
+        }
+    }
+
+    fn delete(&self, id: u32, path: &str, recursive: bool) -> flipper_pb::flipper::Main {
+        let local = match self.local_path(path) {
+            Ok(p) => p,
+            Err(status) => return reply(id, status, None),
+        };
+
+        let result = match fs::metadata(&local) {
+            // the real Flipper says OK for files that don't exist too
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+            Ok(meta) if meta.is_dir() && recursive => fs::remove_dir_all(&local),
+            Ok(meta) if meta.is_dir() => {
+                if fs::read_dir(&local).map(|mut e| e.next().is_some()).unwrap_or(false) {
+                    return reply(id, CommandStatus::ERROR_STORAGE_DIR_NOT_EMPTY, None);
+                }
+                fs::remove_dir(&local)
+            },
+            Ok(_) => fs::remove_file(&local),
+        };
+        match result {
+            Ok(()) => reply(id, CommandStatus::OK, None),
+            Err(e) => reply(id, storage_status(&e), None),
+        }
+    }
+
+    fn mkdir(&self, id: u32, path: &str) -> flipper_pb::flipper::Main {
+        let result = self.local_path(path)
+            .and_then(|local| fs::create_dir(local).map_err(|e| storage_status(&e)));
+        match result {
+            Ok(()) => reply(id, CommandStatus::OK, None),
+            Err(status) => reply(id, status, None),
+        }
+    }
+
+    fn md5sum(&self, id: u32, path: &str) -> flipper_pb::flipper::Main {
+        let result = self.local_path(path)
+            .and_then(|local| fs::read(local).map_err(|e| storage_status(&e)));
+        match result {
+            Ok(data) => {
+                let response = flipper_pb::storage::Md5sumResponse {
+                    md5sum: format!("{:x}", md5::compute(data)),
+
+                    ..Default::default()
+                };
+                reply(id, CommandStatus::OK, Some(Content::StorageMd5sumResponse(response)))
+            },
+            Err(status) => reply(id, status, None),
+        }
+    }
+
+    fn rename(&self, id: u32, old_path: &str, new_path: &str) -> flipper_pb::flipper::Main {
+        let result = self.local_path(old_path).and_then(|old| {
+            let new = self.local_path(new_path)?;
+            if new.exists() {
+                return Err(CommandStatus::ERROR_STORAGE_EXIST);
+            }
+            fs::rename(old, new).map_err(|e| storage_status(&e))
+        });
+        match result {
+            Ok(()) => reply(id, CommandStatus::OK, None),
+            Err(status) => reply(id, status, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `packets` to `flipper` the way they'd come over the wire
+    /// and return what it answers.
+    fn send(flipper: &mut EmulatedFlipper, packets: &[Vec<u8>]) -> Vec<flipper_pb::flipper::Main> {
+        let mut buffer = packets.concat();
+        let mut out = flipper.handle_bytes(&mut buffer).unwrap();
+        assert!(buffer.is_empty());
+
+        let mut responses = Vec::new();
+        while let Some(m) = ProtobufCodec::take_message(&mut out).unwrap() {
+            responses.push(m);
+        }
+        responses
+    }
+
+    fn status(m: &flipper_pb::flipper::Main) -> CommandStatus {
+        m.command_status.enum_value().unwrap()
+    }
+
+    fn setup() -> (tempfile::TempDir, EmulatedFlipper, ProtobufCodec) {
+        let dir = tempfile::tempdir().unwrap();
+        let flipper = EmulatedFlipper::new(dir.path(), "Emu").unwrap();
+        (dir, flipper, ProtobufCodec::new())
+    }
+
+    #[test]
+    fn local_path_test() {
+        let (dir, flipper, _) = setup();
+        assert_eq!(flipper.local_path("/ext/nfc/card.nfc"), Ok(dir.path().join("ext/nfc/card.nfc")));
+        assert_eq!(flipper.local_path("/any/nfc"), Ok(dir.path().join("ext/nfc")));
+        assert_eq!(flipper.local_path("/int//manifest.txt"), Ok(dir.path().join("int/manifest.txt")));
+        for path in ["/", "/etc/passwd", "/extra/file", "ext/../../etc", "/ext/../int", "/ext/./nfc", "/int/.."] {
+            assert_eq!(flipper.local_path(path), Err(CommandStatus::ERROR_STORAGE_INVALID_NAME), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn escape_rejected_test() {
+        let (dir, mut flipper, mut codec) = setup();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+
+        let read = send(&mut flipper, &codec.create_read_request_packet("/ext/../secret.txt").unwrap());
+        assert_eq!(read.len(), 1);
+        assert_eq!(status(&read[0]), CommandStatus::ERROR_STORAGE_INVALID_NAME);
+
+        let chunks = codec.create_write_request_packets(b"oops", "/ext/../../outside.txt").unwrap();
+        let packets: Vec<_> = chunks.into_iter().flat_map(|c| c.packets).collect();
+        let write = send(&mut flipper, &packets);
+        assert_eq!(status(&write[0]), CommandStatus::ERROR_STORAGE_INVALID_NAME);
+        assert!(!dir.path().parent().unwrap().join("outside.txt").exists());
+
+        let delete = send(&mut flipper, &codec.create_delete_request_packet("/ext/../secret.txt", false).unwrap());
+        assert_eq!(status(&delete[0]), CommandStatus::ERROR_STORAGE_INVALID_NAME);
+        assert!(dir.path().join("secret.txt").exists());
+    }
+
+    #[test]
+    fn chunked_write_test() {
+        let (dir, mut flipper, mut codec) = setup();
+        codec.set_write_chunk_size(100);
+        let data: Vec<u8> = (0..350).map(|i| i as u8).collect();
+        let chunks = codec.create_write_request_packets(&data, "/ext/data.bin").unwrap();
+        assert_eq!(chunks.len(), 4);
+
+        // nothing comes back until the last chunk
+        for chunk in &chunks[..3] {
+            assert!(send(&mut flipper, &chunk.packets).is_empty());
+            assert!(!dir.path().join("ext/data.bin").exists());
+        }
+        let done = send(&mut flipper, &chunks[3].packets);
+        assert_eq!(done.len(), 1);
+        assert_eq!(status(&done[0]), CommandStatus::OK);
+        assert_eq!(fs::read(dir.path().join("ext/data.bin")).unwrap(), data);
+    }
+
+    #[test]
+    fn interleaved_writes_test() {
+        let (dir, mut flipper, mut codec) = setup();
+        codec.set_write_chunk_size(4);
+        let a = codec.create_write_request_packets(b"aaaaaaaa", "/ext/a.txt").unwrap();
+        let b = codec.create_write_request_packets(b"bbbbbbbbbb", "/int/b.txt").unwrap();
+
+        // each write's chunks are kept apart by command ID
+        let mut responses = Vec::new();
+        for (a, b) in a.iter().zip(&b) {
+            responses.extend(send(&mut flipper, &a.packets));
+            responses.extend(send(&mut flipper, &b.packets));
+        }
+        responses.extend(send(&mut flipper, &b[2].packets));
+
+        assert_eq!(responses.iter().map(|m| m.command_id).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(fs::read(dir.path().join("ext/a.txt")).unwrap(), b"aaaaaaaa");
+        assert_eq!(fs::read(dir.path().join("int/b.txt")).unwrap(), b"bbbbbbbbbb");
+    }
+
+    #[test]
+    fn empty_write_test() {
+        let (dir, mut flipper, mut codec) = setup();
+        let chunks = codec.create_write_request_packets(b"", "/ext/empty.txt").unwrap();
+        let done = send(&mut flipper, &chunks[0].packets);
+        assert_eq!(status(&done[0]), CommandStatus::OK);
+        assert_eq!(fs::read(dir.path().join("ext/empty.txt")).unwrap(), b"");
+    }
+
+    #[test]
+    fn list_paging_test() {
+        let (dir, mut flipper, mut codec) = setup();
+        for i in 0..20 {
+            fs::write(dir.path().join(format!("ext/{:02}.txt", i)), [0; 10]).unwrap();
+        }
+        fs::create_dir(dir.path().join("ext/nfc")).unwrap();
+
+        let pages = send(&mut flipper, &codec.create_list_request_packet("/ext", false, 0).unwrap());
+        let sizes: Vec<_> = pages.iter().map(|m| match &m.content {
+            Some(Content::StorageListResponse(r)) => r.file.len(),
+            other => panic!("{:?}", other),
+        }).collect();
+        assert_eq!(sizes, [8, 8, 5]);
+        assert_eq!(pages.iter().map(|m| m.has_next).collect::<Vec<_>>(), [true, true, false]);
+
+        // an empty directory still answers once
+        let empty = send(&mut flipper, &codec.create_list_request_packet("/ext/nfc", false, 0).unwrap());
+        assert_eq!(empty.len(), 1);
+        assert!(!empty[0].has_next);
+
+        let missing = send(&mut flipper, &codec.create_list_request_packet("/ext/missing", false, 0).unwrap());
+        assert_eq!(status(&missing[0]), CommandStatus::ERROR_STORAGE_NOT_EXIST);
+    }
+
+    #[test]
+    fn list_root_test() {
+        let (_dir, mut flipper, mut codec) = setup();
+        let root = send(&mut flipper, &codec.create_list_request_packet("/", false, 0).unwrap());
+        match &root[0].content {
+            Some(Content::StorageListResponse(r)) => {
+                assert_eq!(r.file.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), ["int", "ext", "any"]);
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_paging_test() {
+        let (dir, mut flipper, mut codec) = setup();
+        let data: Vec<u8> = (0..1200).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.path().join("ext/data.bin"), &data).unwrap();
+
+        let pages = send(&mut flipper, &codec.create_read_request_packet("/ext/data.bin").unwrap());
+        let mut read: Vec<u8> = Vec::new();
+        for (i, m) in pages.iter().enumerate() {
+            assert_eq!(m.has_next, i + 1 < pages.len());
+            match &m.content {
+                Some(Content::StorageReadResponse(r)) => read.extend(&r.file.data),
+                other => panic!("{:?}", other),
+            }
+        }
+        assert_eq!(pages.len(), 3);
+        assert_eq!(read, data);
+
+        let dir_read = send(&mut flipper, &codec.create_read_request_packet("/ext").unwrap());
+        assert_eq!(status(&dir_read[0]), CommandStatus::ERROR_STORAGE_INVALID_PARAMETER);
+    }
+}
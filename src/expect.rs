@@ -0,0 +1,200 @@
+// Expect-style scripts: press buttons, then wait until the screen
+// shows what it should before going on, instead of guessing how long
+// a menu takes to open. One command per line, # starts a comment:
+//
+//     launch "/ext/apps/Main/nfc.fap"
+//     wait screen nfc_menu.png region 0,0,128,16 timeout 5s
+//     press down down ok
+//     wait hash 1f2e3d4c region 0,52,128,12
+//     sleep 500ms
+//     press back:long
+//
+// `wait screen` compares against a screenshot (a PNG like `screen`
+// or `uitest run --update` makes), allowing `threshold` pixels to be
+// different. `wait hash` compares against a hash from `screen hash`,
+// so nothing but the script needs to be kept.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::duration::parse_duration;
+use crate::keyboard::KeyPress;
+use crate::screen::{self, Frame, Region};
+#[cfg(feature = "ble")]
+use crate::{flipper_ble, uitest};
+
+/// How long `wait` waits when the script doesn't say
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the screen has to look like for a `wait` to finish
+pub enum Condition {
+    /// Within `threshold` pixels of an image
+    Image {
+        path: PathBuf,
+        image: Frame,
+        region: Region,
+        threshold: usize,
+    },
+    /// The pixels in `region` have this hash
+    Hash {
+        hash: u32,
+        region: Region,
+    },
+}
+
+impl Condition {
+    /// Returns true if `frame` looks the way it should.
+    pub fn matches(&self, frame: &Frame) -> bool {
+        match self {
+            Condition::Image { image, region, threshold, .. } =>
+                screen::changed_pixels(image, frame, region) <= *threshold,
+            Condition::Hash { hash, region } => screen::region_hash(frame, region) == *hash,
+        }
+    }
+
+    /// Returns what the condition is waiting for, for messages.
+    pub fn describe(&self) -> String {
+        match self {
+            Condition::Image { path, .. } => format!("screen to match {:?}", path),
+            Condition::Hash { hash, .. } => format!("screen hash {:08x}", hash),
+        }
+    }
+}
+
+/// One line of a script
+pub enum Action {
+    Launch { app: String, args: String },
+    Press(Vec<KeyPress>),
+    Sleep(Duration),
+    Wait { condition: Condition, timeout: Duration },
+}
+
+pub struct Script {
+    /// Each action with the line it came from
+    pub actions: Vec<(usize, Action)>,
+}
+
+impl Script {
+    /// Read the script at `path`. Images are relative to it.
+    pub fn load(path: &Path) -> Result<Script, Box<dyn Error>> {
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut actions = Vec::new();
+        for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let action = parse_line(line, base)
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+            actions.push((i + 1, action));
+        }
+        Ok(Script { actions })
+    }
+}
+
+fn parse_line(line: &str, base: &Path) -> Result<Action, Box<dyn Error>> {
+    let words = shell_words::split(line)?;
+    let (command, rest) = words.split_first().ok_or("empty line")?;
+
+    match command.as_str() {
+        "launch" => {
+            let (app, args) = rest.split_first().ok_or("launch needs an app")?;
+            Ok(Action::Launch { app: app.clone(), args: args.join(" ") })
+        },
+        "press" => {
+            if rest.is_empty() {
+                return Err("press needs at least one button".into());
+            }
+            Ok(Action::Press(rest.iter()
+                .map(|b| b.parse::<KeyPress>())
+                .collect::<Result<Vec<KeyPress>, String>>()?))
+        },
+        "sleep" => match rest {
+            [time] => Ok(Action::Sleep(parse_duration(time)?)),
+            _ => Err("sleep needs one duration, like 500ms".into()),
+        },
+        "wait" => parse_wait(rest, base),
+        _ => Err(format!("unknown command {:?} (use launch, press, sleep, or wait)", command).into()),
+    }
+}
+
+// wait screen <image> | wait hash <hash>, then options in any order
+fn parse_wait(words: &[String], base: &Path) -> Result<Action, Box<dyn Error>> {
+    let (kind, target, options) = match words {
+        [kind, target, options @ ..] => (kind.as_str(), target, options),
+        _ => return Err("use wait screen <image> or wait hash <hash>".into()),
+    };
+
+    let mut region = Region::full();
+    let mut threshold = 0;
+    let mut timeout = DEFAULT_TIMEOUT;
+    for option in options.chunks(2) {
+        match option {
+            [name, value] if name == "region" => region = value.parse()?,
+            [name, value] if name == "threshold" => threshold = value.parse()
+                .map_err(|_| format!("invalid threshold {:?}", value))?,
+            [name, value] if name == "timeout" => timeout = parse_duration(value)?,
+            [name, ..] => return Err(format!("unknown or incomplete wait option {:?} (use region, threshold, or timeout)", name).into()),
+            [] => unreachable!(),
+        }
+    }
+
+    let condition = match kind {
+        "screen" => {
+            let path = base.join(target);
+            let image = fs::read(&path)
+                .map_err(|e| format!("can't read {:?}: {}", path, e))?;
+            Condition::Image {
+                image: screen::parse_png(&image).map_err(|e| format!("{:?}: {}", path, e))?,
+                path,
+                region,
+                threshold,
+            }
+        },
+        "hash" => {
+            if threshold != 0 {
+                return Err("a hash has to match exactly, so it can't have a threshold".into());
+            }
+            Condition::Hash {
+                hash: u32::from_str_radix(target, 16)
+                    .map_err(|_| format!("invalid hash {:?}, use the one `screen hash` prints", target))?,
+                region,
+            }
+        },
+        _ => return Err(format!("unknown wait {:?} (use screen or hash)", kind).into()),
+    };
+    Ok(Action::Wait { condition, timeout })
+}
+
+/// Run the expect script at `path`, stopping at the first line that
+/// fails or a `wait` that times out.
+#[cfg(feature = "ble")]
+pub async fn run(flipper: &mut flipper_ble::FlipperBle, path: &Path) -> Result<(), Box<dyn Error>> {
+    let script = Script::load(path)?;
+    for (line, action) in &script.actions {
+        let result: Result<(), Box<dyn Error>> = match action {
+            Action::Launch { app, args } => flipper.launch(app, args).await,
+            Action::Press(presses) => flipper.press_sequence(presses, uitest::DEFAULT_PRESS_DELAY).await,
+            Action::Sleep(d) => {
+                tokio::time::sleep(*d).await;
+                Ok(())
+            },
+            Action::Wait { condition, timeout } => {
+                debug!("waiting up to {:?} for {}", timeout, condition.describe());
+                let mut matched = false;
+                flipper.stream_screen_for(Some(*timeout), |frame| {
+                    matched = condition.matches(frame);
+                    !matched
+                }).await.and_then(|()| if matched {
+                    Ok(())
+                } else {
+                    Err(format!("gave up waiting for {} after {:?}", condition.describe(), timeout).into())
+                })
+            },
+        };
+        result.map_err(|e| format!("line {}: {}", line, e))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,181 @@
+// Reading the manifest out of a .fap. A .fap is a 32-bit ARM ELF
+// object, and fbt puts a small packed struct in its ".fapmeta"
+// section saying what the app is called and which firmware API it was
+// built against. See FlipperApplicationManifestV1 in the firmware's
+// lib/flipper_application/application_manifest.h.
+
+use std::fmt;
+#[cfg(feature = "ble")]
+use std::error::Error;
+
+use crate::apps;
+use crate::output;
+#[cfg(feature = "ble")]
+use crate::flipper_ble;
+
+/// The first field of every manifest, "HDGR" in the file
+pub const MANIFEST_MAGIC: u32 = 0x52474448;
+
+const FAPMETA_SECTION: &str = ".fapmeta";
+// magic, manifest version, API version, hardware target
+const MANIFEST_BASE_SIZE: usize = 14;
+// then stack size, app version, name[32], has_icon, icon[32]
+const MANIFEST_V1_SIZE: usize = MANIFEST_BASE_SIZE + 2 + 4 + 32 + 1 + 32;
+
+/// What a .fap says about itself
+#[derive(Debug, Clone)]
+pub struct FapManifest {
+    pub manifest_version: u32,
+    /// Firmware API version the app was built against
+    pub api_major: u16,
+    pub api_minor: u16,
+    /// Which hardware the app is for (7 for the Flipper Zero)
+    pub hardware_target: u16,
+    pub stack_size: u16,
+    /// The app's own version, major in the top 16 bits
+    pub app_version: u32,
+    pub name: String,
+    pub has_icon: bool,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+impl FapManifest {
+    /// Returns the manifest in `data`, the contents of a .fapmeta
+    /// section.
+    pub fn parse(data: &[u8]) -> Result<FapManifest, String> {
+        if u32_at(data, 0) != Some(MANIFEST_MAGIC) {
+            return Err("not a .fap manifest".to_string());
+        }
+        let manifest_version = u32_at(data, 4).unwrap_or(0);
+        if manifest_version != 1 {
+            return Err(format!("unknown .fap manifest version {}", manifest_version));
+        }
+        if data.len() < MANIFEST_V1_SIZE {
+            return Err(format!("the manifest is {} bytes long, not {}", data.len(), MANIFEST_V1_SIZE));
+        }
+
+        let name = &data[20..52];
+        let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+        Ok(FapManifest {
+            manifest_version,
+            // the API version is minor then major
+            api_minor: u16_at(data, 8).unwrap(),
+            api_major: u16_at(data, 10).unwrap(),
+            hardware_target: u16_at(data, 12).unwrap(),
+            stack_size: u16_at(data, 14).unwrap(),
+            app_version: u32_at(data, 16).unwrap(),
+            name: String::from_utf8_lossy(name).into_owned(),
+            has_icon: data[52] != 0,
+        })
+    }
+
+    /// Returns why firmware with API version `major`.`minor` can't
+    /// run this app, or None if it can. The loader needs the same
+    /// major version and at least the app's minor version.
+    pub fn api_mismatch(&self, major: u16, minor: u16) -> Option<String> {
+        if self.api_major != major {
+            Some(format!("was built for firmware API {}.{}, but the Flipper's firmware has API {}.{}. \
+                          Rebuild it against this firmware, or update the firmware to match",
+                         self.api_major, self.api_minor, major, minor))
+        } else if self.api_minor > minor {
+            Some(format!("needs firmware API {}.{} or newer, but the Flipper's firmware has API {}.{}. \
+                          Update the firmware",
+                         self.api_major, self.api_minor, major, minor))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the app's version like "1.2".
+    pub fn app_version_string(&self) -> String {
+        format!("{}.{}", self.app_version >> 16, self.app_version & 0xffff)
+    }
+}
+
+impl fmt::Display for FapManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "name: {}", self.name)?;
+        writeln!(f, "version: {}", self.app_version_string())?;
+        writeln!(f, "api: {}.{}", self.api_major, self.api_minor)?;
+        writeln!(f, "target: f{}", self.hardware_target)?;
+        writeln!(f, "stack size: {}", self.stack_size)?;
+        write!(f, "icon: {}", if self.has_icon { "yes" } else { "no" })
+    }
+}
+
+/// Returns the contents of the section called `name` in the 32-bit
+/// little-endian ELF file `elf`, or None if there's no such section
+/// or not enough of the file to find it.
+fn elf_section<'a>(elf: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    if elf.get(..6)? != b"\x7fELF\x01\x01" {
+        return None;
+    }
+    let shoff = usize::try_from(u32_at(elf, 0x20)?).ok()?;
+    let shentsize = usize::from(u16_at(elf, 0x2e)?);
+    let shnum = usize::from(u16_at(elf, 0x30)?);
+    let shstrndx = usize::from(u16_at(elf, 0x32)?);
+
+    let header = |i: usize| elf.get(shoff + i * shentsize..shoff + (i + 1) * shentsize);
+    let contents = |h: &[u8]| -> Option<&'a [u8]> {
+        let offset = usize::try_from(u32_at(h, 0x10)?).ok()?;
+        let size = usize::try_from(u32_at(h, 0x14)?).ok()?;
+        elf.get(offset..offset + size)
+    };
+
+    let names = contents(header(shstrndx)?)?;
+    (0..shnum).find_map(|i| {
+        let h = header(i)?;
+        let start = usize::try_from(u32_at(h, 0)?).ok()?;
+        let end = start + names.get(start..)?.iter().position(|b| *b == 0)?;
+        (names.get(start..end)? == name.as_bytes()).then_some(())?;
+        contents(h)
+    })
+}
+
+/// Returns the manifest in `data`, which is all or the start of a
+/// .fap. With the whole file the .fapmeta section is looked up
+/// properly; with only the start (the section table is at the end),
+/// the manifest is found by its magic number, which works because fbt
+/// puts .fapmeta near the front.
+pub fn find_manifest(data: &[u8]) -> Option<FapManifest> {
+    if let Some(section) = elf_section(data, FAPMETA_SECTION) {
+        return FapManifest::parse(section).ok();
+    }
+    let magic = MANIFEST_MAGIC.to_le_bytes();
+    data.windows(magic.len())
+        .enumerate()
+        .filter(|(_, w)| *w == magic)
+        .find_map(|(i, _)| FapManifest::parse(&data[i..]).ok())
+}
+
+/// Print the manifest of the .fap `name`, whose contents (or the
+/// start of them) are `data`.
+pub fn print_info(name: &str, data: &[u8]) {
+    match find_manifest(data) {
+        Some(m) => {
+            println!("appid: {}", apps::appid(name));
+            println!("{}", m);
+        },
+        None => output::error(format!("{} isn't a .fap, or has no manifest", name)),
+    }
+}
+
+/// Returns as much of the Flipper .fap `path` as it takes to find its
+/// manifest, which is usually only the first few hundred bytes.
+#[cfg(feature = "ble")]
+pub async fn read_head(flipper: &mut flipper_ble::FlipperBle, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut data = Vec::new();
+    flipper.read_file_until(path, |piece| {
+        data.extend_from_slice(piece);
+        Ok(find_manifest(&data).is_some())
+    }).await?;
+    debug!("read {} bytes of {}", data.len(), path);
+    Ok(data)
+}
@@ -0,0 +1,155 @@
+use std::fmt;
+
+// Helpers for making sense of what the Flipper says about its
+// firmware. Everything here works on the key/value pairs that come
+// back from a DeviceInfoRequest.
+
+// The official firmware and the forks each publish their builds in
+// an update directory of the same format, on their own servers.
+const OFFICIAL_UPDATE_DIRECTORY: &str = "https://update.flipperzero.one/firmware/directory.json";
+const MOMENTUM_UPDATE_DIRECTORY: &str = "https://up.momentum-fw.dev/firmware/directory.json";
+const UNLEASHED_UPDATE_DIRECTORY: &str = "https://up.unleashedflip.com/directory.json";
+
+/// Firmware distributions we can tell apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FirmwareFork {
+    Official,
+    Momentum,
+    Unleashed,
+    /// Anything else, with whatever name it reported
+    Other(String),
+}
+
+impl FirmwareFork {
+    /// Work out which firmware the Flipper is running from its
+    /// device info.
+    pub fn detect(info: &[(String, String)]) -> FirmwareFork {
+        // Recent firmware (official and forks alike) reports its
+        // origin directly.
+        if let Some(origin) = lookup(info, "firmware_origin_fork") {
+            return match origin.to_lowercase().as_str() {
+                "official" => FirmwareFork::Official,
+                "momentum" => FirmwareFork::Momentum,
+                "unleashed" => FirmwareFork::Unleashed,
+                _ => FirmwareFork::Other(origin.to_string()),
+            };
+        }
+
+        // Older builds don't have that key, but the forks put their
+        // own prefix on the version string (like "mntm-008" or
+        // "unlshd-079"), and the official firmware doesn't.
+        let version = lookup(info, "firmware_version").unwrap_or("").to_lowercase();
+        if version.starts_with("mntm") {
+            FirmwareFork::Momentum
+        } else if version.starts_with("unlshd") {
+            FirmwareFork::Unleashed
+        } else {
+            FirmwareFork::Official
+        }
+    }
+
+    /// Returns the directory of builds on the update server this
+    /// firmware publishes to, or an error if we don't know of one.
+    pub fn update_directory(&self) -> Result<&'static str, String> {
+        match self {
+            FirmwareFork::Official => Ok(OFFICIAL_UPDATE_DIRECTORY),
+            FirmwareFork::Momentum => Ok(MOMENTUM_UPDATE_DIRECTORY),
+            FirmwareFork::Unleashed => Ok(UNLEASHED_UPDATE_DIRECTORY),
+            FirmwareFork::Other(name) => Err(format!("flipwire doesn't know where {} firmware publishes its updates", name)),
+        }
+    }
+
+    /// Returns an error if this firmware doesn't read the Sub-GHz
+    /// region from /int/.region_data, so setting it would do nothing.
+    pub fn check_region_support(&self) -> Result<(), String> {
+        match self {
+            // both drop the region lock, and go by their own
+            // frequency settings on the SD card instead
+            FirmwareFork::Momentum | FirmwareFork::Unleashed => {
+                Err(format!("{} firmware doesn't use the Sub-GHz region, it has its own frequency settings", self))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for FirmwareFork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FirmwareFork::Official => write!(f, "Official"),
+            FirmwareFork::Momentum => write!(f, "Momentum"),
+            FirmwareFork::Unleashed => write!(f, "Unleashed"),
+            FirmwareFork::Other(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Returns the value for `key` in a device info listing, if the
+/// Flipper sent it.
+pub fn lookup<'a>(info: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    info.iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn detect_origin_test() {
+        let cases = [
+            ("Official", FirmwareFork::Official),
+            ("official", FirmwareFork::Official),
+            ("Momentum", FirmwareFork::Momentum),
+            ("Unleashed", FirmwareFork::Unleashed),
+            ("UNLEASHED", FirmwareFork::Unleashed),
+            ("RogueMaster", FirmwareFork::Other("RogueMaster".to_string())),
+        ];
+        for (origin, fork) in cases {
+            // the origin wins over whatever the version looks like
+            let info = info(&[("firmware_version", "mntm-008"), ("firmware_origin_fork", origin)]);
+            assert_eq!(FirmwareFork::detect(&info), fork, "{:?}", origin);
+        }
+    }
+
+    #[test]
+    fn detect_version_test() {
+        let cases = [
+            ("0.103.1", "release", FirmwareFork::Official),
+            ("1.0.0-rc", "release-candidate", FirmwareFork::Official),
+            ("0.104.0", "dev", FirmwareFork::Official),
+            ("mntm-008", "release", FirmwareFork::Momentum),
+            ("mntm-dev", "dev", FirmwareFork::Momentum),
+            ("MNTM-007", "release", FirmwareFork::Momentum),
+            ("unlshd-079", "release", FirmwareFork::Unleashed),
+            ("unlshd-dev", "dev", FirmwareFork::Unleashed),
+        ];
+        for (version, branch, fork) in cases {
+            let info = info(&[("firmware_version", version), ("firmware_branch", branch)]);
+            assert_eq!(FirmwareFork::detect(&info), fork, "{:?} on {:?}", version, branch);
+        }
+        // nothing to go on
+        assert_eq!(FirmwareFork::detect(&[]), FirmwareFork::Official);
+    }
+
+    #[test]
+    fn update_directory_test() {
+        assert_eq!(FirmwareFork::Official.update_directory(), Ok(OFFICIAL_UPDATE_DIRECTORY));
+        assert_eq!(FirmwareFork::Momentum.update_directory(), Ok(MOMENTUM_UPDATE_DIRECTORY));
+        assert_eq!(FirmwareFork::Unleashed.update_directory(), Ok(UNLEASHED_UPDATE_DIRECTORY));
+        assert!(FirmwareFork::Other("Xtreme".to_string()).update_directory().is_err());
+    }
+
+    #[test]
+    fn region_support_test() {
+        assert!(FirmwareFork::Official.check_region_support().is_ok());
+        assert!(FirmwareFork::Other("Xtreme".to_string()).check_region_support().is_ok());
+        assert!(FirmwareFork::Momentum.check_region_support().unwrap_err().starts_with("Momentum firmware"));
+        assert!(FirmwareFork::Unleashed.check_region_support().is_err());
+    }
+}
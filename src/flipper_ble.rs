@@ -1,26 +1,47 @@
+use futures::future::{self, BoxFuture};
 use futures::StreamExt;
-use futures::FutureExt;
-use btleplug::api::{Central, Manager as _, Peripheral as _, WriteType, Characteristic};
+use btleplug::api::{BDAddr, Central, CentralEvent, Manager as _, Peripheral as _, WriteType, ScanFilter};
 use btleplug::platform::{Manager, Peripheral, Adapter};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
 use tokio::time;
 use tokio::time::Duration;
 use uuid::{uuid, Uuid};
-use indicatif::{ProgressBar, ProgressStyle};
 use chrono::TimeZone;
 
 use std::fs;
-use std::io::Write;
-use std::path::Path;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::error::Error;
 use std::convert::TryFrom;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 use crate::flipper_pb;
-use crate::protobuf_codec::ProtobufCodec;
+use crate::tree::TreeEntry;
+use crate::path_filter::{PathFilter, IGNORE_FILE};
+use crate::backup::TarWriter;
+use crate::rpc_reader::{RpcReader, Responses, UNSOLICITED_COMMAND_ID};
+use crate::session::Session;
+use crate::transport::Transport;
+use crate::flow_control::FlowControl;
+use crate::keyboard::{Keyboard, KeyPress};
+use crate::input_macro::Macro;
+use crate::screen::{Frame, Region};
+use crate::output;
+use crate::progress::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use crate::remote_path;
+use crate::peripheral_cache;
+use crate::fap;
 
 // Each function follows basically the same principle:
-// - Get a protobuf message from protobuf_codec
+// - Have the session build a protobuf message from protobuf_codec,
+//   subscribed to responses for its command ID
 // - Send its chunks to the Flipper's RX characteristic
 // - Wait for a response as necessary.
+// The session can be cloned, so more than one of these can be going
+// on at once over the same connection.
 
 // the uuid that we write to
 const FLIPPER_RX_CHR_UUID: Uuid = uuid!("19ed82ae-ed21-4c9d-4145-228e62fe0000");
@@ -32,13 +53,618 @@ const FLIPPER_FLOW_CTRL_CHR_UUID: Uuid = uuid!("19ed82ae-ed21-4c9d-4145-228e63fe
 // characteristic. 20 ms seems to work, probably because incomplete
 // pieces of a protobuf command sit in memory until they're complete,
 // so we're not waiting on storage or anything else until the command
-// is fully sent.
-const FLIPPER_BLE_PROTOBUF_CHUNK_DELAY: u64 = 20;
+// is fully sent. set_chunk_delay() changes it.
+pub const FLIPPER_BLE_PROTOBUF_CHUNK_DELAY: u64 = 20;
+// Delay between the individual events of a button press. The GUI
+// handles input on its own thread, and without a pause here it can
+// drop events when we send a lot of them in a row.
+const FLIPPER_INPUT_EVENT_DELAY: u64 = 30;
+// How long the desktop's lock menu takes to open, and the lock to
+// take effect, when locking by pressing buttons
+const LOCK_MENU_DELAY: Duration = Duration::from_millis(500);
+// The lock menu opens with "Lock" picked, which the firmware shows with
+// a frame around it. Seeing the frame's edges is how we know Up opened
+// the menu, rather than going to some app that OK would do something
+// in.
+const LOCK_MENU_SELECTION: Region = Region { x: 15, y: 1, width: 98, height: 15 };
+// Transfers bigger than this log the signal strength first, so that
+// one failing partway through can be put down to a bad link.
+const LARGE_TRANSFER: u64 = 64 * 1024;
+// How long a keep-alive ping can take before the connection is
+// considered dead
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+// On macOS we scan until the Flipper turns up, checking this often,
+// and give up after MACOS_SCAN_TIMEOUT. That's 4x the longest
+// advertising interval (see WINDOWS_SCAN_TIMEOUT).
+const MACOS_SCAN_POLL: Duration = Duration::from_millis(250);
+const MACOS_SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+// How long Windows scans for a Flipper it has paired, stopping as soon
+// as it shows up. GapStateAdvLowPower sets a maximum interval of 2.5
+// seconds, so we wait for 3x that. We used to have 5 seconds but no
+// basis for that.
+// See https://github.com/flipperdevices/flipperzero-firmware/blob/e6f078eeb758992aef0edaf94a23eac846ca8746/targets/f7/ble_glue/gap.c#L375
+const WINDOWS_SCAN_TIMEOUT: Duration = Duration::from_millis(3*2500);
+// How long to look for an unpaired Flipper to pair with
+const PAIR_SCAN_TIMEOUT: Duration = Duration::from_secs(20);
+
+// Internal storage is only a few hundred KB, shared with the
+// Flipper's settings and keys. Running it out of space can lose those,
+// so writes bigger than this need to be forced, and writes that would
+// leave less than INT_MIN_FREE free are refused outright.
+const INT_FORCE_THRESHOLD: u64 = 16 * 1024;
+const INT_MIN_FREE: u64 = 32 * 1024;
+
+// File extensions that `play` understands, with the directory the
+// matching app keeps its files in and the name of the built-in app
+// that opens them.
+const PLAY_FILE_TYPES: [(&str, &str, &str); 4] = [
+    ("sub", "/ext/subghz", "Sub-GHz"),
+    ("nfc", "/ext/nfc", "NFC"),
+    ("ir", "/ext/infrared", "Infrared"),
+    ("rfid", "/ext/lfrfid", "125 kHz RFID"),
+];
+
+/// Returns the root of the filesystem a Flipper path is on. Anything
+/// that isn't on internal storage lives on the SD card.
+fn storage_root(path: &str) -> &'static str {
+    if path == "/int" || path.starts_with("/int/") {
+        "/int"
+    } else {
+        "/ext"
+    }
+}
+
+/// Join a Flipper directory and a name inside it with exactly one
+/// slash between them.
+pub fn join_remote(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
 
 /// Representation of a Flipper device connected over Bluetooth LE
 pub struct FlipperBle {
     flipper: Peripheral,
-    proto: ProtobufCodec,
+    session: Session,
+    // what the session writes with
+    transport: Arc<BleTransport>,
+    // kept for reconnecting
+    name: String,
+    options: ConnectOptions,
+    // set while a multi-file operation is running
+    batch: Option<BatchProgress>,
+    on_progress: Option<ProgressCallback>,
+    // allow big writes to /int
+    force_int_writes: bool,
+    // check .faps against the firmware API before uploading them
+    fap_api_check: bool,
+    // (major, minor), once asked for
+    firmware_api: Option<(u16, u16)>,
+    // bytes per second to keep reads under, on average
+    download_rate: Option<u64>,
+    // when the next read can start without going over download_rate
+    next_read: Option<time::Instant>,
+    // ATT MTU, if the OS says
+    mtu: Option<u16>,
+    events: broadcast::Sender<ConnectionEvent>,
+    // sends Disconnected when the OS says the link dropped
+    link_watcher: JoinHandle<()>,
+    // for stats()
+    connected_at: time::Instant,
+    bytes_received: Arc<AtomicU64>,
+    connect_retries: u32,
+    reconnects: u32,
+    last_rssi: Mutex<Option<i16>>,
+}
+
+/// What's happened on the connection so far, from
+/// FlipperBle::stats()
+#[derive(Debug, Clone)]
+pub struct ConnectionStats {
+    /// How long ago the connection was made
+    pub uptime: Duration,
+    /// Messages sent to the Flipper, counting each piece of a file
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Connection attempts that failed and were tried again
+    pub connect_retries: u32,
+    /// Times the connection was lost and made again
+    pub reconnects: u32,
+    /// Signal strength in dBm, the last time the OS said
+    pub rssi: Option<i16>,
+}
+
+/// A change in the link to the Flipper, from
+/// FlipperBle::subscribe_events()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// Connected again after reconnecting
+    Connected,
+    /// The OS says the link dropped, or reconnecting failed
+    Disconnected,
+    /// Trying to connect again after losing the connection
+    Reconnecting,
+    /// The ATT MTU is different after reconnecting (only Linux says
+    /// what it is)
+    MtuChanged(u16),
+}
+
+// events nobody has received yet, per subscriber; link changes are rare
+const CONNECTION_EVENT_BACKLOG: usize = 16;
+
+/// Called with the bytes done so far and the total bytes as a file
+/// transfer goes.
+pub type ProgressCallback = Box<dyn FnMut(u64, u64) + Send>;
+
+/// Progress of an operation on many files. Each file's own bar goes
+/// under the overall one.
+struct BatchProgress {
+    multi: MultiProgress,
+    overall: ProgressBar,
+}
+
+/// Writes requests to the Flipper's serial service with btleplug
+struct BleTransport {
+    flipper: Peripheral,
+    settings: Mutex<WriteSettings>,
+}
+
+/// How requests are written
+#[derive(Debug, Clone, Copy)]
+struct WriteSettings {
+    write_type: WriteType,
+    // pause after each piece when writing without response
+    chunk_delay: Duration,
+}
+
+impl Transport for BleTransport {
+    fn write<'a>(&'a self, piece: &'a [u8]) -> BoxFuture<'a, Result<(), Box<dyn Error>>> {
+        Box::pin(async move {
+            let settings = *self.settings.lock().unwrap();
+            let rx_chr = self.flipper.characteristics()
+                .into_iter()
+                .find(|c| c.uuid == FLIPPER_RX_CHR_UUID)
+                .ok_or("Flipper doesn't have the serial service")?;
+            self.flipper.write(&rx_chr, piece, settings.write_type).await?;
+            // writes with response already wait for the Flipper, and
+            // without, a short pause lets it keep up
+            if matches!(settings.write_type, WriteType::WithoutResponse) {
+                time::sleep(settings.chunk_delay).await;
+            }
+            Ok(())
+        })
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(time::sleep(duration))
+    }
+}
+
+/// Progress bars and logging for each file of a multi-file read,
+/// where files go by faster than a request for each would allow, see
+/// read_files()
+struct FileProgress {
+    // None for hidden progress bars, when there's a progress callback
+    multi: Option<MultiProgress>,
+    overall: Option<ProgressBar>,
+    count: usize,
+    // how many files are done, which is also the index of the one
+    // coming in
+    done: usize,
+    // the progress bar of the file coming in, and when it started
+    current: Option<(ProgressBar, time::Instant)>,
+}
+
+impl FileProgress {
+    fn new(flipper: &FlipperBle, count: usize) -> FileProgress {
+        let multi = match (&flipper.batch, &flipper.on_progress) {
+            (Some(batch), None) => Some(batch.multi.clone()),
+            _ => None,
+        };
+        FileProgress {
+            multi,
+            overall: flipper.batch_overall(),
+            count,
+            done: 0,
+            current: None,
+        }
+    }
+
+    fn current_bar(&mut self, size: u64) -> &ProgressBar {
+        let (multi, overall, count, done) = (&self.multi, &self.overall, self.count, self.done);
+        let (pb, _) = self.current.get_or_insert_with(|| {
+            if let Some(o) = overall {
+                o.set_message(format!("{}/{} files", done + 1, count));
+            }
+            let pb = match multi {
+                Some(multi) => file_progress_bar(Some(multi), size),
+                None => ProgressBar::hidden(),
+            };
+            (pb, time::Instant::now())
+        });
+        pb
+    }
+
+    /// Count `bytes` more of file `i`, which is `size` bytes. Returns
+    /// how much of it has come in.
+    fn inc(&mut self, i: usize, size: u64, bytes: u64) -> u64 {
+        debug_assert_eq!(i, self.done);
+        let pb = self.current_bar(size).clone();
+        pb.inc(bytes);
+        if let Some(o) = &self.overall {
+            o.inc(bytes);
+        }
+        pb.position()
+    }
+
+    /// Finish file `i`, which is `size` bytes, and log it as done.
+    fn finish(&mut self, i: usize, size: u64, operation: &str, path: &str) {
+        debug_assert_eq!(i, self.done);
+        let pb = self.current_bar(size).clone();
+        pb.finish_and_clear();
+        if let Some((_, started)) = self.current.take() {
+            log_operation(operation, path, pb.position(), started, &Ok(()));
+        }
+        self.done += 1;
+    }
+
+    /// If `result` is an error, log the file it happened on as failed
+    /// and return its index in `paths`.
+    fn failed(&mut self, operation: &str, paths: &[&str], result: &Result<(), Box<dyn Error>>) -> Option<usize> {
+        if result.is_ok() || self.done >= self.count {
+            return None;
+        }
+        let (bytes, started) = match self.current.take() {
+            Some((pb, started)) => {
+                pb.abandon();
+                (pb.position(), started)
+            },
+            None => (0, time::Instant::now()),
+        };
+        log_operation(operation, paths[self.done], bytes, started, result);
+        Some(self.done)
+    }
+}
+
+/// The Flipper refused a command because its RPC session is busy,
+/// usually because qFlipper or the mobile app is using it.
+#[derive(Debug)]
+pub struct RpcBusy;
+
+impl std::fmt::Display for RpcBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the Flipper is busy with another RPC session (is qFlipper or the \
+                   Flipper mobile app connected?). Close it, or use --wait to wait for it")
+    }
+}
+
+impl Error for RpcBusy {}
+
+/// The Flipper answered a command with an error status.
+#[derive(Debug)]
+pub struct RpcStatus {
+    pub status: protobuf::EnumOrUnknown<flipper_pb::flipper::CommandStatus>,
+    message: String,
+}
+
+impl RpcStatus {
+    fn new(status: protobuf::EnumOrUnknown<flipper_pb::flipper::CommandStatus>, message: impl Into<String>) -> RpcStatus {
+        RpcStatus { status, message: message.into() }
+    }
+}
+
+impl std::fmt::Display for RpcStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for RpcStatus {}
+
+/// Returns a short code for what kind of error `e` is, for logs:
+/// the Flipper's status (like "ERROR_STORAGE_NOT_EXIST") when it
+/// sent one, the kind of I/O error for local files, or "ERROR".
+pub fn error_code(e: &(dyn Error + 'static)) -> String {
+    if e.is::<RpcBusy>() {
+        format!("{:?}", flipper_pb::flipper::CommandStatus::ERROR_BUSY)
+    } else if let Some(s) = e.downcast_ref::<RpcStatus>() {
+        match s.status.enum_value() {
+            Ok(status) => format!("{:?}", status),
+            Err(n) => format!("ERROR_{}", n),
+        }
+    } else if let Some(io) = e.downcast_ref::<std::io::Error>() {
+        format!("{:?}", io.kind())
+    } else {
+        "ERROR".to_string()
+    }
+}
+
+/// Log how an upload, download, or delete went, with fields for
+/// --log-format json.
+///
+/// # Arguments
+///
+/// * `operation`: What was done, like "upload"
+/// * `path`: Flipper path it was done to
+/// * `bytes`: How much data went over Bluetooth
+/// * `started`: When it started
+/// * `result`: How it went
+fn log_operation(operation: &str, path: &str, bytes: u64, started: time::Instant, result: &Result<(), Box<dyn Error>>) {
+    let duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    match result {
+        Ok(()) => info!(target: "flipwire::ops", operation, path, bytes, duration_ms,
+                        "{} {}: {} bytes in {} ms", operation, path, bytes, duration_ms),
+        Err(e) => warn!(target: "flipwire::ops", operation, path, bytes, duration_ms,
+                        error = %e, error_code = error_code(e.as_ref()),
+                        "{} {} failed: {}", operation, path, e),
+    }
+}
+
+/// Returns a rough description of how good a signal strength (in
+/// dBm) is for BLE.
+pub fn signal_quality(rssi: f64) -> &'static str {
+    if rssi >= -60.0 {
+        "good"
+    } else if rssi >= -75.0 {
+        "fair"
+    } else {
+        "poor, expect slow or failed transfers"
+    }
+}
+
+/// Returns RpcBusy if that's what the Flipper answered with.
+fn check_busy(m: &flipper_pb::flipper::Main) -> Result<(), Box<dyn Error>> {
+    if m.command_status == flipper_pb::flipper::CommandStatus::ERROR_BUSY.into() {
+        debug!("Flipper is busy: {:?}", m);
+        return Err(Box::new(RpcBusy));
+    }
+    Ok(())
+}
+
+/// Returns true if `frame` is the desktop's lock menu, going by the
+/// top and left edges of the frame around the picked item.
+fn is_lock_menu(frame: &Frame) -> bool {
+    let r = LOCK_MENU_SELECTION;
+    // the corners are rounded, so leave them out
+    (r.x + 2..r.x + r.width - 2).all(|x| frame.pixel(x, r.y))
+        && (r.y + 2..r.y + r.height - 2).all(|y| frame.pixel(r.x, y))
+}
+
+/// Returns a progress bar for one file, in `multi` if it's part of a
+/// multi-file operation.
+fn file_progress_bar(multi: Option<&MultiProgress>, bytes_length: u64) -> ProgressBar {
+    let pb = match multi {
+        Some(multi) => multi.add(ProgressBar::new(bytes_length)),
+        None => ProgressBar::new(bytes_length),
+    };
+    pb.set_style(ProgressStyle::with_template(
+        "[{wide_bar:.cyan/blue}] {bytes}/{total_bytes} {binary_bytes_per_sec} {elapsed} (ETA {eta})")
+                 .unwrap()
+                 .progress_chars("#>-"));
+    // Transfers stall for most of a second at a time (like when
+    // upload_file waits on flow control), so redraw on a timer instead
+    // of only when the position changes. Otherwise the rate and ETA
+    // freeze and then jump.
+    pb.enable_steady_tick(Duration::from_millis(250));
+
+    pb
+}
+
+/// Settings for finding and connecting to a Flipper. Start from
+/// `ConnectOptions::default()` and change the fields you need:
+///
+/// ```ignore
+/// let options = ConnectOptions {
+///     retries: 2,
+///     ..Default::default()
+/// };
+/// let flipper = FlipperBle::connect_with("Uwu2", &options).await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    /// How long to scan for devices before looking for the
+    /// Flipper. None means the platform default: Linux can find paired
+    /// devices without scanning, Windows scans until a Flipper it has
+    /// paired shows up (or for 7.5 seconds if it hasn't paired one),
+    /// and macOS scans until the Flipper shows up (for up to 10
+    /// seconds).
+    pub scan_duration: Option<Duration>,
+    /// Use the first Bluetooth adapter whose info contains this
+    /// (like "hci1" on Linux) instead of the first adapter.
+    pub adapter: Option<String>,
+    /// How many more times to try connecting if an attempt fails
+    pub retries: u32,
+    /// How long a single connection attempt can take
+    pub timeout: Duration,
+    /// Ask for the shortest connection interval, for faster
+    /// transfers. Only Linux can, and only as root.
+    pub fast_connection: bool,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions {
+            scan_duration: None,
+            adapter: None,
+            retries: 0,
+            timeout: Duration::from_secs(30),
+            fast_connection: false,
+        }
+    }
+}
+
+impl ConnectOptions {
+    fn scan_duration(&self) -> Option<Duration> {
+        if self.scan_duration.is_some() || !cfg!(target_os = "windows") {
+            return self.scan_duration;
+        }
+        Some(WINDOWS_SCAN_TIMEOUT)
+    }
+}
+
+/// What to ask for when listing a directory
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ListOptions {
+    /// Have the Flipper include each file's MD5 (older firmware
+    /// leaves them empty)
+    pub include_md5: bool,
+    /// Leave out files bigger than this many bytes. The Flipper skips
+    /// them itself, so they're never sent.
+    pub max_size: Option<u32>,
+}
+
+/// Print one line of `ls` for a directory entry.
+fn print_entry(f: &flipper_pb::storage::File) {
+    // dirs don't have size
+    if f.type_ == flipper_pb::storage::file::FileType::DIR.into() {
+        println!(" dir:  {}", output::dir_name(&format!("{:?}", f.name)));
+    } else {
+        println!(" file: {:?}, size: {:?}", f.name, f.size);
+    }
+}
+
+/// Everything below a local directory, relative to it
+struct LocalTree {
+    /// Parents come before their contents
+    dirs: Vec<PathBuf>,
+    /// With the size of each file
+    files: Vec<(PathBuf, u64)>,
+}
+
+/// Returns every directory and file below `dir`, not including `dir`
+/// itself, leaving out what `filter` excludes.
+fn local_tree(dir: &Path, filter: &PathFilter) -> Result<LocalTree, Box<dyn Error>> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(rel) = pending.pop() {
+        let mut entries = fs::read_dir(dir.join(&rel))?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let child = rel.join(entry.file_name());
+            let metadata = entry.metadata()?;
+            if filter.is_excluded(&slash_path(&child), metadata.is_dir()) {
+                debug!("skipping {:?}", child);
+                continue;
+            }
+            if metadata.is_dir() {
+                dirs.push(child.clone());
+                pending.push(child);
+            } else {
+                files.push((child, metadata.len()));
+            }
+        }
+    }
+
+    Ok(LocalTree {
+        dirs,
+        files,
+    })
+}
+
+/// Returns `exclude` plus the patterns in `dir`'s .flipperignore, and
+/// the .flipperignore itself.
+fn upload_filter(dir: &Path, exclude: &PathFilter) -> Result<PathFilter, Box<dyn Error>> {
+    let mut filter = exclude.clone();
+    if filter.add_ignore_file(&dir.join(IGNORE_FILE))? {
+        filter.exclude(&format!("/{}", IGNORE_FILE))?;
+    }
+    Ok(filter)
+}
+
+/// Returns a local relative path with "/" between its components,
+/// the way Flipper paths are.
+fn slash_path(rel: &Path) -> String {
+    let rel: Vec<String> = rel.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    rel.join("/")
+}
+
+/// One file a recursive transfer copies
+#[derive(Debug, Clone)]
+pub struct PlannedFile {
+    pub local: PathBuf,
+    pub remote: String,
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// What a recursive upload or download is going to do, worked out
+/// before anything is copied
+#[derive(Debug, Clone, Default)]
+pub struct TransferPlan {
+    /// Flipper directories an upload creates, parents first
+    pub remote_dirs: Vec<String>,
+    /// Local directories a download creates, parents first
+    pub local_dirs: Vec<PathBuf>,
+    pub files: Vec<PlannedFile>,
+    /// What a mirroring upload deletes from the Flipper first, since
+    /// the local directory doesn't have it. Directories go with
+    /// everything in them.
+    pub remote_deletions: Vec<(String, flipper_pb::storage::File)>,
+    /// What a mirroring download deletes locally first, since the
+    /// Flipper doesn't have it
+    pub local_deletions: Vec<(PathBuf, fs::Metadata)>,
+}
+
+impl TransferPlan {
+    /// Returns the size of all the files together.
+    pub fn total_bytes(&self) -> u64 {
+        self.files.iter().map(|f| f.size).sum()
+    }
+
+    /// Returns what the plan does in a few words, like "upload 3 files
+    /// (1.2 KiB) and delete 1 files and directories".
+    pub fn summary(&self, verb: &str) -> String {
+        let mut summary = format!("{} {} files ({})", verb, self.files.len(), HumanBytes(self.total_bytes()));
+        let deletions = self.remote_deletions.len() + self.local_deletions.len();
+        if deletions > 0 {
+            summary.push_str(&format!(" and delete {} files and directories", deletions));
+        }
+        summary
+    }
+
+    /// Print what the transfer would do, for --dry-run: what it would
+    /// delete, the directories it would create, then each file with
+    /// its size.
+    pub fn print(&self, upload: bool) {
+        for (p, f) in &self.remote_deletions {
+            let size = if f.type_ == flipper_pb::storage::file::FileType::DIR.into() {
+                "dir".to_string()
+            } else {
+                HumanBytes(u64::from(f.size)).to_string()
+            };
+            println!("{:>10}  delete {}", size, p);
+        }
+        for (p, metadata) in &self.local_deletions {
+            let size = if metadata.is_dir() {
+                "dir".to_string()
+            } else {
+                HumanBytes(metadata.len()).to_string()
+            };
+            println!("{:>10}  delete {}", size, p.display());
+        }
+        for d in &self.remote_dirs {
+            println!("{:>10}  {}", "dir", d);
+        }
+        for d in &self.local_dirs {
+            println!("{:>10}  {}", "dir", d.display());
+        }
+        for f in &self.files {
+            let (from, to) = if upload {
+                (f.local.display().to_string(), f.remote.clone())
+            } else {
+                (f.remote.clone(), f.local.display().to_string())
+            };
+            println!("{:>10}  {} -> {}", HumanBytes(f.size).to_string(), from, to);
+        }
+    }
+}
+
+/// Turn a local relative path into a Flipper path under `dest`.
+fn remote_child(dest: &str, rel: &Path) -> String {
+    join_remote(dest, &slash_path(rel))
 }
 
 // TODO: Flipper returns ERROR_DECODE when it gets a malformed
@@ -59,9 +685,7 @@ fn format_u8_slice(bs: &[u8]) -> String {
  */
 
 impl FlipperBle {
-    #[cfg(target_os = "windows")]
-    async fn flipper_scan(central: &Adapter) -> Result<(), Box<dyn Error>> {
-        use btleplug::api::ScanFilter;
+    async fn flipper_scan(central: &Adapter, duration: Duration) -> Result<(), Box<dyn Error>> {
         // Flipper doesn't advertise the serial service, so we just
         // scan. I've tested 5 seconds on several Intel cards
         // (including the broken ones) and it seems to work fine.
@@ -69,34 +693,138 @@ impl FlipperBle {
         // The event stream probably isn't useful because it only
         // shows MAC address, and if we don't know it already, that's
         // not useful.
-
-        // GapStateAdvLowPower sets a maximum interval of 2.5 seconds,
-        // so we wait for 3x that. We used to have 5 seconds but no
-        // basis for that.
-        // See https://github.com/flipperdevices/flipperzero-firmware/blob/e6f078eeb758992aef0edaf94a23eac846ca8746/targets/f7/ble_glue/gap.c#L375
-        time::sleep(Duration::from_millis(3*2500)).await;
+        debug!("scanning for {:?}", duration);
+        time::sleep(duration).await;
         // stop scanning to connect
         central.stop_scan().await?;
         Ok(())
     }
+
+    /// Returns the adapter to use: the first one, or the first one
+    /// matching `name` if it's given.
+    async fn find_adapter(manager: &Manager, name: Option<&str>) -> Result<Adapter, Box<dyn Error>> {
+        let adapters = match manager.adapters().await {
+            Ok(adapters) => adapters,
+            Err(e) => {
+                return Err(format!("error finding Bluetooth adapters: {:?}", e).into());
+            },
+        };
+        if adapters.is_empty() {
+            return Err("no Bluetooth adapters found".into());
+        }
+
+        let name = match name {
+            Some(n) => n,
+            None => return Ok(adapters.into_iter().nth(0).unwrap()),
+        };
+        for adapter in adapters {
+            if adapter.adapter_info().await?.contains(name) {
+                return Ok(adapter);
+            }
+        }
+        Err(format!("no Bluetooth adapter matching {:?} found", name).into())
+    }
     
-    async fn find_device_named(flipper_name: &str, central: &Adapter) -> Option<Peripheral> {
-        for p in central.peripherals().await.unwrap() {
-            if p.properties()
-                .await
-                .unwrap()
-                .unwrap()
-                .local_name
-                .iter()
-                .any(|name| name.contains(flipper_name)) {
-                    info!("found Flipper {}", flipper_name);
-                    debug!("peripheral details: {:?}", p);
-                    return Some(p);
-                }
+    /// Returns the peripheral whose name contains `flipper_name`, or
+    /// whose identifier is `known_id`.
+    async fn find_device_named(flipper_name: &str, known_id: Option<&str>, central: &Adapter) -> Option<Peripheral> {
+        let peripherals = match central.peripherals().await {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("couldn't list Bluetooth devices: {}", e);
+                return None;
+            },
+        };
+        for p in peripherals {
+            let id_matches = known_id.is_some_and(|id| p.id().to_string() == id);
+            // CoreBluetooth can see a device before it knows its name
+            let name_matches = match p.properties().await {
+                Ok(Some(props)) => props.local_name.iter().any(|name| name.contains(flipper_name)),
+                _ => false,
+            };
+            if id_matches || name_matches {
+                info!("found Flipper {}", flipper_name);
+                debug!("peripheral details: {:?}", p);
+                return Some(p);
+            }
         }
         None
     }
+
+    /// Returns the address Windows has for a paired device whose name
+    /// contains `flipper_name`, or None if it hasn't paired one. Always
+    /// None on other platforms.
+    async fn paired_address(flipper_name: &str) -> Option<BDAddr> {
+        #[cfg(target_os = "windows")]
+        {
+            let name = flipper_name.to_string();
+            match tokio::task::spawn_blocking(move || crate::winrt::find_paired(&name)).await {
+                Ok(Ok(address)) => return address,
+                Ok(Err(e)) => warn!("couldn't list paired Bluetooth devices: {}", e),
+                Err(e) => warn!("couldn't list paired Bluetooth devices: {}", e),
+            }
+            None
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            let _ = flipper_name;
+            None
+        }
+    }
+
+    /// Scan until the Flipper shows up or `max` runs out, instead of
+    /// for a fixed time. This is how macOS connects: CoreBluetooth
+    /// only knows about devices it's seen in a scan, even paired ones.
+    /// Windows does too when it has paired the Flipper.
+    async fn scan_until_found(flipper_name: &str, known_id: Option<&str>, central: &Adapter, max: Duration) -> Result<Option<Peripheral>, Box<dyn Error>> {
+        central.start_scan(ScanFilter::default()).await?;
+        debug!("scanning for up to {:?}", max);
+        let deadline = time::Instant::now() + max;
+        let found = loop {
+            if let Some(p) = Self::find_device_named(flipper_name, known_id, central).await {
+                break Some(p);
+            }
+            if time::Instant::now() >= deadline {
+                break None;
+            }
+            time::sleep(MACOS_SCAN_POLL).await;
+        };
+        central.stop_scan().await?;
+        Ok(found)
+    }
     
+    /// Find a Flipper that isn't paired yet and pair with it, asking
+    /// for the PIN it shows on the terminal. Only Linux can, through
+    /// BlueZ; elsewhere, pair in the system's Bluetooth settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `flipper_name`: Search pattern (usually a Flipper name) to
+    ///                   find in the list of discovered devices
+    /// * `options`: Scan settings, the longest to look for it
+    pub async fn pair(flipper_name: &str, options: &ConnectOptions) -> Result<(), Box<dyn Error>> {
+        if !cfg!(target_os = "linux") {
+            return Err("flipwire can only pair on Linux, pair in your system's Bluetooth settings instead".into());
+        }
+        let manager = Manager::new().await?;
+        let central = Self::find_adapter(&manager, options.adapter.as_deref()).await?;
+        let adapter_info = central.adapter_info().await?;
+
+        info!("looking for Flipper {} (open Settings > Bluetooth on it if it doesn't show up)", flipper_name);
+        let max = options.scan_duration.unwrap_or(PAIR_SCAN_TIMEOUT);
+        let flip = Self::scan_until_found(flipper_name, None, &central, max).await?
+            .ok_or_else(|| format!("no device with name {:?} found", flipper_name))?;
+        if let Err(e) = peripheral_cache::remember(flipper_name, &flip.id().to_string()) {
+            debug!("couldn't save peripheral cache: {}", e);
+        }
+
+        #[cfg(target_os = "linux")]
+        crate::bluez::pair(&adapter_info, flip.address()).await?;
+        #[cfg(not(target_os = "linux"))]
+        let _ = (adapter_info, flip);
+        Ok(())
+    }
+
     /// Returns a new FlipperBle with the discovered device connected,
     /// or an error if no device was found or other error
     /// occurred. The Flipper must already be known to the system
@@ -106,22 +834,14 @@ impl FlipperBle {
     ///
     /// * `flipper_name`: Search pattern (usually a Flipper name) to
     ///                   find in the list of discovered devices
-    pub async fn connect_paired_device(flipper_name: &str) -> Result<FlipperBle, Box<dyn Error>> {
+    /// * `options`: Scan and connection settings
+    pub async fn connect_with(flipper_name: &str, options: &ConnectOptions) -> Result<FlipperBle, Box<dyn Error>> {
         let manager = Manager::new().await?;
-        let central = match manager.adapters().await {
-            Ok(adapters) => {
-                if adapters.is_empty() {
-                    return Err("no Bluetooth adapters found".into());
-                }
-                adapters.into_iter().nth(0).unwrap()
-            },
-            Err(e) => {
-                return Err(format!("error finding Bluetooth adapters: {:?}", e).into());
-            },
-        };
+        let central = Self::find_adapter(&manager, options.adapter.as_deref()).await?;
 
         debug!("using adapter {:?}", central);
-        debug!("adapter info: {:?}", central.adapter_info().await?);
+        let adapter_info = central.adapter_info().await?;
+        debug!("adapter info: {:?}", adapter_info);
 
         // Linux remembers devices that have been paired and can try
         // to connect to them without scanning. Windows needs to scan,
@@ -131,159 +851,612 @@ impl FlipperBle {
 
         // We also can't use the nice async scan notification stream,
         // because it doesn't say anything about device names.
-        #[cfg(target_os = "windows")]
-        FlipperBle::flipper_scan(&central).await?;
+        //
+        // macOS has to scan too, but hides MAC addresses, so we keep
+        // the identifier CoreBluetooth gave the Flipper last time and
+        // stop scanning as soon as it turns up.
+        //
+        // Windows keeps a list of paired devices, but btleplug can
+        // only connect to ones it's seen in a scan. If the Flipper is
+        // on that list we know its address, so we can stop scanning as
+        // soon as it turns up too.
+        let known_id = peripheral_cache::lookup(flipper_name);
+        let paired = if options.scan_duration.is_none() {
+            Self::paired_address(flipper_name).await
+        } else {
+            None
+        };
+        let found = if cfg!(target_os = "macos") && options.scan_duration.is_none() {
+            Self::scan_until_found(flipper_name, known_id.as_deref(), &central, MACOS_SCAN_TIMEOUT).await?
+        } else if let Some(address) = paired {
+            debug!("Windows has paired {:?} at {}", flipper_name, address);
+            Self::scan_until_found(flipper_name, Some(&address.to_string()), &central, WINDOWS_SCAN_TIMEOUT).await?
+        } else {
+            if let Some(duration) = options.scan_duration() {
+                FlipperBle::flipper_scan(&central, duration).await?;
+            }
+            Self::find_device_named(flipper_name, known_id.as_deref(), &central).await
+        };
 
         let flip =
-            if let Some(d) = Self::find_device_named(flipper_name, &central).await {
+            if let Some(d) = found {
                 d
             } else {
                 return Err(format!("no device with name {:?} found", flipper_name).into());
             };
+        if let Err(e) = peripheral_cache::remember(flipper_name, &flip.id().to_string()) {
+            debug!("couldn't save peripheral cache: {}", e);
+        }
 
+        let mut connect_retries = 0;
         if !flip.is_connected().await? {
-            flip.connect().await?;
+            // only lasts until we're connected
+            let _interval = if options.fast_connection {
+                Self::request_fast_interval(&adapter_info)
+            } else {
+                None
+            };
+            connect_retries = Self::connect_peripheral(&flip, options).await?;
             info!("connected to Flipper {}", flipper_name);
         } else {
+            if options.fast_connection {
+                warn!("already connected, so the connection interval can't change (disconnect first)");
+            }
             info!("already connected to Flipper {}", flipper_name);
         }
 
-        flip.discover_services().await?;
+        let bytes_received = Arc::new(AtomicU64::new(0));
+        let reader = Self::start_reader(&flip, options, bytes_received.clone()).await?;
+        let mtu = Self::log_link_info(&flip).await;
+        let transport = Arc::new(BleTransport {
+            flipper: flip.clone(),
+            settings: Mutex::new(WriteSettings {
+                write_type: WriteType::WithoutResponse,
+                chunk_delay: Duration::from_millis(FLIPPER_BLE_PROTOBUF_CHUNK_DELAY),
+            }),
+        });
+        let session = Session::new(transport.clone(), reader);
+        session.set_flow_control(Self::start_flow_control(&flip).await?);
+        let (events, _) = broadcast::channel(CONNECTION_EVENT_BACKLOG);
+        let link_watcher = Self::watch_link(&central, &flip, events.clone()).await?;
+
         Ok(FlipperBle {
-            proto: ProtobufCodec::new(),
+            session,
+            transport,
+            name: flipper_name.to_string(),
+            options: options.clone(),
             flipper: flip,
+            batch: None,
+            on_progress: None,
+            force_int_writes: false,
+            fap_api_check: true,
+            firmware_api: None,
+            download_rate: None,
+            next_read: None,
+            mtu,
+            events,
+            link_watcher,
+            connected_at: time::Instant::now(),
+            bytes_received,
+            connect_retries,
+            reconnects: 0,
+            last_rssi: Mutex::new(None),
         })
     }
 
-    pub async fn disconnect(&self) -> Result<(), Box<dyn Error>> {
-        self.flipper.disconnect().await?;
-        Ok(())
+    /// Send Disconnected to `events` whenever the OS says `flip`
+    /// disconnected, on a new task, so nobody has to find out from a
+    /// request failing.
+    async fn watch_link(central: &Adapter, flip: &Peripheral, events: broadcast::Sender<ConnectionEvent>) -> Result<JoinHandle<()>, Box<dyn Error>> {
+        let mut central_events = central.events().await?;
+        let id = flip.id();
+        Ok(tokio::spawn(async move {
+            while let Some(event) = central_events.next().await {
+                if matches!(event, CentralEvent::DeviceDisconnected(ref d) if *d == id) {
+                    debug!("the OS says the Flipper disconnected");
+                    // nobody listening is fine
+                    let _ = events.send(ConnectionEvent::Disconnected);
+                }
+            }
+        }))
     }
 
-    fn get_rx_chr(&self) -> Characteristic {
-        let chars = self.flipper.characteristics();
-        let rx_chr = chars
-            .iter()
-            .find(|c| c.uuid == FLIPPER_RX_CHR_UUID)
-            .unwrap();
+    /// Connect to `flip`, retrying as many times as `options` says.
+    /// Returns how many retries it took.
+    async fn connect_peripheral(flip: &Peripheral, options: &ConnectOptions) -> Result<u32, Box<dyn Error>> {
+        let mut attempt = 0;
+        loop {
+            let error = match time::timeout(options.timeout, flip.connect()).await {
+                Ok(Ok(())) => return Ok(attempt),
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => format!("timed out after {:?}", options.timeout),
+            };
+            if attempt >= options.retries {
+                return Err(error.into());
+            }
+            attempt += 1;
+            warn!("failed to connect ({}), retrying ({}/{})", error, attempt, options.retries);
+        }
+    }
 
-        rx_chr.clone()
+    /// Ask for a short connection interval on the next connection, if
+    /// this platform can. Returns what puts the default back.
+    #[cfg(target_os = "linux")]
+    fn request_fast_interval(adapter_info: &str) -> Option<crate::bluez::IntervalOverride> {
+        match crate::bluez::request_fast_interval(adapter_info) {
+            Ok(o) => Some(o),
+            Err(e) => {
+                warn!("couldn't ask for a faster connection: {}", e);
+                None
+            },
+        }
     }
 
-    fn get_tx_chr(&self) -> Characteristic {
-        let chars = self.flipper.characteristics();
-        let tx_chr = chars
-            .iter()
-            .find(|c| c.uuid == FLIPPER_TX_CHR_UUID)
-            .unwrap();
+    #[cfg(not(target_os = "linux"))]
+    fn request_fast_interval(_adapter_info: &str) -> Option<()> {
+        // CoreBluetooth and WinRT pick the parameters themselves
+        warn!("--fast-connection only works on Linux");
+        None
+    }
+
+    /// Log what's known about the connection, for working out why
+    /// transfers are slow, and return the ATT MTU if it's known. BlueZ
+    /// only tells us the ATT MTU, which caps --tu-size; the interval
+    /// and PHY aren't available anywhere.
+    async fn log_link_info(flip: &Peripheral) -> Option<u16> {
+        #[cfg(target_os = "linux")]
+        match crate::bluez::att_mtu(flip.address(), FLIPPER_RX_CHR_UUID).await {
+            Ok(Some(mtu)) => {
+                debug!("ATT MTU is {} bytes, so at most {} per write", mtu, mtu.saturating_sub(3));
+                Some(mtu)
+            },
+            Ok(None) => {
+                debug!("BlueZ didn't say what the ATT MTU is");
+                None
+            },
+            Err(e) => {
+                debug!("couldn't get the ATT MTU from BlueZ: {}", e);
+                None
+            },
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = flip;
+            debug!("connection parameters aren't available on this platform");
+            None
+        }
+    }
 
-        tx_chr.clone()
+    /// Find the serial service on a newly connected Flipper and start
+    /// reading everything it sends, counting the bytes in
+    /// `bytes_received`.
+    async fn start_reader(flip: &Peripheral, options: &ConnectOptions, bytes_received: Arc<AtomicU64>) -> Result<RpcReader, Box<dyn Error>> {
+        time::timeout(options.timeout, flip.discover_services()).await
+            .map_err(|_| "timed out discovering services")??;
+
+        // Everything the Flipper sends comes in on the TX
+        // characteristic, and the reader takes care of it from here
+        // on out.
+        let tx_chr = flip.characteristics()
+            .into_iter()
+            .find(|c| c.uuid == FLIPPER_TX_CHR_UUID)
+            .ok_or("Flipper doesn't have the serial service")?;
+        flip.subscribe(&tx_chr).await?;
+        let notifications = flip.notifications().await?
+            .filter_map(|n| future::ready((n.uuid == FLIPPER_TX_CHR_UUID).then_some(n.value)))
+            .inspect(move |data| {
+                bytes_received.fetch_add(data.len() as u64, Ordering::Relaxed);
+            });
+
+        Ok(RpcReader::spawn(notifications))
     }
 
-    fn get_flow_chr(&self) -> Characteristic {
-        let chars = self.flipper.characteristics();
-        let flow_chr = chars
-            .iter()
-            .find(|c| c.uuid == FLIPPER_FLOW_CTRL_CHR_UUID)
-            .unwrap();
+    /// Start following the Flipper's buffer space on the flow control
+    /// characteristic. Returns None if it doesn't have one.
+    async fn start_flow_control(flip: &Peripheral) -> Result<Option<FlowControl>, Box<dyn Error>> {
+        let flow_chr = match flip.characteristics().into_iter().find(|c| c.uuid == FLIPPER_FLOW_CTRL_CHR_UUID) {
+            Some(c) => c,
+            None => {
+                warn!("Flipper doesn't have a flow control characteristic, writes may overrun its buffer");
+                return Ok(None);
+            },
+        };
+        flip.subscribe(&flow_chr).await?;
+        let notifications = flip.notifications().await?
+            .filter_map(|n| future::ready((n.uuid == FLIPPER_FLOW_CTRL_CHR_UUID).then_some(n.value)));
+        // without this, the first message is sent blind
+        let initial = match flip.read(&flow_chr).await {
+            Ok(value) => Some(value),
+            Err(e) => {
+                debug!("couldn't read flow control characteristic: {}", e);
+                None
+            },
+        };
 
-        flow_chr.clone()
+        Ok(Some(FlowControl::spawn(initial, notifications)))
     }
-    
-    fn make_file_progress_bar(&self, bytes_length: u64) -> ProgressBar {
-        let pb = ProgressBar::new(bytes_length);
-        pb.set_style(ProgressStyle::with_template(
-            "[{wide_bar:.cyan/blue}] {bytes}/{total_bytes} {elapsed}")
-                     .unwrap()
-                     .progress_chars("#>-"));
 
-        pb
+    /// Connect to the same Flipper again after losing the
+    /// connection. Settings like --reliable carry over.
+    pub async fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        let _ = self.events.send(ConnectionEvent::Reconnecting);
+        match self.restart_link().await {
+            Ok(retries) => self.connect_retries += retries,
+            Err(e) => {
+                let _ = self.events.send(ConnectionEvent::Disconnected);
+                return Err(e);
+            },
+        }
+        self.reconnects += 1;
+        info!("reconnected to Flipper {}", self.name);
+        let _ = self.events.send(ConnectionEvent::Connected);
+
+        if let Some(mtu) = Self::log_link_info(&self.flipper).await {
+            if self.mtu != Some(mtu) {
+                self.mtu = Some(mtu);
+                let _ = self.events.send(ConnectionEvent::MtuChanged(mtu));
+            }
+        }
+        Ok(())
     }
 
+    // returns how many connection retries it took
+    async fn restart_link(&self) -> Result<u32, Box<dyn Error>> {
+        let mut retries = 0;
+        if !self.flipper.is_connected().await? {
+            retries = Self::connect_peripheral(&self.flipper, &self.options).await?;
+        }
+        self.session.set_reader(Self::start_reader(&self.flipper, &self.options, self.bytes_received.clone()).await?);
+        self.session.set_flow_control(Self::start_flow_control(&self.flipper).await?);
+        Ok(retries)
+    }
 
-    /// Upload a file to a specific filename on the Flipper over BLE.
-    ///
-    /// # Arguments
-    ///
-    /// * `file`: Path to file to upload, this will be opened and read
-    ///           by the function.
-    /// * `dest`: Full path (i.e. `/ext/apps/GPIO/app.fap`) on Flipper to upload to
-    pub async fn upload_file(&mut self, file: &Path, dest: &str) -> Result<(), Box<dyn Error>> {
-        let rx_chr = self.get_rx_chr();
-        let tx_chr = self.get_tx_chr();
-        let flow_chr = self.get_flow_chr();
+    /// Returns changes in the link to the Flipper from now on, like
+    /// it disconnecting or being reconnected, for showing the link's
+    /// state without waiting for a request to fail. A receiver that
+    /// falls more than a few events behind loses the oldest.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
 
-        // get filesize for the progress bar
-        let filesize = fs::metadata(file)?.len();
-        let file_contents = fs::read(file)?;
+    /// Returns whether the OS thinks the Flipper is connected.
+    pub async fn is_connected(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(self.flipper.is_connected().await?)
+    }
 
-        let write_request_chunks =
-            self.proto.create_write_request_packets(&file_contents, dest)?;
-        debug!("sending {} packets total", write_request_chunks.len());
-        // The Flipper only responds when the has_next flag is false,
-        // you can see that in action at
-        // https://github.com/flipperdevices/flipperzero-firmware/blob/dev/applications/services/rpc/rpc_storage.c#L473
-        // Also, all it's doing is calling storage_file_write(), so
-        // really we're depending on a handful of cycles and the SD
-        // card.
+    /// Returns the ATT MTU of the connection, if the OS says what it
+    /// is (only Linux does).
+    pub fn mtu(&self) -> Option<u16> {
+        self.mtu
+    }
 
-        // The data is sent correctly but we get warnings (in the
-        // Flipper log) like this every few packets:
-        // `10560 [W][BtSerialSvc] Received 245, while was ready to receive 37 bytes. Can lead to buffer overflow!`
-        // I don't like that it does this but I don't know how to fix it.
+    /// Make sure the connection is still up, reconnecting if it
+    /// isn't. Call this every so often while idle in long-running
+    /// modes like the shell, so that neither the Flipper nor the OS
+    /// decides the connection is unused and drops it.
+    pub async fn keep_alive(&mut self) -> Result<(), Box<dyn Error>> {
+        let alive = self.flipper.is_connected().await?
+            && matches!(time::timeout(KEEP_ALIVE_TIMEOUT, self.ping()).await, Ok(Ok(())));
+        if alive {
+            trace!("keep-alive ping ok");
+            return Ok(());
+        }
 
-        // Furthermore (there are notes on this in protobuf_codec.rs),
-        // uploads are slower than the mobile app. I don't know why
-        // this is, because the mobile app also doesn't cause the
-        // overrun warnings.
-        self.flipper.subscribe(&flow_chr).await?;
-        let mut stream = self.flipper.notifications().await?;
+        warn!("lost connection to Flipper {}, reconnecting", self.name);
+        self.reconnect().await
+    }
+
+    /// Returns the signal strength of the connection in dBm, as last
+    /// reported by the OS, or None if it doesn't know. Some platforms
+    /// (BlueZ in particular) only update it while scanning, so it can
+    /// be out of date.
+    pub async fn rssi(&self) -> Result<Option<i16>, Box<dyn Error>> {
+        let rssi = self.flipper.properties().await?.and_then(|p| p.rssi);
+        if rssi.is_some() {
+            *self.last_rssi.lock().unwrap() = rssi;
+        }
+        Ok(rssi)
+    }
+
+    /// Returns what's happened on the connection since it was made,
+    /// for working out why a long session got slow or flaky. The
+    /// signal strength is asked for again, and if the OS doesn't say,
+    /// it's the last one it did.
+    pub async fn stats(&self) -> ConnectionStats {
+        if let Err(e) = self.rssi().await {
+            debug!("couldn't get the signal strength: {}", e);
+        }
+        let (messages_sent, bytes_sent) = self.session.sent();
+        ConnectionStats {
+            uptime: self.connected_at.elapsed(),
+            messages_sent,
+            bytes_sent,
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            connect_retries: self.connect_retries,
+            reconnects: self.reconnects,
+            rssi: *self.last_rssi.lock().unwrap(),
+        }
+    }
+
+    /// Log the signal strength before a transfer of `bytes`, if it's
+    /// big enough to be worth it.
+    async fn log_rssi(&self, bytes: u64) {
+        if bytes < LARGE_TRANSFER {
+            return;
+        }
+        match self.rssi().await {
+            Ok(Some(r)) => info!("signal strength: {} dBm ({})", r, signal_quality(f64::from(r))),
+            _ => debug!("signal strength unknown"),
+        }
+    }
+
+    pub async fn disconnect(&self) -> Result<(), Box<dyn Error>> {
+        self.flipper.disconnect().await?;
+        Ok(())
+    }
+
+    /// Returns the RPC session, for running requests alongside
+    /// whatever this is doing. Clones share one session.
+    pub fn session(&self) -> Session {
+        self.session.clone()
+    }
+
+    /// Report transfer progress to `callback` instead of drawing
+    /// progress bars, for programs that show it their own way. None
+    /// goes back to progress bars.
+    pub fn set_progress_callback(&mut self, callback: Option<ProgressCallback>) {
+        self.on_progress = callback;
+    }
+
+    /// Returns the Flipper's name, like "Uwu2".
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Allow writes to internal storage bigger than
+    /// INT_FORCE_THRESHOLD. They're still refused if they'd leave it
+    /// nearly full.
+    pub fn set_force_int_writes(&mut self, force: bool) {
+        self.force_int_writes = force;
+    }
+
+    /// Check that the Flipper's firmware can run a .fap before
+    /// uploading it (on by default). A .fap built for a different API
+    /// only fails once it's launched, with "App can't start".
+    pub fn set_fap_api_check(&mut self, check: bool) {
+        self.fap_api_check = check;
+    }
+
+    /// Returns the firmware's API version as (major, minor), or None
+    /// if the firmware doesn't say.
+    pub async fn firmware_api(&mut self) -> Result<Option<(u16, u16)>, Box<dyn Error>> {
+        if self.firmware_api.is_none() {
+            let info = self.device_info().await?;
+            let get = |key: &str| info.iter()
+                .find(|(k, _)| k == key)
+                .and_then(|(_, v)| v.parse::<u16>().ok());
+            self.firmware_api = get("firmware_api_major").zip(get("firmware_api_minor"));
+        }
+        Ok(self.firmware_api)
+    }
+
+    /// Returns an error if `fap`, the contents of a .fap going to
+    /// `dest`, was built for a firmware API the Flipper doesn't have.
+    async fn check_fap_api(&mut self, fap: &[u8], dest: &str) -> Result<(), Box<dyn Error>> {
+        let manifest = match fap::find_manifest(fap) {
+            Some(m) => m,
+            None => {
+                warn!("{} has no .fap manifest, not checking its API version", dest);
+                return Ok(());
+            },
+        };
+        match self.firmware_api().await? {
+            Some((major, minor)) => match manifest.api_mismatch(major, minor) {
+                Some(problem) => Err(format!("{} {} (use --no-api-check to upload it anyway)", dest, problem).into()),
+                None => {
+                    debug!("{} needs API {}.{}, firmware has {}.{}",
+                           dest, manifest.api_major, manifest.api_minor, major, minor);
+                    Ok(())
+                },
+            },
+            None => {
+                warn!("the Flipper didn't report its firmware API version, not checking {}", dest);
+                Ok(())
+            },
+        }
+    }
+
+    /// Use write with response for everything sent to the Flipper, so
+    /// that every packet is acknowledged before the next one goes
+    /// out. It's slower, but flaky adapters (like the Intel Stone
+    /// Peak ones) drop fewer packets.
+    pub fn set_reliable(&mut self, reliable: bool) {
+        self.transport.settings.lock().unwrap().write_type = if reliable {
+            WriteType::WithResponse
+        } else {
+            WriteType::WithoutResponse
+        };
+    }
+
+    /// Set how long to wait after writing each chunk of a request
+    /// (FLIPPER_BLE_PROTOBUF_CHUNK_DELAY ms by default). Only used
+    /// without set_reliable().
+    pub fn set_chunk_delay(&mut self, delay: Duration) {
+        self.transport.settings.lock().unwrap().chunk_delay = delay;
+    }
+
+    /// Keep downloads under `rate` bytes per second on average, or
+    /// don't limit them with None. The Flipper sends a whole file as
+    /// fast as it can once asked, so this waits between reads instead:
+    /// after a file, the next read starts only once the average is
+    /// back under `rate`.
+    pub fn set_download_rate(&mut self, rate: Option<u64>) {
+        self.download_rate = rate.filter(|r| *r > 0);
+    }
+
+    /// Set the largest piece written to the Flipper at once, see
+    /// ProtobufCodec::set_tu_size().
+    pub fn set_tu_size(&mut self, tu_size: usize) {
+        self.session.configure_codec(|p| p.set_tu_size(tu_size));
+    }
+
+    /// Set how many bytes of a file are sent per write request, see
+    /// ProtobufCodec::set_write_chunk_size().
+    pub fn set_write_chunk_size(&mut self, chunk_size: usize) {
+        self.session.configure_codec(|p| p.set_write_chunk_size(chunk_size));
+    }
+
+    fn make_file_progress_bar(&self, bytes_length: u64) -> ProgressBar {
+        if self.on_progress.is_some() {
+            return ProgressBar::hidden();
+        }
+        file_progress_bar(self.batch.as_ref().map(|b| &b.multi), bytes_length)
+    }
+
+    /// In a multi-file operation, only the overall bar should stay
+    /// on screen once each file is done.
+    fn finish_file_progress_bar(&self, pb: &ProgressBar) {
+        if self.batch.is_some() {
+            pb.finish_and_clear();
+        } else {
+            pb.finish();
+        }
+    }
+
+    /// Returns the overall progress bar of the running multi-file
+    /// operation, if there is one, to advance along with each file.
+    fn batch_overall(&self) -> Option<ProgressBar> {
+        self.batch.as_ref().map(|b| b.overall.clone())
+    }
+
+    fn start_batch(&mut self, total_bytes: u64, file_count: usize) {
+        let multi = MultiProgress::new();
+        let overall = multi.add(ProgressBar::new(total_bytes));
+        overall.set_style(ProgressStyle::with_template(
+            "{msg} [{wide_bar:.green/blue}] {bytes}/{total_bytes} {binary_bytes_per_sec} {elapsed} (ETA {eta})")
+                          .unwrap()
+                          .progress_chars("#>-"));
+        overall.set_message(format!("0/{} files", file_count));
+        overall.enable_steady_tick(Duration::from_millis(250));
+
+        self.batch = Some(BatchProgress {
+            multi,
+            overall,
+        });
+    }
+
+    fn finish_batch(&mut self) {
+        if let Some(batch) = self.batch.take() {
+            batch.overall.finish();
+        }
+    }
+
+
+    /// Upload a file to a specific filename on the Flipper over BLE.
+    ///
+    /// # Arguments
+    ///
+    /// * `file`: Path to file to upload, this will be opened and read
+    ///           by the function.
+    /// * `dest`: Full path (i.e. `/ext/apps/GPIO/app.fap`) on Flipper to upload to
+    pub async fn upload_file(&mut self, file: &Path, dest: &str) -> Result<(), Box<dyn Error>> {
+        let file_contents = fs::read(file)?;
+        self.upload_data(&file_contents, dest).await
+    }
+
+    /// Write `file_contents` to a file on the Flipper over BLE,
+    /// replacing the file if it already exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_contents`: Data to write, which can be empty
+    /// * `dest`: Full path on Flipper to write to
+    pub async fn upload_data(&mut self, file_contents: &[u8], dest: &str) -> Result<(), Box<dyn Error>> {
+        let started = time::Instant::now();
+        let result = self.write_data(file_contents, dest).await;
+        log_operation("upload", dest, u64::try_from(file_contents.len())?, started, &result);
+        result
+    }
+
+    async fn write_data(&mut self, file_contents: &[u8], dest: &str) -> Result<(), Box<dyn Error>> {
+        // get filesize for the progress bar
+        let filesize = u64::try_from(file_contents.len())?;
+
+        // A BLE upload can take minutes, so make sure the file will
+        // fit before we start instead of failing partway through.
+        // Replacing a file frees up what it used to take.
+        let (_, mut free) = self.storage_info(dest).await?;
+        if let Some(f) = self.stat(dest).await? {
+            if f.type_ == flipper_pb::storage::file::FileType::FILE.into() {
+                free += u64::from(f.size);
+            }
+        }
+        if filesize > free {
+            return Err(format!(
+                "not enough space on {}: file needs {} bytes but only {} are free",
+                storage_root(dest), filesize, free).into());
+        }
+        if storage_root(dest) == "/int" {
+            if free - filesize < INT_MIN_FREE {
+                return Err(format!(
+                    "writing {} bytes to {} would leave internal storage with only {} bytes free, \
+                     which can break the Flipper's settings",
+                    filesize, dest, free - filesize).into());
+            }
+            if filesize > INT_FORCE_THRESHOLD && !self.force_int_writes {
+                return Err(format!(
+                    "{} is on internal storage, which is small and holds the Flipper's settings. \
+                     Use --force to write {} bytes there anyway, or use /ext",
+                    dest, filesize).into());
+            }
+            warn!("writing {} bytes to internal storage ({} bytes free), \
+                   most files belong on the SD card in /ext", filesize, free);
+        }
+        if self.fap_api_check && dest.ends_with(".fap") {
+            self.check_fap_api(file_contents, dest).await?;
+        }
+
+        self.log_rssi(filesize).await;
+        let (mut responses, write_request_chunks) =
+            self.session.prepare(|p| p.create_write_request_packets(file_contents, dest))?;
+        debug!("sending {} packets total", write_request_chunks.len());
+        // The Flipper only responds when the has_next flag is false,
+        // you can see that in action at
+        // https://github.com/flipperdevices/flipperzero-firmware/blob/dev/applications/services/rpc/rpc_storage.c#L473
+        // Also, all it's doing is calling storage_file_write(), so
+        // really we're depending on a handful of cycles and the SD
+        // card.
+
+        // We used to get warnings (in the Flipper log) like this
+        // every few packets:
+        // `10560 [W][BtSerialSvc] Received 245, while was ready to receive 37 bytes. Can lead to buffer overflow!`
+        // The session now waits for room in the Flipper's buffer
+        // before writing each piece (see flow_control.rs), which is
+        // what the mobile app does too.
+
+        // Uploads are still slower than the mobile app (there are
+        // notes on this in protobuf_codec.rs).
 
         // Progress bar is representative of only the actual bytes in
         // the file, not including the data in the protobuf messages.
         let pb = self.make_file_progress_bar(filesize);
+        let overall = self.batch_overall();
 
-        // This loop waits a small time between packets, but if it
-        // gets a notification on the flow control char, it waits a
-        // long time. (This seems counterintuitive, because every time
-        // we actually get a notification, the available buffer size
-        // is the full 1024 bytes. Basically, I don't know why this
-        // works, but it does).
-        let mut pos: u64 = 0;
+        // This loop waits a small time between packets. Waiting for
+        // room in the Flipper's buffer happens in write_message().
         for p in write_request_chunks {
             // Write one chunk, which will be a couple of
             // packets. These are continuous pieces of a single
             // protobuf message, so we don't wait for a response
             // because there won't be one.
-            for v in p.packets {
-                self.flipper.write(&rx_chr, &v, WriteType::WithoutResponse).await?;
-                time::sleep(Duration::from_millis(FLIPPER_BLE_PROTOBUF_CHUNK_DELAY)).await;
-            }
-            pos += u64::try_from(p.file_byte_count)?;
-            pb.set_position(pos);
-            // now_or_never() evaluates and consumes the future
-            // immediately, returning an Option with the
-            // ValueNotification. We're using it to check if there's a
-            // new notification in the stream.
-
-            // Waiting when we get this notification also seems to
-            // help (slightly fewer buffer overrun warnings?), but we
-            // still get them. Furthermore, it's not good to run with
-            // debug-level logging, because it causes a timeout.
-            if stream.next().now_or_never().is_some() {
-                // (we don't care about the value of the notification)
-                
-                // The data in this characteristic is the free space
-                // left in the BLE serial buffer on the Flipper, as a
-                // 32-bit big-endian integer. In this situation, it's
-                // always the value 1024, indicating that the buffer
-                // is empty.
-                
-                // 800 ms is a good sleep here. Sometimes we end up in
-                // this state many times during a transfer, so keeping
-                // this short is desirable.
-                time::sleep(Duration::from_millis(800)).await;
-
+            self.session.write_message(&p.packets).await?;
+            let sent = u64::try_from(p.file_byte_count)?;
+            pb.inc(sent);
+            if let Some(o) = &overall {
+                o.inc(sent);
+            }
+            if let Some(callback) = &mut self.on_progress {
+                callback(pb.position(), filesize);
             }
             // On Linux at least (with my goofy Intel 7265), 140 ms
             // works very well and stops the host from timing out
@@ -293,127 +1466,972 @@ impl FlipperBle {
             time::sleep(Duration::from_millis(140)).await;
         }
         
-        pb.finish();
+        self.finish_file_progress_bar(&pb);
         debug!("sent all packets!");
 
-        // This is the place where the ATT error occurs. It might be
-        // another Stone Peak oddity, but sometimes the upload
-        // finishes but this step fails with an error about ATT
-        // 0x0b. 0x0b is a Read Response opcode, maybe it's something
-        // with the delay?
-        time::sleep(Duration::from_millis(400)).await;
-        let response = self.flipper.read(&tx_chr).await?;
-        let pb_response = ProtobufCodec::parse_response(&response)?;
-        debug!("response received: {:?}", pb_response);
+        // The Flipper answers once the last chunk has been written
+        // to storage.
+        let response = responses.next().await?;
+        debug!("response received: {:?}", response);
 
-        if pb_response.1.command_status == flipper_pb::flipper::CommandStatus::OK.into() {
+        check_busy(&response)?;
+        if response.command_status == flipper_pb::flipper::CommandStatus::OK.into() {
             Ok(())
         } else {
-            Err(format!("Flipper returned error: {:?}", pb_response.1).into())
+            Err(Box::new(RpcStatus::new(response.command_status,
+                                        format!("Flipper returned error: {:?}", response))))
         }
     }
 
-    // This is the main thing that doesn't work with Intel Stone Peak adapters.
-    pub async fn download_file(&mut self, path: &str, dest: &Path) -> Result<(), Box<dyn Error>> {
-        let rx_chr = self.get_rx_chr();
-        let tx_chr = self.get_tx_chr();
+    /// Append a local file to the end of a file on the Flipper,
+    /// creating it if it doesn't exist.
+    ///
+    /// The storage RPC can only write whole files, so this reads the
+    /// existing file back and rewrites it with the new data on the
+    /// end. That's fine for logs and lists, but slow for big files.
+    ///
+    /// # Arguments
+    ///
+    /// * `file`: Local file with the data to append
+    /// * `dest`: Full path on Flipper to append to
+    pub async fn append_file(&mut self, file: &Path, dest: &str) -> Result<(), Box<dyn Error>> {
+        let new_contents = fs::read(file)?;
+        self.append_data(&new_contents, dest).await
+    }
 
-        // Getting data back from the Flipper is basically as simple
-        // as waiting for indications and checking if it's a full
-        // protobuf message.
-        self.flipper.subscribe(&tx_chr).await?;
+    /// Add `new_contents` to the end of a file on the Flipper, creating
+    /// it if it doesn't exist. See append_file() for how.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_contents`: Data to append
+    /// * `dest`: Full path on Flipper to append to
+    pub async fn append_data(&mut self, new_contents: &[u8], dest: &str) -> Result<(), Box<dyn Error>> {
+        let mut file_contents = Vec::new();
+        match self.stat(dest).await? {
+            Some(f) if f.type_ == flipper_pb::storage::file::FileType::DIR.into() => {
+                return Err(format!("{} is a directory", dest).into());
+            },
+            Some(f) => {
+                debug!("reading {} existing bytes of {}", f.size, dest);
+                self.read_file(dest, None, |data| {
+                    file_contents.extend(data);
+                    Ok(())
+                }).await?;
+            },
+            None => {
+                debug!("{} doesn't exist, creating it", dest);
+            },
+        };
 
-        // Do a stat request so that we can get the size of the file
-        let mut stream = self.flipper.notifications().await?;
-        let stat_request = self.proto.create_stat_request_packet(path)?;
-
-        for chunk in stat_request {
-            self.flipper.write(&rx_chr, &chunk, WriteType::WithoutResponse).await?;
-            time::sleep(Duration::from_millis(FLIPPER_BLE_PROTOBUF_CHUNK_DELAY)).await;
-        }
-
-        let mut full_protobuf: Vec<u8> = Vec::new();
-
-        let filesize = loop {
-            if let Some(Some(response)) = stream.next().now_or_never() {
-                full_protobuf.extend(response.value);
-                match ProtobufCodec::parse_response(&full_protobuf) {
-                    Ok(m) => {
-                        if let Some(flipper_pb::flipper::main::Content::StorageStatResponse(
-                            r)) = m.1.content {
-                            debug!("received file size: {:?}", r.file.size);
-                            break r.file.size;
-                        } else if let Some(flipper_pb::flipper::main::Content::Empty(_)) = m.1.content {
-                            // Flipper returns Empty { } when the path is bad
-                            debug!("received empty response (bad path)");
-                            return Err("Invalid Flipper path! Check that the path is correct.".into());
-                        } else {
-                            error!("received unexpected protobuf response: {:?}", m.1.content);
-                            return Err("".into());
-                        }
-                    },
-                    Err(e) => {
-                        debug!("protobuf error (incomplete packet): {:?}", e);
-                    }
-                };
+        file_contents.extend_from_slice(new_contents);
+        self.upload_data(&file_contents, dest).await
+    }
+
+    /// Create an empty file on the Flipper. An existing file is left
+    /// alone, unless `exclusive` is set, in which case it's an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Full path on Flipper of the file to create
+    /// * `exclusive`: Fail if `path` already exists
+    pub async fn touch(&mut self, path: &str, exclusive: bool) -> Result<(), Box<dyn Error>> {
+        match self.stat(path).await? {
+            Some(f) if f.type_ == flipper_pb::storage::file::FileType::DIR.into() => {
+                Err(format!("{} is a directory", path).into())
+            },
+            Some(_) if exclusive => {
+                Err(format!("{} already exists", path).into())
+            },
+            Some(_) => {
+                // the storage RPC can't set timestamps, so there's
+                // nothing else to do
+                debug!("{} already exists, leaving it alone", path);
+                Ok(())
+            },
+            None => self.upload_data(&[], path).await,
+        }
+    }
+
+    /// Returns the File entry the Flipper reports for `path`, or None
+    /// if the path doesn't exist. Directories come back with type DIR
+    /// and no size.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper path to get stats about
+    pub async fn stat(&mut self, path: &str) -> Result<Option<flipper_pb::storage::File>, Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_stat_request_packet(path)).await?;
+
+        let m = responses.next().await?;
+        if let Some(flipper_pb::flipper::main::Content::StorageStatResponse(r)) = m.content {
+            debug!("received stat: {:?}", r.file);
+            Ok(r.file.into_option())
+        } else if let Some(flipper_pb::flipper::main::Content::Empty(_)) = m.content {
+            // Flipper returns Empty { } when the path is bad, and
+            // also when it's busy
+            check_busy(&m)?;
+            debug!("received empty response (bad path)");
+            Ok(None)
+        } else {
+            error!("received unexpected protobuf response: {:?}", m.content);
+            Err("".into())
+        }
+    }
+
+    /// Returns when a Flipper file was last changed, in seconds since
+    /// 1970 as the Flipper's clock had it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper file or directory
+    pub async fn timestamp(&mut self, path: &str) -> Result<u32, Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_timestamp_request_packet(path)).await?;
+
+        let m = responses.next().await?;
+        if let Some(flipper_pb::flipper::main::Content::StorageTimestampResponse(r)) = m.content {
+            Ok(r.timestamp)
+        } else {
+            check_busy(&m)?;
+            Err(format!("couldn't get timestamp of {}: {:?}", path, m.command_status).into())
+        }
+    }
+
+    /// Send a ping and wait for the Flipper to answer. Returns RpcBusy
+    /// if another RPC session has the Flipper tied up.
+    pub async fn ping(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_ping_request_packet(b"flipwire").map(|packet| vec![packet])).await?;
+
+        let m = responses.next().await?;
+        if let Some(flipper_pb::flipper::main::Content::SystemPingResponse(_)) = m.content {
+            return Ok(());
+        }
+        check_busy(&m)?;
+        error!("received unexpected protobuf response: {:?}", m);
+        Err("".into())
+    }
+
+    /// Send any RPC message and return every response to it, for the
+    /// ones flipwire doesn't have a function for. The command ID in
+    /// `msg` is replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg`: Message to send
+    pub async fn send_raw(&mut self, msg: flipper_pb::flipper::Main) -> Result<Vec<flipper_pb::flipper::Main>, Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_raw_request_packet(msg)).await?;
+
+        let mut found = Vec::new();
+        loop {
+            let m = responses.next().await?;
+            debug!("response received: {:?}", m);
+            let has_next = m.has_next;
+            found.push(m);
+            if !has_next {
+                return Ok(found);
             }
-        };
+        }
+    }
 
-        // now read the contents of the file
-        let read_request = self.proto.create_read_request_packet(path)?;
-        
-        for chunk in read_request {
-            self.flipper.write(&rx_chr, &chunk, WriteType::WithoutResponse).await?;
-            time::sleep(Duration::from_millis(FLIPPER_BLE_PROTOBUF_CHUNK_DELAY)).await;
+    /// Returns the MD5 of a Flipper file as a lowercase hex string,
+    /// calculated on the Flipper.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper file to hash
+    pub async fn md5sum(&mut self, path: &str) -> Result<String, Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_md5sum_request_packet(path)).await?;
+
+        let m = responses.next().await?;
+        if let Some(flipper_pb::flipper::main::Content::StorageMd5sumResponse(r)) = m.content {
+            debug!("md5 of {}: {}", path, r.md5sum);
+            Ok(r.md5sum.to_lowercase())
+        } else if let Some(flipper_pb::flipper::main::Content::Empty(_)) = m.content {
+            check_busy(&m)?;
+            debug!("received empty response (bad path)");
+            Err("Invalid Flipper path! Check that the path is correct.".into())
+        } else {
+            error!("received unexpected protobuf response: {:?}", m.content);
+            Err("".into())
+        }
+    }
+
+    /// Returns the total and free space in bytes of the filesystem
+    /// (`/int` or `/ext`) that `path` is on.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Any Flipper path on the filesystem to check
+    pub async fn storage_info(&mut self, path: &str) -> Result<(u64, u64), Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_storage_info_request_packet(storage_root(path))).await?;
+
+        let m = responses.next().await?;
+        if let Some(flipper_pb::flipper::main::Content::StorageInfoResponse(r)) = m.content {
+            debug!("storage info: {:?}", r);
+            Ok((r.total_space, r.free_space))
+        } else {
+            check_busy(&m)?;
+            error!("received unexpected protobuf response: {:?}", m);
+            Err("".into())
+        }
+    }
+
+    /// Read a file from the Flipper, handing each piece of data to
+    /// `on_data` as it arrives. Returns the number of bytes read.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper file to read
+    /// * `limit`: Stop after this many bytes instead of reading the
+    ///   whole file
+    /// * `on_data`: Called with every piece of the file, in order
+    pub async fn read_file<F>(&mut self, path: &str, limit: Option<u64>, mut on_data: F) -> Result<u64, Box<dyn Error>>
+    where F: FnMut(&[u8]) -> Result<(), Box<dyn Error>> {
+        let mut file_pos: u64 = 0;
+        self.read_file_until(path, |mut data| {
+            if let Some(limit) = limit {
+                let left = limit - file_pos;
+                if u64::try_from(data.len())? > left {
+                    data = &data[..usize::try_from(left)?];
+                }
+            }
+
+            on_data(data)?;
+            file_pos += u64::try_from(data.len())?;
+            Ok(limit.is_some_and(|l| file_pos >= l))
+        }).await?;
+        Ok(file_pos)
+    }
+
+    /// Read a file from the Flipper until `on_data` says to stop,
+    /// handing it each piece of data as it arrives. Returns the
+    /// number of bytes read.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper file to read
+    /// * `on_data`: Called with every piece of the file, in order,
+    ///   and returns true once it's seen enough
+    pub async fn read_file_until<F>(&mut self, path: &str, on_data: F) -> Result<u64, Box<dyn Error>>
+    where F: FnMut(&[u8]) -> Result<bool, Box<dyn Error>> {
+        self.wait_for_download_rate().await;
+        let started = time::Instant::now();
+        let result = self.read_file_inner(path, on_data).await;
+        // the whole file comes over even after an early stop, but it
+        // isn't counted, so the average is only roughly right then
+        if let Ok(bytes) = &result {
+            self.count_download(started, *bytes);
+        }
+        result
+    }
+
+    /// Wait until the next read can start without going over the
+    /// download rate.
+    async fn wait_for_download_rate(&self) {
+        if let Some(next) = self.next_read {
+            if next > time::Instant::now() {
+                debug!("waiting {:?} to stay under the download rate", next - time::Instant::now());
+                time::sleep_until(next).await;
+            }
         }
+    }
+
+    /// Count `bytes` read since `started` against the download rate.
+    fn count_download(&mut self, started: time::Instant, bytes: u64) {
+        if let Some(rate) = self.download_rate {
+            self.next_read = Some(started + Duration::from_secs_f64(bytes as f64 / rate as f64));
+        }
+    }
 
-        time::sleep(Duration::from_millis(200)).await;
+    async fn read_file_inner<F>(&mut self, path: &str, mut on_data: F) -> Result<u64, Box<dyn Error>>
+    where F: FnMut(&[u8]) -> Result<bool, Box<dyn Error>> {
+        // Getting data back from the Flipper is basically as simple
+        // as waiting for messages until one says there's no more.
+        let mut responses = self.session.request(|p| p.create_read_request_packet(path)).await?;
         debug!("wrote read request");
-        let pb = self.make_file_progress_bar(From::from(filesize));
 
         let mut file_pos: u64 = 0;
-        full_protobuf.clear();
-        let mut file_contents = Vec::new();
-        // data arrives when we get a notification
         loop {
-            if let Some(Some(response)) = stream.next().now_or_never() {
-                full_protobuf.extend(response.value);
-                // if the protobuf message is complete, do something
-                // with it, otherwise just wait for the next message
-                match ProtobufCodec::parse_response(&full_protobuf) {
-                    Ok(m) => {
-                        if let Some(flipper_pb::flipper::main::Content::StorageReadResponse(
-                            r)) = m.1.content {
-                            file_contents.extend(r.file.data.iter());
-                            file_pos += u64::try_from(r.file.data.len())?;
-                            pb.set_position(file_pos);
-                        }
-                        // if we're on the last packet, stop getting data
-                        if !m.1.has_next {
-                            break;
-                        }
-                        full_protobuf.clear();
-                    },
-                    Err(e) => {
-                        debug!("protobuf error (incomplete packet): {:?}", e);
-                    }
-                };
+            let m = responses.next().await?;
+            if m.command_status != flipper_pb::flipper::CommandStatus::OK.into() {
+                check_busy(&m)?;
+                debug!("read failed: {:?}", m.command_status);
+                return Err(Box::new(RpcStatus::new(m.command_status,
+                                                   "Invalid Flipper path! Check that the path is correct.")));
+            }
+
+            if let Some(flipper_pb::flipper::main::Content::StorageReadResponse(r)) = m.content {
+                let done = on_data(&r.file.data)?;
+                file_pos += u64::try_from(r.file.data.len())?;
+
+                // There's no way to tell the Flipper to stop partway
+                // through, so it sends the rest of the file
+                // anyway. The reader throws it away once we stop
+                // listening.
+                if done {
+                    debug!("read {} bytes, stopping early", file_pos);
+                    return Ok(file_pos);
+                }
+            }
+            // if we're on the last packet, stop getting data
+            if !m.has_next {
+                return Ok(file_pos);
             }
         }
+    }
+
+    /// Read several files one after another, handing every piece of
+    /// each to `on_data` along with which file it's from, then None
+    /// once that file is done. The Flipper takes a round trip between
+    /// asking for a file and starting to send it, which is most of
+    /// the time a small file takes, so the next file is asked for
+    /// right after the current one, while it's still coming in. The
+    /// Flipper handles requests in order, so it has the next one
+    /// waiting as soon as it's done, and each one's responses come
+    /// back under its own command ID. With a download rate, each
+    /// file waits for the one before instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths`: Flipper files to read, in order
+    /// * `on_data`: Called with the index of the file in `paths` and
+    ///   every piece of it, in order, then None
+    async fn read_files<F>(&mut self, paths: &[&str], mut on_data: F) -> Result<(), Box<dyn Error>>
+    where F: FnMut(usize, Option<&[u8]>) -> Result<(), Box<dyn Error>> {
+        let pipelined = self.download_rate.is_none();
+        // the request for the next file, if it's gone out already
+        let mut next: Option<Responses> = None;
+        for (i, path) in paths.iter().enumerate() {
+            let mut responses = match next.take() {
+                Some(r) => r,
+                None => {
+                    self.wait_for_download_rate().await;
+                    self.session.request(|p| p.create_read_request_packet(path)).await?
+                },
+            };
+            if let (true, Some(following)) = (pipelined, paths.get(i + 1)) {
+                debug!("asking for {} while {} is still coming", following, path);
+                next = Some(self.session.request(|p| p.create_read_request_packet(following)).await?);
+            }
+
+            let started = time::Instant::now();
+            let mut bytes: u64 = 0;
+            loop {
+                let m = responses.next().await?;
+                if m.command_status != flipper_pb::flipper::CommandStatus::OK.into() {
+                    check_busy(&m)?;
+                    debug!("read of {} failed: {:?}", path, m.command_status);
+                    return Err(Box::new(RpcStatus::new(m.command_status,
+                                                       format!("can't read {}, check that the path is correct", path))));
+                }
+
+                if let Some(flipper_pb::flipper::main::Content::StorageReadResponse(r)) = m.content {
+                    on_data(i, Some(&r.file.data))?;
+                    bytes += u64::try_from(r.file.data.len())?;
+                }
+                if !m.has_next {
+                    break;
+                }
+            }
+            on_data(i, None)?;
+            self.count_download(started, bytes);
+        }
+        Ok(())
+    }
+
+    // This is the main thing that doesn't work with Intel Stone Peak adapters.
+    pub async fn download_file(&mut self, path: &str, dest: &Path) -> Result<(), Box<dyn Error>> {
+        self.download_part(path, dest, 0, None).await
+    }
+
+    /// Download part of a file, starting `offset` bytes in. The
+    /// Flipper can only send files from the beginning, so everything
+    /// before `offset` still comes over Bluetooth, but nothing after
+    /// the part does.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper file to download
+    /// * `dest`: Local file to save the part to
+    /// * `offset`: Where the part starts in the file
+    /// * `length`: How long the part is, or the rest of the file if
+    ///   None
+    pub async fn download_part(&mut self, path: &str, dest: &Path, offset: u64, length: Option<u64>) -> Result<(), Box<dyn Error>> {
+        let started = time::Instant::now();
+        let mut bytes = 0;
+        let result = self.read_part(path, dest, offset, length, &mut bytes).await;
+        log_operation("download", path, bytes, started, &result);
+        result
+    }
+
+    // `bytes` is set to how much comes over Bluetooth once that's
+    // known, for the log
+    async fn read_part(&mut self, path: &str, dest: &Path, offset: u64, length: Option<u64>, bytes: &mut u64) -> Result<(), Box<dyn Error>> {
+        // Do a stat request so that we can get the size of the file
+        let filesize = match self.stat(path).await? {
+            Some(f) => u64::from(f.size),
+            None => {
+                return Err(Box::new(RpcStatus::new(flipper_pb::flipper::CommandStatus::ERROR_STORAGE_NOT_EXIST.into(),
+                                                   "Invalid Flipper path! Check that the path is correct.")));
+            },
+        };
+        if offset > filesize {
+            return Err(format!("offset {} is past the end of the file, which is {} bytes", offset, filesize).into());
+        }
+        // where to stop reading
+        let end = match length {
+            Some(l) => filesize.min(offset.saturating_add(l)),
+            None => filesize,
+        };
+        *bytes = end;
+
+        self.log_rssi(end).await;
+        let pb = self.make_file_progress_bar(end);
+        let overall = self.batch_overall();
+
+        // read_file() needs all of self, so hold on to the callback
+        // ourselves until it's done
+        let mut on_progress = self.on_progress.take();
+        let mut file_contents = Vec::new();
+        let mut pos: u64 = 0;
+        let limit = if end < filesize { Some(end) } else { None };
+        let result = self.read_file(path, limit, |data| {
+            let received = u64::try_from(data.len())?;
+            if pos + received > offset {
+                let skip = usize::try_from(offset.saturating_sub(pos))?;
+                file_contents.extend(&data[skip..]);
+            }
+            pos += received;
+            pb.inc(received);
+            if let Some(o) = &overall {
+                o.inc(received);
+            }
+            if let Some(callback) = &mut on_progress {
+                callback(pb.position(), end);
+            }
+            Ok(())
+        }).await;
+        self.on_progress = on_progress;
+        result?;
         debug!("all packets received, saving file");
 
-        pb.finish();
+        self.finish_file_progress_bar(&pb);
         // write out the file
         let mut out = fs::File::create(dest)?;
         out.write_all(&file_contents)?;
 
         // should we send an OK?
-        self.proto.inc_command_id();
+        self.session.send(|p| {
+            p.inc_command_id();
+            p.create_ok_packet()
+        }).await?;
+        debug!("Wrote OK to Flipper");
+        Ok(())
+    }
 
-        let ok_response = self.proto.create_ok_packet()?;
+    /// Returns the full Flipper path to upload `file` to. If `dest` is
+    /// a directory, or ends in "/" to say it is one, that's `file`'s
+    /// name inside it. Otherwise it's just `dest`.
+    ///
+    /// # Arguments
+    ///
+    /// * `file`: Local file that's going to be uploaded
+    /// * `dest`: Flipper path the user asked to upload to
+    pub async fn upload_destination(&mut self, file: &Path, dest: &str) -> Result<String, Box<dyn Error>> {
+        let is_dir = dest.ends_with('/') || remote_path::is_root(dest) || match self.stat(dest).await? {
+            Some(f) => f.type_ == flipper_pb::storage::file::FileType::DIR.into(),
+            None => false,
+        };
+        if !is_dir {
+            return Ok(dest.to_string());
+        }
 
-        self.flipper.write(&rx_chr, &ok_response, WriteType::WithoutResponse).await?;
-        debug!("Wrote OK to Flipper");
+        let name = file.file_name()
+            .ok_or(format!("can't tell what to name {:?} on the Flipper", file))?;
+        Ok(join_remote(dest, &name.to_string_lossy()))
+    }
+
+    /// Returns what a recursive upload of `dir` to `dest` would do,
+    /// deleting what `dir` doesn't have first if `delete` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir`: Local directory to upload
+    /// * `dest`: Flipper directory to upload into
+    /// * `exclude`: Paths to leave out
+    /// * `delete`: Mirror `dir`, see plan_mirror_upload()
+    pub async fn plan_upload(&mut self, dir: &Path, dest: &str, exclude: &PathFilter, delete: bool) -> Result<TransferPlan, Box<dyn Error>> {
+        if delete {
+            self.plan_mirror_upload(dir, dest, exclude).await
+        } else {
+            FlipperBle::plan_upload_dir(dir, dest, exclude)
+        }
+    }
+
+    /// Returns what a recursive download of `path` to `dest` would do,
+    /// deleting what the Flipper doesn't have first if `delete` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory to download
+    /// * `dest`: Local directory to download into
+    /// * `filter`: Paths to take and leave out
+    /// * `delete`: Mirror `path`, see plan_mirror_download()
+    pub async fn plan_download(&mut self, path: &str, dest: &Path, filter: &PathFilter, delete: bool) -> Result<TransferPlan, Box<dyn Error>> {
+        if delete {
+            self.plan_mirror_download(path, dest, filter).await
+        } else {
+            self.plan_download_dir(path, dest, filter).await
+        }
+    }
+
+    /// Returns what upload_dir() would do: the Flipper directories it
+    /// would create and the files it would upload. Nothing is sent to
+    /// the Flipper, so this is what --dry-run prints.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir`: Local directory to upload
+    /// * `dest`: Flipper directory to upload into
+    /// * `exclude`: Paths to leave out, along with the ones in `dir`'s
+    ///              .flipperignore
+    pub fn plan_upload_dir(dir: &Path, dest: &str, exclude: &PathFilter) -> Result<TransferPlan, Box<dyn Error>> {
+        let filter = upload_filter(dir, exclude)?;
+        let LocalTree { dirs, files } = local_tree(dir, &filter)?;
+
+        let mut remote_dirs = vec![dest.to_string()];
+        remote_dirs.extend(dirs.iter().map(|d| remote_child(dest, d)));
+        Ok(TransferPlan {
+            remote_dirs,
+            local_dirs: Vec::new(),
+            files: files.into_iter()
+                .map(|(rel, size)| PlannedFile {
+                    remote: remote_child(dest, &rel),
+                    local: dir.join(rel),
+                    size,
+                })
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    /// Returns what a mirroring upload would do: plan_upload_dir(),
+    /// plus deleting whatever's in `dest` on the Flipper that isn't in
+    /// `dir`. Excluded paths are left alone on the Flipper too.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir`: Local directory to upload
+    /// * `dest`: Flipper directory to make the same as `dir`
+    /// * `exclude`: Paths to leave out, along with the ones in `dir`'s
+    ///              .flipperignore
+    pub async fn plan_mirror_upload(&mut self, dir: &Path, dest: &str, exclude: &PathFilter) -> Result<TransferPlan, Box<dyn Error>> {
+        let mut plan = Self::plan_upload_dir(dir, dest, exclude)?;
+        if self.stat(dest).await?.is_none() {
+            return Ok(plan);
+        }
+
+        let filter = upload_filter(dir, exclude)?;
+        let mut doomed_dirs: Vec<String> = Vec::new();
+        // walk() lists parents before what's in them
+        for (path, f) in self.walk_filtered(dest, &filter).await? {
+            if doomed_dirs.iter().any(|d| path.starts_with(&format!("{}/", d))) {
+                continue;
+            }
+            let is_dir = f.type_ == flipper_pb::storage::file::FileType::DIR.into();
+            let kept = if is_dir {
+                plan.remote_dirs.contains(&path)
+            } else {
+                plan.files.iter().any(|p| p.remote == path)
+            };
+            if !kept {
+                if is_dir {
+                    doomed_dirs.push(path.clone());
+                }
+                plan.remote_deletions.push((path, f));
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Upload a local directory and everything in it to `dest` on the
+    /// Flipper, creating directories as needed. Anything matching the
+    /// patterns in `exclude`, or in a .flipperignore file at the top
+    /// of `dir`, is left out (and so is the .flipperignore).
+    ///
+    /// # Arguments
+    ///
+    /// * `dir`: Local directory to upload
+    /// * `dest`: Flipper directory to upload into (which becomes the
+    ///           equivalent of `dir`)
+    /// * `exclude`: Paths to leave out
+    pub async fn upload_dir(&mut self, dir: &Path, dest: &str, exclude: &PathFilter) -> Result<(), Box<dyn Error>> {
+        let plan = Self::plan_upload_dir(dir, dest, exclude)?;
+        self.upload_plan(&plan).await
+    }
+
+    /// Do what `plan`, from plan_upload_dir() or plan_mirror_upload(),
+    /// says: delete, create directories, then upload.
+    pub async fn upload_plan(&mut self, plan: &TransferPlan) -> Result<(), Box<dyn Error>> {
+        for (path, _) in &plan.remote_deletions {
+            info!("deleting {}", path);
+            self.delete_file(path, true).await?;
+        }
+        for d in &plan.remote_dirs {
+            self.mkdir(d).await?;
+        }
+
+        self.start_batch(plan.total_bytes(), plan.files.len());
+        let result = async {
+            for (i, f) in plan.files.iter().enumerate() {
+                if let Some(o) = self.batch_overall() {
+                    o.set_message(format!("{}/{} files", i + 1, plan.files.len()));
+                }
+                self.upload_file(&f.local, &f.remote).await?;
+            }
+            Ok(())
+        }.await;
+        self.finish_batch();
+
+        result
+    }
+
+    /// Returns what download_dir() would do: the local directories it
+    /// would create and the files it would download. Nothing is
+    /// written locally, so this is what --dry-run prints.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory to download
+    /// * `dest`: Local directory to download into
+    /// * `filter`: Paths to leave out, relative to `path`
+    pub async fn plan_download_dir(&mut self, path: &str, dest: &Path, filter: &PathFilter) -> Result<TransferPlan, Box<dyn Error>> {
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        let mut local_dirs = vec![dest.to_path_buf()];
+        let mut files = Vec::new();
+        for (full_path, f) in self.walk_filtered(path, filter).await? {
+            let local = dest.join(full_path.trim_start_matches(&prefix));
+            if f.type_ == flipper_pb::storage::file::FileType::DIR.into() {
+                // with includes, only directories something ends up in
+                if !filter.has_includes() {
+                    local_dirs.push(local);
+                }
+            } else {
+                if let Some(parent) = local.parent().filter(|p| !local_dirs.iter().any(|d| d == p)) {
+                    local_dirs.push(parent.to_path_buf());
+                }
+                files.push(PlannedFile {
+                    remote: full_path,
+                    local,
+                    size: u64::from(f.size),
+                });
+            }
+        }
+
+        Ok(TransferPlan {
+            local_dirs,
+            files,
+            ..Default::default()
+        })
+    }
+
+    /// Returns what a mirroring download would do: plan_download_dir(),
+    /// plus deleting whatever's in `dest` that isn't in `path` on the
+    /// Flipper. Paths `filter` leaves out are left alone locally too,
+    /// and with include patterns, only files are deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory to download
+    /// * `dest`: Local directory to make the same as `path`
+    /// * `filter`: Paths to leave out, relative to `path`
+    pub async fn plan_mirror_download(&mut self, path: &str, dest: &Path, filter: &PathFilter) -> Result<TransferPlan, Box<dyn Error>> {
+        let mut plan = self.plan_download_dir(path, dest, filter).await?;
+        if !dest.is_dir() {
+            return Ok(plan);
+        }
+
+        let LocalTree { dirs, files } = local_tree(dest, filter)?;
+        let mut doomed_dirs: Vec<PathBuf> = Vec::new();
+        // local_tree() lists parents before what's in them
+        let entries = dirs.into_iter().map(|d| (d, true))
+            .chain(files.into_iter().map(|(f, _)| (f, false)));
+        for (rel, is_dir) in entries {
+            if doomed_dirs.iter().any(|d| rel.starts_with(d)) {
+                continue;
+            }
+            let local = dest.join(&rel);
+            let kept = if is_dir {
+                // with includes, directories aren't all in the plan
+                filter.has_includes() || plan.local_dirs.contains(&local)
+            } else {
+                plan.files.iter().any(|p| p.local == local)
+            };
+            if !kept {
+                if is_dir {
+                    doomed_dirs.push(rel);
+                }
+                let metadata = fs::symlink_metadata(&local)?;
+                plan.local_deletions.push((local, metadata));
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Download a Flipper directory and everything in it to `dest`,
+    /// creating local directories as needed. With include patterns in
+    /// `filter`, only directories with something to download in them
+    /// are created.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory to download
+    /// * `dest`: Local directory to download into (which becomes the
+    ///           equivalent of `path`)
+    /// * `filter`: Paths to leave out, relative to `path`
+    pub async fn download_dir(&mut self, path: &str, dest: &Path, filter: &PathFilter) -> Result<(), Box<dyn Error>> {
+        let plan = self.plan_download_dir(path, dest, filter).await?;
+        self.download_plan(&plan).await
+    }
+
+    /// Do what `plan`, from plan_download_dir() or
+    /// plan_mirror_download(), says: delete, create directories, then
+    /// download, with the files pipelined by read_files(). The sizes
+    /// in the plan are only for the progress bars, each file is
+    /// downloaded whole.
+    pub async fn download_plan(&mut self, plan: &TransferPlan) -> Result<(), Box<dyn Error>> {
+        for (path, metadata) in &plan.local_deletions {
+            info!("deleting {:?}", path);
+            if metadata.is_dir() {
+                fs::remove_dir_all(path)?;
+            } else {
+                fs::remove_file(path)?;
+            }
+        }
+        for d in &plan.local_dirs {
+            fs::create_dir_all(d)?;
+        }
+
+        self.start_batch(plan.total_bytes(), plan.files.len());
+        self.log_rssi(plan.total_bytes()).await;
+        let paths: Vec<&str> = plan.files.iter().map(|f| f.remote.as_str()).collect();
+        let mut on_progress = self.on_progress.take();
+        let mut files = FileProgress::new(self, plan.files.len());
+        // the file being written
+        let mut out: Option<io::BufWriter<fs::File>> = None;
+        let result = self.read_files(&paths, |i, data| {
+            let f = &plan.files[i];
+            match data {
+                Some(data) => {
+                    let out = match &mut out {
+                        Some(out) => out,
+                        None => out.insert(io::BufWriter::new(fs::File::create(&f.local)?)),
+                    };
+                    out.write_all(data)?;
+                    let position = files.inc(i, f.size, u64::try_from(data.len())?);
+                    if let Some(callback) = &mut on_progress {
+                        callback(position, f.size);
+                    }
+                },
+                None => {
+                    // empty files never get any data
+                    match out.take() {
+                        Some(out) => out.into_inner().map_err(|e| e.into_error())?,
+                        None => fs::File::create(&f.local)?,
+                    };
+                    files.finish(i, f.size, "download", &f.remote);
+                },
+            }
+            Ok(())
+        }).await;
+        self.on_progress = on_progress;
+        if let Some(i) = files.failed("download", &paths, &result) {
+            // don't leave half a file behind
+            if out.take().is_some() {
+                let _ = fs::remove_file(&plan.files[i].local);
+            }
+        }
+        self.finish_batch();
+
+        result
+    }
+
+    /// Back up Flipper files and directories into a tar archive,
+    /// writing each file into it as it downloads. Names in the
+    /// archive are the Flipper paths without the leading "/", like
+    /// "ext/nfc/card.nfc". The Flipper doesn't say when files were
+    /// changed in a listing, so everything gets `mtime`. Returns the
+    /// number of files backed up.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths`: Flipper files and directories to back up
+    /// * `archive`: Where to write them
+    /// * `mtime`: Time to give everything in the archive, in seconds
+    ///   since 1970
+    pub async fn backup<W: Write>(&mut self, paths: &[String], archive: &mut TarWriter<W>, mtime: u64) -> Result<usize, Box<dyn Error>> {
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+        for path in paths {
+            if !remote_path::is_root(path) {
+                let f = self.stat(path).await?.ok_or_else(|| format!("{} doesn't exist", path))?;
+                if f.type_ != flipper_pb::storage::file::FileType::DIR.into() {
+                    files.push(PlannedFile { remote: path.clone(), local: PathBuf::new(), size: u64::from(f.size) });
+                    continue;
+                }
+            }
+            dirs.push(path.clone());
+            for (full_path, f) in self.walk(path).await? {
+                if f.type_ == flipper_pb::storage::file::FileType::DIR.into() {
+                    dirs.push(full_path);
+                } else {
+                    files.push(PlannedFile { remote: full_path, local: PathBuf::new(), size: u64::from(f.size) });
+                }
+            }
+        }
+        let archive_name = |path: &str| path.trim_matches('/').to_string();
+
+        // tar doesn't need directories before what's in them, but
+        // it's tidier to extract that way
+        dirs.sort();
+        for d in dirs.iter().filter(|d| !remote_path::is_root(d)) {
+            archive.add_dir(&archive_name(d), mtime)?;
+        }
+
+        let total: u64 = files.iter().map(|f| f.size).sum();
+        self.start_batch(total, files.len());
+        self.log_rssi(total).await;
+        let paths: Vec<&str> = files.iter().map(|f| f.remote.as_str()).collect();
+        let mut progress = FileProgress::new(self, files.len());
+        let mut started = false;
+        let result = self.read_files(&paths, |i, data| {
+            let f = &files[i];
+            if !started {
+                archive.start_file(&archive_name(&f.remote), f.size, mtime)?;
+                started = true;
+            }
+            match data {
+                Some(data) => {
+                    archive.write_data(data)?;
+                    progress.inc(i, f.size, u64::try_from(data.len())?);
+                },
+                None => {
+                    archive.finish_file()?;
+                    started = false;
+                    progress.finish(i, f.size, "backup", &f.remote);
+                },
+            }
+            Ok(())
+        }).await;
+        progress.failed("backup", &paths, &result);
+        self.finish_batch();
+
+        result.map(|_| files.len())
+    }
+
+    /// Create a directory on the Flipper. It's not an error if it
+    /// already exists, but its parent has to.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper path of the directory to create
+    pub async fn mkdir(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_mkdir_request_packet(path)).await?;
+
+        let pb_response = responses.next().await?;
+        debug!("response received: {:?}", pb_response);
+
+        check_busy(&pb_response)?;
+        if pb_response.command_status == flipper_pb::flipper::CommandStatus::OK.into()
+            || pb_response.command_status == flipper_pb::flipper::CommandStatus::ERROR_STORAGE_EXIST.into() {
+            Ok(())
+        } else if pb_response.command_status == flipper_pb::flipper::CommandStatus::ERROR_STORAGE_INVALID_NAME.into() {
+            Err("Invalid name specified!".into())
+        } else {
+            Err(format!("Flipper returned unexpected response: {:?}", pb_response).into())
+        }
+    }
+
+    /// Rename a file or directory. Both paths have to be on the same
+    /// storage; use move_path() to move between /int and /ext.
+    ///
+    /// # Arguments
+    ///
+    /// * `old_path`: Flipper path to rename
+    /// * `new_path`: What to rename it to
+    pub async fn rename(&mut self, old_path: &str, new_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_rename_request_packet(old_path, new_path)).await?;
+
+        let pb_response = responses.next().await?;
+        debug!("response received: {:?}", pb_response);
+
+        check_busy(&pb_response)?;
+        if pb_response.command_status == flipper_pb::flipper::CommandStatus::OK.into() {
+            Ok(())
+        } else if pb_response.command_status == flipper_pb::flipper::CommandStatus::ERROR_STORAGE_EXIST.into() {
+            Err(format!("{} already exists", new_path).into())
+        } else if pb_response.command_status == flipper_pb::flipper::CommandStatus::ERROR_STORAGE_NOT_EXIST.into() {
+            Err(format!("{} doesn't exist", old_path).into())
+        } else {
+            Err(format!("Flipper returned unexpected response: {:?}", pb_response).into())
+        }
+    }
+
+    /// Move a file or directory, even between /int and /ext. The
+    /// firmware can only rename within one storage, so anything
+    /// crossing over is copied, checked against its MD5, and only
+    /// then deleted from where it was.
+    ///
+    /// # Arguments
+    ///
+    /// * `src`: Flipper path to move
+    /// * `dest`: Where to move it to
+    pub async fn move_path(&mut self, src: &str, dest: &str) -> Result<(), Box<dyn Error>> {
+        if storage_root(src) == storage_root(dest) {
+            return self.rename(src, dest).await;
+        }
+
+        let is_dir = match self.stat(src).await? {
+            Some(f) => f.type_ == flipper_pb::storage::file::FileType::DIR.into(),
+            None => return Err(format!("{} doesn't exist", src).into()),
+        };
+        if self.stat(dest).await?.is_some() {
+            return Err(format!("{} already exists", dest).into());
+        }
+
+        if is_dir {
+            self.mkdir(dest).await?;
+            // walk() lists parents before what's in them
+            for (path, f) in self.walk(src).await? {
+                let target = join_remote(dest, &path[src.trim_end_matches('/').len() + 1..]);
+                if f.type_ == flipper_pb::storage::file::FileType::DIR.into() {
+                    self.mkdir(&target).await?;
+                } else {
+                    self.copy_verified(&path, &target).await?;
+                }
+            }
+        } else {
+            self.copy_verified(src, dest).await?;
+        }
+        self.delete_file(src, true).await
+    }
+
+    /// Copy one file on the Flipper by reading it and writing it back,
+    /// then make sure the copy's MD5 matches.
+    async fn copy_verified(&mut self, src: &str, dest: &str) -> Result<(), Box<dyn Error>> {
+        let mut data = Vec::new();
+        self.read_file(src, None, |d| {
+            data.extend_from_slice(d);
+            Ok(())
+        }).await?;
+        self.upload_data(&data, dest).await?;
+
+        let expected = format!("{:x}", md5::compute(&data));
+        let actual = self.md5sum(dest).await?;
+        if !actual.eq_ignore_ascii_case(&expected) {
+            return Err(format!("copy of {} at {} doesn't match (MD5 {}, expected {}), leaving the original alone",
+                               src, dest, actual, expected).into());
+        }
         Ok(())
     }
 
@@ -424,28 +2442,29 @@ impl FlipperBle {
     /// `path`: Flipper path to file to delete
     /// `recursive`: Delete recursively if true
     pub async fn delete_file(&mut self, path: &str, recursive: bool) -> Result<(), Box<dyn Error>> {
-        let rx_chr = self.get_rx_chr();
-        let tx_chr = self.get_tx_chr();
+        let started = time::Instant::now();
+        let result = self.delete_inner(path, recursive).await;
+        log_operation("delete", path, 0, started, &result);
+        result
+    }
 
-        let delete_packet = self.proto.create_delete_request_packet(path, recursive)?;
-        for chunk in delete_packet {
-            self.flipper.write(&rx_chr, &chunk, WriteType::WithoutResponse).await?;
-            time::sleep(Duration::from_millis(FLIPPER_BLE_PROTOBUF_CHUNK_DELAY)).await;
-        }
+    async fn delete_inner(&mut self, path: &str, recursive: bool) -> Result<(), Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_delete_request_packet(path, recursive)).await?;
 
-        let response = self.flipper.read(&tx_chr).await?;
-        let pb_response = ProtobufCodec::parse_response(&response)?;
+        let pb_response = responses.next().await?;
         debug!("response received: {:?}", pb_response);
 
         // If the file doesn't exist, Flipper explicitly returns
         // CommandStatus OK. See
         // https://github.com/flipperdevices/flipperzero-firmware/blob/dev/applications/services/rpc/rpc_storage.c#L550
-        if pb_response.1.command_status == flipper_pb::flipper::CommandStatus::OK.into() {
+        check_busy(&pb_response)?;
+        if pb_response.command_status == flipper_pb::flipper::CommandStatus::OK.into() {
             Ok(())
-        } else if pb_response.1.command_status == flipper_pb::flipper::CommandStatus::ERROR_STORAGE_INVALID_NAME.into() {
-            Err("Invalid name specified!".into())
+        } else if pb_response.command_status == flipper_pb::flipper::CommandStatus::ERROR_STORAGE_INVALID_NAME.into() {
+            Err(Box::new(RpcStatus::new(pb_response.command_status, "Invalid name specified!")))
         } else {
-            Err(format!("Flipper returned unexpected response: {:?}", pb_response).into())
+            Err(Box::new(RpcStatus::new(pb_response.command_status,
+                                        format!("Flipper returned unexpected response: {:?}", pb_response))))
         }
     }
     
@@ -457,95 +2476,311 @@ impl FlipperBle {
     /// `app`: Flipper path to .fap file to launch
     /// `args`: Arguments to the app, can be blank
     pub async fn launch(&mut self, app: &str, args: &str) -> Result<(), Box<dyn Error>> {
-        let rx_chr = self.get_rx_chr();
-        let tx_chr = self.get_tx_chr();
-
-        let launch_packet = self.proto.create_launch_request_packet(app, args)?;
-        for chunk in launch_packet {
-            self.flipper.write(&rx_chr, &chunk, WriteType::WithoutResponse).await?;
-            time::sleep(Duration::from_millis(20)).await;
-        }
+        let mut responses = self.session.request(|p| p.create_launch_request_packet(app, args)).await?;
 
-        // we're expecting just an Ok or something similarly short, so we don't need the loop
-        let response = self.flipper.read(&tx_chr).await?;
-        let pb_response = ProtobufCodec::parse_response(&response)?;
+        // we're expecting just an Ok or something similarly short
+        let pb_response = responses.next().await?;
         debug!("response received: {:?}", pb_response);
 
         // If you try to load a nonexistent file in an app, the app is
         // the one that displays an error. No error is relayed back
         // over RPC.
-        if pb_response.1.command_status == flipper_pb::flipper::CommandStatus::OK.into() {
+        check_busy(&pb_response)?;
+        if pb_response.command_status == flipper_pb::flipper::CommandStatus::OK.into() {
             Ok(())
-        } else if pb_response.1.command_status == flipper_pb::flipper::CommandStatus::ERROR_INVALID_PARAMETERS.into() {
+        } else if pb_response.command_status == flipper_pb::flipper::CommandStatus::ERROR_INVALID_PARAMETERS.into() {
             Err("Application path is invalid!".into())
-        } else if pb_response.1.command_status == flipper_pb::flipper::CommandStatus::ERROR_APP_CANT_START.into() {
+        } else if pb_response.command_status == flipper_pb::flipper::CommandStatus::ERROR_APP_CANT_START.into() {
             Err("App can't start! Did you specify the path to a Flipper app and is the app up to date?".into())
-        } else if pb_response.1.command_status == flipper_pb::flipper::CommandStatus::ERROR_APP_SYSTEM_LOCKED.into() {
+        } else if pb_response.command_status == flipper_pb::flipper::CommandStatus::ERROR_APP_SYSTEM_LOCKED.into() {
             Err("Another app is already running! Close it and try again.".into())
         } else {
             Err(format!("Flipper returned unexpected response: {:?}", pb_response).into())
         }
     }
 
-    /// Print directories and files found at a certain path on the
-    /// Flipper. Path must be less than PROTOBUF_CHUNK_SIZE.
+    /// Upload a capture file to the directory its app expects (if the
+    /// Flipper doesn't already have it) and open it in that app.
     ///
     /// # Arguments
     ///
-    /// * `path`: Flipper path to get listing from
-    pub async fn list(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
-        let rx_chr = self.get_rx_chr();
-        let tx_chr = self.get_tx_chr();
-
-        // the tx char has attribute indicate, and the Flipper expects
-        // the indicate ACK before it will send the next protobuf packet, if has_next is true
-        self.flipper.subscribe(&tx_chr).await?;
-        let mut stream = self.flipper.notifications().await?;
+    /// * `file`: Local `.sub`, `.nfc`, `.ir`, or `.rfid` file
+    pub async fn play(&mut self, file: &Path) -> Result<(), Box<dyn Error>> {
+        let ext = file.extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+
+        let (dir, app) = match PLAY_FILE_TYPES.iter().find(|t| t.0 == ext) {
+            Some(t) => (t.1, t.2),
+            None => {
+                return Err(format!("don't know which app opens {:?} files", ext).into());
+            },
+        };
 
-        // write the list request
-        let list_packet = self.proto.create_list_request_packet(path)?;
-        for chunk in list_packet {
-            self.flipper.write(&rx_chr, &chunk, WriteType::WithoutResponse).await?;
-            // 20 ms seems to work, this is all going into Flipper
-            // memory anyway so it's quick
-            time::sleep(Duration::from_millis(20)).await;
+        let name = match file.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n,
+            None => return Err("invalid local filename".into()),
+        };
+        let dest = format!("{}/{}", dir, name);
+
+        // We don't have checksums, so a file of the same size at the
+        // same path is considered to be the same file.
+        let local_size = fs::metadata(file)?.len();
+        match self.stat(&dest).await? {
+            Some(f) if u64::from(f.size) == local_size => {
+                info!("{} is already on the Flipper, not uploading", dest);
+            },
+            _ => {
+                info!("uploading to {}", dest);
+                self.upload_file(file, &dest).await?;
+            },
         }
 
+        self.launch(app, &dest).await
+    }
+
+    /// Returns the entries in a Flipper directory, in the order the
+    /// Flipper sends them. Path must be less than PROTOBUF_CHUNK_SIZE.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory to list
+    pub async fn list_entries(&mut self, path: &str) -> Result<Vec<flipper_pb::storage::File>, Box<dyn Error>> {
+        self.list_entries_with(path, ListOptions::default()).await
+    }
+
+    /// Like list_entries(), but each file comes with its MD5 in
+    /// `md5sum`, all hashed by the Flipper in one request. Older
+    /// firmware leaves them empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory to list
+    pub async fn list_entries_with_md5(&mut self, path: &str) -> Result<Vec<flipper_pb::storage::File>, Box<dyn Error>> {
+        self.list_entries_with(path, ListOptions { include_md5: true, ..Default::default() }).await
+    }
+
+    /// Like list_entries(), with `options` for what to ask for.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory to list
+    /// * `options`: MD5s and filtering
+    pub async fn list_entries_with(&mut self, path: &str, options: ListOptions) -> Result<Vec<flipper_pb::storage::File>, Box<dyn Error>> {
         let mut entries = Vec::new();
+        self.for_each_entry(path, options, |f| entries.push(f)).await?;
+        Ok(entries)
+    }
+
+    /// Call `on_entry` with each entry in a Flipper directory as it
+    /// arrives, in the order the Flipper sends them. Unlike
+    /// list_entries(), this never holds the whole listing, so it's
+    /// the way to go through a directory with thousands of entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory to list
+    /// * `options`: MD5s and filtering
+    /// * `on_entry`: Called for every entry
+    pub async fn for_each_entry<F>(&mut self, path: &str, options: ListOptions, mut on_entry: F) -> Result<(), Box<dyn Error>>
+    where F: FnMut(flipper_pb::storage::File) {
+        // write the list request
+        let mut responses = self.session.request(|p| p.create_list_request_packet(
+            path, options.include_md5, options.max_size.unwrap_or(0))).await?;
 
         // wait for data from flipper, receiving as long as the
         // has_next field in the protobuf packet is true
-        let mut full_protobuf = Vec::new();
         loop {
-            if let Some(Some(response)) = stream.next().now_or_never() {
-                full_protobuf.extend(response.value);
-                match ProtobufCodec::parse_response(&full_protobuf) {
-                    Ok(m) => {
-                        if let Some(flipper_pb::flipper::main::Content::StorageListResponse(r)) = m.1.content {
-                            for f in r.file {
-                                debug!("complete File block: {:?}", f);
-                                entries.push(f);
-                            }
-                            // if we're on the last packet, stop getting data
-                            if !m.1.has_next {
-                                break;
-                            };
-                        } else if let Some(flipper_pb::flipper::main::Content::Empty(_)) = m.1.content {
-                            debug!("received empty response (bad path)");
-                            return Err("Invalid Flipper path! Check that the path is correct.".into());
-                        } else {
-                            error!("received unexpected protobuf response: {:?}", m.1.content);
-                            return Err("".into());
+            let m = responses.next().await?;
+            if let Some(flipper_pb::flipper::main::Content::StorageListResponse(r)) = m.content {
+                for f in r.file {
+                    debug!("complete File block: {:?}", f);
+                    on_entry(f);
+                }
+                // if we're on the last packet, stop getting data
+                if !m.has_next {
+                    return Ok(());
+                };
+            } else if let Some(flipper_pb::flipper::main::Content::Empty(_)) = m.content {
+                check_busy(&m)?;
+                debug!("received empty response (bad path)");
+                return Err("Invalid Flipper path! Check that the path is correct.".into());
+            } else {
+                error!("received unexpected protobuf response: {:?}", m.content);
+                return Err("".into());
+            }
+        }
+    }
+
+    /// Returns every file and directory below `path` (not including
+    /// `path` itself), with their full Flipper paths.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory to start from
+    pub async fn walk(&mut self, path: &str) -> Result<Vec<(String, flipper_pb::storage::File)>, Box<dyn Error>> {
+        self.walk_filtered(path, &PathFilter::default()).await
+    }
+
+    /// Like walk(), but leaving out what `filter` excludes, without
+    /// listing excluded directories at all.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory to start from
+    /// * `filter`: Paths to leave out, relative to `path`
+    pub async fn walk_filtered(&mut self, path: &str, filter: &PathFilter) -> Result<Vec<(String, flipper_pb::storage::File)>, Box<dyn Error>> {
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        let mut found = Vec::new();
+        // directories we still have to list
+        let mut pending = vec![path.to_string()];
+
+        while let Some(dir) = pending.pop() {
+            for f in self.list_entries(&dir).await? {
+                let full_path = join_remote(&dir, &f.name);
+                let is_dir = f.type_ == flipper_pb::storage::file::FileType::DIR.into();
+                if filter.is_excluded(full_path.trim_start_matches(&prefix), is_dir) {
+                    debug!("skipping {}", full_path);
+                    continue;
+                }
+                if is_dir {
+                    pending.push(full_path.clone());
+                }
+                found.push((full_path, f));
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Returns every file and directory below `path` as a tree to
+    /// compare, without MD5s.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory at the top of the tree
+    pub async fn tree(&mut self, path: &str) -> Result<Vec<TreeEntry>, Box<dyn Error>> {
+        let root = if path == "/" { path } else { path.trim_end_matches('/') };
+        let mut entries: Vec<TreeEntry> = self.walk(root).await?
+            .into_iter()
+            .map(|(full_path, f)| {
+                let dir = f.type_ == flipper_pb::storage::file::FileType::DIR.into();
+                TreeEntry {
+                    path: full_path[root.len()..].trim_start_matches('/').to_string(),
+                    dir,
+                    size: if dir { 0 } else { u64::from(f.size) },
+                    md5: None,
+                }
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    /// Fill in the MD5 of every file in `entries` that `wanted` says
+    /// to. The Flipper hashes them there, so skip the ones that don't
+    /// need it. A directory with more than one file to hash is listed
+    /// with MD5s in a single request instead of asking for each file
+    /// separately, which saves a round trip per file.
+    ///
+    /// # Arguments
+    ///
+    /// * `root`: Flipper directory the entries' paths are relative to
+    /// * `entries`: Entries from tree()
+    /// * `wanted`: Returns whether to hash a file
+    pub async fn hash_tree<F>(&mut self, root: &str, entries: &mut [TreeEntry], wanted: F) -> Result<(), Box<dyn Error>>
+    where F: Fn(&TreeEntry) -> bool {
+        // the files to hash in each directory, by index into entries
+        let mut by_dir: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (i, entry) in entries.iter().enumerate().filter(|(_, e)| !e.dir && wanted(e)) {
+            let dir = entry.path.rsplit_once('/').map_or("", |(dir, _)| dir);
+            by_dir.entry(dir.to_string()).or_default().push(i);
+        }
+
+        for (dir, files) in by_dir {
+            let mut md5s = BTreeMap::new();
+            if files.len() > 1 {
+                let full_dir = if dir.is_empty() { root.to_string() } else { join_remote(root, &dir) };
+                for f in self.list_entries_with_md5(&full_dir).await? {
+                    if !f.md5sum.is_empty() {
+                        md5s.insert(f.name, f.md5sum);
+                    }
+                }
+            }
+
+            for i in files {
+                let entry = &mut entries[i];
+                let name = entry.path.rsplit('/').next().unwrap_or(&entry.path);
+                entry.md5 = Some(match md5s.remove(name) {
+                    Some(md5) => md5,
+                    // only one file, or firmware that doesn't include them
+                    None => self.md5sum(&join_remote(root, &entry.path)).await?,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Expand a Flipper path with wildcards (`*`, `?`, `[...]`) in
+    /// any of its components into the matching paths, sorted. A path
+    /// without wildcards comes back as-is, whether it exists or not.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern`: Flipper path that may contain wildcards
+    pub async fn expand_glob(&mut self, pattern: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let is_wild = |s: &str| s.contains(['*', '?', '[']);
+        if !is_wild(pattern) {
+            return Ok(vec![pattern.to_string()]);
+        }
+
+        // Expand one component at a time, listing a directory only
+        // when the component has a wildcard in it.
+        let mut matches = vec![String::new()];
+        for component in pattern.split('/').filter(|c| !c.is_empty()) {
+            let mut next = Vec::new();
+            for base in &matches {
+                if is_wild(component) {
+                    let glob = glob::Pattern::new(component)?;
+                    let dir = if base.is_empty() { "/" } else { base.as_str() };
+                    // a base that turns out to be a file just doesn't match
+                    let entries = match self.list_entries(dir).await {
+                        Ok(e) => e,
+                        Err(_) => continue,
+                    };
+                    for f in entries {
+                        if glob.matches(&f.name) {
+                            next.push(join_remote(base, &f.name));
                         }
-                        full_protobuf.clear();
-                    },
-                    Err(e) => {
-                        debug!("protobuf error (incomplete packet): {:?}", e);
                     }
-                };
+                } else {
+                    next.push(join_remote(base, component));
+                }
             }
-        };
-        
+            matches = next;
+        }
+
+        matches.sort();
+        Ok(matches)
+    }
+
+    /// Print directories and files found at a certain path on the
+    /// Flipper. Path must be less than PROTOBUF_CHUNK_SIZE.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper path to get listing from
+    /// * `options`: Filtering (MD5s aren't printed)
+    /// * `sorted`: Sort directories and then files by name. Otherwise
+    ///   each entry is printed as soon as it arrives.
+    pub async fn list(&mut self, path: &str, options: ListOptions, sorted: bool) -> Result<(), Box<dyn Error>> {
+        if !sorted {
+            info!("files at Flipper path {:?}:", path);
+            return self.for_each_entry(path, options, |f| print_entry(&f)).await;
+        }
+        let entries = self.list_entries_with(path, options).await?;
+
         // process into dirs and files, and sort by name
         let mut dirs = Vec::new();
         let mut files = Vec::new();
@@ -562,76 +2797,488 @@ impl FlipperBle {
         dirs.sort_by(|a, b| a.name.cmp(&b.name));
         files.sort_by(|a, b| a.name.cmp(&b.name));
 
-        // dirs don't have size
-        for d in dirs {
-            println!(" dir:  {:?}", d.name);
-        }
-
-        for f in files {
-            println!(" file: {:?}, size: {:?}", f.name, f.size);
+        for f in dirs.iter().chain(&files) {
+            print_entry(f);
         }
         
         Ok(())
     }
 
+    /// Returns the total size of the files under each directory below
+    /// `path`, largest first. The last entry is `path` itself unless
+    /// `summary` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `path`: Flipper directory to measure
+    /// * `summary`: Only total up each entry directly in `path`
+    ///   (like `nfc` and `subghz` in `/ext`) instead of every
+    ///   directory
+    pub async fn disk_usage(&mut self, path: &str, summary: bool) -> Result<Vec<(String, u64)>, Box<dyn Error>> {
+        let root = if path == "/" { path } else { path.trim_end_matches('/') };
+        let entries = self.walk(root).await?;
+
+        let mut totals: BTreeMap<String, u64> = BTreeMap::new();
+        let mut root_total = 0;
+        for (full_path, f) in &entries {
+            let is_dir = f.type_ == flipper_pb::storage::file::FileType::DIR.into();
+            let size = if is_dir { 0 } else { u64::from(f.size) };
+            root_total += size;
+
+            let relative = full_path[root.len()..].trim_start_matches('/');
+            let parts: Vec<&str> = relative.split('/').collect();
+
+            if summary {
+                *totals.entry(join_remote(root, parts[0])).or_insert(0) += size;
+            } else if is_dir {
+                // make sure empty directories show up too
+                totals.entry(full_path.clone()).or_insert(0);
+            } else {
+                // count the file in every directory it's inside of
+                let mut dir = root.to_string();
+                for part in &parts[..parts.len() - 1] {
+                    dir = join_remote(&dir, part);
+                    *totals.entry(dir.clone()).or_insert(0) += size;
+                }
+            }
+        }
+
+        let mut totals: Vec<(String, u64)> = totals.into_iter().collect();
+        totals.sort_by_key(|t| std::cmp::Reverse(t.1));
+        if !summary {
+            totals.push((root.to_string(), root_total));
+        }
+
+        Ok(totals)
+    }
+
     /// Play the AV alert on the Flipper to help you find it.
     pub async fn alert(&mut self) -> Result<(), Box<dyn Error>> {
-        let rx_chr = self.get_rx_chr();
+        self.session.send(|p| p.create_alert_request_packet()).await
+    }
+
+    /// Returns true if the Flipper's desktop is locked.
+    pub async fn is_locked(&mut self) -> Result<bool, Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_desktop_is_locked_request_packet().map(|packet| vec![packet])).await?;
+
+        let m = responses.next().await?;
+        debug!("response received: {:?}", m);
+        check_busy(&m)?;
+        // the answer is the status: OK if locked, ERROR if not
+        Ok(m.command_status == flipper_pb::flipper::CommandStatus::OK.into())
+    }
+
+    /// Lock the Flipper's desktop, with the PIN if one is set. There's
+    /// no RPC for locking, so this opens the lock menu with Up and
+    /// picks Lock, which only works when the desktop is showing. If
+    /// the menu doesn't open, Back undoes the Up and nothing else is
+    /// pressed.
+    pub async fn lock(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.is_locked().await? {
+            debug!("already locked");
+            return Ok(());
+        }
+
+        self.press_key(flipper_pb::gui::InputKey::UP, false).await?;
+        time::sleep(LOCK_MENU_DELAY).await;
+        if !is_lock_menu(&self.screenshot().await?) {
+            // back out of whatever did get the press
+            self.press_key(flipper_pb::gui::InputKey::BACK, false).await?;
+            return Err("the lock menu didn't open, close any open app so the desktop is showing".into());
+        }
+        self.press_key(flipper_pb::gui::InputKey::OK, false).await?;
+        time::sleep(LOCK_MENU_DELAY).await;
+
+        if self.is_locked().await? {
+            Ok(())
+        } else {
+            Err("the Flipper didn't lock, close any open app so the desktop is showing".into())
+        }
+    }
 
-        // only one chunk
-        let packet = self.proto.create_alert_request_packet()?;
-        self.flipper.write(&rx_chr, &packet, WriteType::WithoutResponse).await?;
+    /// Wait for the empty OK the Flipper answers some requests with.
+    ///
+    /// # Arguments
+    ///
+    /// * `responses`: Responses to the request
+    async fn expect_ok(&mut self, mut responses: Responses) -> Result<(), Box<dyn Error>> {
+        let m = responses.next().await?;
+        debug!("response received: {:?}", m);
+        check_busy(&m)?;
+        if m.command_status != flipper_pb::flipper::CommandStatus::OK.into() {
+            return Err(format!("Flipper returned error: {:?}", m.command_status).into());
+        }
+        Ok(())
+    }
+
+    /// Make a GPIO pin an input or an output.
+    ///
+    /// # Arguments
+    ///
+    /// * `pin`: Pin to set up
+    /// * `mode`: Input or output
+    pub async fn gpio_set_mode(&mut self, pin: flipper_pb::gpio::GpioPin, mode: flipper_pb::gpio::GpioPinMode) -> Result<(), Box<dyn Error>> {
+        let responses = self.session.request(|p| p.create_gpio_set_pin_mode_request_packet(pin, mode).map(|packet| vec![packet])).await?;
+        self.expect_ok(responses).await
+    }
+
+    /// Set a GPIO output pin high (1) or low (0).
+    ///
+    /// # Arguments
+    ///
+    /// * `pin`: Pin to set, which has to be an output
+    /// * `value`: 1 for high or 0 for low
+    pub async fn gpio_write(&mut self, pin: flipper_pb::gpio::GpioPin, value: u32) -> Result<(), Box<dyn Error>> {
+        let responses = self.session.request(|p| p.create_gpio_write_pin_request_packet(pin, value).map(|packet| vec![packet])).await?;
+        self.expect_ok(responses).await
+    }
+
+    /// Returns whether a GPIO pin is high (1) or low (0).
+    ///
+    /// # Arguments
+    ///
+    /// * `pin`: Pin to read
+    pub async fn gpio_read(&mut self, pin: flipper_pb::gpio::GpioPin) -> Result<u32, Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_gpio_read_pin_request_packet(pin).map(|packet| vec![packet])).await?;
+
+        let m = responses.next().await?;
+        debug!("response received: {:?}", m);
+        if let Some(flipper_pb::flipper::main::Content::GpioReadPinResponse(r)) = m.content {
+            Ok(r.value)
+        } else {
+            check_busy(&m)?;
+            Err(format!("Flipper returned unexpected response: {:?}", m).into())
+        }
+    }
+
+    /// Collect the answer to a request that's a stream of key/value
+    /// messages, until the Flipper says there are no more.
+    ///
+    /// # Arguments
+    ///
+    /// * `responses`: Responses to the request
+    /// * `extract`: Pulls the key and value out of each response,
+    ///   returns None for the wrong kind of response
+    async fn collect_key_values<F>(&mut self, mut responses: Responses, extract: F) -> Result<Vec<(String, String)>, Box<dyn Error>>
+    where F: Fn(&flipper_pb::flipper::main::Content) -> Option<(String, String)> {
+        // Each key/value pair is its own (small) message.
+        let mut values = Vec::new();
+        loop {
+            let m = responses.next().await?;
+            check_busy(&m)?;
+            if m.command_status != flipper_pb::flipper::CommandStatus::OK.into() {
+                return Err(format!("Flipper returned error: {:?}", m.command_status).into());
+            }
+
+            match m.content {
+                // some firmware ends the list with an empty message
+                None | Some(flipper_pb::flipper::main::Content::Empty(_)) => {},
+                Some(ref content) => {
+                    match extract(content) {
+                        Some((key, value)) => {
+                            debug!("{} = {}", key, value);
+                            values.push((key, value));
+                        },
+                        None => {
+                            error!("received unexpected protobuf response: {:?}", m.content);
+                            return Err("".into());
+                        },
+                    }
+                },
+            }
+
+            if !m.has_next {
+                return Ok(values);
+            }
+        }
+    }
+
+    /// Returns every key/value pair the Flipper reports in response
+    /// to a DeviceInfoRequest, in the order it sent them.
+    pub async fn device_info(&mut self) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let responses = self.session.request(|p| p.create_device_info_request_packet().map(|packet| vec![packet])).await?;
+        self.collect_key_values(responses, |c| match c {
+            flipper_pb::flipper::main::Content::SystemDeviceInfoResponse(r) => Some((r.key.clone(), r.value.clone())),
+            _ => None,
+        }).await
+    }
+
+    /// Returns the key/value pairs for a property key. Keys ending in
+    /// `.` are prefixes and return every property under them (like
+    /// `devinfo.` or `pwrinfo.`).
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: Property key or prefix to get
+    pub async fn property_get(&mut self, key: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let responses = self.session.request(|p| p.create_property_get_request_packet(key)).await?;
+        self.collect_key_values(responses, |c| match c {
+            flipper_pb::flipper::main::Content::PropertyGetResponse(r) => Some((r.key.clone(), r.value.clone())),
+            _ => None,
+        }).await
+    }
+
+    /// Stream the Flipper's screen, calling `on_frame` with each new
+    /// frame until it returns false or Ctrl-C is pressed.
+    ///
+    /// # Arguments
+    ///
+    /// * `on_frame`: Called for every frame, return false to stop
+    pub async fn stream_screen<F>(&mut self, on_frame: F) -> Result<(), Box<dyn Error>>
+    where F: FnMut(&Frame) -> bool {
+        self.stream_screen_for(None, on_frame).await
+    }
+
+    /// Like stream_screen(), but also stops after `timeout`. The
+    /// Flipper only sends a frame when the screen changes, so this is
+    /// how to stop waiting for one that might never come.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout`: Longest time to stream for, or None for no limit
+    /// * `on_frame`: Called for every frame, return false to stop
+    pub async fn stream_screen_for<F>(&mut self, timeout: Option<Duration>, on_frame: F) -> Result<(), Box<dyn Error>>
+    where F: FnMut(&Frame) -> bool {
+        let stop = async {
+            let deadline = async {
+                match timeout {
+                    Some(t) => time::sleep(t).await,
+                    None => future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => debug!("got Ctrl-C, stopping screen stream"),
+                _ = deadline => debug!("screen stream timed out"),
+            }
+        };
+        stream_screen_until(&self.session, stop, on_frame).await
+    }
+
+    /// Returns what's on the Flipper's screen right now.
+    pub async fn screenshot(&mut self) -> Result<Frame, Box<dyn Error>> {
+        let mut shot = None;
+        self.stream_screen(|frame| {
+            shot = Some(frame.clone());
+            false
+        }).await?;
+        shot.ok_or_else(|| "no frame came from the Flipper".into())
+    }
+
+    /// Replace the Flipper's screen with `frame` until
+    /// stop_virtual_display() is called or the connection closes.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame`: What to show on the screen
+    pub async fn start_virtual_display(&mut self, frame: &Frame) -> Result<(), Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_start_virtual_display_request_packet(frame.to_pb())).await?;
+
+        let response = responses.next().await?;
+        debug!("response received: {:?}", response);
+
+        check_busy(&response)?;
+        if response.command_status == flipper_pb::flipper::CommandStatus::OK.into() {
+            Ok(())
+        } else if response.command_status == flipper_pb::flipper::CommandStatus::ERROR_VIRTUAL_DISPLAY_ALREADY_STARTED.into() {
+            Err("something else is already using the virtual display".into())
+        } else {
+            Err(format!("Flipper returned unexpected response: {:?}", response).into())
+        }
+    }
+
+    /// Give the screen back to the Flipper after
+    /// start_virtual_display().
+    pub async fn stop_virtual_display(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut responses = self.session.request(|p| p.create_stop_virtual_display_request_packet().map(|packet| vec![packet])).await?;
+
+        let response = responses.next().await?;
+        debug!("response received: {:?}", response);
+        if response.command_status == flipper_pb::flipper::CommandStatus::OK.into() {
+            Ok(())
+        } else {
+            Err(format!("Flipper returned unexpected response: {:?}", response).into())
+        }
+    }
+
+    /// Press and release a button on the Flipper, just like pressing
+    /// the real button.
+    ///
+    /// # Arguments
+    ///
+    /// * `key`: Button to press
+    /// * `long`: Hold the button down long enough to count as a long
+    ///   press
+    pub async fn press_key(&mut self, key: flipper_pb::gui::InputKey, long: bool) -> Result<(), Box<dyn Error>> {
+        // This is the same sequence of events the firmware's input
+        // service generates for a physical button.
+        let press_type = if long {
+            flipper_pb::gui::InputType::LONG
+        } else {
+            flipper_pb::gui::InputType::SHORT
+        };
+        let events = [
+            flipper_pb::gui::InputType::PRESS,
+            press_type,
+            flipper_pb::gui::InputType::RELEASE,
+        ];
+
+        for input_type in events {
+            // only one chunk
+            self.session.send(|p| p.create_input_event_request_packet(key, input_type)).await?;
+            time::sleep(Duration::from_millis(FLIPPER_INPUT_EVENT_DELAY)).await;
+        }
+
+        Ok(())
+    }
+
+    /// Press a series of buttons one after another.
+    ///
+    /// # Arguments
+    ///
+    /// * `presses`: Buttons to press, in order
+    /// * `delay`: Time to wait after each press, so the app on the
+    ///   Flipper can keep up
+    pub async fn press_sequence(&mut self, presses: &[KeyPress], delay: Duration) -> Result<(), Box<dyn Error>> {
+        for p in presses {
+            debug!("pressing {:?} (long: {})", p.key, p.long);
+            self.press_key(p.key, p.long).await?;
+            time::sleep(delay).await;
+        }
+
+        Ok(())
+    }
+
+    /// Play back a recorded macro, waiting before each press as long
+    /// as was waited when it was recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `recording`: Presses and the time between them
+    pub async fn play_macro(&mut self, recording: &Macro) -> Result<(), Box<dyn Error>> {
+        for step in &recording.steps {
+            time::sleep(step.delay).await;
+            debug!("pressing {} after {:?}", step.press, step.delay);
+            self.press_key(step.press.key, step.press.long).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Type `text` into the on-screen keyboard of whatever app is
+    /// open, by moving around the keyboard and pressing OK.
+    ///
+    /// # Arguments
+    ///
+    /// * `text`: Text to type, only characters on the keyboard work
+    /// * `keyboard`: Layout of the on-screen keyboard
+    /// * `enter`: Press the keyboard's Enter key when done
+    pub async fn type_text(&mut self, text: &str, keyboard: &Keyboard, enter: bool) -> Result<(), Box<dyn Error>> {
+        // plan everything first so that a bad character doesn't
+        // leave half the text typed
+        let presses = keyboard.plan(text, enter)?;
+        debug!("typing {:?} with {} presses", text, presses.len());
+
+        for p in presses {
+            self.press_key(p.key, p.long).await?;
+        }
 
         Ok(())
     }
 
-    /// Sync the Flipper's date and time to the computer's date and time.
-    pub async fn sync_datetime(&mut self) -> Result<(), Box<dyn Error>> {
+    /// Sync the Flipper's date and time to the computer's date and
+    /// time. Returns how far off the Flipper was beforehand, in
+    /// milliseconds (computer time minus Flipper time). The Flipper
+    /// only reports whole seconds, so it's never more accurate than
+    /// that.
+    pub async fn sync_datetime(&mut self) -> Result<i64, Box<dyn Error>> {
         // things in this function are a little out of order for
         // Flipper time accuracy, even if it doesn't really matter
-        let rx_chr = self.get_rx_chr();
-        let tx_chr = self.get_tx_chr();
-
-        // no chunking here
-        let request = self.proto.create_get_datetime_request_packet()?;
-        self.flipper.write(&rx_chr, &request, WriteType::WithoutResponse).await?;
+        let mut responses = self.session.request(|p| p.create_get_datetime_request_packet().map(|packet| vec![packet])).await?;
         let mut now = chrono::Local::now();
         // only one packet comes in response
-        let response = self.flipper.read(&tx_chr).await?;
-
-        match ProtobufCodec::parse_response(&response) {
-            Ok(m) => {
-                if let Some(flipper_pb::flipper::main::Content::SystemGetDatetimeResponse(r)) = m.1.content {
-                    // calculate time skew
-                    let flipper_time = chrono::Local.with_ymd_and_hms(
-                        r.datetime.year as i32,
-                        r.datetime.month,
-                        r.datetime.day,
-                        r.datetime.hour,
-                        r.datetime.minute,
-                        r.datetime.second,
-                    ).unwrap();
-
-                    info!("Flipper time skew in ms: {:?}", (now - flipper_time).num_milliseconds());
-                } else {
-                    error!("received unexpected protobuf response: {:?}", m.1.content);
-                    return Err("".into());
-                }
-            },
-            Err(e) => {
-                error!("protobuf error: {:?}", e);
-            },
+        let m = responses.next().await?;
+
+        let skew = if let Some(flipper_pb::flipper::main::Content::SystemGetDatetimeResponse(r)) = m.content {
+            // calculate time skew. A time that happens twice when the
+            // clocks go back is taken as the first one; one that never
+            // happens (or an RTC reset to day 0) can't be compared.
+            let flipper_time = chrono::Local.with_ymd_and_hms(
+                r.datetime.year as i32,
+                r.datetime.month,
+                r.datetime.day,
+                r.datetime.hour,
+                r.datetime.minute,
+                r.datetime.second,
+            ).earliest().ok_or_else(|| format!("the Flipper's clock reads {}-{:02}-{:02} {:02}:{:02}:{:02}, which isn't a valid local time",
+                                               r.datetime.year, r.datetime.month, r.datetime.day,
+                                               r.datetime.hour, r.datetime.minute, r.datetime.second))?;
+
+            let skew = (now - flipper_time).num_milliseconds();
+            info!("Flipper time skew in ms: {:?}", skew);
+            skew
+        } else {
+            check_busy(&m)?;
+            error!("received unexpected protobuf response: {:?}", m.content);
+            return Err("".into());
         };
 
         // recalculate time for update
         now = chrono::Local::now();
-        let packet = self.proto.create_set_datetime_request_packet(now.into())?;
-        self.flipper.write(&rx_chr, &packet, WriteType::WithoutResponse).await?;
+        self.session.send(|p| p.create_set_datetime_request_packet(now.into())).await?;
 
         debug!("using datetime {:?}", now);
         
-        Ok(())
+        Ok(skew)
     }
 
 }
 
+impl Drop for FlipperBle {
+    fn drop(&mut self) {
+        self.link_watcher.abort();
+    }
+}
+
+/// Stream the screen over `session` until `stop` finishes or
+/// `on_frame` returns false. This only needs the session, so it can
+/// run alongside other requests on the same connection, like
+/// mirroring the screen during a sync. Only one screen stream can run
+/// at a time.
+///
+/// # Arguments
+///
+/// * `session`: Session with a connected Flipper
+/// * `stop`: Stops the stream when it finishes
+/// * `on_frame`: Called for every frame, return false to stop
+pub async fn stream_screen_until<S, F>(session: &Session, stop: S, mut on_frame: F) -> Result<(), Box<dyn Error>>
+where S: std::future::Future<Output = ()>, F: FnMut(&Frame) -> bool {
+    // Frames aren't a response to anything, so they come with the
+    // unsolicited command ID.
+    let mut frames = session.subscribe(UNSOLICITED_COMMAND_ID);
+
+    // only one chunk
+    session.send(|p| p.create_start_screen_stream_request_packet()).await?;
+
+    // Frames keep coming until we ask the Flipper to stop.
+    tokio::pin!(stop);
+    loop {
+        tokio::select! {
+            m = frames.next() => {
+                let m = m?;
+                match m.content {
+                    Some(flipper_pb::flipper::main::Content::GuiScreenFrame(f)) => {
+                        if !on_frame(&Frame::from_pb(&f)) {
+                            break;
+                        }
+                    },
+                    _ => {
+                        debug!("ignoring message during screen stream: {:?}", m);
+                    },
+                }
+            },
+            _ = &mut stop => break,
+        }
+    }
+
+    session.send(|p| p.create_stop_screen_stream_request_packet()).await
+}
@@ -0,0 +1,163 @@
+// Keeping writes within the Flipper's serial buffer. The Flipper says
+// how many bytes its buffer has room for on the flow control
+// characteristic, as a 32-bit big-endian number, whenever that
+// changes. Writing more than that before it's had time to empty the
+// buffer gets logged on the Flipper as
+// `Received 245, while was ready to receive 37 bytes`, and can lose
+// data. So every piece written takes its size out of the room left,
+// and once there isn't enough, the next piece waits for the Flipper
+// to say there's more.
+//
+// Like the RPC reader, this only needs the raw notification values,
+// not BLE, and the transport's clock rather than tokio's.
+
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::{self, AbortHandle, Either};
+use futures::{Future, Stream, StreamExt};
+use tokio::sync::Notify;
+
+use crate::transport::Transport;
+
+// How long to wait for the Flipper to make room before writing anyway.
+// It only notifies when the buffer changes, so if a notification gets
+// lost, waiting forever would hang the transfer.
+const ROOM_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Default)]
+struct Space {
+    // None until the Flipper says
+    free: Mutex<Option<usize>>,
+    changed: Notify,
+}
+
+impl Space {
+    fn update(&self, free: usize) {
+        trace!("Flipper has room for {} bytes", free);
+        *self.free.lock().unwrap() = Some(free);
+        self.changed.notify_waiters();
+    }
+}
+
+pub struct FlowControl {
+    space: Arc<Space>,
+    // stops following the notifications
+    task: AbortHandle,
+}
+
+impl FlowControl {
+    /// Start following the buffer space the Flipper reports on a new
+    /// task.
+    ///
+    /// # Arguments
+    ///
+    /// * `initial`: The flow control characteristic's value right
+    ///              now, if it could be read
+    /// * `stream`: Data of every flow control notification
+    pub fn spawn<S>(initial: Option<Vec<u8>>, stream: S) -> FlowControl
+    where S: Stream<Item = Vec<u8>> + Send + Unpin + 'static {
+        let (flow, task) = FlowControl::new(initial, stream);
+        tokio::spawn(task);
+        flow
+    }
+
+    /// Returns flow control for the buffer space the Flipper reports,
+    /// and the task that follows it, for running on something other
+    /// than tokio. It finishes once the stream ends or the flow
+    /// control is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `initial`: The flow control characteristic's value right
+    ///              now, if it could be read
+    /// * `stream`: Data of every flow control notification
+    pub fn new<S>(initial: Option<Vec<u8>>, mut stream: S) -> (FlowControl, impl Future<Output = ()> + Send + 'static)
+    where S: Stream<Item = Vec<u8>> + Send + Unpin + 'static {
+        let space = Arc::new(Space::default());
+        if let Some(free) = initial.as_deref().and_then(parse_free) {
+            space.update(free);
+        }
+
+        let (task, abort) = future::abortable({
+            let space = space.clone();
+            async move {
+                while let Some(value) = stream.next().await {
+                    match parse_free(&value) {
+                        Some(free) => space.update(free),
+                        None => debug!("ignoring flow control value {:?}", value),
+                    }
+                }
+                debug!("flow control stream ended");
+            }
+        });
+
+        (FlowControl {
+            space,
+            task: abort,
+        }, async move {
+            let _ = task.await;
+        })
+    }
+
+    /// Wait until the Flipper has room for `bytes` more, and count
+    /// them as sent. Returns right away if the Flipper hasn't said
+    /// how much room it has.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: Size of the piece about to be written
+    /// * `transport`: What it's written with, for timing out
+    pub async fn reserve(&self, bytes: usize, transport: &dyn Transport) {
+        // only started once there's something to wait for
+        let mut timeout = None;
+        loop {
+            // made before checking, so an update in between still
+            // wakes it up
+            let changed = self.space.changed.notified();
+            {
+                let mut free = self.space.free.lock().unwrap();
+                match *free {
+                    Some(f) if f < bytes => {},
+                    Some(f) => {
+                        *free = Some(f - bytes);
+                        return;
+                    },
+                    None => return,
+                }
+            }
+
+            let timeout = timeout.get_or_insert_with(|| transport.sleep(ROOM_TIMEOUT));
+            if let Either::Right(_) = future::select(pin!(changed), timeout).await {
+                debug!("Flipper didn't make room for {} bytes in {:?}, writing anyway", bytes, ROOM_TIMEOUT);
+                *self.space.free.lock().unwrap() = Some(0);
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for FlowControl {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn parse_free(value: &[u8]) -> Option<usize> {
+    let bytes: [u8; 4] = value.try_into().ok()?;
+    usize::try_from(u32::from_be_bytes(bytes)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_free_test() {
+        assert_eq!(parse_free(&[0, 0, 4, 0]), Some(1024));
+        assert_eq!(parse_free(&[0, 0, 0, 0]), Some(0));
+        assert_eq!(parse_free(&[0, 4, 0]), None);
+        assert_eq!(parse_free(&[]), None);
+    }
+}
@@ -0,0 +1,76 @@
+// Names for the Flipper's GPIO pins. The RPC numbers them 0 to 7 in
+// its own order, but people know them by the port names and header
+// pin numbers printed on the Flipper, so all three work.
+
+use crate::flipper_pb::gpio::{GpioPin, GpioPinMode};
+
+/// A GPIO pin and everything it goes by
+pub struct PinInfo {
+    pub pin: GpioPin,
+    /// Port name, like "PA7"
+    pub port: &'static str,
+    /// Pin number on the GPIO header, as printed on the Flipper
+    pub header: u8,
+}
+
+/// Every pin the RPC can control, in header order
+pub const PINS: [PinInfo; 8] = [
+    PinInfo { pin: GpioPin::PA7, port: "PA7", header: 2 },
+    PinInfo { pin: GpioPin::PA6, port: "PA6", header: 3 },
+    PinInfo { pin: GpioPin::PA4, port: "PA4", header: 4 },
+    PinInfo { pin: GpioPin::PB3, port: "PB3", header: 5 },
+    PinInfo { pin: GpioPin::PB2, port: "PB2", header: 6 },
+    PinInfo { pin: GpioPin::PC3, port: "PC3", header: 7 },
+    PinInfo { pin: GpioPin::PC1, port: "PC1", header: 15 },
+    PinInfo { pin: GpioPin::PC0, port: "PC0", header: 16 },
+];
+
+/// Returns the pin `name` refers to: a port name like "PA7" or
+/// "pa7", a header pin number like "2", or the RPC's own number with
+/// "idx:" in front, like "idx:7".
+pub fn parse_pin(name: &str) -> Result<GpioPin, String> {
+    let name = name.trim();
+    let found = if let Some(index) = name.strip_prefix("idx:") {
+        index.parse::<i32>().ok()
+            .and_then(|i| PINS.iter().find(|p| p.pin as i32 == i))
+    } else if let Ok(header) = name.parse::<u8>() {
+        PINS.iter().find(|p| p.header == header)
+    } else {
+        PINS.iter().find(|p| p.port.eq_ignore_ascii_case(name))
+    };
+
+    match found {
+        Some(p) => Ok(p.pin),
+        None => Err(format!("no GPIO pin {:?}, run `gpio pins` to see them", name)),
+    }
+}
+
+/// Returns the port name of `pin`, like "PA7".
+pub fn port_name(pin: GpioPin) -> &'static str {
+    PINS.iter().find(|p| p.pin == pin).map_or("?", |p| p.port)
+}
+
+/// Which way a pin goes
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug)]
+pub enum PinMode {
+    Input,
+    Output,
+}
+
+impl PinMode {
+    pub fn to_pb(self) -> GpioPinMode {
+        match self {
+            PinMode::Input => GpioPinMode::INPUT,
+            PinMode::Output => GpioPinMode::OUTPUT,
+        }
+    }
+}
+
+/// Print the GPIO pin table.
+pub fn print_pins() {
+    println!("{:<6}  {:<4}  index", "header", "port");
+    for p in &PINS {
+        println!("{:<6}  {:<4}  {}", p.header, p.port, p.pin as i32);
+    }
+}
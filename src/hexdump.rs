@@ -0,0 +1,70 @@
+// Canonical hex+ASCII dump, the same layout as `hexdump -C`:
+//
+// 00000100  48 65 6c 6c 6f 2c 20 46  6c 69 70 70 65 72 21 0a  |Hello, Flipper!.|
+// 00000110
+
+/// Format `data` as a canonical hex dump, numbering lines from
+/// `start` (the offset of `data[0]` in the file).
+pub fn format(data: &[u8], start: u64) -> String {
+    let mut out = String::new();
+
+    for (i, line) in data.chunks(16).enumerate() {
+        out.push_str(&format!("{:08x}  ", start + (i as u64) * 16));
+
+        for col in 0..16 {
+            match line.get(col) {
+                Some(b) => out.push_str(&format!("{:02x} ", b)),
+                None => out.push_str("   "),
+            }
+            // extra space between the two groups of 8
+            if col == 7 {
+                out.push(' ');
+            }
+        }
+
+        out.push_str(" |");
+        for b in line {
+            out.push(if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' });
+        }
+        out.push_str("|\n");
+    }
+
+    // the last line is the offset just past the end
+    out.push_str(&format!("{:08x}\n", start + data.len() as u64));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_full_line_test() {
+        assert_eq!(format(b"Hello, Flipper!\n", 0x100),
+                   "00000100  48 65 6c 6c 6f 2c 20 46  6c 69 70 70 65 72 21 0a  |Hello, Flipper!.|\n\
+                    00000110\n");
+    }
+
+    #[test]
+    fn format_partial_line_test() {
+        assert_eq!(format(&[0x00, 0x41, 0x7f, 0xff, b' '], 0),
+                   "00000000  00 41 7f ff 20                                    |.A.. |\n\
+                    00000005\n");
+    }
+
+    #[test]
+    fn format_several_lines_test() {
+        let data: Vec<u8> = (0..20).collect();
+        let out = format(&data, 16);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("00000010  00 01 02 03 04 05 06 07  08 09 0a 0b"));
+        assert!(lines[1].starts_with("00000020  10 11 12 13  "));
+        assert_eq!(lines[2], "00000024");
+    }
+
+    #[test]
+    fn format_empty_test() {
+        assert_eq!(format(&[], 0x20), "00000020\n");
+    }
+}
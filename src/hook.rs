@@ -0,0 +1,12 @@
+// Running the commands people give flipwire to run when something
+// happens, like `screen watch --on-change` or `daemon --low-battery`.
+
+use std::process;
+
+/// Returns a Command that runs `command` with the system shell.
+pub fn command(command: &str) -> process::Command {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let mut c = process::Command::new(shell);
+    c.args([flag, command]);
+    c
+}
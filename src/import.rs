@@ -0,0 +1,341 @@
+// Turning dumps and lists from other tools into files the Flipper's
+// NFC and 125 kHz RFID apps can open. Everything here only builds the
+// file contents; uploading them is up to the caller.
+
+use std::fmt::Write;
+
+/// Where the NFC app keeps its files
+pub const NFC_DIR: &str = "/ext/nfc";
+/// Where the 125 kHz RFID app keeps its files
+pub const RFID_DIR: &str = "/ext/lfrfid";
+
+const MFC_BLOCK_SIZE: usize = 16;
+
+/// Returns `bytes` as uppercase hex pairs separated by spaces, the way
+/// Flipper files write them.
+pub fn hex_spaced(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join(" ")
+}
+
+/// Returns the bytes in `text`, which is hex with or without spaces
+/// or colons between the bytes, like "04:A1:B2:C3" or "04a1b2c3".
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text.chars().filter(|c| !c.is_whitespace() && *c != ':').collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{:?} isn't a whole number of hex bytes", text));
+    }
+    Ok((0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+/// Returns the blocks in a Proxmark3 MIFARE Classic dump, either a
+/// binary `.bin` dump or a `.eml` dump with one block of hex per line.
+pub fn parse_proxmark_dump(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let bytes = match std::str::from_utf8(data) {
+        // .eml dumps are text, .bin dumps almost never are
+        Ok(text) if text.lines().any(|l| !l.trim().is_empty()) && text.lines().all(|l| parse_hex(l).is_ok() || l.trim().is_empty()) => {
+            let mut bytes = Vec::new();
+            for (n, line) in text.lines().filter(|l| !l.trim().is_empty()).enumerate() {
+                let block = parse_hex(line)?;
+                if block.len() != MFC_BLOCK_SIZE {
+                    return Err(format!("block {} is {} bytes long, not {}", n, block.len(), MFC_BLOCK_SIZE));
+                }
+                bytes.extend(block);
+            }
+            bytes
+        },
+        _ => data.to_vec(),
+    };
+
+    if !bytes.len().is_multiple_of(MFC_BLOCK_SIZE) || mfc_type(bytes.len() / MFC_BLOCK_SIZE).is_none() {
+        return Err(format!("a {}-byte dump isn't a MIFARE Classic Mini, 1K, 2K, or 4K", bytes.len()));
+    }
+    Ok(bytes.chunks(MFC_BLOCK_SIZE).map(|b| b.to_vec()).collect())
+}
+
+/// Returns the Flipper's name for a MIFARE Classic card with `blocks`
+/// blocks.
+fn mfc_type(blocks: usize) -> Option<&'static str> {
+    match blocks {
+        20 => Some("MINI"),
+        64 => Some("1K"),
+        128 => Some("2K"),
+        256 => Some("4K"),
+        _ => None,
+    }
+}
+
+/// Returns the contents of a Flipper `.nfc` file for the MIFARE
+/// Classic card in `blocks`. The UID, ATQA, and SAK come from the
+/// manufacturer block.
+///
+/// # Arguments
+///
+/// * `blocks`: Every block on the card, as from parse_proxmark_dump()
+pub fn mfc_to_nfc(blocks: &[Vec<u8>]) -> Result<String, String> {
+    let card_type = mfc_type(blocks.len()).ok_or(format!("{} blocks isn't a MIFARE Classic card", blocks.len()))?;
+    let block0 = &blocks[0];
+
+    // A 4-byte UID is followed by its BCC (the XOR of the UID bytes),
+    // then SAK and ATQA. Anything else is a 7-byte UID.
+    let bcc = block0[..4].iter().fold(0, |acc, b| acc ^ b);
+    let (uid, sak, atqa) = if bcc == block0[4] {
+        (&block0[..4], block0[5], [block0[7], block0[6]])
+    } else {
+        (&block0[..7], block0[7], [block0[9], block0[8]])
+    };
+
+    let mut nfc = String::new();
+    writeln!(nfc, "Filetype: Flipper NFC device").unwrap();
+    writeln!(nfc, "Version: 4").unwrap();
+    writeln!(nfc, "# Imported by flipwire from a Proxmark3 dump").unwrap();
+    writeln!(nfc, "Device type: Mifare Classic").unwrap();
+    writeln!(nfc, "# UID is common for all formats").unwrap();
+    writeln!(nfc, "UID: {}", hex_spaced(uid)).unwrap();
+    writeln!(nfc, "# ISO14443-3A specific data").unwrap();
+    writeln!(nfc, "ATQA: {}", hex_spaced(&atqa)).unwrap();
+    writeln!(nfc, "SAK: {:02X}", sak).unwrap();
+    writeln!(nfc, "# Mifare Classic specific data").unwrap();
+    writeln!(nfc, "Mifare Classic type: {}", card_type).unwrap();
+    writeln!(nfc, "Data format version: 2").unwrap();
+    writeln!(nfc, "# Mifare Classic blocks, '??' means unknown data").unwrap();
+    for (n, block) in blocks.iter().enumerate() {
+        writeln!(nfc, "Block {}: {}", n, hex_spaced(block)).unwrap();
+    }
+    Ok(nfc)
+}
+
+/// Returns the UIDs in a list. Without `uid_len`, the list is text
+/// with one UID in hex per line, and blank lines and lines starting
+/// with "#" are skipped. With it, the list is binary, and every
+/// `uid_len` bytes is a UID.
+pub fn parse_uid_list(data: &[u8], uid_len: Option<usize>) -> Result<Vec<Vec<u8>>, String> {
+    if let Some(len) = uid_len {
+        if len == 0 || !data.len().is_multiple_of(len) {
+            return Err(format!("a {}-byte list isn't a whole number of {}-byte UIDs", data.len(), len));
+        }
+        return Ok(data.chunks(len).map(|c| c.to_vec()).collect());
+    }
+
+    let text = std::str::from_utf8(data)
+        .map_err(|_| "the list isn't text, use --uid-len for a binary list".to_string())?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+        .map(|(n, l)| parse_hex(l).map_err(|e| format!("line {}: {}", n + 1, e)))
+        .collect()
+}
+
+/// Returns the contents of a Flipper `.nfc` file that emulates just
+/// `uid`, as an ISO14443-3A card.
+pub fn uid_to_nfc(uid: &[u8]) -> Result<String, String> {
+    // the ATQA says how long the UID is
+    let atqa = match uid.len() {
+        4 => "00 04",
+        7 => "00 44",
+        10 => "00 84",
+        n => return Err(format!("{}-byte UIDs aren't possible, only 4, 7, or 10", n)),
+    };
+
+    let mut nfc = String::new();
+    writeln!(nfc, "Filetype: Flipper NFC device").unwrap();
+    writeln!(nfc, "Version: 4").unwrap();
+    writeln!(nfc, "# Imported by flipwire from a UID list").unwrap();
+    writeln!(nfc, "Device type: ISO14443-3A").unwrap();
+    writeln!(nfc, "# UID is common for all formats").unwrap();
+    writeln!(nfc, "UID: {}", hex_spaced(uid)).unwrap();
+    writeln!(nfc, "# ISO14443-3A specific data").unwrap();
+    writeln!(nfc, "ATQA: {}", atqa).unwrap();
+    writeln!(nfc, "SAK: 00").unwrap();
+    Ok(nfc)
+}
+
+/// Returns the contents of a Flipper `.rfid` file for the EM4100 tag
+/// with ID `id`.
+pub fn em4100_to_rfid(id: &[u8]) -> Result<String, String> {
+    if id.len() != 5 {
+        return Err(format!("EM4100 IDs are 5 bytes, not {}", id.len()));
+    }
+
+    let mut rfid = String::new();
+    writeln!(rfid, "Filetype: Flipper RFID key").unwrap();
+    writeln!(rfid, "Version: 1").unwrap();
+    writeln!(rfid, "Key type: EM4100").unwrap();
+    writeln!(rfid, "Data: {}", hex_spaced(id)).unwrap();
+    Ok(rfid)
+}
+
+/// What each UID in a list is turned into
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug)]
+pub enum UidKind {
+    /// An ISO14443-3A card for the NFC app
+    Nfc,
+    /// An EM4100 tag for the 125 kHz RFID app
+    Em4100,
+}
+
+impl UidKind {
+    /// Returns the contents of the file for `uid`.
+    pub fn convert(self, uid: &[u8]) -> Result<String, String> {
+        match self {
+            UidKind::Nfc => uid_to_nfc(uid),
+            UidKind::Em4100 => em4100_to_rfid(uid),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            UidKind::Nfc => "nfc",
+            UidKind::Em4100 => "rfid",
+        }
+    }
+
+    /// Returns where the app that opens these files keeps them.
+    pub fn default_dir(self) -> &'static str {
+        match self {
+            UidKind::Nfc => NFC_DIR,
+            UidKind::Em4100 => RFID_DIR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // manufacturer block of a card with UID 2A 3B 4C 5E: the BCC, then
+    // SAK 08 and ATQA 00 04 (stored backwards)
+    const BLOCK0: [u8; 16] = [0x2a, 0x3b, 0x4c, 0x5e, 0x03, 0x08, 0x04, 0x00,
+                              0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69];
+
+    /// Returns a binary dump of a card with `blocks` blocks.
+    fn dump(blocks: usize) -> Vec<u8> {
+        let mut data = BLOCK0.to_vec();
+        for n in 1..blocks {
+            data.extend([n as u8; MFC_BLOCK_SIZE]);
+        }
+        data
+    }
+
+    #[test]
+    fn parse_hex_test() {
+        assert_eq!(parse_hex("04a1B2c3"), Ok(vec![0x04, 0xa1, 0xb2, 0xc3]));
+        assert_eq!(parse_hex("04:A1:B2:C3"), Ok(vec![0x04, 0xa1, 0xb2, 0xc3]));
+        assert_eq!(parse_hex(" 04 A1\tB2 C3 "), Ok(vec![0x04, 0xa1, 0xb2, 0xc3]));
+    }
+
+    #[test]
+    fn parse_hex_bad_test() {
+        // odd length
+        assert!(parse_hex("04A").is_err());
+        assert!(parse_hex("0 4A").is_err());
+        assert!(parse_hex("").is_err());
+        assert!(parse_hex(" : ").is_err());
+        assert!(parse_hex("0x04").is_err());
+        assert!(parse_hex("zz").is_err());
+        // not ASCII, so slicing by byte would break
+        assert!(parse_hex("é0").is_err());
+    }
+
+    #[test]
+    fn parse_proxmark_bin_test() {
+        let blocks = parse_proxmark_dump(&dump(64)).unwrap();
+        assert_eq!(blocks.len(), 64);
+        assert_eq!(blocks[0], BLOCK0);
+        assert_eq!(blocks[63], vec![63; MFC_BLOCK_SIZE]);
+        assert_eq!(parse_proxmark_dump(&dump(20)).unwrap().len(), 20);
+        assert_eq!(parse_proxmark_dump(&dump(256)).unwrap().len(), 256);
+    }
+
+    #[test]
+    fn parse_proxmark_eml_test() {
+        let eml: String = dump(64).chunks(MFC_BLOCK_SIZE)
+            .map(|b| format!("{}\n", b.iter().map(|x| format!("{:02x}", x)).collect::<String>()))
+            .collect();
+        assert_eq!(parse_proxmark_dump(eml.as_bytes()).unwrap(), parse_proxmark_dump(&dump(64)).unwrap());
+
+        // a short line is a bad block, not a binary dump
+        let bad = eml.replacen(&"01".repeat(16), "0101", 1);
+        assert!(parse_proxmark_dump(bad.as_bytes()).unwrap_err().contains("block 1"));
+    }
+
+    #[test]
+    fn parse_proxmark_wrong_size_test() {
+        // a 1K dump one block short
+        assert!(parse_proxmark_dump(&dump(63)).is_err());
+        // not a whole number of blocks
+        let mut data = dump(64);
+        data.pop();
+        assert!(parse_proxmark_dump(&data).is_err());
+        assert!(parse_proxmark_dump(&[]).is_err());
+    }
+
+    #[test]
+    fn mfc_to_nfc_test() {
+        let blocks = parse_proxmark_dump(&dump(64)).unwrap();
+        let nfc = mfc_to_nfc(&blocks).unwrap();
+        assert!(nfc.starts_with("Filetype: Flipper NFC device\nVersion: 4\n"));
+        assert!(nfc.contains("\nUID: 2A 3B 4C 5E\n"));
+        assert!(nfc.contains("\nATQA: 00 04\n"));
+        assert!(nfc.contains("\nSAK: 08\n"));
+        assert!(nfc.contains("\nMifare Classic type: 1K\n"));
+        assert!(nfc.contains("\nBlock 0: 2A 3B 4C 5E 03 08 04 00 62 63 64 65 66 67 68 69\n"));
+        assert!(nfc.ends_with("Block 63: 3F 3F 3F 3F 3F 3F 3F 3F 3F 3F 3F 3F 3F 3F 3F 3F\n"));
+    }
+
+    #[test]
+    fn mfc_to_nfc_7_byte_uid_test() {
+        // no BCC after the first four bytes, so the UID is 7 bytes,
+        // then SAK and ATQA
+        let mut blocks = parse_proxmark_dump(&dump(64)).unwrap();
+        blocks[0] = vec![0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x18, 0x02, 0x00,
+                         0, 0, 0, 0, 0, 0];
+        let nfc = mfc_to_nfc(&blocks).unwrap();
+        assert!(nfc.contains("\nUID: 04 11 22 33 44 55 66\n"));
+        assert!(nfc.contains("\nATQA: 00 02\n"));
+        assert!(nfc.contains("\nSAK: 18\n"));
+    }
+
+    #[test]
+    fn mfc_to_nfc_wrong_size_test() {
+        let blocks = vec![BLOCK0.to_vec(); 63];
+        assert!(mfc_to_nfc(&blocks).is_err());
+    }
+
+    #[test]
+    fn uid_to_nfc_test() {
+        let nfc = uid_to_nfc(&[0x04, 0xa1, 0xb2, 0xc3]).unwrap();
+        assert!(nfc.contains("\nDevice type: ISO14443-3A\n"));
+        assert!(nfc.contains("\nUID: 04 A1 B2 C3\n"));
+        assert!(nfc.contains("\nATQA: 00 04\n"));
+        assert!(uid_to_nfc(&[0; 7]).unwrap().contains("\nATQA: 00 44\n"));
+        assert!(uid_to_nfc(&[0; 10]).unwrap().contains("\nATQA: 00 84\n"));
+        for len in [0, 3, 5, 8, 11] {
+            assert!(uid_to_nfc(&vec![0; len]).is_err(), "{}-byte UID", len);
+        }
+    }
+
+    #[test]
+    fn em4100_to_rfid_test() {
+        let rfid = em4100_to_rfid(&[0x01, 0x23, 0x45, 0x67, 0x89]).unwrap();
+        assert_eq!(rfid, "Filetype: Flipper RFID key\nVersion: 1\nKey type: EM4100\nData: 01 23 45 67 89\n");
+        for len in [0, 4, 6, 10] {
+            assert!(em4100_to_rfid(&vec![0; len]).is_err(), "{}-byte ID", len);
+        }
+    }
+
+    #[test]
+    fn parse_uid_list_test() {
+        let list = b"# badge UIDs\n04A1B2C3\n\n  # old one\n04:11:22:33:44:55:66\n";
+        assert_eq!(parse_uid_list(list, None).unwrap(),
+                   vec![vec![0x04, 0xa1, 0xb2, 0xc3], vec![0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]]);
+        assert!(parse_uid_list(b"04A1B2C3\n04A\n", None).unwrap_err().starts_with("line 2"));
+
+        assert_eq!(parse_uid_list(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], Some(5)).unwrap().len(), 2);
+        assert!(parse_uid_list(&[1, 2, 3], Some(5)).is_err());
+        assert!(parse_uid_list(&[1, 2, 3], Some(0)).is_err());
+        assert!(parse_uid_list(&[0xff, 0xfe], None).is_err());
+    }
+}
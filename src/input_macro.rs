@@ -0,0 +1,76 @@
+// Recorded button presses. `remote --record` saves one of these and
+// `press --macro` plays it back:
+//
+//     {
+//       "steps": [
+//         { "button": "ok", "delay_ms": 0 },
+//         { "button": "down", "delay_ms": 350 },
+//         { "button": "back:long", "delay_ms": 1200 }
+//       ]
+//     }
+//
+// Each step's delay is how long after the previous press it happened.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::keyboard::KeyPress;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StepJson {
+    button: String,
+    #[serde(default)]
+    delay_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MacroJson {
+    steps: Vec<StepJson>,
+}
+
+/// One recorded press
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MacroStep {
+    pub press: KeyPress,
+    /// Time since the previous press (or the start)
+    pub delay: Duration,
+}
+
+/// A recorded sequence of button presses
+#[derive(Debug, Default)]
+pub struct Macro {
+    pub steps: Vec<MacroStep>,
+}
+
+impl Macro {
+    /// Read the macro at `path`.
+    pub fn load(path: &Path) -> Result<Macro, Box<dyn Error>> {
+        let file: MacroJson = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let steps = file.steps.iter()
+            .enumerate()
+            .map(|(i, s)| Ok(MacroStep {
+                press: s.button.parse().map_err(|e| format!("step {}: {}", i + 1, e))?,
+                delay: Duration::from_millis(s.delay_ms),
+            }))
+            .collect::<Result<Vec<MacroStep>, String>>()?;
+        Ok(Macro { steps })
+    }
+
+    /// Write the macro to `path`, replacing anything there.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let file = MacroJson {
+            steps: self.steps.iter()
+                .map(|s| StepJson {
+                    button: s.press.to_string(),
+                    delay_ms: u64::try_from(s.delay.as_millis()).unwrap_or(u64::MAX),
+                })
+                .collect(),
+        };
+        fs::write(path, serde_json::to_string_pretty(&file)? + "\n")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::flipper_pb::gui::InputKey;
+
+// The Flipper's on-screen keyboard (the TextInput view in the
+// firmware) is a grid you move around with the d-pad, pressing OK to
+// type the selected key. To type a string, we work out which button
+// presses get from key to key, using the same movement rules the
+// firmware does.
+//
+// Backspace and Enter are keys on the grid too. In keyboard maps,
+// they're written as '\b' and '\n'.
+const BACKSPACE_KEY: char = '\x08';
+const ENTER_KEY: char = '\n';
+
+/// A single button press to send to the Flipper
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyPress {
+    pub key: InputKey,
+    /// Hold the button down instead of a short press
+    pub long: bool,
+}
+
+impl FromStr for KeyPress {
+    type Err = String;
+
+    /// Parse a button name like `ok` or `down`, with `:long` on the
+    /// end for a long press (`back:long`).
+    fn from_str(s: &str) -> Result<KeyPress, String> {
+        let lower = s.to_lowercase();
+        let (name, long) = match lower.strip_suffix(":long") {
+            Some(name) => (name, true),
+            None => (lower.as_str(), false),
+        };
+
+        let key = match name {
+            "up" => InputKey::UP,
+            "down" => InputKey::DOWN,
+            "left" => InputKey::LEFT,
+            "right" => InputKey::RIGHT,
+            "ok" => InputKey::OK,
+            "back" => InputKey::BACK,
+            _ => {
+                return Err(format!(
+                    "unknown button {:?} (use up, down, left, right, ok, or back)", s));
+            },
+        };
+
+        Ok(KeyPress { key, long })
+    }
+}
+
+impl fmt::Display for KeyPress {
+    // The same names FromStr takes
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.key {
+            InputKey::UP => "up",
+            InputKey::DOWN => "down",
+            InputKey::LEFT => "left",
+            InputKey::RIGHT => "right",
+            InputKey::OK => "ok",
+            InputKey::BACK => "back",
+        };
+        write!(f, "{}{}", name, if self.long { ":long" } else { "" })
+    }
+}
+
+/// Layout of the on-screen keyboard and where the cursor starts
+#[derive(Deserialize, Debug, Clone)]
+pub struct Keyboard {
+    rows: Vec<String>,
+    #[serde(default)]
+    start_row: usize,
+    #[serde(default)]
+    start_column: usize,
+}
+
+impl Default for Keyboard {
+    // The layout from the official firmware's text_input.c
+    fn default() -> Keyboard {
+        Keyboard {
+            rows: vec![
+                "qwertyuiop0123".to_string(),
+                "asdfghjkl\x08456".to_string(),
+                "zxcvbnm_\n789".to_string(),
+            ],
+            start_row: 0,
+            start_column: 0,
+        }
+    }
+}
+
+impl Keyboard {
+    /// Load a keyboard map from a JSON file like
+    /// `{"rows": ["qwe...", ...], "start_row": 0, "start_column": 0}`.
+    pub fn load(path: &Path) -> Result<Keyboard, Box<dyn Error>> {
+        let keyboard: Keyboard = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if keyboard.rows.is_empty() || keyboard.rows.iter().any(|r| r.is_empty()) {
+            return Err("keyboard map has an empty row".into());
+        }
+        Ok(keyboard)
+    }
+
+    fn row_size(&self, row: usize) -> usize {
+        self.rows[row].chars().count()
+    }
+
+    fn key_at(&self, row: usize, column: usize) -> char {
+        self.rows[row].chars().nth(column).unwrap()
+    }
+
+    /// Where the cursor ends up after pressing `key` at (`row`,
+    /// `column`). Up and down nudge the column because the rows
+    /// aren't the same length; these are the firmware's rules.
+    fn step(&self, row: usize, column: usize, key: InputKey) -> (usize, usize) {
+        let (row, column) = match key {
+            InputKey::UP if row > 0 => {
+                let row = row - 1;
+                if column + 6 > self.row_size(row) {
+                    (row, column + 1)
+                } else {
+                    (row, column)
+                }
+            },
+            InputKey::DOWN if row + 1 < self.rows.len() => {
+                let row = row + 1;
+                if column + 4 > self.row_size(row) {
+                    (row, column.saturating_sub(1))
+                } else {
+                    (row, column)
+                }
+            },
+            InputKey::LEFT => {
+                if column > 0 {
+                    (row, column - 1)
+                } else {
+                    (row, self.row_size(row) - 1)
+                }
+            },
+            InputKey::RIGHT => {
+                if column + 1 < self.row_size(row) {
+                    (row, column + 1)
+                } else {
+                    (row, 0)
+                }
+            },
+            _ => (row, column),
+        };
+        // custom maps could have rows short enough to walk off
+        (row, column.min(self.row_size(row) - 1))
+    }
+
+    /// Shortest list of d-pad presses from (`row`, `column`) to the
+    /// key `target`, along with where that leaves the cursor.
+    fn path_to(&self, row: usize, column: usize, target: char) -> Option<(Vec<InputKey>, (usize, usize))> {
+        let mut queue = VecDeque::new();
+        let mut seen = vec![(row, column)];
+        queue.push_back(((row, column), Vec::new()));
+
+        while let Some(((r, c), path)) = queue.pop_front() {
+            if self.key_at(r, c) == target {
+                return Some((path, (r, c)));
+            }
+
+            for key in [InputKey::UP, InputKey::DOWN, InputKey::LEFT, InputKey::RIGHT] {
+                let next = self.step(r, c, key);
+                if !seen.contains(&next) {
+                    seen.push(next);
+                    let mut next_path = path.clone();
+                    next_path.push(key);
+                    queue.push_back((next, next_path));
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the button presses that type `text` into an empty
+    /// text field, optionally pressing Enter at the end.
+    ///
+    /// The firmware capitalizes the first letter on its own and a
+    /// long press on OK flips the case, so uppercase letters anywhere
+    /// else (and a lowercase first letter) get a long press.
+    pub fn plan(&self, text: &str, enter: bool) -> Result<Vec<KeyPress>, Box<dyn Error>> {
+        let mut presses = Vec::new();
+        let mut position = (self.start_row.min(self.rows.len() - 1), self.start_column);
+        position.1 = position.1.min(self.row_size(position.0) - 1);
+
+        let mut targets: Vec<char> = text.chars().collect();
+        if enter {
+            targets.push(ENTER_KEY);
+        }
+
+        for (typed, ch) in targets.into_iter().enumerate() {
+            if ch == BACKSPACE_KEY {
+                return Err("can't type a backspace".into());
+            }
+
+            let target = ch.to_ascii_lowercase();
+            let (moves, end) = match self.path_to(position.0, position.1, target) {
+                Some(p) => p,
+                None => {
+                    return Err(format!("{:?} isn't on the Flipper keyboard", ch).into());
+                },
+            };
+
+            presses.extend(moves.into_iter().map(|key| KeyPress { key, long: false }));
+            let auto_upper = typed == 0;
+            let long = ch.is_ascii_alphabetic() && ch.is_ascii_uppercase() != auto_upper;
+            presses.push(KeyPress { key: InputKey::OK, long });
+            position = end;
+        }
+
+        Ok(presses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn presses(s: &str) -> Vec<KeyPress> {
+        s.split_whitespace().map(|p| p.parse().unwrap()).collect()
+    }
+
+    /// Follow `plan` on `keyboard` and return what it types, with
+    /// the case the firmware would give it.
+    fn replay(keyboard: &Keyboard, plan: &[KeyPress]) -> String {
+        let (mut row, mut column) = (keyboard.start_row, keyboard.start_column);
+        let mut typed = String::new();
+        for p in plan {
+            if p.key != InputKey::OK {
+                (row, column) = keyboard.step(row, column, p.key);
+                continue;
+            }
+            let key = keyboard.key_at(row, column);
+            let upper = typed.is_empty() != p.long;
+            typed.push(if upper { key.to_ascii_uppercase() } else { key });
+        }
+        typed
+    }
+
+    #[test]
+    fn key_press_parse_test() {
+        assert_eq!("OK".parse::<KeyPress>(), Ok(KeyPress { key: InputKey::OK, long: false }));
+        assert_eq!("back:long".parse::<KeyPress>(), Ok(KeyPress { key: InputKey::BACK, long: true }));
+        assert!("sideways".parse::<KeyPress>().is_err());
+        assert_eq!(KeyPress { key: InputKey::LEFT, long: true }.to_string(), "left:long");
+    }
+
+    #[test]
+    fn plan_first_letter_test() {
+        let keyboard = Keyboard::default();
+        // the firmware already capitalizes the first letter
+        assert_eq!(keyboard.plan("Q", false).unwrap(), presses("ok"));
+        assert_eq!(keyboard.plan("q", false).unwrap(), presses("ok:long"));
+        assert_eq!(keyboard.plan("w", false).unwrap(), presses("right ok:long"));
+    }
+
+    #[test]
+    fn plan_mixed_case_test() {
+        let keyboard = Keyboard::default();
+        assert_eq!(keyboard.plan("Hi", false).unwrap(),
+                   presses("down right right right right right ok up right right ok"));
+        assert_eq!(keyboard.plan("aB", false).unwrap(),
+                   presses("down ok:long down right right right right ok:long"));
+    }
+
+    #[test]
+    fn plan_wraps_around_test() {
+        let keyboard = Keyboard::default();
+        // left from the start of a row wraps to its end, which is
+        // shorter than going right
+        assert_eq!(keyboard.plan("a_b", false).unwrap(),
+                   presses("down ok:long down left left left left left ok left left left ok"));
+    }
+
+    #[test]
+    fn plan_uneven_rows_test() {
+        let keyboard = Keyboard::default();
+        // going up from the end of a shorter row moves a column right
+        assert_eq!(keyboard.plan("ab1", false).unwrap(),
+                   presses("down ok:long down right right right right ok right right right right up up right ok"));
+    }
+
+    #[test]
+    fn plan_enter_test() {
+        let keyboard = Keyboard::default();
+        assert_eq!(keyboard.plan("ok", true).unwrap(),
+                   presses("left left left left left left ok:long down left ok down right ok"));
+    }
+
+    #[test]
+    fn plan_replays_test() {
+        let keyboard = Keyboard::default();
+        for text in ["Flipper", "hello_World42", "UPPER", "m0mentum_9", "Zz"] {
+            let plan = keyboard.plan(text, false).unwrap();
+            assert_eq!(replay(&keyboard, &plan), text);
+        }
+    }
+
+    #[test]
+    fn plan_missing_key_test() {
+        let keyboard = Keyboard::default();
+        for text in ["x y", "dash-es", "dot.", "é", "tab\t"] {
+            let err = keyboard.plan(text, false).unwrap_err();
+            assert!(err.to_string().contains("isn't on the Flipper keyboard"), "{:?}: {}", text, err);
+        }
+        assert!(keyboard.plan("a\x08", false).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+// Everything that knows how to talk to a Flipper. The command line
+// tool in main.rs is built on this, and so are the C API in
+// flipwire-ffi and the Web Bluetooth client in flipwire-web.
+
+#[macro_use] extern crate tracing;
+
+// The messages and codec live in their own crate, so other tools
+// can use them without any of flipwire's transports.
+pub use flipper_rpc_codec::{flipper_pb, protobuf_codec};
+#[cfg(feature = "ble")]
+pub mod flipper_ble;
+pub mod transport;
+pub mod session;
+pub mod flow_control;
+#[cfg(all(feature = "ble", target_os = "linux"))]
+pub mod bluez;
+#[cfg(all(feature = "ble", target_os = "windows"))]
+pub mod winrt;
+pub mod peripheral_cache;
+pub mod rpc_reader;
+pub mod rpc_json;
+#[cfg(feature = "tcp")]
+pub mod emu;
+#[cfg(feature = "tcp")]
+pub mod sim_link;
+pub mod firmware;
+#[cfg(feature = "update")]
+pub mod update;
+#[cfg(feature = "update")]
+pub mod region;
+pub mod drift;
+pub mod keyboard;
+pub mod input_macro;
+#[cfg(feature = "cli")]
+pub mod expect;
+pub mod gpio;
+pub mod screen;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "web")]
+pub mod screen_server;
+#[cfg(feature = "cli")]
+pub mod uitest;
+#[cfg(feature = "web")]
+pub mod web_ui;
+#[cfg(unix)]
+pub mod systemd;
+pub mod banner;
+pub mod hexdump;
+#[cfg(feature = "cli")]
+pub mod ufbt;
+#[cfg(feature = "cli")]
+pub mod deploy_manifest;
+#[cfg(feature = "serial")]
+pub mod serial;
+pub mod output;
+pub mod progress;
+pub mod prompt;
+pub mod hook;
+#[cfg(feature = "cli")]
+pub mod json_log;
+pub mod duration;
+#[cfg(feature = "cli")]
+pub mod config;
+#[cfg(feature = "notify")]
+pub mod notify;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod remote_path;
+#[cfg(all(feature = "ble", feature = "cli"))]
+pub mod remote_file;
+pub mod tree;
+pub mod path_filter;
+pub mod backup;
+pub mod manifest;
+pub mod import;
+pub mod apps;
+pub mod fap;
+pub mod status;
+#[cfg(feature = "ble")]
+pub mod daemon;
+pub mod power;
+pub mod profile;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
@@ -0,0 +1,99 @@
+// Listings for other tools to read. Unlike `ls --output json`, which
+// is for people and can change, this format is versioned: fields are
+// only ever added within a version, and anything else bumps it.
+
+#[cfg(feature = "ble")]
+use std::error::Error;
+
+use serde::Serialize;
+
+#[cfg(feature = "ble")]
+use crate::{flipper_ble, flipper_pb};
+
+/// Name in every manifest, so tools can tell what they're reading
+pub const MANIFEST_SCHEMA: &str = "flipwire-manifest";
+/// Bumped whenever a field changes or goes away
+pub const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Serialize, Debug)]
+pub struct Manifest {
+    pub schema: &'static str,
+    pub version: u32,
+    /// Flipper directory that was listed
+    pub root: String,
+    /// When the manifest was made, in RFC 3339
+    pub generated: String,
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    File,
+    Dir,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ManifestEntry {
+    /// Full Flipper path
+    pub path: String,
+    #[serde(rename = "type")]
+    pub entry_type: EntryType,
+    /// Size in bytes, 0 for directories
+    pub size: u64,
+    /// MD5 as lowercase hex, null for directories
+    pub md5: Option<String>,
+    /// Last change in seconds since 1970, by the Flipper's clock
+    pub timestamp: Option<u64>,
+}
+
+impl Manifest {
+    pub fn new(root: &str, entries: Vec<ManifestEntry>) -> Manifest {
+        Manifest {
+            schema: MANIFEST_SCHEMA,
+            version: MANIFEST_VERSION,
+            root: root.to_string(),
+            generated: chrono::Local::now().to_rfc3339(),
+            entries,
+        }
+    }
+}
+
+/// Write a manifest of the directory `path` to `file`. Returns how
+/// many entries it has.
+#[cfg(feature = "ble")]
+pub async fn write(flipper: &mut flipper_ble::FlipperBle, path: &str, file: &std::path::Path) -> Result<usize, Box<dyn Error>> {
+    let mut files = flipper.list_entries_with_md5(path).await?;
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut entries = Vec::new();
+    for f in files {
+        let full_path = flipper_ble::join_remote(path, &f.name);
+        let is_dir = f.type_ == flipper_pb::storage::file::FileType::DIR.into();
+        let md5 = match (is_dir, f.md5sum.is_empty()) {
+            (true, _) => None,
+            (false, false) => Some(f.md5sum),
+            // older firmware doesn't include them in the list
+            (false, true) => Some(flipper.md5sum(&full_path).await?),
+        };
+        // older firmware doesn't know about timestamps
+        let timestamp = match flipper.timestamp(&full_path).await {
+            Ok(t) => Some(t as u64),
+            Err(e) => {
+                debug!("no timestamp for {}: {}", full_path, e);
+                None
+            },
+        };
+        entries.push(ManifestEntry {
+            path: full_path,
+            entry_type: if is_dir { EntryType::Dir } else { EntryType::File },
+            size: if is_dir { 0 } else { f.size as u64 },
+            md5,
+            timestamp,
+        });
+    }
+
+    let m = Manifest::new(path, entries);
+    std::fs::write(file, serde_json::to_string_pretty(&m)?)?;
+    Ok(m.entries.len())
+}
@@ -0,0 +1,21 @@
+// Desktop notifications, so long transfers can run in the background
+// and say when they're done. notify-rust uses the notification server
+// on Linux and the BSDs, Notification Center on macOS, and toasts on
+// Windows.
+
+use std::error::Error;
+
+/// Show a desktop notification.
+///
+/// # Arguments
+///
+/// * `summary`: Title of the notification
+/// * `body`: Text under the title
+pub fn send(summary: &str, body: &str) -> Result<(), Box<dyn Error>> {
+    notify_rust::Notification::new()
+        .appname("flipwire")
+        .summary(summary)
+        .body(body)
+        .show()?;
+    Ok(())
+}
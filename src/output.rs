@@ -0,0 +1,197 @@
+// How results get shown to the user. Statuses and errors go to
+// stderr, so stdout only has the actual output (file contents,
+// listings) and can be piped somewhere.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[cfg(feature = "cli")]
+use console::style;
+#[cfg(not(feature = "cli"))]
+use plain::style;
+
+/// When to use colors
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+/// How log messages are written
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Lines for people to read
+    Text,
+    /// A JSON object per line, for log aggregators
+    Json,
+}
+
+// With JSON logs, statuses and errors are logged too, so everything
+// on stderr is JSON
+static LOG_EVENTS: AtomicBool = AtomicBool::new(false);
+
+/// Set up colors for everything printed after this. `Auto` leaves
+/// console to check each stream for a terminal (and NO_COLOR).
+#[cfg(feature = "cli")]
+pub fn init(choice: ColorChoice, log_format: LogFormat) {
+    LOG_EVENTS.store(log_format == LogFormat::Json, Ordering::Relaxed);
+    match choice {
+        ColorChoice::Auto => {},
+        ColorChoice::Always | ColorChoice::Never => {
+            let enabled = choice == ColorChoice::Always;
+            console::set_colors_enabled(enabled);
+            console::set_colors_enabled_stderr(enabled);
+        },
+    }
+}
+
+/// Print that something worked.
+pub fn status<D: Display>(msg: D) {
+    if LOG_EVENTS.load(Ordering::Relaxed) {
+        info!(target: "flipwire::status", "{}", msg);
+        return;
+    }
+    eprintln!("{}", style(msg).green().for_stderr());
+}
+
+// How many errors have been printed, so callers can tell whether
+// something they ran failed
+static ERROR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Print that something failed.
+pub fn error<D: Display>(msg: D) {
+    ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+    if LOG_EVENTS.load(Ordering::Relaxed) {
+        error!(target: "flipwire::status", "{}", msg);
+        return;
+    }
+    eprintln!("{} {}", style("error:").red().bold().for_stderr(), style(msg).red().for_stderr());
+}
+
+/// Returns how many errors have been printed so far.
+pub fn error_count() -> usize {
+    ERROR_COUNT.load(Ordering::Relaxed)
+}
+
+/// How to print a listing
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListFormat {
+    /// The usual human-readable listing
+    Text,
+    /// Columns lined up under a header
+    Table,
+    /// Comma-separated values with a header row, for spreadsheets
+    Csv,
+    /// A JSON array with an object per row
+    Json,
+}
+
+/// Returns `value` as it should look in a table or CSV cell, without
+/// quotes around strings.
+fn cell_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Returns `cell` quoted for CSV if it needs to be.
+fn csv_field(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// Print rows of a listing as a table, CSV, or JSON. `Text` is up to
+/// each command, so it's printed as a table here.
+///
+/// # Arguments
+///
+/// * `format`: How to print the rows
+/// * `headers`: Name of each column, also the keys in JSON
+/// * `rows`: Values for each row, in the same order as `headers`
+pub fn print_rows(format: ListFormat, headers: &[&str], rows: &[Vec<serde_json::Value>]) {
+    match format {
+        ListFormat::Json => {
+            let objects: Vec<serde_json::Value> = rows.iter()
+                .map(|row| headers.iter().map(|h| h.to_string()).zip(row.iter().cloned()).collect())
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&objects).unwrap());
+        },
+        ListFormat::Csv => {
+            println!("{}", headers.join(","));
+            for row in rows {
+                let fields: Vec<String> = row.iter().map(|v| csv_field(&cell_text(v))).collect();
+                println!("{}", fields.join(","));
+            }
+        },
+        ListFormat::Table | ListFormat::Text => {
+            let cells: Vec<Vec<String>> = rows.iter()
+                .map(|row| row.iter().map(cell_text).collect())
+                .collect();
+            let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+            for row in &cells {
+                for (w, cell) in widths.iter_mut().zip(row) {
+                    *w = (*w).max(cell.chars().count());
+                }
+            }
+            // numbers line up on the right, everything else on the left
+            let numeric: Vec<bool> = (0..headers.len())
+                .map(|i| rows.iter().any(|r| r.get(i).is_some_and(|v| v.is_number()))
+                     && rows.iter().all(|r| r.get(i).is_some_and(|v| v.is_number() || v.is_null())))
+                .collect();
+
+            let line = |row: &[String]| {
+                let padded: Vec<String> = row.iter().zip(&widths).zip(&numeric)
+                    .map(|((cell, w), n)| if *n { format!("{:>w$}", cell) } else { format!("{:<w$}", cell) })
+                    .collect();
+                padded.join("  ").trim_end().to_string()
+            };
+            let header_row: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+            println!("{}", line(&header_row));
+            for row in &cells {
+                println!("{}", line(row));
+            }
+        },
+    }
+}
+
+/// A directory name in a listing.
+pub fn dir_name(name: &str) -> String {
+    style(name).blue().bold().to_string()
+}
+
+// Without the "cli" feature there's no console crate to color things
+// with, so they're printed plain.
+#[cfg(not(feature = "cli"))]
+mod plain {
+    use std::fmt::{self, Display};
+
+    pub struct Plain<D>(D);
+
+    pub fn style<D>(d: D) -> Plain<D> {
+        Plain(d)
+    }
+
+    impl<D> Plain<D> {
+        pub fn green(self) -> Plain<D> { self }
+        pub fn red(self) -> Plain<D> { self }
+        pub fn blue(self) -> Plain<D> { self }
+        pub fn bold(self) -> Plain<D> { self }
+        pub fn for_stderr(self) -> Plain<D> { self }
+    }
+
+    impl<D: Display> Display for Plain<D> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+}
@@ -0,0 +1,332 @@
+// Deciding which files a recursive transfer skips, with gitignore
+// patterns. They come from --exclude and from a .flipperignore file
+// at the top of the directory being uploaded, so build output, .git,
+// and editor junk don't end up on the SD card. --include goes the
+// other way: once there's one, only files matching an include are
+// transferred, like just the .sub captures out of a big SD card.
+//
+// Patterns work like in a .gitignore:
+// - `#` starts a comment, and blank lines do nothing
+// - a pattern without a slash matches a name at any depth, so `*.o`
+//   skips every object file
+// - a pattern with a slash is relative to the top, so `build/out`
+//   only skips that one directory (a leading slash just anchors it)
+// - a trailing slash only matches directories, like `target/`
+// - `**` matches any number of directories
+// - `!` brings back something an earlier pattern skipped, and the
+//   last pattern that matches wins
+//
+// Like git, a skipped directory skips everything in it, and nothing
+// in it can be brought back.
+
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use glob::{MatchOptions, Pattern};
+
+/// Name of the ignore file read from the top of an uploaded directory
+pub const IGNORE_FILE: &str = ".flipperignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    // so `*` doesn't match across directories
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    // "!" brings things back instead of skipping them
+    negated: bool,
+    dir_only: bool,
+}
+
+impl Rule {
+    /// Returns the rule for a line of a .gitignore, or None for
+    /// comments and blank lines.
+    fn parse(pattern: &str) -> Result<Option<Rule>, Box<dyn Error>> {
+        let mut pattern = pattern.trim_end();
+        if pattern.is_empty() || pattern.starts_with('#') {
+            return Ok(None);
+        }
+        let negated = pattern.starts_with('!');
+        if negated {
+            pattern = &pattern[1..];
+        }
+        // for names that really start with "!" or "#"
+        if let Some(rest) = pattern.strip_prefix('\\') {
+            pattern = rest;
+        }
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        if pattern.is_empty() {
+            return Err("empty pattern".into());
+        }
+
+        // without a slash it matches at any depth
+        let glob = match pattern.strip_prefix('/') {
+            Some(anchored) => anchored.to_string(),
+            None if pattern.contains('/') => pattern.to_string(),
+            None => format!("**/{}", pattern),
+        };
+        Ok(Some(Rule {
+            pattern: Pattern::new(&glob).map_err(|e| format!("bad pattern {:?}: {}", pattern, e))?,
+            negated,
+            dir_only,
+        }))
+    }
+
+    fn matches(&self, path: &str, dir: bool) -> bool {
+        (dir || !self.dir_only) && self.pattern.matches_with(path, MATCH_OPTIONS)
+    }
+}
+
+/// Patterns deciding which paths a recursive transfer skips
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    rules: Vec<Rule>,
+    // if there are any, files have to match one of them
+    includes: Vec<Rule>,
+}
+
+impl PathFilter {
+    /// Returns a filter taking only paths matching one of `include`
+    /// (or everything, if there are none), and leaving out paths
+    /// matching any of `exclude`.
+    pub fn from_patterns(include: &[String], exclude: &[String]) -> Result<PathFilter, Box<dyn Error>> {
+        let mut filter = PathFilter::default();
+        for p in include {
+            filter.include(p)?;
+        }
+        for p in exclude {
+            filter.exclude(p)?;
+        }
+        Ok(filter)
+    }
+
+    /// Skip paths matching `pattern`, a line of a .gitignore.
+    /// Comments and blank lines are ignored.
+    pub fn exclude(&mut self, pattern: &str) -> Result<(), Box<dyn Error>> {
+        self.rules.extend(Rule::parse(pattern)?);
+        Ok(())
+    }
+
+    /// Only take files matching `pattern`, or in a directory matching
+    /// it (or one of the other includes), written the same way as for
+    /// exclude(). Excludes still apply.
+    pub fn include(&mut self, pattern: &str) -> Result<(), Box<dyn Error>> {
+        match Rule::parse(pattern)? {
+            Some(r) if r.negated => Err(format!("{:?} can't start with \"!\" (exclude it instead)", pattern).into()),
+            rule => {
+                self.includes.extend(rule);
+                Ok(())
+            },
+        }
+    }
+
+    /// Returns whether there are any include patterns, so only some
+    /// files are taken.
+    pub fn has_includes(&self) -> bool {
+        !self.includes.is_empty()
+    }
+
+    /// Add every pattern in the gitignore-style file at `path`.
+    /// Returns false if there's no such file.
+    pub fn add_ignore_file(&mut self, path: &Path) -> Result<bool, Box<dyn Error>> {
+        let text = match fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(format!("can't read {:?}: {}", path, e).into()),
+        };
+        for line in text.lines() {
+            self.exclude(line).map_err(|e| format!("{:?}: {}", path, e))?;
+        }
+        debug!("using ignore patterns from {:?}", path);
+        Ok(true)
+    }
+
+    /// Returns whether the file or directory at `path`, relative to
+    /// the top of the transfer with "/" between components, should be
+    /// skipped. It is if any of its parent directories is, or if it's
+    /// a file that doesn't match any of the include patterns there
+    /// are.
+    pub fn is_excluded(&self, path: &str, dir: bool) -> bool {
+        let path = path.trim_matches('/');
+        if !dir && self.has_includes() && !self.is_included(path) {
+            return true;
+        }
+        if self.rules.is_empty() {
+            return false;
+        }
+        // every parent directory, then the path itself
+        let parent_excluded = path.match_indices('/')
+            .any(|(i, _)| self.excludes(&path[..i], true));
+        parent_excluded || self.excludes(path, dir)
+    }
+
+    /// Returns whether the file `path`, or one of its parent
+    /// directories, matches an include.
+    fn is_included(&self, path: &str) -> bool {
+        let matches = |p: &str, dir: bool| self.includes.iter().any(|r| r.matches(p, dir));
+        path.match_indices('/').any(|(i, _)| matches(&path[..i], true)) || matches(path, false)
+    }
+
+    /// Returns whether the last rule matching `path` skips it.
+    fn excludes(&self, path: &str, dir: bool) -> bool {
+        self.rules.iter()
+            .rev()
+            .find(|r| r.matches(path, dir))
+            .is_some_and(|r| !r.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(excludes: &[&str], includes: &[&str]) -> PathFilter {
+        let mut f = PathFilter::default();
+        for p in excludes {
+            f.exclude(p).unwrap();
+        }
+        for p in includes {
+            f.include(p).unwrap();
+        }
+        f
+    }
+
+    #[test]
+    fn from_patterns_test() {
+        let f = PathFilter::from_patterns(&["*.nfc".to_string()], &["old/".to_string()]).unwrap();
+        assert!(f.has_includes());
+        assert!(!f.is_excluded("card.nfc", false));
+        assert!(f.is_excluded("card.sub", false));
+        assert!(f.is_excluded("old", true));
+        assert!(!PathFilter::from_patterns(&[], &[]).unwrap().has_includes());
+        assert!(PathFilter::from_patterns(&["!*.nfc".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn unanchored_pattern_test() {
+        let f = filter(&["*.o"], &[]);
+        assert!(f.is_excluded("main.o", false));
+        assert!(f.is_excluded("src/deep/main.o", false));
+        assert!(!f.is_excluded("main.c", false));
+    }
+
+    #[test]
+    fn anchored_pattern_test() {
+        let f = filter(&["/build", "out/log.txt"], &[]);
+        assert!(f.is_excluded("build", true));
+        assert!(!f.is_excluded("src/build", true));
+        assert!(f.is_excluded("out/log.txt", false));
+        assert!(!f.is_excluded("sub/out/log.txt", false));
+    }
+
+    #[test]
+    fn star_stays_in_directory_test() {
+        let f = filter(&["src/*.c"], &[]);
+        assert!(f.is_excluded("src/a.c", false));
+        assert!(!f.is_excluded("src/lib/a.c", false));
+    }
+
+    #[test]
+    fn dir_only_pattern_test() {
+        let f = filter(&["target/"], &[]);
+        assert!(f.is_excluded("target", true));
+        assert!(f.is_excluded("app/target", true));
+        // a file with the same name stays
+        assert!(!f.is_excluded("target", false));
+        // and so does nothing in a directory that isn't excluded
+        assert!(!f.is_excluded("targets/a", false));
+    }
+
+    #[test]
+    fn excluded_parent_test() {
+        let f = filter(&["target/"], &[]);
+        assert!(f.is_excluded("target/debug/app", false));
+        assert!(f.is_excluded("/target/debug/", true));
+    }
+
+    #[test]
+    fn negation_test() {
+        let f = filter(&["*.log", "!keep.log"], &[]);
+        assert!(f.is_excluded("a.log", false));
+        assert!(!f.is_excluded("keep.log", false));
+        assert!(!f.is_excluded("logs/keep.log", false));
+
+        // the last matching pattern wins
+        let f = filter(&["!keep.log", "*.log"], &[]);
+        assert!(f.is_excluded("keep.log", false));
+    }
+
+    #[test]
+    fn negation_inside_excluded_dir_test() {
+        // like git, nothing in an excluded directory comes back
+        let f = filter(&["build/", "!build/keep.txt"], &[]);
+        assert!(f.is_excluded("build/keep.txt", false));
+    }
+
+    #[test]
+    fn double_star_test() {
+        let f = filter(&["a/**/z.txt"], &[]);
+        assert!(f.is_excluded("a/z.txt", false));
+        assert!(f.is_excluded("a/b/z.txt", false));
+        assert!(f.is_excluded("a/b/c/z.txt", false));
+        assert!(!f.is_excluded("x/a/b/z.txt", false));
+
+        let f = filter(&["docs/**"], &[]);
+        assert!(f.is_excluded("docs/a/b.md", false));
+        assert!(!f.is_excluded("src/docs.md", false));
+    }
+
+    #[test]
+    fn comments_and_escapes_test() {
+        let f = filter(&["# a comment", "", "  ", "\\#notes", "\\!bang"], &[]);
+        assert!(f.is_excluded("#notes", false));
+        assert!(f.is_excluded("!bang", false));
+        assert!(!f.is_excluded("a comment", false));
+        assert!(PathFilter::default().exclude("/").is_err());
+    }
+
+    #[test]
+    fn include_test() {
+        let f = filter(&[], &["*.sub"]);
+        assert!(f.has_includes());
+        assert!(!f.is_excluded("subghz/garage.sub", false));
+        assert!(f.is_excluded("subghz/garage.txt", false));
+        // directories are still walked, to find what's in them
+        assert!(!f.is_excluded("subghz", true));
+    }
+
+    #[test]
+    fn include_directory_test() {
+        let f = filter(&[], &["nfc/"]);
+        assert!(!f.is_excluded("nfc/card.nfc", false));
+        assert!(!f.is_excluded("nfc/assets/x.bin", false));
+        assert!(f.is_excluded("card.nfc", false));
+    }
+
+    #[test]
+    fn exclude_wins_over_include_test() {
+        let f = filter(&["temp/**"], &["*.sub"]);
+        assert!(!f.is_excluded("garage.sub", false));
+        assert!(f.is_excluded("temp/garage.sub", false));
+        assert!(f.is_excluded("temp/other.txt", false));
+
+        // in either order
+        let mut f = PathFilter::default();
+        f.include("*.sub").unwrap();
+        f.exclude("secret.sub").unwrap();
+        assert!(f.is_excluded("secret.sub", false));
+        assert!(!f.is_excluded("open.sub", false));
+    }
+
+    #[test]
+    fn negated_include_test() {
+        assert!(PathFilter::default().include("!*.sub").is_err());
+    }
+}
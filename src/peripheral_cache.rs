@@ -0,0 +1,96 @@
+// Remembering which Bluetooth peripheral each Flipper turned out to
+// be. macOS doesn't show MAC addresses, only an identifier
+// CoreBluetooth makes up for each device, and only knows a device once
+// a scan has seen it. With the identifier from last time, a scan can
+// stop the moment the Flipper shows up, even before its name does.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::drift;
+use crate::output;
+
+/// Returns where the cache is kept, like
+/// `~/Library/Caches/flipwire/peripherals.json` on macOS.
+pub fn cache_path() -> io::Result<PathBuf> {
+    match dirs::cache_dir() {
+        Some(d) => Ok(d.join("flipwire").join("peripherals.json")),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "couldn't find a cache directory")),
+    }
+}
+
+fn load() -> io::Result<BTreeMap<String, String>> {
+    match fs::read_to_string(cache_path()?) {
+        Ok(s) => serde_json::from_str(&s).map_err(io::Error::other),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the names of every Flipper that's been connected to.
+pub fn names() -> Vec<String> {
+    load().map(|cache| cache.into_keys().collect()).unwrap_or_default()
+}
+
+/// Returns the peripheral identifier the Flipper named `flipper_name`
+/// had last time, if it's been connected to before.
+pub fn lookup(flipper_name: &str) -> Option<String> {
+    match load() {
+        Ok(cache) => cache.get(flipper_name).cloned(),
+        Err(e) => {
+            debug!("couldn't read peripheral cache: {}", e);
+            None
+        },
+    }
+}
+
+/// Remember that the Flipper named `flipper_name` is the peripheral
+/// `id`.
+pub fn remember(flipper_name: &str, id: &str) -> io::Result<()> {
+    let mut cache = load().unwrap_or_default();
+    if cache.get(flipper_name).map(String::as_str) == Some(id) {
+        return Ok(());
+    }
+    cache.insert(flipper_name.to_string(), id.to_string());
+
+    let path = cache_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(&cache).map_err(io::Error::other)?)
+}
+
+/// Forget the peripheral the Flipper named `flipper_name` was, so
+/// the next connection finds it from scratch. Returns whether there
+/// was anything to forget.
+pub fn forget(flipper_name: &str) -> io::Result<bool> {
+    let mut cache = load()?;
+    if cache.remove(flipper_name).is_none() {
+        return Ok(false);
+    }
+    fs::write(cache_path()?, serde_json::to_string_pretty(&cache).map_err(io::Error::other)?)?;
+    Ok(true)
+}
+
+/// Remove everything cached about the Flipper named `name`.
+pub fn forget_everything(name: &str) {
+    let mut forgot = Vec::new();
+    match forget(name) {
+        Ok(true) => forgot.push("its Bluetooth identifier".to_string()),
+        Ok(false) => {},
+        Err(e) => output::error(format!("failed to update peripheral cache: {}", e)),
+    }
+    match drift::forget(name) {
+        Ok(0) => {},
+        Ok(n) => forgot.push(format!("{} clock drift measurements", n)),
+        Err(e) => output::error(format!("failed to update drift log: {}", e)),
+    }
+
+    if forgot.is_empty() {
+        output::status(format!("nothing remembered about Flipper {}", name));
+    } else {
+        output::status(format!("forgot {} for Flipper {}", forgot.join(" and "), name));
+    }
+}
@@ -0,0 +1,215 @@
+// Battery samples for `power log`, from the Flipper's "pwrinfo."
+// properties, written out as CSV rows to graph battery health and how
+// much power apps draw. Plain `power` prints one sample, in a shape a
+// desktop status bar can show (waybar's JSON, or a single line for
+// i3blocks, polybar, and the like).
+
+use chrono::{DateTime, Local};
+
+#[cfg(feature = "ble")]
+use std::error::Error;
+#[cfg(feature = "ble")]
+use std::io::Write;
+#[cfg(feature = "ble")]
+use std::time::Duration;
+
+#[cfg(feature = "ble")]
+use crate::{flipper_ble, output};
+
+/// First line of a power log
+pub const CSV_HEADER: &str = "time,charge_percent,state,voltage_mv,current_ma,temperature_c,health_percent,capacity_mah";
+
+// At or below this many percent, status bars get told the battery is
+// low
+const LOW_CHARGE: u8 = 20;
+
+/// How `power` prints the battery
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerFormat {
+    /// Everything the Flipper says, a line each
+    Text,
+    /// Just the charge and state on one line, for status bars like
+    /// i3blocks and polybar
+    Line,
+    /// JSON for a waybar custom module with `"return-type": "json"`
+    Waybar,
+}
+
+/// The battery at one point in time. Fields the firmware didn't
+/// report are left empty in the CSV.
+#[derive(Debug, Clone)]
+pub struct PowerSample {
+    pub time: DateTime<Local>,
+    pub charge: Option<u8>,
+    /// "charging", "discharging", or "charged"
+    pub state: Option<String>,
+    pub voltage_mv: Option<u32>,
+    /// Negative while discharging
+    pub current_ma: Option<i32>,
+    pub temperature_c: Option<f32>,
+    pub health: Option<u8>,
+    /// Charge left
+    pub capacity_mah: Option<u32>,
+}
+
+impl PowerSample {
+    /// Returns a sample taken at `time` with what's in `properties`,
+    /// the result of getting "pwrinfo.".
+    pub fn from_properties(time: DateTime<Local>, properties: &[(String, String)]) -> PowerSample {
+        let get = |suffix: &str| properties.iter()
+            .find(|(key, _)| key.ends_with(suffix))
+            .map(|(_, value)| value.as_str());
+        PowerSample {
+            time,
+            charge: get("charge.level").and_then(|v| v.parse().ok()),
+            state: get("charge.state").map(str::to_string),
+            voltage_mv: get("battery.voltage").and_then(|v| v.parse().ok()),
+            current_ma: get("battery.current").and_then(|v| v.parse().ok()),
+            // the fuel gauge's temperature is the battery's
+            temperature_c: get("battery.temp.gauge").and_then(|v| v.parse().ok()),
+            health: get("battery.health").and_then(|v| v.parse().ok()),
+            capacity_mah: get("capacity.remain").and_then(|v| v.parse().ok()),
+        }
+    }
+
+    /// Returns the sample as a line of CSV, without the newline.
+    pub fn csv_row(&self) -> String {
+        fn cell<T: ToString>(value: &Option<T>) -> String {
+            value.as_ref().map(T::to_string).unwrap_or_default()
+        }
+        [
+            self.time.format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+            cell(&self.charge),
+            cell(&self.state),
+            cell(&self.voltage_mv),
+            cell(&self.current_ma),
+            cell(&self.temperature_c),
+            cell(&self.health),
+            cell(&self.capacity_mah),
+        ].join(",")
+    }
+
+    /// Returns the charge and state in a few words, like "85%
+    /// charging".
+    pub fn summary(&self) -> String {
+        let charge = self.charge.map_or_else(|| "?%".to_string(), |c| format!("{}%", c));
+        match &self.state {
+            Some(state) => format!("{} {}", charge, state),
+            None => charge,
+        }
+    }
+
+    /// Returns everything in the sample, a line each, leaving out
+    /// what the firmware didn't report.
+    pub fn details(&self) -> Vec<String> {
+        let mut lines = vec![format!("battery: {}", self.summary())];
+        if let Some(v) = self.voltage_mv {
+            lines.push(format!("voltage: {} mV", v));
+        }
+        if let Some(c) = self.current_ma {
+            lines.push(format!("current: {} mA", c));
+        }
+        if let Some(t) = self.temperature_c {
+            lines.push(format!("temperature: {} °C", t));
+        }
+        if let Some(h) = self.health {
+            lines.push(format!("health: {}%", h));
+        }
+        if let Some(c) = self.capacity_mah {
+            lines.push(format!("remaining: {} mAh", c));
+        }
+        lines
+    }
+
+    /// Returns whether the battery is low and not being charged.
+    pub fn is_low(&self) -> bool {
+        self.charge.is_some_and(|c| c <= LOW_CHARGE) && self.state.as_deref() != Some("charging")
+    }
+
+    /// Returns the sample as waybar wants it from a custom module: the
+    /// text to show, a tooltip with the details, the charge for
+    /// format-icons, and classes for styling (the state, and "low"
+    /// when it's low).
+    pub fn waybar_json(&self) -> serde_json::Value {
+        let mut classes: Vec<&str> = self.state.iter().map(String::as_str).collect();
+        if self.is_low() {
+            classes.push("low");
+        }
+        serde_json::json!({
+            "text": self.summary(),
+            "tooltip": self.details().join("\n"),
+            "percentage": self.charge,
+            "class": classes,
+        })
+    }
+}
+
+/// Print one battery sample the way `format` says.
+pub fn print_sample(sample: &PowerSample, format: PowerFormat) {
+    match format {
+        PowerFormat::Text => {
+            for line in sample.details() {
+                println!("{}", line);
+            }
+        },
+        PowerFormat::Line => println!("{}", sample.summary()),
+        PowerFormat::Waybar => println!("{}", sample.waybar_json()),
+    }
+}
+
+/// Sample the battery every `interval` until Ctrl+C, adding a CSV row
+/// to `out` (or printing it) for each sample. Returns how many samples
+/// were taken. A sample that fails is skipped, so a dropped connection
+/// only leaves a gap.
+#[cfg(feature = "ble")]
+pub async fn log(flipper: &mut flipper_ble::FlipperBle, interval: Duration, out: Option<&std::path::Path>) -> Result<usize, Box<dyn Error>> {
+    if interval.is_zero() {
+        return Err("the interval has to be longer than 0".into());
+    }
+    let mut file = match out {
+        Some(path) => {
+            let mut f = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+            // an existing log keeps going without a second header
+            if f.metadata()?.len() == 0 {
+                writeln!(f, "{}", CSV_HEADER)?;
+            }
+            output::status(format!("logging the battery to {:?} every {:?} (Ctrl+C to stop)", path, interval));
+            Some(f)
+        },
+        None => {
+            println!("{}", CSV_HEADER);
+            None
+        },
+    };
+
+    let mut samples = 0;
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {},
+            _ = tokio::signal::ctrl_c() => break,
+        }
+        let properties = match flipper.keep_alive().await {
+            Ok(()) => flipper.property_get("pwrinfo.").await,
+            Err(e) => Err(e),
+        };
+        let sample = match properties {
+            Ok(p) => PowerSample::from_properties(chrono::Local::now(), &p),
+            Err(e) => {
+                warn!("couldn't get power info: {}", e);
+                continue;
+            },
+        };
+        match &mut file {
+            Some(f) => {
+                writeln!(f, "{}", sample.csv_row())?;
+                debug!("battery: {}", sample.csv_row());
+            },
+            None => println!("{}", sample.csv_row()),
+        }
+        samples += 1;
+    }
+    Ok(samples)
+}
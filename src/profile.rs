@@ -0,0 +1,124 @@
+// Settings profiles: the dotfiles at the top of /int and /ext, where
+// the firmware and apps keep their settings, copied to a local
+// directory and back so several Flippers can be set up the same way.
+// The profile directory has an "int" and an "ext" directory, with the
+// files laid out as they are on the Flipper.
+
+use std::path::{Path, PathBuf};
+#[cfg(feature = "ble")]
+use std::error::Error;
+
+#[cfg(feature = "ble")]
+use crate::{flipper_ble, flipper_pb};
+#[cfg(feature = "ble")]
+use crate::path_filter::PathFilter;
+#[cfg(feature = "ble")]
+use crate::prompt;
+
+/// Flipper directories whose dotfiles make up a profile
+pub const PROFILE_ROOTS: [&str; 2] = ["/int", "/ext"];
+
+/// Dotfiles that belong to one Flipper and aren't copied: its
+/// Bluetooth pairing keys
+pub const EXCLUDED: [&str; 1] = [".bt.keys"];
+
+/// Returns whether `name`, at the top of one of the profile roots,
+/// belongs in a profile.
+pub fn is_profile_entry(name: &str) -> bool {
+    name.starts_with('.') && name != "." && name != ".." && !EXCLUDED.contains(&name)
+}
+
+/// Returns where `name` in the Flipper directory `root` is kept in
+/// the profile directory `dir`.
+pub fn local_path(dir: &Path, root: &str, name: &str) -> PathBuf {
+    dir.join(root.trim_start_matches('/')).join(name)
+}
+
+/// Download the Flipper's settings into the profile directory `dir`.
+/// Returns how many files and directories were saved.
+#[cfg(feature = "ble")]
+pub async fn save(flipper: &mut flipper_ble::FlipperBle, dir: &Path) -> Result<usize, Box<dyn Error>> {
+    let mut count = 0;
+    for root in PROFILE_ROOTS {
+        for f in flipper.list_entries(root).await? {
+            if !is_profile_entry(&f.name) {
+                continue;
+            }
+            let remote = flipper_ble::join_remote(root, &f.name);
+            let local = local_path(dir, root, &f.name);
+            info!("saving {}", remote);
+            if f.type_ == flipper_pb::storage::file::FileType::DIR.into() {
+                flipper.download_dir(&remote, &local, &PathFilter::default()).await?;
+            } else {
+                if let Some(parent) = local.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                flipper.download_file(&remote, &local).await?;
+            }
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Returns the settings in the profile directory `dir`, each as its
+/// local path and where it goes on the Flipper.
+#[cfg(feature = "ble")]
+pub fn entries(dir: &Path) -> Result<Vec<(PathBuf, String)>, Box<dyn Error>> {
+    if !dir.is_dir() {
+        return Err(format!("{} isn't a profile directory", dir.display()).into());
+    }
+    let mut entries = Vec::new();
+    for root in PROFILE_ROOTS {
+        let local_root = dir.join(root.trim_start_matches('/'));
+        if !local_root.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&local_root)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // a profile edited by hand shouldn't be able to replace
+            // another Flipper's pairing keys
+            if !is_profile_entry(&name) {
+                warn!("skipping {}, it doesn't belong in a profile", entry.path().display());
+                continue;
+            }
+            entries.push((entry.path(), flipper_ble::join_remote(root, &name)));
+        }
+    }
+    Ok(entries)
+}
+
+/// Upload the settings in the profile directory `dir` to the Flipper.
+/// Returns how many files and directories were applied.
+#[cfg(feature = "ble")]
+pub async fn apply(flipper: &mut flipper_ble::FlipperBle, dir: &Path) -> Result<usize, Box<dyn Error>> {
+    let entries = entries(dir)?;
+    for (local, remote) in &entries {
+        info!("applying {}", remote);
+        if local.is_dir() {
+            flipper.upload_dir(local, remote, &PathFilter::default()).await?;
+        } else {
+            flipper.upload_file(local, remote).await?;
+        }
+    }
+    Ok(entries.len())
+}
+
+/// List the settings on the Flipper that applying the profile in
+/// `dir` would replace and ask whether to go ahead. Doesn't ask if
+/// nothing would be replaced.
+#[cfg(feature = "ble")]
+pub async fn confirm_apply(flipper: &mut flipper_ble::FlipperBle, dir: &Path) -> Result<bool, Box<dyn Error>> {
+    let mut replaced = Vec::new();
+    for (_, remote) in entries(dir)? {
+        if flipper.stat(&remote).await?.is_some() {
+            replaced.push(remote);
+        }
+    }
+    if replaced.is_empty() {
+        return Ok(true);
+    }
+    eprintln!("replacing:");
+    prompt::confirm_deletions(&replaced, &format!("replace {} settings on the Flipper?", replaced.len())).await
+}
@@ -0,0 +1,69 @@
+// Asking questions on the terminal. Everything that reads stdin goes
+// through next_line(), so the shell and a confirmation it asks for
+// don't both try to read the same line.
+
+use std::error::Error;
+use std::io::{self, IsTerminal, Write};
+use std::sync::OnceLock;
+
+type StdinLines = tokio::sync::Mutex<tokio::sync::mpsc::UnboundedReceiver<io::Result<String>>>;
+static STDIN_LINES: OnceLock<StdinLines> = OnceLock::new();
+
+// How many paths to show before asking to delete a directory
+const DELETE_PREVIEW_COUNT: usize = 10;
+
+/// Returns the next line typed on stdin, or None at EOF. The shell
+/// and confirmation prompts both read through here, so they take
+/// turns instead of fighting over stdin.
+pub async fn next_line() -> Option<io::Result<String>> {
+    let lines = STDIN_LINES.get_or_init(|| {
+        // Reading stdin blocks, so it gets its own thread.
+        let (line_tx, lines) = tokio::sync::mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            for line in io::stdin().lines() {
+                if line_tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        tokio::sync::Mutex::new(lines)
+    });
+    lines.lock().await.recv().await
+}
+
+/// True once something has started reading stdin through
+/// next_line(), which keeps it reading for good.
+pub fn reading_stdin() -> bool {
+    STDIN_LINES.get().is_some()
+}
+
+/// Ask a yes or no question, defaulting to no. Fails when there's
+/// nobody at a terminal to answer.
+///
+/// # Arguments
+///
+/// * `question`: What to ask, without the `[y/N]`
+pub async fn confirm(question: &str) -> Result<bool, Box<dyn Error>> {
+    if !io::stdin().is_terminal() {
+        return Err("not asking for confirmation without a terminal, pass --yes to go ahead anyway".into());
+    }
+    eprint!("{} [y/N] ", question);
+    let _ = io::stderr().flush();
+
+    match next_line().await {
+        Some(Ok(answer)) => Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")),
+        Some(Err(e)) => Err(e.into()),
+        None => Ok(false),
+    }
+}
+
+/// Show up to DELETE_PREVIEW_COUNT of `paths` and ask `question`.
+pub async fn confirm_deletions(paths: &[String], question: &str) -> Result<bool, Box<dyn Error>> {
+    for p in paths.iter().take(DELETE_PREVIEW_COUNT) {
+        eprintln!("  {}", p);
+    }
+    if paths.len() > DELETE_PREVIEW_COUNT {
+        eprintln!("  ...and {} more", paths.len() - DELETE_PREVIEW_COUNT);
+    }
+    confirm(question).await
+}
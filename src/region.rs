@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use protobuf::CodedOutputStream;
+use serde::Deserialize;
+
+// Provisioning the Sub-GHz region, the same way qFlipper does it. The
+// update server publishes which frequency bands each country allows,
+// and the Flipper reads the bands for its country from a small
+// protobuf file on internal storage when it boots.
+const REGION_BUNDLE_URL: &str = "https://update.flipperzero.one/regions/api/v0/bundle";
+
+/// Where the Flipper looks for its region
+pub const REGION_DATA_PATH: &str = "/int/.region_data";
+
+#[derive(Deserialize, Debug)]
+struct BundleResponse {
+    success: Option<Bundle>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Bundle {
+    bands: HashMap<String, Band>,
+    countries: HashMap<String, Vec<String>>,
+}
+
+/// One frequency band the Flipper is allowed to transmit in
+#[derive(Deserialize, Debug, Clone)]
+pub struct Band {
+    /// Lowest frequency in Hz
+    pub start: u32,
+    /// Highest frequency in Hz
+    pub end: u32,
+    /// Transmit power limit in dBm
+    pub max_power: i32,
+    /// Duty cycle limit in percent
+    pub duty_cycle: u32,
+}
+
+/// Download the region bundle and return the bands `country` is
+/// allowed. This blocks, so call it from spawn_blocking() or outside
+/// the runtime.
+///
+/// # Arguments
+///
+/// * `country`: Two-letter country code, like "US"
+pub fn fetch_bands(country: &str) -> Result<Vec<Band>, Box<dyn Error>> {
+    debug!("fetching {}", REGION_BUNDLE_URL);
+    let response: BundleResponse = ureq::get(REGION_BUNDLE_URL)
+        .call()?
+        .into_body()
+        .read_json()?;
+
+    let bundle = match response {
+        BundleResponse { success: Some(b), .. } => b,
+        BundleResponse { error: Some(e), .. } => return Err(format!("region server returned an error: {}", e).into()),
+        _ => return Err("region server returned an empty response".into()),
+    };
+
+    let names = match bundle.countries.get(country) {
+        Some(n) => n,
+        None => return Err(format!("the region server doesn't know country {:?}", country).into()),
+    };
+    names.iter()
+        .map(|name| match bundle.bands.get(name) {
+            Some(b) => Ok(b.clone()),
+            None => Err(format!("region server is missing band {:?}", name).into()),
+        })
+        .collect()
+}
+
+/// Returns the contents of the region file for `country` with
+/// `bands`, in the firmware's Region protobuf format.
+///
+/// # Arguments
+///
+/// * `country`: Two-letter country code, like "US"
+/// * `bands`: Bands the Flipper may transmit in
+pub fn encode(country: &str, bands: &[Band]) -> Result<Vec<u8>, Box<dyn Error>> {
+    // The Region message isn't in flipperzero-protobuf, so it's
+    // written out by hand:
+    //
+    // message Region {
+    //     message Band {
+    //         uint32 start = 1;
+    //         uint32 end = 2;
+    //         int32 power_limit = 3;
+    //         uint32 duty_cycle = 4;
+    //     }
+    //     bytes country_code = 1;
+    //     repeated Band bands = 2;
+    // }
+    let mut region = Vec::new();
+    let mut os = CodedOutputStream::vec(&mut region);
+    os.write_bytes(1, country.as_bytes())?;
+    for band in bands {
+        let mut encoded = Vec::new();
+        let mut band_os = CodedOutputStream::vec(&mut encoded);
+        band_os.write_uint32(1, band.start)?;
+        band_os.write_uint32(2, band.end)?;
+        band_os.write_int32(3, band.max_power)?;
+        band_os.write_uint32(4, band.duty_cycle)?;
+        band_os.flush()?;
+        drop(band_os);
+
+        os.write_bytes(2, &encoded)?;
+    }
+    os.flush()?;
+    drop(os);
+
+    Ok(region)
+}
+
+/// Returns `country` uppercased if it looks like a two-letter country
+/// code.
+pub fn parse_country(country: &str) -> Result<String, String> {
+    if country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(country.to_ascii_uppercase())
+    } else {
+        Err(format!("{:?} isn't a two-letter country code", country))
+    }
+}
@@ -0,0 +1,213 @@
+// Commands that work on single Flipper files and directories in
+// place: printing, searching, editing, checking, and deleting them.
+
+use std::env;
+use std::error::Error;
+use std::io::{self, Write};
+use std::process;
+
+use crate::flipper_ble;
+use crate::flipper_pb;
+use crate::output;
+use crate::prompt;
+
+/// Stream a Flipper file (or just its first `limit` bytes) to stdout,
+/// for `cat` and `head`.
+pub async fn print(flipper: &mut flipper_ble::FlipperBle, file: &str, limit: Option<u64>) {
+    let mut stdout = io::stdout().lock();
+    let result = flipper.read_file(file, limit, |data| {
+        stdout.write_all(data)?;
+        Ok(())
+    }).await;
+
+    match result.and_then(|_| Ok(stdout.flush()?)) {
+        Ok(()) => {},
+        Err(e) => {
+            output::error(format!("failed to read file {:?}: {}", file, e));
+        }
+    };
+}
+
+/// Print the lines matching `re` in every Flipper file matching
+/// `pattern`, prefixed with the file name. Files are read straight
+/// from the Flipper and never saved.
+pub async fn grep(flipper: &mut flipper_ble::FlipperBle, re: &regex::Regex, pattern: &str) -> Result<(), Box<dyn Error>> {
+    let paths = flipper.expand_glob(pattern).await?;
+    if paths.is_empty() {
+        return Err("no files match".into());
+    }
+
+    for path in paths {
+        // directories can match a wildcard too
+        match flipper.stat(&path).await? {
+            Some(f) if f.type_ == flipper_pb::storage::file::FileType::DIR.into() => continue,
+            _ => {},
+        }
+
+        // Lines can be split across reads, so hold on to the partial
+        // line at the end of each piece until the rest arrives.
+        let mut partial = Vec::new();
+        let print_matches = |line: &[u8]| {
+            let line = String::from_utf8_lossy(line);
+            let line = line.trim_end_matches('\r');
+            if re.is_match(line) {
+                println!("{}:{}", path, line);
+            }
+        };
+
+        flipper.read_file(&path, None, |data| {
+            partial.extend(data);
+            while let Some(newline) = partial.iter().position(|b| *b == b'\n') {
+                print_matches(&partial[..newline]);
+                partial.drain(..=newline);
+            }
+            Ok(())
+        }).await?;
+
+        // last line without a newline
+        if !partial.is_empty() {
+            print_matches(&partial);
+        }
+    }
+
+    Ok(())
+}
+
+/// Download a Flipper file to a temporary file, open it in the user's
+/// editor, and upload it again if it changed. Returns whether it was
+/// uploaded.
+pub async fn edit(flipper: &mut flipper_ble::FlipperBle, file: &str) -> Result<bool, Box<dyn Error>> {
+    // keep the extension so the editor can pick the right mode
+    let suffix = match std::path::Path::new(file).extension() {
+        Some(ext) => format!(".{}", ext.to_string_lossy()),
+        None => String::new(),
+    };
+    let temp = tempfile::Builder::new()
+        .prefix("flipwire-")
+        .suffix(&suffix)
+        .tempfile()?;
+
+    // None means the file doesn't exist yet and we're creating it
+    let original_md5 = match flipper.stat(file).await? {
+        Some(_) => {
+            flipper.download_file(file, temp.path()).await?;
+            Some(format!("{:x}", md5::compute(std::fs::read(temp.path())?)))
+        },
+        None => {
+            info!("{} doesn't exist, starting a new file", file);
+            None
+        },
+    };
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad".to_string() } else { "vi".to_string() });
+    // $EDITOR can have arguments in it, like "code --wait"
+    let mut editor_args = editor.split_whitespace();
+    let program = editor_args.next().ok_or("$EDITOR is empty")?;
+
+    let status = process::Command::new(program)
+        .args(editor_args)
+        .arg(temp.path())
+        .status()?;
+    if !status.success() {
+        return Err(format!("editor exited with {}", status).into());
+    }
+
+    let edited_md5 = format!("{:x}", md5::compute(std::fs::read(temp.path())?));
+    if original_md5.as_ref() == Some(&edited_md5) {
+        return Ok(false);
+    }
+
+    // Make sure nothing else changed the file on the Flipper while
+    // we were editing, so we don't overwrite those changes.
+    let current_md5 = match flipper.stat(file).await? {
+        Some(_) => Some(flipper.md5sum(file).await?),
+        None => None,
+    };
+    if current_md5 != original_md5 {
+        let (_, kept) = temp.keep()?;
+        return Err(format!(
+            "{} changed on the Flipper while you were editing it, your version is saved at {:?}",
+            file, kept).into());
+    }
+
+    flipper.upload_file(temp.path(), file).await?;
+    Ok(true)
+}
+
+/// Returns the MD5s of `local` and the Flipper file `remote`, as hex.
+pub async fn md5s(flipper: &mut flipper_ble::FlipperBle, local: &std::path::Path, remote: &str) -> Result<(String, String), Box<dyn Error>> {
+    let data = std::fs::read(local).map_err(|e| format!("can't read {:?}: {}", local, e))?;
+    let local_md5 = format!("{:x}", md5::compute(data));
+    Ok((local_md5, flipper.md5sum(remote).await?))
+}
+
+/// Print everything `rm` would delete at `path`, with sizes, in the
+/// order it would go. Returns how much that is, from
+/// delete_summary().
+pub async fn print_delete_plan(flipper: &mut flipper_ble::FlipperBle, path: &str) -> Result<String, Box<dyn Error>> {
+    let doomed = delete_plan(flipper, path).await?;
+    for (p, f) in &doomed {
+        if is_dir(f) {
+            println!("{:>10}  {}", "dir", p);
+        } else {
+            println!("{:>10}  {}", indicatif::HumanBytes(u64::from(f.size)).to_string(), p);
+        }
+    }
+    Ok(delete_summary(&doomed))
+}
+
+/// Returns everything deleting `path` gets rid of, in the order the
+/// Flipper deletes it: what's in a directory, then the directory.
+pub async fn delete_plan(flipper: &mut flipper_ble::FlipperBle, path: &str) -> Result<Vec<(String, flipper_pb::storage::File)>, Box<dyn Error>> {
+    let top = flipper.stat(path).await?.ok_or("it doesn't exist")?;
+    let mut doomed = if is_dir(&top) {
+        flipper.walk(path).await?
+    } else {
+        Vec::new()
+    };
+    doomed.reverse();
+    doomed.push((path.to_string(), top));
+    Ok(doomed)
+}
+
+/// True if `f` is a directory.
+pub fn is_dir(f: &flipper_pb::storage::File) -> bool {
+    f.type_ == flipper_pb::storage::file::FileType::DIR.into()
+}
+
+/// Returns how much is in `doomed`, from delete_plan(), like "132
+/// files / 4 directories (8.2 MiB)".
+pub fn delete_summary(doomed: &[(String, flipper_pb::storage::File)]) -> String {
+    let dirs = doomed.iter().filter(|(_, f)| is_dir(f)).count();
+    let files = doomed.len() - dirs;
+    let bytes: u64 = doomed.iter()
+        .filter(|(_, f)| !is_dir(f))
+        .map(|(_, f)| u64::from(f.size))
+        .sum();
+    format!("{} file{} / {} director{} ({})",
+            files, if files == 1 { "" } else { "s" },
+            dirs, if dirs == 1 { "y" } else { "ies" },
+            indicatif::HumanBytes(bytes))
+}
+
+/// If `path` is a directory with things in it, show what's in it and
+/// ask before deleting it all. Returns whether to go ahead.
+pub async fn confirm_delete(flipper: &mut flipper_ble::FlipperBle, path: &str) -> Result<bool, Box<dyn Error>> {
+    match flipper.stat(path).await? {
+        Some(f) if is_dir(&f) => {},
+        // a single file (or nothing at all) only does what it says
+        _ => return Ok(true),
+    }
+    let doomed = delete_plan(flipper, path).await?;
+    // just the directory itself
+    if doomed.len() == 1 {
+        return Ok(true);
+    }
+
+    eprintln!("{} contains:", path);
+    // delete_plan() has the deepest first, but top down reads better
+    let paths: Vec<String> = doomed.iter().rev().skip(1).map(|(p, _)| p.clone()).collect();
+    prompt::confirm_deletions(&paths, &format!("this will delete {}, go ahead?", delete_summary(&doomed))).await
+}
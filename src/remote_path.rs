@@ -0,0 +1,118 @@
+// Turning the paths people type into full Flipper paths. Anything
+// that doesn't start with "/" is relative to a working directory,
+// which is /ext unless you change it with --cwd or `cd` in the shell.
+
+use std::path::{Path, PathBuf};
+
+/// Where relative paths start from by default
+pub const DEFAULT_CWD: &str = "/ext";
+
+/// Returns `path` as a full Flipper path, resolving it against `cwd`
+/// if it's relative and cleaning up "." and "..". A trailing slash is
+/// kept, since that's how you say "this is a directory".
+///
+/// # Arguments
+///
+/// * `cwd`: Full Flipper path of the working directory
+/// * `path`: Path to resolve, like "nfc", "../int", or "/ext/nfc"
+pub fn resolve(cwd: &str, path: &str) -> String {
+    let joined = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("{}/{}", cwd, path)
+    };
+
+    let mut parts = Vec::new();
+    for part in joined.split('/') {
+        match part {
+            "" | "." => {},
+            ".." => {
+                parts.pop();
+            },
+            p => parts.push(p),
+        }
+    }
+
+    let mut resolved = format!("/{}", parts.join("/"));
+    if path.ends_with('/') && resolved != "/" {
+        resolved.push('/');
+    }
+    resolved
+}
+
+/// True for paths that are a storage root (or "/" itself), which the
+/// Flipper can list but not stat.
+pub fn is_root(path: &str) -> bool {
+    matches!(path.trim_end_matches('/'), "" | "/int" | "/ext" | "/any")
+}
+
+/// Returns the local path to download the Flipper path `file` to,
+/// like scp does: `dest` itself, unless it's an existing directory
+/// (or not given at all, meaning the current directory), in which
+/// case it's `file`'s name inside that directory.
+pub fn download_destination(file: &str, dest: Option<&Path>) -> PathBuf {
+    let dir = match dest {
+        Some(d) if !d.is_dir() => return d.to_path_buf(),
+        Some(d) => d,
+        None => Path::new("."),
+    };
+    let name = file.trim_end_matches('/').rsplit('/').next().unwrap_or(file);
+    dir.join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_relative_test() {
+        assert_eq!(resolve("/ext", "nfc"), "/ext/nfc");
+        assert_eq!(resolve("/ext", "nfc/card.nfc"), "/ext/nfc/card.nfc");
+        assert_eq!(resolve("/ext/nfc/", "card.nfc"), "/ext/nfc/card.nfc");
+        assert_eq!(resolve("/ext", ""), "/ext");
+    }
+
+    #[test]
+    fn resolve_absolute_test() {
+        assert_eq!(resolve("/ext/nfc", "/int/manifest.txt"), "/int/manifest.txt");
+        assert_eq!(resolve("/ext/nfc", "/"), "/");
+        assert_eq!(resolve("/ext", "//ext//nfc"), "/ext/nfc");
+    }
+
+    #[test]
+    fn resolve_dots_test() {
+        assert_eq!(resolve("/ext", "."), "/ext");
+        assert_eq!(resolve("/ext", "./nfc/./card.nfc"), "/ext/nfc/card.nfc");
+        assert_eq!(resolve("/ext/nfc", ".."), "/ext");
+        assert_eq!(resolve("/ext", "../int"), "/int");
+        assert_eq!(resolve("/ext", "nfc/../subghz"), "/ext/subghz");
+        assert_eq!(resolve("/ext", "/int/../ext/nfc"), "/ext/nfc");
+        // there's nothing above the root to go up to
+        assert_eq!(resolve("/ext", "../../.."), "/");
+        assert_eq!(resolve("/", "../ext"), "/ext");
+    }
+
+    #[test]
+    fn resolve_trailing_slash_test() {
+        assert_eq!(resolve("/ext", "nfc/"), "/ext/nfc/");
+        assert_eq!(resolve("/ext", "/int/"), "/int/");
+        assert_eq!(resolve("/ext/nfc", "../"), "/ext/");
+        // the root never gets a second slash
+        assert_eq!(resolve("/ext", "/"), "/");
+        assert_eq!(resolve("/ext", "../"), "/");
+        // the slash only counts on the path that was typed
+        assert_eq!(resolve("/ext/", "nfc"), "/ext/nfc");
+    }
+
+    #[test]
+    fn is_root_test() {
+        for path in ["/", "", "/int", "/ext", "/any", "/ext/", "/int//"] {
+            assert!(is_root(path), "{:?}", path);
+        }
+        for path in ["/ext/nfc", "/ext/nfc/", "/extra", "ext", "/int/manifest.txt"] {
+            assert!(!is_root(path), "{:?}", path);
+        }
+        assert!(is_root(&resolve("/ext/nfc", "..")));
+        assert!(is_root(&resolve("/ext", "..")));
+    }
+}
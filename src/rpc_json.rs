@@ -0,0 +1,158 @@
+// Converting RPC messages to and from JSON, following protobuf's JSON
+// mapping: fields by name (either `storage_list_request` or
+// `storageListRequest`), enums by name, 64-bit numbers as strings, and
+// bytes as base64. This is what lets `rpc send` take any message
+// without flipwire knowing about it.
+
+use std::error::Error;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use protobuf::MessageDyn;
+use protobuf::reflect::{FieldDescriptor, MessageDescriptor, ReflectValueBox, ReflectValueRef,
+                        RuntimeFieldType, RuntimeType};
+use serde_json::{Map, Value};
+
+use crate::flipper_pb;
+
+/// Returns the Main message described by `json`, like
+/// `{"storage_list_request": {"path": "/ext"}}`.
+///
+/// # Arguments
+///
+/// * `json`: A JSON object with Main's fields
+pub fn main_from_json(json: &str) -> Result<flipper_pb::flipper::Main, Box<dyn Error>> {
+    let value: Value = serde_json::from_str(json)?;
+    let descriptor = MessageDescriptor::for_type::<flipper_pb::flipper::Main>();
+    let message = message_from_value(&descriptor, &value)?;
+    match message.downcast_box::<flipper_pb::flipper::Main>() {
+        Ok(m) => Ok(*m),
+        Err(_) => Err("message isn't a Main".into()),
+    }
+}
+
+/// Returns `message` as a JSON object. Fields that are unset or have
+/// their default value are left out.
+pub fn to_json(message: &dyn MessageDyn) -> Value {
+    let mut object = Map::new();
+    for field in message.descriptor_dyn().fields() {
+        let value = match field.runtime_field_type() {
+            RuntimeFieldType::Singular(_) => match field.get_singular(message) {
+                Some(v) => value_to_json(v),
+                None => continue,
+            },
+            RuntimeFieldType::Repeated(_) => {
+                let repeated = field.get_repeated(message);
+                if repeated.is_empty() {
+                    continue;
+                }
+                Value::Array(repeated.into_iter().map(value_to_json).collect())
+            },
+            // none of the Flipper's messages have maps
+            RuntimeFieldType::Map(..) => continue,
+        };
+        object.insert(field.json_name().to_string(), value);
+    }
+    Value::Object(object)
+}
+
+fn value_to_json(value: ReflectValueRef) -> Value {
+    match value {
+        ReflectValueRef::U32(n) => Value::from(n),
+        ReflectValueRef::I32(n) => Value::from(n),
+        // 64-bit numbers don't fit in a double
+        ReflectValueRef::U64(n) => Value::from(n.to_string()),
+        ReflectValueRef::I64(n) => Value::from(n.to_string()),
+        ReflectValueRef::F32(n) => Value::from(n),
+        ReflectValueRef::F64(n) => Value::from(n),
+        ReflectValueRef::Bool(b) => Value::from(b),
+        ReflectValueRef::String(s) => Value::from(s),
+        ReflectValueRef::Bytes(b) => Value::from(BASE64.encode(b)),
+        ReflectValueRef::Enum(e, n) => match e.value_by_number(n) {
+            Some(v) => Value::from(v.name()),
+            None => Value::from(n),
+        },
+        ReflectValueRef::Message(m) => to_json(&*m),
+    }
+}
+
+fn message_from_value(descriptor: &MessageDescriptor, value: &Value) -> Result<Box<dyn MessageDyn>, Box<dyn Error>> {
+    let object = match value {
+        Value::Object(o) => o,
+        _ => return Err(format!("expected an object for {}, got {}", descriptor.name(), value).into()),
+    };
+
+    let mut message = descriptor.new_instance();
+    for (name, value) in object {
+        let field = match descriptor.field_by_name_or_json_name(name) {
+            Some(f) => f,
+            None => return Err(format!("{} has no field {:?}", descriptor.name(), name).into()),
+        };
+        set_field(&field, &mut *message, value)?;
+    }
+    Ok(message)
+}
+
+fn set_field(field: &FieldDescriptor, message: &mut dyn MessageDyn, value: &Value) -> Result<(), Box<dyn Error>> {
+    match field.runtime_field_type() {
+        RuntimeFieldType::Singular(t) => {
+            // null means the default, which is what's already there
+            if !value.is_null() {
+                field.set_singular_field(message, value_from_json(field, &t, value)?);
+            }
+        },
+        RuntimeFieldType::Repeated(t) => {
+            let items = match value {
+                Value::Array(a) => a,
+                _ => return Err(format!("expected a list for {}", field.name()).into()),
+            };
+            for item in items {
+                let item = value_from_json(field, &t, item)?;
+                field.mut_repeated(message).push(item);
+            }
+        },
+        RuntimeFieldType::Map(..) => return Err(format!("map field {} isn't supported", field.name()).into()),
+    }
+    Ok(())
+}
+
+fn value_from_json(field: &FieldDescriptor, t: &RuntimeType, value: &Value) -> Result<ReflectValueBox, Box<dyn Error>> {
+    let wrong_type = || -> Box<dyn Error> {
+        format!("wrong type for {}: {}", field.name(), value).into()
+    };
+    // numbers can be given as JSON numbers or strings
+    let number = || -> Option<String> {
+        match value {
+            Value::Number(n) => Some(n.to_string()),
+            Value::String(s) => Some(s.clone()),
+            _ => None,
+        }
+    };
+
+    Ok(match t {
+        RuntimeType::I32 => ReflectValueBox::I32(number().and_then(|n| n.parse().ok()).ok_or_else(wrong_type)?),
+        RuntimeType::I64 => ReflectValueBox::I64(number().and_then(|n| n.parse().ok()).ok_or_else(wrong_type)?),
+        RuntimeType::U32 => ReflectValueBox::U32(number().and_then(|n| n.parse().ok()).ok_or_else(wrong_type)?),
+        RuntimeType::U64 => ReflectValueBox::U64(number().and_then(|n| n.parse().ok()).ok_or_else(wrong_type)?),
+        RuntimeType::F32 => ReflectValueBox::F32(number().and_then(|n| n.parse().ok()).ok_or_else(wrong_type)?),
+        RuntimeType::F64 => ReflectValueBox::F64(number().and_then(|n| n.parse().ok()).ok_or_else(wrong_type)?),
+        RuntimeType::Bool => ReflectValueBox::Bool(value.as_bool().ok_or_else(wrong_type)?),
+        RuntimeType::String => ReflectValueBox::String(value.as_str().ok_or_else(wrong_type)?.to_string()),
+        RuntimeType::VecU8 => {
+            let encoded = value.as_str().ok_or_else(wrong_type)?;
+            ReflectValueBox::Bytes(BASE64.decode(encoded)?)
+        },
+        RuntimeType::Enum(e) => {
+            let v = match value {
+                Value::String(name) => e.value_by_name(name),
+                Value::Number(n) => n.as_i64().and_then(|n| i32::try_from(n).ok()).and_then(|n| e.value_by_number(n)),
+                _ => None,
+            };
+            match v {
+                Some(v) => ReflectValueBox::Enum(e.clone(), v.value()),
+                None => return Err(format!("{} isn't a {}", value, e.name()).into()),
+            }
+        },
+        RuntimeType::Message(m) => ReflectValueBox::Message(message_from_value(m, value)?),
+    })
+}
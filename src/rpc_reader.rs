@@ -0,0 +1,160 @@
+// Reading RPC messages in the background. One task owns the stream of
+// notifications for the whole connection, splits it back up into
+// messages, and hands each one to whoever is waiting on its command
+// ID. Commands just wait on a channel instead of polling the stream,
+// and nothing gets lost in between commands.
+//
+// This doesn't know anything about BLE, it only needs the raw bytes
+// the Flipper sends, in order. Nor does it need tokio to run the task,
+// see new().
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use futures::future::{self, AbortHandle};
+use futures::{Future, Stream, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::flipper_pb;
+use crate::protobuf_codec::ProtobufCodec;
+
+/// Command ID the Flipper uses for messages that aren't the response
+/// to a request, like screen frames. Requests should never use it.
+pub const UNSOLICITED_COMMAND_ID: u32 = 0;
+
+type Routes = Arc<Mutex<HashMap<u32, mpsc::UnboundedSender<flipper_pb::flipper::Main>>>>;
+
+pub struct RpcReader {
+    routes: Routes,
+    // stops the reading task
+    task: AbortHandle,
+}
+
+/// Messages the Flipper sends for one command ID. The reader stops
+/// passing them along when this is dropped.
+pub struct Responses {
+    command_id: u32,
+    rx: mpsc::UnboundedReceiver<flipper_pb::flipper::Main>,
+    routes: Routes,
+}
+
+impl RpcReader {
+    /// Start reading messages from `stream` on a new task.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream`: Data of every notification from the Flipper, in
+    ///             the order it arrived
+    pub fn spawn<S>(stream: S) -> RpcReader
+    where S: Stream<Item = Vec<u8>> + Send + Unpin + 'static {
+        let (reader, task) = RpcReader::new(stream);
+        tokio::spawn(task);
+        reader
+    }
+
+    /// Returns a reader for messages from `stream`, and the task that
+    /// reads them, for running on something other than tokio (like a
+    /// browser's event loop). Nothing gets read until the task runs.
+    /// It finishes once the stream ends or the reader is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream`: Data of every notification from the Flipper, in
+    ///             the order it arrived
+    pub fn new<S>(stream: S) -> (RpcReader, impl Future<Output = ()> + Send + 'static)
+    where S: Stream<Item = Vec<u8>> + Send + Unpin + 'static {
+        let routes = Routes::default();
+        let (task, abort) = future::abortable(read_messages(stream, routes.clone()));
+
+        (RpcReader {
+            routes,
+            task: abort,
+        }, async move {
+            let _ = task.await;
+        })
+    }
+
+    /// Returns the messages the Flipper sends with `command_id`. Call
+    /// this before sending the request, so that the response can't
+    /// arrive before anyone is waiting for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `command_id`: Command ID of the request
+    pub fn subscribe(&self, command_id: u32) -> Responses {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.routes.lock().unwrap().insert(command_id, tx);
+
+        Responses {
+            command_id,
+            rx,
+            routes: self.routes.clone(),
+        }
+    }
+}
+
+impl Drop for RpcReader {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Responses {
+    /// Wait for the next message. Fails if the connection is gone.
+    pub async fn next(&mut self) -> Result<flipper_pb::flipper::Main, Box<dyn Error>> {
+        self.rx.recv().await.ok_or_else(|| "lost connection to the Flipper".into())
+    }
+}
+
+impl Drop for Responses {
+    fn drop(&mut self) {
+        // Only remove the route if it's still ours, someone else may
+        // have subscribed to the same ID since.
+        self.rx.close();
+        let mut routes = self.routes.lock().unwrap();
+        if routes.get(&self.command_id).is_some_and(|tx| tx.is_closed()) {
+            routes.remove(&self.command_id);
+        }
+    }
+}
+
+async fn read_messages<S>(mut stream: S, routes: Routes)
+where S: Stream<Item = Vec<u8>> + Unpin {
+    // The Flipper splits big messages over several notifications and
+    // packs small ones together, so notifications and messages don't
+    // line up.
+    let mut buffer = Vec::new();
+    while let Some(data) = stream.next().await {
+        buffer.extend(data);
+        loop {
+            match ProtobufCodec::take_message(&mut buffer) {
+                Ok(Some(m)) => dispatch(&routes, m),
+                Ok(None) => break,
+                Err(e) => {
+                    // there's no telling where the next message
+                    // starts, so start over with the next notification
+                    warn!("couldn't decode data from the Flipper, dropping {} bytes: {}", buffer.len(), e);
+                    buffer.clear();
+                    break;
+                },
+            }
+        }
+    }
+
+    debug!("notification stream ended");
+    // dropping the senders wakes up everyone still waiting
+    routes.lock().unwrap().clear();
+}
+
+fn dispatch(routes: &Routes, m: flipper_pb::flipper::Main) {
+    let routes = routes.lock().unwrap();
+    match routes.get(&m.command_id) {
+        // this only fails if the Responses is being dropped right now
+        Some(tx) => {
+            let _ = tx.send(m);
+        },
+        // like the rest of a file after `head` stopped reading it
+        None => debug!("dropping message nobody is waiting for: {:?}", m),
+    }
+}
@@ -0,0 +1,393 @@
+#[cfg(feature = "png")]
+use std::io::{self, Write};
+use std::str::FromStr;
+#[cfg(feature = "ble")]
+use std::error::Error;
+#[cfg(feature = "ble")]
+use std::process;
+#[cfg(feature = "ble")]
+use std::time::Duration;
+
+#[cfg(feature = "png")]
+use flate2::read::ZlibDecoder;
+#[cfg(feature = "png")]
+use flate2::write::ZlibEncoder;
+#[cfg(feature = "png")]
+use flate2::Compression;
+
+use crate::flipper_pb;
+#[cfg(feature = "ble")]
+use crate::{flipper_ble, hook, output};
+
+// The Flipper streams its screen as 1024-byte frames in the display
+// controller's native layout: 8 "pages" of 128 bytes, where each byte
+// is a column of 8 pixels in that page, lowest bit on top. A set bit
+// is a dark pixel.
+pub const SCREEN_WIDTH: usize = 128;
+pub const SCREEN_HEIGHT: usize = 64;
+
+/// One frame of the Flipper's screen
+#[derive(Clone)]
+pub struct Frame {
+    data: Vec<u8>,
+    // the Flipper sends frames upside down when the screen is
+    // flipped for left-handed mode
+    flipped: bool,
+}
+
+impl Frame {
+    pub fn from_pb(frame: &flipper_pb::gui::ScreenFrame) -> Frame {
+        Frame {
+            data: frame.data.clone(),
+            flipped: frame.orientation == flipper_pb::gui::ScreenOrientation::HORIZONTAL_FLIP.into(),
+        }
+    }
+
+    /// Returns a frame with every pixel light.
+    pub fn blank() -> Frame {
+        Frame {
+            data: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT / 8],
+            flipped: false,
+        }
+    }
+
+    /// Returns the frame as a ScreenFrame to send to the Flipper.
+    pub fn to_pb(&self) -> flipper_pb::gui::ScreenFrame {
+        let mut frame = flipper_pb::gui::ScreenFrame::new();
+        frame.data = self.data.clone();
+        frame
+    }
+
+    /// Make the pixel at (`x`, `y`) dark or light. Pixels off the
+    /// screen are ignored.
+    pub fn set_pixel(&mut self, x: usize, y: usize, dark: bool) {
+        if x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT {
+            return;
+        }
+        let (x, y) = if self.flipped {
+            (SCREEN_WIDTH - 1 - x, SCREEN_HEIGHT - 1 - y)
+        } else {
+            (x, y)
+        };
+
+        if let Some(byte) = self.data.get_mut((y / 8) * SCREEN_WIDTH + x) {
+            if dark {
+                *byte |= 1 << (y % 8);
+            } else {
+                *byte &= !(1 << (y % 8));
+            }
+        }
+    }
+
+    /// Returns true if the pixel at (`x`, `y`) is dark. (0, 0) is the
+    /// top left corner.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let (x, y) = if self.flipped {
+            (SCREEN_WIDTH - 1 - x, SCREEN_HEIGHT - 1 - y)
+        } else {
+            (x, y)
+        };
+
+        match self.data.get((y / 8) * SCREEN_WIDTH + x) {
+            Some(byte) => byte & (1 << (y % 8)) != 0,
+            None => false,
+        }
+    }
+}
+
+/// A rectangle of the screen, in pixels from the top left corner
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    /// Returns the whole screen.
+    pub fn full() -> Region {
+        Region { x: 0, y: 0, width: SCREEN_WIDTH, height: SCREEN_HEIGHT }
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    /// Parse a region like "0,0,128,16", as x, y, width, height.
+    fn from_str(s: &str) -> Result<Region, String> {
+        let numbers = s.split(',')
+            .map(|n| n.trim().parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|_| format!("invalid region {:?}, use x,y,width,height", s))?;
+        let region = match numbers[..] {
+            [x, y, width, height] => Region { x, y, width, height },
+            _ => return Err(format!("invalid region {:?}, use x,y,width,height", s)),
+        };
+        if region.width == 0 || region.height == 0
+            || region.x + region.width > SCREEN_WIDTH || region.y + region.height > SCREEN_HEIGHT {
+            return Err(format!("region {:?} isn't inside the {}x{} screen", s, SCREEN_WIDTH, SCREEN_HEIGHT));
+        }
+        Ok(region)
+    }
+}
+
+/// Returns how many pixels inside `region` are different between `a`
+/// and `b`.
+pub fn changed_pixels(a: &Frame, b: &Frame, region: &Region) -> usize {
+    let mut changed = 0;
+    for y in region.y..region.y + region.height {
+        for x in region.x..region.x + region.width {
+            if a.pixel(x, y) != b.pixel(x, y) {
+                changed += 1;
+            }
+        }
+    }
+    changed
+}
+
+/// Returns a hash of the pixels inside `region`, for recognizing a
+/// screen without keeping an image of it. Frames with the same pixels
+/// there hash the same.
+#[cfg(feature = "png")]
+pub fn region_hash(frame: &Frame, region: &Region) -> u32 {
+    let mut pixels = Vec::with_capacity(4 + region.width * region.height);
+    pixels.extend([region.x, region.y, region.width, region.height].map(|n| n as u8));
+    for y in region.y..region.y + region.height {
+        for x in region.x..region.x + region.width {
+            pixels.push(u8::from(frame.pixel(x, y)));
+        }
+    }
+    crc32fast::hash(&pixels)
+}
+
+/// Encode a frame as a PNG, with each pixel blown up to `scale` x
+/// `scale` pixels. It's in the Flipper's own colors, black on orange.
+#[cfg(feature = "png")]
+pub fn render_png(frame: &Frame, scale: usize) -> io::Result<Vec<u8>> {
+    let width = SCREEN_WIDTH * scale;
+    let height = SCREEN_HEIGHT * scale;
+
+    // The image is 1 bit per pixel with a two color palette, so each
+    // row is the filter type (0, none) and then 8 pixels per byte.
+    let row_bytes = width.div_ceil(8);
+    let mut raw = Vec::with_capacity(height * (row_bytes + 1));
+    for y in 0..height {
+        raw.push(0);
+        let mut row = vec![0u8; row_bytes];
+        for x in 0..width {
+            if frame.pixel(x / scale, y / scale) {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        raw.extend(row);
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&raw)?;
+    let image_data = encoder.finish()?;
+
+    let mut header = Vec::new();
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // bit depth 1, palette color, default compression, filtering,
+    // and no interlacing
+    header.extend([1, 3, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_png_chunk(&mut png, b"IHDR", &header);
+    // background orange, then pixel black
+    write_png_chunk(&mut png, b"PLTE", &[0xff, 0x82, 0x00, 0x00, 0x00, 0x00]);
+    write_png_chunk(&mut png, b"IDAT", &image_data);
+    write_png_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+/// Returns the frame in a PNG made by render_png(), at any scale. Other
+/// 1-bit PNGs work too, as long as they're a multiple of 128x64 and
+/// not interlaced; darker colors are dark pixels.
+#[cfg(feature = "png")]
+pub fn parse_png(png: &[u8]) -> Result<Frame, String> {
+    let mut rest = png.strip_prefix(b"\x89PNG\r\n\x1a\n").ok_or("not a PNG")?;
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut image_data = Vec::new();
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let kind = &rest[4..8];
+        let data = rest.get(8..8 + length).ok_or("PNG is cut off")?;
+        match kind {
+            b"IHDR" => header = Some(data.to_vec()),
+            b"PLTE" => palette = data.to_vec(),
+            b"IDAT" => image_data.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {},
+        }
+        rest = rest.get(8 + length + 4..).ok_or("PNG is cut off")?;
+    }
+
+    let header = header.filter(|h| h.len() == 13).ok_or("PNG has no header")?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, color_type, interlace) = (header[8], header[9], header[12]);
+    if depth != 1 || !(color_type == 0 || color_type == 3) || interlace != 0 {
+        return Err("only 1-bit grayscale or palette PNGs without interlacing work".to_string());
+    }
+    if width == 0 || !width.is_multiple_of(SCREEN_WIDTH) || width / SCREEN_WIDTH * SCREEN_HEIGHT != height {
+        return Err(format!("a {}x{} image isn't the Flipper's screen at any scale", width, height));
+    }
+    // which of the two colors is dark
+    let dark = |index: usize| -> bool {
+        if color_type == 0 {
+            return index == 0;
+        }
+        match palette.get(index * 3..index * 3 + 3) {
+            Some(rgb) => 299 * u32::from(rgb[0]) + 587 * u32::from(rgb[1]) + 114 * u32::from(rgb[2]) < 128_000,
+            None => false,
+        }
+    };
+
+    let mut raw = Vec::new();
+    io::Read::read_to_end(&mut ZlibDecoder::new(&image_data[..]), &mut raw)
+        .map_err(|e| format!("PNG data is corrupt: {}", e))?;
+    let row_bytes = width.div_ceil(8);
+    if raw.len() < height * (row_bytes + 1) {
+        return Err("PNG data is cut off".to_string());
+    }
+
+    // Undo each row's filter. With under 8 bits per pixel, filters
+    // work on whole bytes and "left" is the byte before.
+    let mut rows: Vec<Vec<u8>> = Vec::with_capacity(height);
+    for y in 0..height {
+        let line = &raw[y * (row_bytes + 1)..(y + 1) * (row_bytes + 1)];
+        let mut row = line[1..].to_vec();
+        let up = |i: usize| if y > 0 { rows[y - 1][i] } else { 0 };
+        for i in 0..row_bytes {
+            let left = if i > 0 { row[i - 1] } else { 0 };
+            let up_left = if i > 0 { up(i - 1) } else { 0 };
+            row[i] = row[i].wrapping_add(match line[0] {
+                0 => 0,
+                1 => left,
+                2 => up(i),
+                3 => ((u16::from(left) + u16::from(up(i))) / 2) as u8,
+                4 => paeth(left, up(i), up_left),
+                f => return Err(format!("unknown PNG filter {}", f)),
+            });
+        }
+        rows.push(row);
+    }
+
+    let scale = width / SCREEN_WIDTH;
+    let mut frame = Frame::blank();
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            // the middle of each scaled up pixel
+            let (px, py) = (x * scale + scale / 2, y * scale + scale / 2);
+            let index = (rows[py][px / 8] >> (7 - px % 8)) & 1;
+            frame.set_pixel(x, y, dark(usize::from(index)));
+        }
+    }
+    Ok(frame)
+}
+
+#[cfg(feature = "png")]
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = ((p - i16::from(a)).abs(), (p - i16::from(b)).abs(), (p - i16::from(c)).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Returns a frame where the pixels that are different between `a`
+/// and `b` are dark, for showing what changed.
+pub fn diff_frame(a: &Frame, b: &Frame) -> Frame {
+    let mut diff = Frame::blank();
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            diff.set_pixel(x, y, a.pixel(x, y) != b.pixel(x, y));
+        }
+    }
+    diff
+}
+
+#[cfg(feature = "png")]
+fn write_png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend(crc.finalize().to_be_bytes());
+}
+
+/// Watch the screen until Ctrl+C, running `on_change` (or printing a
+/// line without it) whenever at least `threshold` pixels in `region`
+/// change from one frame to the next. A change while the command from
+/// the last one is still running doesn't start it again.
+#[cfg(feature = "ble")]
+pub async fn watch(flipper: &mut flipper_ble::FlipperBle, on_change: Option<&str>, region: &Region, threshold: usize) -> Result<(), Box<dyn Error>> {
+    output::status(format!("watching {}x{} pixels at ({}, {}) (Ctrl+C to stop)",
+                           region.width, region.height, region.x, region.y));
+    let mut previous: Option<Frame> = None;
+    let mut running: Option<process::Child> = None;
+
+    flipper.stream_screen(|frame| {
+        let changed = match &previous {
+            Some(p) => changed_pixels(p, frame, region),
+            // the first frame is what the screen starts out as
+            None => 0,
+        };
+        previous = Some(frame.clone());
+        if changed < threshold {
+            return true;
+        }
+
+        let command = match on_change {
+            Some(c) => c,
+            None => {
+                println!("{}  screen changed ({} pixels)", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"), changed);
+                return true;
+            },
+        };
+        if let Some(child) = &mut running {
+            if matches!(child.try_wait(), Ok(None)) {
+                debug!("{:?} is still running, skipping this change", command);
+                return true;
+            }
+        }
+        match hook::command(command)
+            .env("FLIPWIRE_CHANGED_PIXELS", changed.to_string())
+            .spawn() {
+            Ok(child) => running = Some(child),
+            Err(e) => warn!("failed to run {:?}: {}", command, e),
+        }
+        true
+    }).await
+}
+
+/// Show `frame` on the Flipper's virtual display until Ctrl+C, or
+/// for `duration` if it's given.
+#[cfg(feature = "ble")]
+pub async fn show(flipper: &mut flipper_ble::FlipperBle, frame: &Frame, duration: Option<Duration>) -> Result<(), Box<dyn Error>> {
+    flipper.start_virtual_display(frame).await?;
+    match duration {
+        Some(d) => {
+            output::status(format!("showing on the Flipper for {:?}", d));
+            tokio::select! {
+                _ = tokio::time::sleep(d) => {},
+                _ = tokio::signal::ctrl_c() => {},
+            }
+        },
+        None => {
+            output::status("showing on the Flipper, press Ctrl+C to stop");
+            tokio::signal::ctrl_c().await?;
+        },
+    }
+    flipper.stop_virtual_display().await
+}
@@ -0,0 +1,227 @@
+// Serving the Flipper's screen over HTTP, so it can go into OBS (as a
+// browser source) or a dashboard. Frames go out as a
+// multipart/x-mixed-replace stream of PNGs, which browsers play like
+// MJPEG.
+//
+// This is just enough HTTP for that: every request gets one response
+// and then the connection closes.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+#[cfg(feature = "ble")]
+use std::error::Error;
+#[cfg(feature = "ble")]
+use std::future::Future;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+#[cfg(feature = "ble")]
+use crate::{flipper_ble, output, screen};
+#[cfg(feature = "ble")]
+use crate::session::Session;
+
+const BOUNDARY: &str = "flipwireframe";
+// Requests are tiny, anything bigger than this isn't for us
+const MAX_REQUEST_SIZE: usize = 8192;
+
+const INDEX_PAGE: &str = "<!DOCTYPE html>\n\
+<html><head><title>Flipper screen</title></head>\n\
+<body style=\"margin: 0; background: black\">\n\
+<img src=\"/stream\" style=\"width: 100%; image-rendering: pixelated\">\n\
+</body></html>\n";
+
+type LatestFrame = Option<Arc<Vec<u8>>>;
+
+pub struct ScreenServer {
+    frames: watch::Sender<LatestFrame>,
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+/// Returns the address to bind to for `listen`. Like a lot of servers,
+/// a bare `:port` means every interface.
+pub(crate) fn bind_address(listen: &str) -> String {
+    match listen.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => listen.to_string(),
+    }
+}
+
+impl ScreenServer {
+    /// Start serving on `listen` (like `127.0.0.1:8081` or `:8081`).
+    /// `/` is a page showing the screen, `/stream` is the stream
+    /// itself, and `/frame.png` is the latest frame.
+    pub async fn bind(listen: &str) -> io::Result<ScreenServer> {
+        let listener = TcpListener::bind(bind_address(listen)).await?;
+        let addr = listener.local_addr()?;
+        let (frames, _) = watch::channel(None);
+
+        let task = tokio::spawn(accept_clients(listener, frames.clone()));
+
+        Ok(ScreenServer {
+            frames,
+            addr,
+            task,
+        })
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Send a new frame (a PNG) to everyone watching.
+    pub fn publish(&self, png: Vec<u8>) {
+        self.frames.send_replace(Some(Arc::new(png)));
+    }
+}
+
+impl Drop for ScreenServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn accept_clients(listener: TcpListener, frames: watch::Sender<LatestFrame>) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, addr)) => {
+                debug!("screen client {} connected", addr);
+                let frames = frames.subscribe();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_client(socket, frames).await {
+                        debug!("screen client {} went away: {}", addr, e);
+                    }
+                });
+            },
+            Err(e) => warn!("failed to accept screen client: {}", e),
+        }
+    }
+}
+
+/// Returns the path in the request line of an HTTP GET request.
+fn request_path(request: &[u8]) -> Option<&str> {
+    let line = std::str::from_utf8(request).ok()?.lines().next()?;
+    let mut parts = line.split(' ');
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => Some(path),
+        _ => None,
+    }
+}
+
+async fn serve_client(mut socket: TcpStream, mut frames: watch::Receiver<LatestFrame>) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut data = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = socket.read(&mut data).await?;
+        if n == 0 || request.len() > MAX_REQUEST_SIZE {
+            return Ok(());
+        }
+        request.extend(&data[..n]);
+    }
+
+    match request_path(&request) {
+        Some("/") => {
+            let header = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\
+                                  Connection: close\r\n\r\n", INDEX_PAGE.len());
+            socket.write_all(header.as_bytes()).await?;
+            socket.write_all(INDEX_PAGE.as_bytes()).await?;
+        },
+        Some("/frame.png") => {
+            let frame = frames.borrow().clone();
+            match frame {
+                Some(png) => {
+                    let header = format!("HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\
+                                          Cache-Control: no-cache\r\nConnection: close\r\n\r\n", png.len());
+                    socket.write_all(header.as_bytes()).await?;
+                    socket.write_all(&png).await?;
+                },
+                None => {
+                    socket.write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\
+                                       Connection: close\r\n\r\n").await?;
+                },
+            }
+        },
+        Some("/stream") => {
+            let header = format!("HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={}\r\n\
+                                  Cache-Control: no-cache\r\nConnection: close\r\n\r\n", BOUNDARY);
+            socket.write_all(header.as_bytes()).await?;
+
+            // start with whatever's on screen now, then send every
+            // new frame as it comes
+            frames.mark_changed();
+            while frames.changed().await.is_ok() {
+                let frame = frames.borrow_and_update().clone();
+                if let Some(png) = frame {
+                    let part = format!("--{}\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\r\n",
+                                       BOUNDARY, png.len());
+                    socket.write_all(part.as_bytes()).await?;
+                    socket.write_all(&png).await?;
+                    socket.write_all(b"\r\n").await?;
+                }
+            }
+        },
+        _ => {
+            socket.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Serve the Flipper's screen over HTTP until Ctrl+C.
+#[cfg(feature = "ble")]
+pub async fn serve(flipper: &mut flipper_ble::FlipperBle, listen: &str, scale: usize) -> Result<(), Box<dyn Error>> {
+    let server = ScreenServer::bind(listen).await?;
+    output::status(format!("serving the Flipper's screen at http://{}/ (Ctrl+C to stop)", server.local_addr()));
+
+    flipper.stream_screen(|frame| publish_frame(&server, frame, scale)).await
+}
+
+/// Serve the Flipper's screen from `server` while `command` runs, for
+/// --serve-screen. Returns what `command` does.
+///
+/// # Arguments
+///
+/// * `session`: From FlipperBle::session(), since `command` has the
+///   FlipperBle itself. Frames and the command's responses have
+///   different command IDs, so they can share it.
+/// * `server`: Where to serve the screen, or None to just run `command`
+/// * `command`: Command to run
+#[cfg(feature = "ble")]
+pub async fn mirror_while<T>(session: &Session, server: Option<&ScreenServer>, command: impl Future<Output = T>) -> T {
+    let server = match server {
+        Some(s) => s,
+        None => return command.await,
+    };
+    let (stop_mirror, mirror_stopped) = tokio::sync::oneshot::channel::<()>();
+    let command = async {
+        let result = command.await;
+        drop(stop_mirror);
+        result
+    };
+    let stop = async {
+        let _ = mirror_stopped.await;
+    };
+
+    let (result, mirrored) = tokio::join!(command, flipper_ble::stream_screen_until(session, stop, |frame| publish_frame(server, frame, 4)));
+    if let Err(e) = mirrored {
+        warn!("screen mirror stopped: {}", e);
+    }
+    result
+}
+
+/// Send `frame` to everyone watching `server`. Always returns true, to
+/// keep the stream going.
+#[cfg(feature = "ble")]
+fn publish_frame(server: &ScreenServer, frame: &screen::Frame, scale: usize) -> bool {
+    match screen::render_png(frame, scale) {
+        Ok(png) => server.publish(png),
+        Err(e) => warn!("failed to encode frame: {}", e),
+    }
+    true
+}
@@ -0,0 +1,171 @@
+// USB serial transport. When the Flipper is plugged in, it shows up
+// as a CDC ACM serial port running its text CLI, which can do a few
+// things the BLE RPC can't, like streaming the debug log.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::error::Error;
+use std::sync::Arc;
+
+use serialport::{SerialPort, SerialPortType};
+
+// STMicroelectronics VID and the Flipper's CDC PID
+const FLIPPER_USB_VID: u16 = 0x0483;
+const FLIPPER_USB_PID: u16 = 0x5740;
+
+// The CLI prints this when it's ready for a command
+const CLI_PROMPT: &[u8] = b">: ";
+const CLI_PROMPT_TIMEOUT: Duration = Duration::from_secs(3);
+const CTRL_C: u8 = 0x03;
+
+/// Log levels the Flipper's `log` CLI command understands. Each one
+/// includes everything more severe than it.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn cli_name(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+pub struct FlipperSerial {
+    port: Box<dyn SerialPort>,
+}
+
+/// Find the serial port of a Flipper plugged in over USB.
+fn find_port() -> Result<String, Box<dyn Error>> {
+    serialport::available_ports()?
+        .into_iter()
+        .find(|p| matches!(&p.port_type, SerialPortType::UsbPort(usb)
+                           if usb.vid == FLIPPER_USB_VID && usb.pid == FLIPPER_USB_PID))
+        .map(|p| p.port_name)
+        .ok_or("couldn't find a Flipper connected over USB".into())
+}
+
+impl FlipperSerial {
+    /// Open the Flipper's serial port and wait for the CLI prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `port_name`: Serial port to use (like `/dev/ttyACM0` or
+    ///                `COM3`), or None to find the Flipper automatically
+    pub fn open(port_name: Option<&str>) -> Result<FlipperSerial, Box<dyn Error>> {
+        let port_name = match port_name {
+            Some(p) => p.to_string(),
+            None => find_port()?,
+        };
+        debug!("opening serial port {}", port_name);
+
+        // it's USB CDC, so the baud rate doesn't matter
+        let mut port = serialport::new(&port_name, 230400)
+            .timeout(Duration::from_millis(100))
+            .open()?;
+        // the CLI session only starts once DTR is set
+        port.write_data_terminal_ready(true)?;
+
+        let mut serial = FlipperSerial { port };
+        // a blank line gets us a fresh prompt no matter what was
+        // on the CLI before
+        serial.port.write_all(b"\r")?;
+        serial.wait_for_prompt()?;
+
+        Ok(serial)
+    }
+
+    fn wait_for_prompt(&mut self) -> io::Result<()> {
+        let start = Instant::now();
+        let mut seen: Vec<u8> = Vec::new();
+        let mut buf = [0u8; 256];
+
+        while start.elapsed() < CLI_PROMPT_TIMEOUT {
+            match self.port.read(&mut buf) {
+                Ok(n) => {
+                    seen.extend(&buf[..n]);
+                    if seen.windows(CLI_PROMPT.len()).any(|w| w == CLI_PROMPT) {
+                        return Ok(());
+                    }
+                },
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {},
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::TimedOut, "Flipper CLI didn't respond"))
+    }
+
+    /// Put the CLI in log mode and copy the log to `out` until `stop`
+    /// is set, then leave log mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `level`: Least severe level to show
+    /// * `out`: Where to write the log
+    /// * `stop`: Set this (from another thread) to stop streaming
+    pub fn stream_logs<W: Write>(&mut self, level: LogLevel, out: &mut W, stop: &AtomicBool) -> io::Result<()> {
+        self.port.write_all(format!("log {}\r", level.cli_name()).as_bytes())?;
+
+        // The CLI echoes the command back to us, which we don't want
+        // mixed in with the log.
+        let mut echo_done = false;
+        let mut buf = [0u8; 1024];
+        while !stop.load(Ordering::Relaxed) {
+            match self.port.read(&mut buf) {
+                Ok(n) => {
+                    let mut data = &buf[..n];
+                    if !echo_done {
+                        match data.iter().position(|&b| b == b'\n') {
+                            Some(i) => {
+                                echo_done = true;
+                                data = &data[i + 1..];
+                            },
+                            None => continue,
+                        }
+                    }
+                    out.write_all(data)?;
+                    out.flush()?;
+                },
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {},
+                Err(e) => return Err(e),
+            }
+        }
+
+        debug!("leaving log mode");
+        self.port.write_all(&[CTRL_C])?;
+        Ok(())
+    }
+}
+
+/// Stream the Flipper's log over USB serial to stdout until Ctrl+C.
+pub async fn print_logs(level: LogLevel, port: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut flipper = FlipperSerial::open(port)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_on_ctrl_c = stop.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            stop_on_ctrl_c.store(true, Ordering::Relaxed);
+        }
+    });
+
+    // serial port reads block, so keep them off the async threads
+    tokio::task::spawn_blocking(move || {
+        flipper.stream_logs(level, &mut io::stdout(), &stop)
+    }).await??;
+
+    Ok(())
+}
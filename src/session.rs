@@ -0,0 +1,142 @@
+// The RPC session with a connected Flipper, shared by everything
+// talking to it. A request takes its command ID, subscribes to its
+// responses, and gets built all in one step, and the pieces of one
+// message are written with nobody else's in between. Clones share the
+// same session, so several requests can be in flight at once (from
+// tokio::join! or select!, say), each waiting on its own responses
+// from the reader. Writes stay within the room the Flipper says its
+// buffer has, see flow_control.rs. What they're written with is up to
+// the Transport, see transport.rs.
+
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::flow_control::FlowControl;
+use crate::protobuf_codec::ProtobufCodec;
+use crate::rpc_reader::{Responses, RpcReader};
+use crate::transport::Transport;
+
+struct Inner {
+    transport: Arc<dyn Transport>,
+    // the codec holds the command ID counter
+    proto: Mutex<ProtobufCodec>,
+    // replaced when reconnecting
+    reader: RwLock<RpcReader>,
+    // replaced when reconnecting too, None if the Flipper doesn't
+    // have flow control
+    flow: RwLock<Option<Arc<FlowControl>>>,
+    // held while the pieces of one message are written
+    writing: tokio::sync::Mutex<()>,
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+/// A handle to the RPC session, cheap to clone
+#[derive(Clone)]
+pub struct Session {
+    inner: Arc<Inner>,
+}
+
+impl Session {
+    /// Start a session with a connected Flipper.
+    ///
+    /// # Arguments
+    ///
+    /// * `transport`: Writes to the connected Flipper
+    /// * `reader`: Reader for everything the Flipper sends
+    pub fn new(transport: Arc<dyn Transport>, reader: RpcReader) -> Session {
+        // Command ID 0 is for messages the Flipper sends on its own,
+        // so our requests start at 1.
+        let mut proto = ProtobufCodec::new();
+        proto.inc_command_id();
+
+        Session {
+            inner: Arc::new(Inner {
+                transport,
+                proto: Mutex::new(proto),
+                reader: RwLock::new(reader),
+                flow: RwLock::new(None),
+                writing: tokio::sync::Mutex::new(()),
+                messages_sent: AtomicU64::new(0),
+                bytes_sent: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Use `reader` for everything from now on, after reconnecting.
+    /// Requests waiting on the old one fail.
+    pub fn set_reader(&self, reader: RpcReader) {
+        *self.inner.reader.write().unwrap() = reader;
+    }
+
+    /// Keep writes within the buffer space `flow` reports, from now
+    /// on.
+    pub fn set_flow_control(&self, flow: Option<FlowControl>) {
+        *self.inner.flow.write().unwrap() = flow.map(Arc::new);
+    }
+
+    /// Change the codec's settings, like its chunk sizes.
+    pub fn configure_codec<F>(&self, configure: F)
+    where F: FnOnce(&mut ProtobufCodec) {
+        configure(&mut self.inner.proto.lock().unwrap());
+    }
+
+    /// Returns how many messages have been sent to the Flipper, and
+    /// how many bytes they took.
+    pub fn sent(&self) -> (u64, u64) {
+        (self.inner.messages_sent.load(Ordering::Relaxed), self.inner.bytes_sent.load(Ordering::Relaxed))
+    }
+
+    /// Returns messages the Flipper sends with `command_id` without
+    /// making a request, like screen frames.
+    pub fn subscribe(&self, command_id: u32) -> Responses {
+        self.inner.reader.read().unwrap().subscribe(command_id)
+    }
+
+    /// Build a request with `build` and subscribe to its responses.
+    /// Nothing else can take the request's command ID in between.
+    /// Send it with write_message().
+    pub fn prepare<T, F>(&self, build: F) -> Result<(Responses, T), Box<dyn Error>>
+    where F: FnOnce(&mut ProtobufCodec) -> Result<T, Box<dyn Error>> {
+        let mut proto = self.inner.proto.lock().unwrap();
+        let responses = self.subscribe(proto.next_command_id());
+        let request = build(&mut proto)?;
+        Ok((responses, request))
+    }
+
+    /// Build a request with `build`, send it, and return its
+    /// responses.
+    pub async fn request<F>(&self, build: F) -> Result<Responses, Box<dyn Error>>
+    where F: FnOnce(&mut ProtobufCodec) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let (responses, packets) = self.prepare(build)?;
+        self.write_message(&packets).await?;
+        Ok(responses)
+    }
+
+    /// Build a message with `build` and send it without waiting for
+    /// anything back, for the ones the Flipper doesn't answer.
+    pub async fn send<F>(&self, build: F) -> Result<(), Box<dyn Error>>
+    where F: FnOnce(&mut ProtobufCodec) -> Result<Vec<u8>, Box<dyn Error>> {
+        let packet = build(&mut self.inner.proto.lock().unwrap())?;
+        self.write_message(&[packet]).await
+    }
+
+    /// Write the pieces of one message to the Flipper, each once its
+    /// buffer has room for it. Other messages wait until they're all
+    /// written.
+    pub async fn write_message(&self, pieces: &[Vec<u8>]) -> Result<(), Box<dyn Error>> {
+        let _writing = self.inner.writing.lock().await;
+        let flow = self.inner.flow.read().unwrap().clone();
+        let transport = self.inner.transport.as_ref();
+        for piece in pieces {
+            if let Some(flow) = &flow {
+                flow.reserve(piece.len(), transport).await;
+            }
+            transport.write(piece).await?;
+            self.inner.bytes_sent.fetch_add(piece.len() as u64, Ordering::Relaxed);
+        }
+        self.inner.messages_sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
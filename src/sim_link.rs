@@ -0,0 +1,371 @@
+// A pretend BLE link in front of an EmulatedFlipper, for testing how
+// writes are paced without hardware or a good (or bad) radio. Each
+// write is one frame, like one write without response over BLE. Frames
+// can be held up by latency and jitter, or lost outright, and with a
+// bandwidth, they go out one after another at that speed, so a big
+// frame holds up the ones behind it. The Flipper's serial buffer is
+// simulated too: a frame bigger than the
+// room left in it is thrown away, the way the real one logs
+// `Received 245, while was ready to receive 37 bytes` and drops it.
+// The room comes back once the Flipper has had the frame for a
+// latency, and it says so on the flow control stream.
+//
+// What comes out is the same as what btleplug gives us, so RpcReader
+// and FlowControl can run on top of it unchanged: a stream of what the
+// Flipper sends, and a stream of flow control values. It's a Transport
+// too, so a Session writes to it like it would to a real Flipper. The
+// random numbers come from a seed, so a run can be repeated exactly.
+
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use tokio::time::{self, Duration, Instant};
+
+use crate::emu::EmulatedFlipper;
+use crate::transport::Transport;
+
+/// How good the pretend link is
+#[derive(Debug, Clone)]
+pub struct LinkConditions {
+    /// How long every frame takes to get to the Flipper
+    pub latency: Duration,
+    /// Up to this much longer, picked at random for each frame
+    pub jitter: Duration,
+    /// Chance from 0 to 1 that a frame never arrives
+    pub drop_rate: f64,
+    /// How many bytes per second the link carries, or None for as
+    /// fast as frames are written
+    pub bandwidth: Option<u64>,
+    /// Size of the Flipper's serial buffer in bytes, or None for a
+    /// buffer that never fills (and no flow control values)
+    pub buffer_size: Option<usize>,
+    /// Where the random numbers for jitter and drops start
+    pub seed: u64,
+}
+
+impl Default for LinkConditions {
+    fn default() -> Self {
+        LinkConditions {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            drop_rate: 0.0,
+            bandwidth: None,
+            // what the Flipper's firmware has
+            buffer_size: Some(1024),
+            seed: 1,
+        }
+    }
+}
+
+/// What happened on the link so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    /// Frames written
+    pub frames: u64,
+    /// Frames lost on the way
+    pub dropped: u64,
+    /// Frames thrown away for not fitting in the buffer
+    pub overflows: u64,
+    /// Bytes the Flipper got
+    pub bytes: u64,
+}
+
+struct State {
+    flipper: EmulatedFlipper,
+    // what's arrived of a message so far
+    pending: Vec<u8>,
+    // room left in the buffer, None without one
+    free: Option<usize>,
+    rng: u64,
+    // when the link is done sending the frames so far, with a
+    // bandwidth
+    busy_until: Option<Instant>,
+    stats: LinkStats,
+}
+
+impl State {
+    /// Returns the next random number (xorshift64*).
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a random number from 0 up to (not including) 1.
+    fn random_fraction(&mut self) -> f64 {
+        (self.next_random() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+pub struct SimulatedLink {
+    conditions: LinkConditions,
+    state: Arc<Mutex<State>>,
+    to_host: mpsc::UnboundedSender<Vec<u8>>,
+    flow: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl SimulatedLink {
+    /// Put `flipper` behind a link with `conditions`. Returns the
+    /// link, what the Flipper sends (for RpcReader::spawn()), and the
+    /// flow control values (for FlowControl::spawn()).
+    pub fn new(flipper: EmulatedFlipper, conditions: LinkConditions)
+               -> (SimulatedLink, mpsc::UnboundedReceiver<Vec<u8>>, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let (to_host, notifications) = mpsc::unbounded();
+        let (flow, flow_notifications) = mpsc::unbounded();
+        let state = State {
+            flipper,
+            pending: Vec::new(),
+            free: conditions.buffer_size,
+            // xorshift gets stuck on 0
+            rng: conditions.seed.max(1),
+            busy_until: None,
+            stats: LinkStats::default(),
+        };
+        let link = SimulatedLink {
+            conditions,
+            state: Arc::new(Mutex::new(state)),
+            to_host,
+            flow,
+        };
+        (link, notifications, flow_notifications)
+    }
+
+    /// Returns the flow control value right now, like reading the
+    /// characteristic, or None without a buffer.
+    pub fn flow_value(&self) -> Option<Vec<u8>> {
+        let free = self.state.lock().unwrap().free?;
+        Some(flow_value(free))
+    }
+
+    /// Returns what's happened on the link so far.
+    pub fn stats(&self) -> LinkStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Send one frame to the Flipper. Like a write without response,
+    /// this doesn't say whether the frame made it. It only fails if
+    /// what arrived isn't a valid message, which is what a lost frame
+    /// in the middle of one leads to.
+    pub async fn write(&self, frame: &[u8]) -> Result<(), Box<dyn Error>> {
+        let (delay, lost) = {
+            let mut s = self.state.lock().unwrap();
+            let jitter_ns = self.conditions.jitter.as_nanos() as u64;
+            let jitter = if jitter_ns == 0 { 0 } else { s.next_random() % jitter_ns };
+            let lost = s.random_fraction() < self.conditions.drop_rate;
+            // the frame goes out once the ones before it have
+            let sending = match self.conditions.bandwidth {
+                Some(bandwidth) => {
+                    let now = Instant::now();
+                    let start = s.busy_until.map_or(now, |b| b.max(now));
+                    let done = start + Duration::from_secs_f64(frame.len() as f64 / bandwidth.max(1) as f64);
+                    s.busy_until = Some(done);
+                    done - now
+                },
+                None => Duration::ZERO,
+            };
+            (sending + self.conditions.latency + Duration::from_nanos(jitter), lost)
+        };
+        if !delay.is_zero() {
+            time::sleep(delay).await;
+        }
+
+        let responses = {
+            let mut guard = self.state.lock().unwrap();
+            let s = &mut *guard;
+            s.stats.frames += 1;
+            if lost {
+                s.stats.dropped += 1;
+                debug!("link dropped a {} byte frame", frame.len());
+                return Ok(());
+            }
+            if let Some(free) = s.free {
+                if frame.len() > free {
+                    s.stats.overflows += 1;
+                    warn!("Received {}, while was ready to receive {} bytes", frame.len(), free);
+                    return Ok(());
+                }
+                s.free = Some(free - frame.len());
+            }
+            s.stats.bytes += frame.len() as u64;
+            s.pending.extend_from_slice(frame);
+            match s.flipper.handle_bytes(&mut s.pending) {
+                Ok(r) => r,
+                Err(e) => {
+                    // start over with the next message
+                    s.pending.clear();
+                    return Err(e);
+                },
+            }
+        };
+        if !responses.is_empty() {
+            // nobody listening is fine
+            let _ = self.to_host.unbounded_send(responses);
+        }
+
+        // the Flipper empties its buffer as it goes, and says so a
+        // latency later
+        if self.conditions.buffer_size.is_some() {
+            let state = self.state.clone();
+            let flow = self.flow.clone();
+            let latency = self.conditions.latency;
+            let len = frame.len();
+            tokio::spawn(async move {
+                time::sleep(latency).await;
+                let free = {
+                    let mut s = state.lock().unwrap();
+                    let free = s.free.unwrap_or(0) + len;
+                    s.free = Some(free);
+                    free
+                };
+                let _ = flow.unbounded_send(flow_value(free));
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Transport for SimulatedLink {
+    fn write<'a>(&'a self, piece: &'a [u8]) -> BoxFuture<'a, Result<(), Box<dyn Error>>> {
+        Box::pin(SimulatedLink::write(self, piece))
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(time::sleep(duration))
+    }
+}
+
+/// Returns `free` the way the flow control characteristic says it.
+fn flow_value(free: usize) -> Vec<u8> {
+    u32::try_from(free).unwrap_or(u32::MAX).to_be_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::flipper_pb;
+    use crate::flipper_pb::flipper::CommandStatus;
+    use crate::flow_control::FlowControl;
+    use crate::rpc_reader::RpcReader;
+    use crate::session::Session;
+
+    type UploadResult = Result<flipper_pb::flipper::Main, Box<dyn Error>>;
+
+    /// A session writing over a link with `conditions` to an emulated
+    /// Flipper, which keeps its files in a new temporary directory.
+    /// The session keeps to the link's flow control values if
+    /// `flow_control` is set.
+    fn setup(conditions: LinkConditions, flow_control: bool) -> (tempfile::TempDir, Arc<SimulatedLink>, Session) {
+        let dir = tempfile::tempdir().unwrap();
+        let flipper = EmulatedFlipper::new(dir.path(), "Test").unwrap();
+        let (link, notifications, flow) = SimulatedLink::new(flipper, conditions);
+        let link = Arc::new(link);
+        let session = Session::new(link.clone(), RpcReader::spawn(notifications));
+        if flow_control {
+            session.set_flow_control(Some(FlowControl::spawn(link.flow_value(), flow)));
+        }
+        (dir, link, session)
+    }
+
+    /// Upload `data` to `path` like FlipperBle::upload_data() does, a
+    /// message per chunk, and wait for the Flipper's answer.
+    async fn upload(session: &Session, data: &[u8], path: &str) -> UploadResult {
+        let (mut responses, chunks) = session.prepare(|p| p.create_write_request_packets(data, path))?;
+        for chunk in chunks {
+            session.write_message(&chunk.packets).await?;
+        }
+        responses.next().await
+    }
+
+    /// Returns whether an upload finished in time with an OK from the
+    /// Flipper.
+    fn succeeded(result: &Result<UploadResult, time::error::Elapsed>) -> bool {
+        matches!(result, Ok(Ok(m)) if m.command_status == CommandStatus::OK.into())
+    }
+
+    fn sample_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn flow_control_upload_test() {
+        let bad_link = LinkConditions {
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(10),
+            bandwidth: Some(50_000),
+            ..LinkConditions::default()
+        };
+        for conditions in [LinkConditions::default(), bad_link] {
+            let (dir, link, session) = setup(conditions, true);
+            let data = sample_data(5000);
+            let response = upload(&session, &data, "/ext/upload.bin").await.unwrap();
+            assert_eq!(response.command_status, CommandStatus::OK.into());
+            assert_eq!(fs::read(dir.path().join("ext/upload.bin")).unwrap(), data);
+
+            let stats = link.stats();
+            // several messages of a couple of frames each
+            assert!(stats.frames > 10, "only {} frames", stats.frames);
+            assert_eq!(stats.overflows, 0);
+            assert_eq!(stats.dropped, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn overflow_without_flow_control_test() {
+        // Nothing holds the writes back, so they fill the buffer
+        // faster than the Flipper empties it. The frames that don't
+        // fit leave it with half a message.
+        let (_dir, link, session) = setup(LinkConditions::default(), false);
+        let result = time::timeout(Duration::from_secs(2), upload(&session, &sample_data(5000), "/ext/upload.bin")).await;
+        assert!(!succeeded(&result));
+        assert!(link.stats().overflows > 0);
+    }
+
+    #[tokio::test]
+    async fn dropped_frame_test() {
+        let conditions = LinkConditions {
+            latency: Duration::from_millis(2),
+            jitter: Duration::from_millis(2),
+            drop_rate: 0.2,
+            ..LinkConditions::default()
+        };
+        let (_dir, link, session) = setup(conditions, true);
+        // a lost piece of a message fails the upload or leaves it
+        // without an answer, it doesn't hang
+        let result = time::timeout(Duration::from_secs(2), upload(&session, &sample_data(5000), "/ext/upload.bin")).await;
+        assert!(!succeeded(&result));
+        assert!(link.stats().dropped > 0);
+    }
+
+    #[tokio::test]
+    async fn lost_request_test() {
+        let conditions = LinkConditions {
+            drop_rate: 1.0,
+            ..LinkConditions::default()
+        };
+        let (_dir, link, session) = setup(conditions, true);
+        let mut responses = session.request(|p| p.create_ping_request_packet(b"flipwire").map(|packet| vec![packet])).await.unwrap();
+        assert!(time::timeout(Duration::from_millis(200), responses.next()).await.is_err());
+        assert_eq!(link.stats().dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn bandwidth_test() {
+        let conditions = LinkConditions {
+            bandwidth: Some(20_000),
+            ..LinkConditions::default()
+        };
+        let (_dir, link, session) = setup(conditions, true);
+        let started = Instant::now();
+        let result = upload(&session, &sample_data(2000), "/ext/upload.bin").await;
+        assert_eq!(result.unwrap().command_status, CommandStatus::OK.into());
+        // every byte had to go over the link at 20 kB/s
+        let bytes = link.stats().bytes;
+        assert!(started.elapsed() >= Duration::from_secs_f64(bytes as f64 / 20_000.0));
+    }
+}
@@ -0,0 +1,201 @@
+// A summary of how the Flipper is doing, for the daemon to report:
+// whether it's connected, its battery, and how full its storage is.
+
+#[cfg(feature = "ble")]
+use std::error::Error;
+#[cfg(feature = "ble")]
+use std::time::Duration;
+
+use serde::Serialize;
+
+#[cfg(feature = "ble")]
+use crate::duration::format_duration;
+#[cfg(feature = "ble")]
+use crate::{flipper_ble, output};
+use crate::progress::HumanBytes;
+
+/// How full one of the Flipper's storages is
+#[derive(Serialize, Debug, Clone)]
+pub struct StorageStatus {
+    /// "int" or "ext"
+    pub name: String,
+    /// Size in bytes
+    pub total: u64,
+    /// Free space in bytes
+    pub free: u64,
+}
+
+/// Everything the daemon knows about the Flipper at one point
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct FlipperStatus {
+    pub connected: bool,
+    /// Charge in percent
+    pub battery: Option<u8>,
+    /// "charging", "discharging", or "charged", as the firmware says it
+    pub charging: Option<String>,
+    pub storage: Vec<StorageStatus>,
+}
+
+impl FlipperStatus {
+    /// Returns the status of a Flipper that isn't connected, so nothing
+    /// else about it is known.
+    pub fn disconnected() -> FlipperStatus {
+        FlipperStatus::default()
+    }
+
+    /// Fill in the battery from the Flipper's "pwrinfo." properties.
+    pub fn set_power_info(&mut self, properties: &[(String, String)]) {
+        for (key, value) in properties {
+            if key.ends_with("charge.level") {
+                self.battery = value.parse().ok();
+            } else if key.ends_with("charge.state") {
+                self.charging = Some(value.clone());
+            }
+        }
+    }
+}
+
+/// Decides when the daemon should warn about a low battery: once when
+/// the charge drops below the threshold, then not again until the
+/// Flipper has been charged back up.
+#[derive(Debug)]
+pub struct LowBatteryAlarm {
+    /// Percent to warn below
+    threshold: u8,
+    armed: bool,
+}
+
+// How far above the threshold the charge has to get before the alarm
+// can go off again, so a battery hovering around the threshold
+// doesn't set it off over and over
+const LOW_BATTERY_HYSTERESIS: u8 = 5;
+
+impl LowBatteryAlarm {
+    pub fn new(threshold: u8) -> LowBatteryAlarm {
+        LowBatteryAlarm { threshold, armed: true }
+    }
+
+    /// Returns true if the alarm should go off for `status`.
+    pub fn check(&mut self, status: &FlipperStatus) -> bool {
+        let battery = match status.battery {
+            Some(b) if status.connected => b,
+            _ => return false,
+        };
+        if status.charging.as_deref() == Some("charging")
+            || battery >= self.threshold.saturating_add(LOW_BATTERY_HYSTERESIS) {
+            self.armed = true;
+            return false;
+        }
+        if self.armed && battery < self.threshold {
+            self.armed = false;
+            return true;
+        }
+        false
+    }
+}
+
+impl std::fmt::Display for FlipperStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if !self.connected {
+            return write!(f, "disconnected");
+        }
+        write!(f, "connected")?;
+        if let Some(b) = self.battery {
+            write!(f, ", battery {}%", b)?;
+        }
+        if let Some(c) = &self.charging {
+            write!(f, " ({})", c)?;
+        }
+        for s in &self.storage {
+            write!(f, ", {} {} free of {}", s.name,
+                   HumanBytes(s.free), HumanBytes(s.total))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns what's happened on the connection as JSON, for the web
+/// page and `stats` in the shell.
+///
+/// # Arguments
+///
+/// * `stats`: From FlipperBle::stats()
+/// * `commands`: How many commands the shell or daemon has run
+#[cfg(feature = "ble")]
+pub fn stats_json(stats: &flipper_ble::ConnectionStats, commands: u64) -> serde_json::Value {
+    serde_json::json!({
+        "uptime_s": stats.uptime.as_secs(),
+        "commands": commands,
+        "messages_sent": stats.messages_sent,
+        "bytes_sent": stats.bytes_sent,
+        "bytes_received": stats.bytes_received,
+        "connect_retries": stats.connect_retries,
+        "reconnects": stats.reconnects,
+        "rssi": stats.rssi,
+    })
+}
+
+/// Print what's happened on the connection, for `stats` in the
+/// shell.
+#[cfg(feature = "ble")]
+pub fn print_stats(stats: &flipper_ble::ConnectionStats, commands: u64) {
+    println!("uptime:    {}", format_duration(stats.uptime));
+    println!("commands:  {} ({} messages sent)", commands, stats.messages_sent);
+    println!("sent:      {}", HumanBytes(stats.bytes_sent));
+    println!("received:  {}", HumanBytes(stats.bytes_received));
+    println!("retries:   {} connection retries, {} reconnects", stats.connect_retries, stats.reconnects);
+    match stats.rssi {
+        Some(r) => println!("signal:    {} dBm ({})", r, flipper_ble::signal_quality(f64::from(r))),
+        None => println!("signal:    unknown"),
+    }
+}
+
+/// Returns how the Flipper is doing, reconnecting first if the
+/// connection dropped.
+#[cfg(feature = "ble")]
+pub async fn fetch(flipper: &mut flipper_ble::FlipperBle) -> FlipperStatus {
+    if let Err(e) = flipper.keep_alive().await {
+        warn!("Flipper isn't connected: {}", e);
+        return FlipperStatus::disconnected();
+    }
+
+    let mut s = FlipperStatus { connected: true, ..Default::default() };
+    match flipper.property_get("pwrinfo.").await {
+        Ok(properties) => s.set_power_info(&properties),
+        Err(e) => warn!("couldn't get power info: {}", e),
+    }
+    for name in ["int", "ext"] {
+        match flipper.storage_info(&format!("/{}", name)).await {
+            Ok((total, free)) => s.storage.push(StorageStatus { name: name.to_string(), total, free }),
+            Err(e) => warn!("couldn't get info for /{}: {}", name, e),
+        }
+    }
+    s
+}
+
+/// Print the signal strength every so often for `duration`, then the
+/// average.
+#[cfg(feature = "ble")]
+pub async fn print_rssi(flipper: &mut flipper_ble::FlipperBle, duration: Duration) -> Result<(), Box<dyn Error>> {
+    const RSSI_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+    let mut samples = Vec::new();
+    let start = std::time::Instant::now();
+    loop {
+        if let Some(r) = flipper.rssi().await? {
+            println!("{} dBm", r);
+            samples.push(f64::from(r));
+        }
+        if start.elapsed() >= duration {
+            break;
+        }
+        tokio::time::sleep(RSSI_SAMPLE_INTERVAL).await;
+    }
+
+    if samples.is_empty() {
+        return Err("the OS isn't reporting the signal strength of this connection".into());
+    }
+    let average = samples.iter().sum::<f64>() / samples.len() as f64;
+    output::status(format!("average: {:.1} dBm ({})", average, flipper_ble::signal_quality(average)));
+    Ok(())
+}
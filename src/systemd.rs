@@ -0,0 +1,74 @@
+// systemd integration for the daemon. With socket activation, systemd
+// holds the web page's socket and only starts the daemon when someone
+// connects to it, handing the socket over. sd_notify tells systemd
+// when the daemon is ready and when it's stopping, for Type=notify
+// services. Both protocols are simple enough (see sd_listen_fds(3) and
+// sd_notify(3)) that libsystemd isn't needed.
+
+use std::env;
+use std::error::Error;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, IntoRawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+
+// systemd passes sockets from here on
+const LISTEN_FDS_START: i32 = 3;
+
+/// Returns the listening sockets systemd passed, in the order of the
+/// socket unit's ListenStream= lines, or none if flipwire wasn't
+/// started by socket activation.
+pub fn listeners() -> Result<Vec<TcpListener>, Box<dyn Error>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    // they're only meant for us, not for hooks we run
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(Vec::new());
+    };
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        debug!("LISTEN_FDS is for process {}, not us", pid);
+        return Ok(Vec::new());
+    }
+    let count: i32 = fds.parse().map_err(|_| format!("LISTEN_FDS isn't a number: {:?}", fds))?;
+    (LISTEN_FDS_START..LISTEN_FDS_START + count).map(|fd| {
+        // SAFETY: systemd hands these to us and nothing else in
+        // flipwire knows about them, so they're ours to own and close.
+        let listener = unsafe { TcpListener::from_raw_fd(fd) };
+        if let Err(e) = listener.local_addr() {
+            // leave it open, it's not ours after all
+            let _ = listener.into_raw_fd();
+            return Err(format!("socket {} from systemd isn't a TCP socket: {}", fd, e).into());
+        }
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    }).collect()
+}
+
+/// Tell systemd how the daemon is doing, like "READY=1" or
+/// "STOPPING=1". Does nothing when systemd isn't listening.
+pub fn notify(state: &str) -> Result<(), Box<dyn Error>> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        // an abstract socket, which only Linux has
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        },
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err("NOTIFY_SOCKET is an abstract socket, which only Linux has".into()),
+        None => {
+            socket.send_to(state.as_bytes(), &path)?;
+        },
+    }
+    debug!("told systemd {:?}", state);
+    Ok(())
+}
@@ -0,0 +1,25 @@
+// What a Session needs from the link to a Flipper. The session writes
+// each piece of a message with a Transport, and what the Flipper sends
+// back (and its flow control values, if it has them) comes in as plain
+// streams of notification data, for an RpcReader and a FlowControl.
+// None of the three know anything about BLE, so the same RPC code runs
+// over btleplug (flipper_ble.rs), Web Bluetooth in a browser
+// (flipwire-web), and a simulated link in tests (sim_link.rs).
+//
+// A browser has no tokio runtime, so the transport also says how to
+// wait. Background tasks are up to whoever starts the reader and flow
+// control, see RpcReader::new() and FlowControl::new().
+
+use std::error::Error;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+
+pub trait Transport: Send + Sync {
+    /// Write one piece of a message to the Flipper, returning once the
+    /// next piece can be written.
+    fn write<'a>(&'a self, piece: &'a [u8]) -> BoxFuture<'a, Result<(), Box<dyn Error>>>;
+
+    /// Returns a future that finishes after `duration`.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
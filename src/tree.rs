@@ -0,0 +1,231 @@
+// Comparing file trees by path, size, and MD5, whether they're on the
+// Flipper or the computer. Paths are relative to the top of the tree
+// and always use "/", so the two sides line up.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "ble")]
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ble")]
+use crate::flipper_ble;
+
+/// A file or directory in a tree
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    /// Path relative to the top of the tree, like "nfc/card.nfc"
+    pub path: String,
+    pub dir: bool,
+    /// Size in bytes, 0 for directories
+    pub size: u64,
+    /// MD5 as lowercase hex, if it was computed
+    pub md5: Option<String>,
+}
+
+/// A tree on the Flipper as it was at some point, to compare against
+/// later
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    /// Flipper directory at the top of the tree
+    pub root: String,
+    /// When the snapshot was taken, in RFC 3339
+    pub taken: String,
+    pub entries: Vec<TreeEntry>,
+}
+
+/// One way two trees don't match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// In the expected tree but not the actual one
+    Missing(TreeEntry),
+    /// In the actual tree but not the expected one
+    Extra(TreeEntry),
+    /// In both, but with a different size, type, or MD5
+    Differs { expected: TreeEntry, actual: TreeEntry },
+}
+
+impl Difference {
+    pub fn path(&self) -> &str {
+        match self {
+            Difference::Missing(e) | Difference::Extra(e) => &e.path,
+            Difference::Differs { expected, .. } => &expected.path,
+        }
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Missing(e) => write!(f, "missing: {}", e.path),
+            Difference::Extra(e) => write!(f, "extra:   {}", e.path),
+            Difference::Differs { expected, actual } if expected.dir != actual.dir => {
+                let kind = |e: &TreeEntry| if e.dir { "directory" } else { "file" };
+                write!(f, "differs: {} ({} -> {})", expected.path, kind(expected), kind(actual))
+            },
+            Difference::Differs { expected, actual } if expected.size != actual.size => {
+                write!(f, "differs: {} ({} -> {} bytes)", expected.path, expected.size, actual.size)
+            },
+            Difference::Differs { expected, .. } => write!(f, "differs: {} (contents)", expected.path),
+        }
+    }
+}
+
+/// Returns everything that doesn't match between `expected` and
+/// `actual`, sorted by path. MD5s are only compared when both sides
+/// have one. Things inside a missing or extra directory aren't listed
+/// separately.
+pub fn compare(expected: &[TreeEntry], actual: &[TreeEntry]) -> Vec<Difference> {
+    let expected: BTreeMap<&str, &TreeEntry> = expected.iter().map(|e| (e.path.as_str(), e)).collect();
+    let actual: BTreeMap<&str, &TreeEntry> = actual.iter().map(|e| (e.path.as_str(), e)).collect();
+
+    let mut found = Vec::new();
+    for (path, e) in &expected {
+        match actual.get(path) {
+            None => found.push(Difference::Missing((*e).clone())),
+            Some(a) => {
+                let md5_differs = match (&e.md5, &a.md5) {
+                    (Some(x), Some(y)) => !x.eq_ignore_ascii_case(y),
+                    _ => false,
+                };
+                if e.dir != a.dir || (!e.dir && (e.size != a.size || md5_differs)) {
+                    found.push(Difference::Differs { expected: (*e).clone(), actual: (*a).clone() });
+                }
+            },
+        }
+    }
+    for (path, a) in &actual {
+        if !expected.contains_key(path) {
+            found.push(Difference::Extra((*a).clone()));
+        }
+    }
+    found.sort_by(|a, b| a.path().cmp(b.path()));
+
+    // "nfc/" being missing says enough about "nfc/card.nfc"
+    let mut pruned: Vec<Difference> = Vec::new();
+    for d in found {
+        let inside_reported = pruned.iter().any(|p| match (p, &d) {
+            (Difference::Missing(dir), Difference::Missing(_)) | (Difference::Extra(dir), Difference::Extra(_)) =>
+                dir.dir && d.path().starts_with(&format!("{}/", dir.path)),
+            _ => false,
+        });
+        if !inside_reported {
+            pruned.push(d);
+        }
+    }
+    pruned
+}
+
+/// Returns every file and directory below `dir` on the computer, with
+/// MD5s of the files.
+pub fn scan_local(dir: &Path) -> io::Result<Vec<TreeEntry>> {
+    let mut found = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(rel) = pending.pop() {
+        for entry in fs::read_dir(dir.join(&rel))? {
+            let entry = entry?;
+            let child = rel.join(entry.file_name());
+            let path = child.components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<String>>()
+                .join("/");
+
+            if entry.metadata()?.is_dir() {
+                found.push(TreeEntry { path, dir: true, size: 0, md5: None });
+                pending.push(child);
+            } else {
+                let data = fs::read(dir.join(&child))?;
+                found.push(TreeEntry {
+                    path,
+                    dir: false,
+                    size: data.len() as u64,
+                    md5: Some(format!("{:x}", md5::compute(&data))),
+                });
+            }
+        }
+    }
+
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(found)
+}
+
+/// Returns the size of every file in `entries`, by path.
+#[cfg(feature = "ble")]
+fn file_sizes(entries: &[TreeEntry]) -> std::collections::HashMap<&str, u64> {
+    entries.iter()
+        .filter(|e| !e.dir)
+        .map(|e| (e.path.as_str(), e.size))
+        .collect()
+}
+
+/// Save a snapshot of everything under `path` to `file`. Returns how
+/// many entries it has.
+#[cfg(feature = "ble")]
+pub async fn save_snapshot(flipper: &mut flipper_ble::FlipperBle, path: &str, file: &Path) -> Result<usize, Box<dyn Error>> {
+    let mut entries = flipper.tree(path).await?;
+    flipper.hash_tree(path, &mut entries, |_| true).await?;
+
+    let snapshot = Snapshot {
+        root: path.to_string(),
+        taken: chrono::Local::now().to_rfc3339(),
+        entries,
+    };
+    std::fs::write(file, serde_json::to_string_pretty(&snapshot)?)?;
+    Ok(snapshot.entries.len())
+}
+
+/// Print what changed on the Flipper since the snapshot in `file` was
+/// taken. Returns how many changes there were.
+#[cfg(feature = "ble")]
+pub async fn diff_snapshot(flipper: &mut flipper_ble::FlipperBle, file: &Path) -> Result<usize, Box<dyn Error>> {
+    let snapshot: Snapshot = serde_json::from_str(&std::fs::read_to_string(file)?)?;
+    info!("comparing against snapshot of {} from {}", snapshot.root, snapshot.taken);
+
+    let mut current = flipper.tree(&snapshot.root).await?;
+    // a file that changed size has changed, no need to hash it
+    let saved_sizes = file_sizes(&snapshot.entries);
+    flipper.hash_tree(&snapshot.root, &mut current, |c| saved_sizes.get(c.path.as_str()) == Some(&c.size)).await?;
+
+    let changes = compare(&snapshot.entries, &current);
+    for c in &changes {
+        match c {
+            Difference::Missing(e) => println!("removed: {}", e.path),
+            Difference::Extra(e) => println!("added:   {}", e.path),
+            Difference::Differs { expected, actual } if expected.size != actual.size => {
+                println!("changed: {} ({} -> {} bytes)", expected.path, expected.size, actual.size);
+            },
+            Difference::Differs { expected, .. } => println!("changed: {}", expected.path),
+        }
+    }
+    Ok(changes.len())
+}
+
+/// Compare `local` to `remote` and print everything that doesn't
+/// match. Returns how many differences there were and how many
+/// entries were checked.
+///
+/// # Arguments
+///
+/// * `local`: Local directory with what should be on the Flipper
+/// * `remote`: Flipper directory to check
+#[cfg(feature = "ble")]
+pub async fn verify(flipper: &mut flipper_ble::FlipperBle, local: &Path, remote: &str) -> Result<(usize, usize), Box<dyn Error>> {
+    let expected = scan_local(local)?;
+    let mut actual = flipper.tree(remote).await?;
+
+    // Hashing on the Flipper is slow, so only do it for files that
+    // could match.
+    let expected_sizes = file_sizes(&expected);
+    flipper.hash_tree(remote, &mut actual, |a| expected_sizes.get(a.path.as_str()) == Some(&a.size)).await?;
+
+    let differences = compare(&expected, &actual);
+    for d in &differences {
+        println!("{}", d);
+    }
+    Ok((differences.len(), expected.len()))
+}
@@ -0,0 +1,172 @@
+// Drawing the Flipper's screen in the terminal and pressing its
+// buttons from the keyboard.
+
+use std::io::{self, Write};
+#[cfg(feature = "ble")]
+use std::error::Error;
+#[cfg(feature = "ble")]
+use std::io::IsTerminal;
+#[cfg(feature = "ble")]
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+use crate::screen::{Frame, SCREEN_HEIGHT, SCREEN_WIDTH};
+#[cfg(feature = "ble")]
+use crate::{flipper_ble, flipper_pb, input_macro, keyboard, output};
+
+/// How to draw the screen in a terminal
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum TerminalMode {
+    /// Braille characters, 2x4 pixels each (64x16 characters)
+    Braille,
+    /// Half blocks, 1x2 pixels each (128x32 characters)
+    HalfBlock,
+}
+
+/// Draw a frame with Braille characters. Each character covers 2x4
+/// pixels, so the whole screen is 64x16 characters.
+pub fn render_braille(frame: &Frame) -> String {
+    // bit for each dot in a Braille cell, indexed by [y][x]
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+    let mut out = String::new();
+    for cell_y in 0..SCREEN_HEIGHT / 4 {
+        for cell_x in 0..SCREEN_WIDTH / 2 {
+            let mut bits = 0;
+            for (dy, row) in DOTS.iter().enumerate() {
+                for (dx, dot) in row.iter().enumerate() {
+                    if frame.pixel(cell_x * 2 + dx, cell_y * 4 + dy) {
+                        bits |= dot;
+                    }
+                }
+            }
+            out.push(char::from_u32(0x2800 + bits).unwrap());
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Draw a frame with half-block characters. Each character covers
+/// 1x2 pixels, so the whole screen is 128x32 characters.
+pub fn render_half_blocks(frame: &Frame) -> String {
+    let mut out = String::new();
+    for cell_y in 0..SCREEN_HEIGHT / 2 {
+        for x in 0..SCREEN_WIDTH {
+            out.push(match (frame.pixel(x, cell_y * 2), frame.pixel(x, cell_y * 2 + 1)) {
+                (true, true) => '\u{2588}',
+                (true, false) => '\u{2580}',
+                (false, true) => '\u{2584}',
+                (false, false) => ' ',
+            });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Redraw the frame in place at the top of the terminal.
+pub fn draw_in_terminal(frame: &Frame, mode: TerminalMode) -> io::Result<()> {
+    let text = match mode {
+        TerminalMode::Braille => render_braille(frame),
+        TerminalMode::HalfBlock => render_half_blocks(frame),
+    };
+
+    let mut stdout = io::stdout().lock();
+    // move the cursor home instead of clearing, so it doesn't flicker
+    write!(stdout, "\x1b[H{}", text)?;
+    stdout.flush()
+}
+
+/// Clear the terminal and hide the cursor before drawing frames.
+pub fn start_terminal() -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "\x1b[2J\x1b[?25l")?;
+    stdout.flush()
+}
+
+/// Show the cursor again when we're done drawing frames.
+pub fn finish_terminal() -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "\x1b[?25h")?;
+    stdout.flush()
+}
+
+/// Mirror the Flipper's screen in the terminal until Ctrl+C.
+#[cfg(feature = "ble")]
+pub async fn mirror_screen(flipper: &mut flipper_ble::FlipperBle, mode: TerminalMode) -> Result<(), Box<dyn Error>> {
+    start_terminal().map_err(|e| format!("failed to set up terminal: {}", e))?;
+    let result = flipper.stream_screen(|frame| draw_in_terminal(frame, mode).is_ok()).await;
+    // always put the cursor back, even if streaming failed
+    let _ = finish_terminal();
+    result
+}
+
+/// Press buttons on the Flipper from the keyboard until q or Ctrl+C,
+/// recording them to `record` if given. Returns how many presses were
+/// recorded.
+#[cfg(feature = "ble")]
+pub async fn remote_control(flipper: &mut flipper_ble::FlipperBle, record: Option<&std::path::Path>) -> Result<usize, Box<dyn Error>> {
+    use console::Key;
+    use flipper_pb::gui::InputKey;
+
+    if !io::stdin().is_terminal() {
+        return Err("remote needs a terminal to read keys from".into());
+    }
+    output::status("arrows move, Enter is OK, Backspace or Escape is back, l then a button long-presses, q quits");
+
+    // Reading a key blocks, so it gets its own thread. It stops
+    // itself on q, since Ctrl+C doesn't raise a signal while a key
+    // is being read.
+    let (key_tx, mut keys) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let term = console::Term::stdout();
+        loop {
+            let key = term.read_key();
+            let quit = matches!(key, Ok(Key::Char('q')) | Ok(Key::CtrlC) | Err(_));
+            if key_tx.send(key).is_err() || quit {
+                break;
+            }
+        }
+    });
+
+    let mut recording = input_macro::Macro::default();
+    let mut last_press: Option<std::time::Instant> = None;
+    let mut long = false;
+    while let Some(key) = keys.recv().await {
+        let key = match key {
+            Ok(Key::ArrowUp) => InputKey::UP,
+            Ok(Key::ArrowDown) => InputKey::DOWN,
+            Ok(Key::ArrowLeft) => InputKey::LEFT,
+            Ok(Key::ArrowRight) => InputKey::RIGHT,
+            Ok(Key::Enter) => InputKey::OK,
+            Ok(Key::Backspace) | Ok(Key::Escape) => InputKey::BACK,
+            Ok(Key::Char('l')) => {
+                long = true;
+                continue;
+            },
+            Ok(Key::Char('q')) | Ok(Key::CtrlC) => break,
+            // Ctrl+C while reading shows up as an interrupted read
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => break,
+            Err(e) => return Err(e.into()),
+            Ok(_) => continue,
+        };
+
+        let press = keyboard::KeyPress { key, long };
+        long = false;
+        flipper.press_key(press.key, press.long).await?;
+        println!("{}", press);
+
+        // the first press starts the macro right away
+        let now = std::time::Instant::now();
+        let delay = last_press.map_or(Duration::ZERO, |t| now - t);
+        last_press = Some(now);
+        recording.steps.push(input_macro::MacroStep { press, delay });
+    }
+
+    if let Some(path) = record {
+        recording.save(path)?;
+    }
+    Ok(recording.steps.len())
+}
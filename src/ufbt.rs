@@ -0,0 +1,86 @@
+// Finding the app in a ufbt project directory, so `deploy` can work
+// out what to upload and where without being told.
+//
+// ufbt builds apps into dist/<appid>.fap, and the Flipper keeps
+// external apps in /ext/apps/<fap_category>/. Both of those come from
+// the App() call in application.fam.
+
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::error::Error;
+#[cfg(feature = "ble")]
+use std::env;
+
+use regex::Regex;
+
+#[cfg(feature = "ble")]
+use crate::flipper_ble;
+
+const MANIFEST_NAME: &str = "application.fam";
+
+#[derive(Debug)]
+pub struct UfbtApp {
+    pub appid: String,
+    pub category: String,
+    /// Built .fap under dist/
+    pub fap: PathBuf,
+}
+
+impl UfbtApp {
+    /// Full path on the Flipper the app should be installed to.
+    pub fn remote_path(&self) -> String {
+        format!("/ext/apps/{}/{}.fap", self.category, self.appid)
+    }
+}
+
+/// Pull a string argument like `appid="my_app"` out of the App()
+/// call. application.fam is Python, but the fields we care about are
+/// always plain string literals.
+fn manifest_field(manifest: &str, field: &str) -> Option<String> {
+    let re = Regex::new(&format!(r#"\b{}\s*=\s*["']([^"']*)["']"#, field)).ok()?;
+    re.captures(manifest).map(|c| c[1].to_string())
+}
+
+/// Find the ufbt project containing `start` (looking in parent
+/// directories too, like git does) and the app it builds.
+pub fn find_app(start: &Path) -> Result<UfbtApp, Box<dyn Error>> {
+    let project_dir = start.ancestors()
+        .find(|dir| dir.join(MANIFEST_NAME).is_file())
+        .ok_or(format!("no {} found in {:?} or its parents, is this a ufbt app?",
+                       MANIFEST_NAME, start))?;
+    debug!("found ufbt project at {:?}", project_dir);
+
+    let manifest = fs::read_to_string(project_dir.join(MANIFEST_NAME))?;
+    let appid = manifest_field(&manifest, "appid")
+        .ok_or(format!("no appid in {}", MANIFEST_NAME))?;
+    let category = manifest_field(&manifest, "fap_category")
+        .ok_or(format!("no fap_category in {}, can't tell where to install the app",
+                       MANIFEST_NAME))?;
+
+    let fap = project_dir.join("dist").join(format!("{}.fap", appid));
+    if !fap.is_file() {
+        return Err(format!("{:?} doesn't exist, run `ufbt` to build the app first", fap).into());
+    }
+
+    Ok(UfbtApp {
+        appid,
+        category,
+        fap,
+    })
+}
+
+/// Upload the app built in the ufbt project we're running in, and
+/// launch it if `launch` is set. Returns where it was uploaded to.
+#[cfg(feature = "ble")]
+pub async fn deploy(flipper: &mut flipper_ble::FlipperBle, launch: bool) -> Result<String, Box<dyn Error>> {
+    let app = find_app(&env::current_dir()?)?;
+    let dest = app.remote_path();
+    info!("uploading {:?} to {}", app.fap, dest);
+
+    flipper.upload_file(&app.fap, &dest).await?;
+    if launch {
+        flipper.launch(&dest, "").await?;
+    }
+
+    Ok(dest)
+}
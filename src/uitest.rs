@@ -0,0 +1,291 @@
+// Screenshot tests for Flipper apps. A test file (TOML) lists tests,
+// each of which optionally launches an app, then presses buttons and
+// waits, and at each checkpoint compares the screen against a golden
+// image:
+//
+//     golden_dir = "golden"
+//
+//     [[test]]
+//     name = "main menu"
+//     launch = "/ext/apps/Games/snake_game.fap"
+//     steps = [
+//         { wait_ms = 1000 },
+//         { checkpoint = "title" },
+//         { press = ["ok", "down"] },
+//         { checkpoint = "menu", region = "0,0,128,16", threshold = 2 },
+//     ]
+//
+// Golden images are PNGs like `screen` makes, named after their
+// checkpoint.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::keyboard::KeyPress;
+use crate::screen::Region;
+#[cfg(feature = "ble")]
+use crate::{flipper_ble, output, screen};
+
+/// How long to wait after the last step before taking a screenshot,
+/// so the app has drawn whatever the buttons did
+pub const DEFAULT_SETTLE: Duration = Duration::from_millis(500);
+/// How long to wait between button presses
+pub const DEFAULT_PRESS_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TestFileToml {
+    golden_dir: Option<PathBuf>,
+    settle_ms: Option<u64>,
+    press_delay_ms: Option<u64>,
+    #[serde(rename = "test", default)]
+    tests: Vec<TestToml>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TestToml {
+    name: String,
+    launch: Option<String>,
+    #[serde(default)]
+    args: String,
+    #[serde(default)]
+    steps: Vec<StepToml>,
+}
+
+// Each step has exactly one of press, wait_ms, or checkpoint
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct StepToml {
+    press: Option<Vec<String>>,
+    wait_ms: Option<u64>,
+    checkpoint: Option<String>,
+    region: Option<String>,
+    #[serde(default)]
+    threshold: usize,
+}
+
+/// One thing a test does
+#[derive(Debug)]
+pub enum Step {
+    Press(Vec<KeyPress>),
+    Wait(Duration),
+    /// Compare the screen to the golden image called `name`
+    Checkpoint {
+        name: String,
+        /// Only compare this part of the screen
+        region: Region,
+        /// How many pixels can be different and still pass
+        threshold: usize,
+    },
+}
+
+#[derive(Debug)]
+pub struct UiTest {
+    pub name: String,
+    /// App to launch first, and its arguments
+    pub launch: Option<(String, String)>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug)]
+pub struct TestFile {
+    /// Where golden images are kept
+    pub golden_dir: PathBuf,
+    pub settle: Duration,
+    pub press_delay: Duration,
+    pub tests: Vec<UiTest>,
+}
+
+impl TestFile {
+    /// Read the test file at `path`. The golden image directory is
+    /// relative to it, and is "golden" next to it by default.
+    pub fn load(path: &Path) -> Result<TestFile, Box<dyn Error>> {
+        let file: TestFileToml = toml::from_str(&fs::read_to_string(path)?)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+
+        let mut tests = Vec::new();
+        for t in file.tests {
+            let mut steps = Vec::new();
+            for (i, s) in t.steps.into_iter().enumerate() {
+                let step = match (s.press, s.wait_ms, s.checkpoint) {
+                    (Some(buttons), None, None) => Step::Press(buttons.iter()
+                        .map(|b| b.parse::<KeyPress>())
+                        .collect::<Result<Vec<KeyPress>, String>>()?),
+                    (None, Some(ms), None) => Step::Wait(Duration::from_millis(ms)),
+                    (None, None, Some(name)) => Step::Checkpoint {
+                        region: match s.region {
+                            Some(r) => r.parse()?,
+                            None => Region::full(),
+                        },
+                        threshold: s.threshold,
+                        name,
+                    },
+                    _ => return Err(format!("step {} of test {:?} needs exactly one of press, wait_ms, or checkpoint",
+                                            i + 1, t.name).into()),
+                };
+                steps.push(step);
+            }
+            tests.push(UiTest {
+                name: t.name,
+                launch: t.launch.map(|app| (app, t.args)),
+                steps,
+            });
+        }
+
+        Ok(TestFile {
+            golden_dir: base.join(file.golden_dir.unwrap_or_else(|| PathBuf::from("golden"))),
+            settle: file.settle_ms.map_or(DEFAULT_SETTLE, Duration::from_millis),
+            press_delay: file.press_delay_ms.map_or(DEFAULT_PRESS_DELAY, Duration::from_millis),
+            tests,
+        })
+    }
+
+    /// Returns where the golden image for the checkpoint `name` is.
+    pub fn golden_path(&self, name: &str) -> PathBuf {
+        self.golden_dir.join(format!("{}.png", name))
+    }
+}
+
+/// How one checkpoint went
+#[derive(Serialize, Debug)]
+pub struct CheckpointResult {
+    pub test: String,
+    pub checkpoint: String,
+    pub passed: bool,
+    /// Pixels that were different from the golden image, or None if
+    /// there was nothing to compare against
+    pub changed_pixels: Option<usize>,
+    /// Screenshot and diff, if a report was asked for and it failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Returns `name` with anything that doesn't belong in a file name
+/// replaced, for naming report images.
+pub fn file_stem(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Run the UI tests in `file`. Returns how many checkpoints passed and
+/// failed. A test that goes wrong partway (like an app that won't
+/// launch) counts as a failure, and the next test still runs.
+///
+/// # Arguments
+///
+/// * `update`: Save golden images instead of comparing against them
+/// * `report`: Directory to write a JSON report and failure images to
+#[cfg(feature = "ble")]
+pub async fn run(flipper: &mut flipper_ble::FlipperBle, file: &Path, update: bool, report: Option<&Path>) -> Result<(usize, usize), Box<dyn Error>> {
+    let tests = TestFile::load(file)?;
+    if update {
+        std::fs::create_dir_all(&tests.golden_dir)?;
+    }
+    if let Some(dir) = report {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut results = Vec::new();
+    for test in &tests.tests {
+        output::status(format!("running {}", test.name));
+        let result: Result<(), Box<dyn Error>> = async {
+            if let Some((app, args)) = &test.launch {
+                flipper.launch(app, args).await?;
+            }
+            for step in &test.steps {
+                let (name, region, threshold) = match step {
+                    Step::Press(presses) => {
+                        flipper.press_sequence(presses, tests.press_delay).await?;
+                        continue;
+                    },
+                    Step::Wait(d) => {
+                        tokio::time::sleep(*d).await;
+                        continue;
+                    },
+                    Step::Checkpoint { name, region, threshold } => (name, region, *threshold),
+                };
+
+                tokio::time::sleep(tests.settle).await;
+                let actual = flipper.screenshot().await?;
+                let golden_path = tests.golden_path(name);
+                let mut result = CheckpointResult {
+                    test: test.name.clone(),
+                    checkpoint: name.clone(),
+                    passed: false,
+                    changed_pixels: None,
+                    actual: None,
+                    diff: None,
+                    error: None,
+                };
+
+                if update {
+                    std::fs::write(&golden_path, screen::render_png(&actual, 4)?)?;
+                    info!("saved {:?}", golden_path);
+                    result.passed = true;
+                } else {
+                    let golden = std::fs::read(&golden_path).map_err(|e| e.to_string())
+                        .and_then(|png| screen::parse_png(&png));
+                    match golden {
+                        Ok(golden) => {
+                            let changed = screen::changed_pixels(&golden, &actual, region);
+                            result.changed_pixels = Some(changed);
+                            result.passed = changed <= threshold;
+                            if !result.passed {
+                                if let Some(dir) = report {
+                                    let stem = format!("{}-{}", file_stem(&test.name), file_stem(name));
+                                    let actual_path = dir.join(format!("{}-actual.png", stem));
+                                    let diff_path = dir.join(format!("{}-diff.png", stem));
+                                    std::fs::write(&actual_path, screen::render_png(&actual, 4)?)?;
+                                    std::fs::write(&diff_path, screen::render_png(&screen::diff_frame(&golden, &actual), 4)?)?;
+                                    result.actual = Some(actual_path);
+                                    result.diff = Some(diff_path);
+                                }
+                            }
+                        },
+                        Err(e) => result.error = Some(format!("couldn't read golden image {:?}: {} (run with --update to save one)",
+                                                              golden_path, e)),
+                    }
+                }
+
+                match (&result.error, result.changed_pixels) {
+                    (Some(e), _) => output::error(format!("{} / {}: {}", test.name, name, e)),
+                    (None, Some(n)) if !result.passed => {
+                        output::error(format!("{} / {}: {} pixels are different (up to {} allowed)", test.name, name, n, threshold));
+                    },
+                    _ => output::status(format!("{} / {}: ok", test.name, name)),
+                }
+                results.push(result);
+            }
+            Ok(())
+        }.await;
+
+        if let Err(e) = result {
+            output::error(format!("{}: {}", test.name, e));
+            results.push(CheckpointResult {
+                test: test.name.clone(),
+                checkpoint: String::new(),
+                passed: false,
+                changed_pixels: None,
+                actual: None,
+                diff: None,
+                error: Some(e.to_string()),
+            });
+        }
+    }
+
+    if let Some(dir) = report {
+        std::fs::write(dir.join("report.json"), serde_json::to_string_pretty(&results)?)?;
+    }
+    let passed = results.iter().filter(|r| r.passed).count();
+    Ok((passed, results.len() - passed))
+}
@@ -0,0 +1,102 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::firmware;
+
+// Checking for updates against the same update server qFlipper and
+// the mobile app use, or the fork's own server for Momentum and
+// Unleashed. The server publishes a directory of channels, each with
+// its versions (newest first) and their files. We only care about the
+// newest version in each channel.
+
+#[derive(Deserialize, Debug)]
+struct Directory {
+    channels: Vec<Channel>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Channel {
+    id: String,
+    versions: Vec<Version>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Version {
+    version: String,
+}
+
+/// Result of comparing the Flipper's firmware against an update
+/// channel
+#[derive(Serialize, Debug)]
+pub struct UpdateStatus {
+    pub channel: String,
+    pub installed: String,
+    pub latest: String,
+    pub update_available: bool,
+}
+
+/// Returns the update channel that matches the installed firmware,
+/// based on the branch and version in the device info.
+fn installed_channel(info: &[(String, String)]) -> &'static str {
+    let branch = firmware::lookup(info, "firmware_branch").unwrap_or("");
+    let version = firmware::lookup(info, "firmware_version").unwrap_or("");
+
+    if branch == "dev" {
+        "development"
+    } else if version.ends_with("-rc") || branch.ends_with("-rc") {
+        "release-candidate"
+    } else {
+        "release"
+    }
+}
+
+/// Download the update directory for the Flipper's firmware and
+/// compare the newest version in a channel against what the Flipper
+/// has installed. This blocks, so
+/// call it from spawn_blocking() or outside the runtime.
+///
+/// # Arguments
+///
+/// * `info`: Device info from the Flipper
+/// * `channel`: Channel to compare against (`release`,
+///   `release-candidate`, or `development`), or None to use the one
+///   the installed firmware came from
+pub fn check(info: &[(String, String)], channel: Option<&str>) -> Result<UpdateStatus, Box<dyn Error>> {
+    let directory_url = firmware::FirmwareFork::detect(info).update_directory()?;
+
+    let channel = channel.unwrap_or_else(|| installed_channel(info));
+
+    // Development builds are published under their commit hash
+    // instead of a version number.
+    let installed = if channel == "development" {
+        firmware::lookup(info, "firmware_commit")
+    } else {
+        firmware::lookup(info, "firmware_version")
+    };
+    let installed = match installed {
+        Some(v) => v.to_string(),
+        None => return Err("Flipper didn't report its firmware version".into()),
+    };
+
+    debug!("fetching {}", directory_url);
+    let directory: Directory = ureq::get(directory_url)
+        .call()?
+        .into_body()
+        .read_json()?;
+
+    let latest = match directory.channels.iter().find(|c| c.id == channel) {
+        Some(c) => match c.versions.first() {
+            Some(v) => v.version.clone(),
+            None => return Err(format!("update channel {:?} has no versions", channel).into()),
+        },
+        None => return Err(format!("no update channel named {:?}", channel).into()),
+    };
+
+    Ok(UpdateStatus {
+        channel: channel.to_string(),
+        update_available: installed != latest,
+        installed,
+        latest,
+    })
+}
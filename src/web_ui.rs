@@ -0,0 +1,421 @@
+// A small web page for the daemon, for people who'd rather not use
+// the command line: a file browser, the battery level, and a
+// screenshot button. The server can't talk to the Flipper itself,
+// since the daemon owns the connection, so it hands each request to
+// the daemon as a Job and waits for the answer. The link's state is
+// the exception: the daemon keeps it up to date in a watch channel,
+// so the page can show it even while the daemon is busy reconnecting.
+//
+// Like screen_server.rs, this is just enough HTTP for one page: every
+// request gets one response and then the connection closes.
+//
+// Anything the browser has open can send requests here, so the API
+// only answers requests with the token from the URL the daemon prints,
+// and nothing is answered unless the Host (and Origin, if there is
+// one) is this server's own address. Otherwise any web page could
+// delete everything on the SD card, or read it by pointing its own
+// domain name at us.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+#[cfg(feature = "ble")]
+use std::error::Error;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+
+use crate::screen_server::bind_address;
+#[cfg(feature = "ble")]
+use crate::{flipper_ble, flipper_pb, output, screen, status};
+
+// Uploads are the only big requests, and BLE is slow enough that
+// anything bigger would take ages anyway
+const MAX_REQUEST_SIZE: usize = 16 * 1024 * 1024;
+const MAX_HEADER_SIZE: usize = 8192;
+
+const INDEX_PAGE: &str = include_str!("web_ui.html");
+
+/// Something the page wants done with the Flipper
+#[derive(Debug)]
+pub enum WebRequest {
+    /// Battery, charging, and storage, as JSON
+    Status,
+    /// What's happened on the connection, as JSON
+    Stats,
+    /// What's in a directory, as JSON
+    List(String),
+    Download(String),
+    Upload { path: String, data: Vec<u8> },
+    Delete(String),
+    /// The screen, as a PNG
+    Screenshot,
+}
+
+/// The daemon's answer to a WebRequest
+#[derive(Debug)]
+pub enum WebResponse {
+    Json(serde_json::Value),
+    /// A file to send back, with its Content-Type
+    Data { content_type: &'static str, body: Vec<u8> },
+    /// It worked and there's nothing to send back
+    Done,
+    Error(String),
+}
+
+/// A request and where to send the answer
+pub struct Job {
+    pub request: WebRequest,
+    pub reply: oneshot::Sender<WebResponse>,
+}
+
+pub struct WebServer {
+    addr: SocketAddr,
+    token: Arc<str>,
+    task: JoinHandle<()>,
+}
+
+impl WebServer {
+    /// Start serving on `listen` (like `127.0.0.1:8080` or `:8080`).
+    /// Returns the server and where its Jobs come out; every Job
+    /// needs an answer, or the page waits forever.
+    ///
+    /// # Arguments
+    ///
+    /// * `listen`: Address to listen on
+    /// * `token`: What the page has to send with every API request. A
+    ///   random one is made if it's not given.
+    /// * `link`: The link's state as JSON, like `{"state":
+    ///   "connected", "mtu": 247}`, served as is
+    pub async fn bind(listen: &str, token: Option<&str>, link: watch::Receiver<serde_json::Value>) -> io::Result<(WebServer, mpsc::Receiver<Job>)> {
+        Self::serve(TcpListener::bind(bind_address(listen)).await?, token, link)
+    }
+
+    /// Start serving on a socket that's already listening, like one
+    /// from systemd. Otherwise the same as bind().
+    pub fn from_listener(listener: std::net::TcpListener, token: Option<&str>, link: watch::Receiver<serde_json::Value>) -> io::Result<(WebServer, mpsc::Receiver<Job>)> {
+        Self::serve(TcpListener::from_std(listener)?, token, link)
+    }
+
+    fn serve(listener: TcpListener, token: Option<&str>, link: watch::Receiver<serde_json::Value>) -> io::Result<(WebServer, mpsc::Receiver<Job>)> {
+        let addr = listener.local_addr()?;
+        let token: Arc<str> = match token {
+            Some(t) => t.into(),
+            None => uuid::Uuid::new_v4().simple().to_string().into(),
+        };
+        let (jobs, job_receiver) = mpsc::channel(8);
+
+        let task = tokio::spawn(accept_clients(listener, addr, token.clone(), jobs, link));
+        Ok((WebServer { addr, token, task }, job_receiver))
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the page's URL, with the token in it.
+    pub fn url(&self) -> String {
+        format!("http://{}/?token={}", self.addr, self.token)
+    }
+}
+
+impl Drop for WebServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn accept_clients(listener: TcpListener, local: SocketAddr, token: Arc<str>,
+                        jobs: mpsc::Sender<Job>, link: watch::Receiver<serde_json::Value>) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, addr)) => {
+                let token = token.clone();
+                let jobs = jobs.clone();
+                let link = link.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_client(socket, local, &token, jobs, link).await {
+                        debug!("web client {} went away: {}", addr, e);
+                    }
+                });
+            },
+            Err(e) => warn!("failed to accept web client: {}", e),
+        }
+    }
+}
+
+/// Returns `s` with %XX escapes and "+" decoded.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let escaped = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match escaped {
+                    Some(b) => {
+                        decoded.push(b);
+                        i += 3;
+                        continue;
+                    },
+                    None => decoded.push(b'%'),
+                }
+            },
+            b'+' => decoded.push(b' '),
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Returns the value of `name` in the query string of `target`.
+fn query_param(target: &str, name: &str) -> Option<String> {
+    let (_, query) = target.split_once('?')?;
+    query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| percent_decode(v))
+}
+
+/// Returns whether a request with these Host and Origin headers came
+/// from a page this server (listening on `local`) served. The Host
+/// has to be an address, or "localhost" when that's where we are, so
+/// that a domain name someone else controls can't be pointed at us.
+///
+/// # Arguments
+///
+/// * `host`: The Host header, like "127.0.0.1:8080"
+/// * `origin`: The Origin header, if the browser sent one
+/// * `local`: Address the server is listening on
+fn same_origin(host: Option<&str>, origin: Option<&str>, local: SocketAddr) -> bool {
+    let Some(host) = host else {
+        return false;
+    };
+    let (name, port) = match host.rsplit_once(':') {
+        // "[::1]" has colons but no port
+        Some((name, port)) if !port.ends_with(']') => (name, port.parse::<u16>().ok()),
+        _ => (host, Some(80)),
+    };
+    if port != Some(local.port()) {
+        return false;
+    }
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    let host_ok = match name.parse::<IpAddr>() {
+        // on every interface, there's no telling which address was used
+        Ok(ip) => local.ip().is_unspecified() || ip == local.ip(),
+        Err(_) => name.eq_ignore_ascii_case("localhost") && (local.ip().is_loopback() || local.ip().is_unspecified()),
+    };
+    host_ok && origin.is_none_or(|o| o.strip_prefix("http://") == Some(host))
+}
+
+/// Returns the value of the header `name`.
+fn header_value<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.lines()
+        .filter_map(|l| l.split_once(':'))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.trim())
+}
+
+/// What a client asked for
+struct Request {
+    method: String,
+    target: String,
+    host: Option<String>,
+    origin: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Returns the request the client sent.
+async fn read_request(socket: &mut TcpStream) -> io::Result<Option<Request>> {
+    let mut request = Vec::new();
+    let mut data = [0u8; 4096];
+    let header_end = loop {
+        if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let n = socket.read(&mut data).await?;
+        if n == 0 || request.len() > MAX_HEADER_SIZE {
+            return Ok(None);
+        }
+        request.extend(&data[..n]);
+    };
+
+    let header = String::from_utf8_lossy(&request[..header_end]).into_owned();
+    let mut parts = header.lines().next().unwrap_or("").split(' ');
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (m.to_string(), t.to_string()),
+        _ => return Ok(None),
+    };
+    let length = header_value(&header, "content-length")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    if length > MAX_REQUEST_SIZE {
+        return Ok(None);
+    }
+
+    let mut body = request.split_off(header_end);
+    while body.len() < length {
+        let n = socket.read(&mut data).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        body.extend(&data[..n]);
+    }
+    body.truncate(length);
+    Ok(Some(Request {
+        method,
+        target,
+        host: header_value(&header, "host").map(str::to_string),
+        origin: header_value(&header, "origin").map(str::to_string),
+        body,
+    }))
+}
+
+async fn respond(socket: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    let header = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                          Cache-Control: no-cache\r\nConnection: close\r\n\r\n", status, content_type, body.len());
+    socket.write_all(header.as_bytes()).await?;
+    socket.write_all(body).await
+}
+
+async fn serve_client(mut socket: TcpStream, local: SocketAddr, token: &str,
+                      jobs: mpsc::Sender<Job>, link: watch::Receiver<serde_json::Value>) -> io::Result<()> {
+    let Request { method, target, host, origin, body } = match read_request(&mut socket).await? {
+        Some(r) => r,
+        None => return respond(&mut socket, "400 Bad Request", "text/plain", b"bad request").await,
+    };
+    if !same_origin(host.as_deref(), origin.as_deref(), local) {
+        debug!("refused web request from host {:?}, origin {:?}", host, origin);
+        return respond(&mut socket, "403 Forbidden", "text/plain", b"wrong host or origin").await;
+    }
+    let route = target.split('?').next().unwrap_or("");
+    if route.starts_with("/api/") && query_param(&target, "token").as_deref() != Some(token) {
+        return respond(&mut socket, "403 Forbidden", "text/plain", b"wrong or missing token").await;
+    }
+    let path = query_param(&target, "path");
+
+    let request = match (method.as_str(), route, path) {
+        ("GET", "/", _) => return respond(&mut socket, "200 OK", "text/html", INDEX_PAGE.as_bytes()).await,
+        ("GET", "/api/link", _) => {
+            let state = link.borrow().to_string();
+            return respond(&mut socket, "200 OK", "application/json", state.as_bytes()).await;
+        },
+        ("GET", "/api/status", _) => WebRequest::Status,
+        ("GET", "/api/stats", _) => WebRequest::Stats,
+        ("GET", "/api/screenshot", _) => WebRequest::Screenshot,
+        ("GET", "/api/list", Some(p)) => WebRequest::List(p),
+        ("GET", "/api/download", Some(p)) => WebRequest::Download(p),
+        ("POST", "/api/upload", Some(p)) => WebRequest::Upload { path: p, data: body },
+        ("POST", "/api/delete", Some(p)) => WebRequest::Delete(p),
+        _ => return respond(&mut socket, "404 Not Found", "text/plain", b"not found").await,
+    };
+
+    let (reply, answer) = oneshot::channel();
+    if jobs.send(Job { request, reply }).await.is_err() {
+        return respond(&mut socket, "503 Service Unavailable", "text/plain", b"daemon is stopping").await;
+    }
+    match answer.await {
+        Ok(WebResponse::Json(v)) => respond(&mut socket, "200 OK", "application/json", v.to_string().as_bytes()).await,
+        Ok(WebResponse::Data { content_type, body }) => respond(&mut socket, "200 OK", content_type, &body).await,
+        Ok(WebResponse::Done) => respond(&mut socket, "204 No Content", "text/plain", b"").await,
+        Ok(WebResponse::Error(e)) => respond(&mut socket, "500 Internal Server Error", "text/plain", e.as_bytes()).await,
+        Err(_) => respond(&mut socket, "503 Service Unavailable", "text/plain", b"daemon is stopping").await,
+    }
+}
+
+/// Returns the link's state as the web page gets it.
+#[cfg(feature = "ble")]
+pub fn link_json(state: &str, mtu: Option<u16>) -> serde_json::Value {
+    serde_json::json!({ "state": state, "mtu": mtu })
+}
+
+/// Do what the web page asked for.
+#[cfg(feature = "ble")]
+pub async fn handle_request(flipper: &mut flipper_ble::FlipperBle, request: WebRequest, commands: u64) -> WebResponse {
+    debug!("web request: {:?}", request);
+    let result: Result<WebResponse, Box<dyn Error>> = async {
+        Ok(match request {
+            WebRequest::Status => WebResponse::Json(serde_json::to_value(status::fetch(flipper).await)?),
+            WebRequest::Stats => WebResponse::Json(status::stats_json(&flipper.stats().await, commands)),
+            WebRequest::List(path) => {
+                let mut entries = flipper.list_entries(&path).await?;
+                let is_dir = |f: &flipper_pb::storage::File| f.type_ == flipper_pb::storage::file::FileType::DIR.into();
+                entries.sort_by(|a, b| is_dir(b).cmp(&is_dir(a)).then_with(|| a.name.cmp(&b.name)));
+                WebResponse::Json(entries.iter()
+                    .map(|f| serde_json::json!({ "name": f.name, "dir": is_dir(f), "size": f.size }))
+                    .collect())
+            },
+            WebRequest::Download(path) => {
+                let mut body = Vec::new();
+                flipper.read_file(&path, None, |d| {
+                    body.extend_from_slice(d);
+                    Ok(())
+                }).await?;
+                WebResponse::Data { content_type: "application/octet-stream", body }
+            },
+            WebRequest::Upload { path, data } => {
+                flipper.upload_data(&data, &path).await?;
+                output::status(format!("uploaded {} from the web page", path));
+                WebResponse::Done
+            },
+            WebRequest::Delete(path) => {
+                flipper.delete_file(&path, true).await?;
+                output::status(format!("deleted {} from the web page", path));
+                WebResponse::Done
+            },
+            WebRequest::Screenshot => {
+                let frame = flipper.screenshot().await?;
+                WebResponse::Data { content_type: "image/png", body: screen::render_png(&frame, 4)? }
+            },
+        })
+    }.await;
+
+    result.unwrap_or_else(|e| WebResponse::Error(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_origin_host_test() {
+        let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert!(same_origin(Some("127.0.0.1:8080"), None, local));
+        assert!(same_origin(Some("localhost:8080"), None, local));
+        assert!(same_origin(Some("LocalHost:8080"), None, local));
+        // someone else's domain pointed at us
+        assert!(!same_origin(Some("evil.example:8080"), None, local));
+        assert!(!same_origin(Some("127.0.0.1:8081"), None, local));
+        assert!(!same_origin(Some("127.0.0.1"), None, local));
+        assert!(!same_origin(Some("192.168.1.2:8080"), None, local));
+        assert!(!same_origin(None, None, local));
+
+        let local: SocketAddr = "[::1]:80".parse().unwrap();
+        assert!(same_origin(Some("[::1]"), None, local));
+        assert!(same_origin(Some("[::1]:80"), None, local));
+        assert!(!same_origin(Some("[::2]"), None, local));
+    }
+
+    #[test]
+    fn same_origin_every_interface_test() {
+        let local: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        assert!(same_origin(Some("192.168.1.2:8080"), None, local));
+        assert!(same_origin(Some("localhost:8080"), None, local));
+        assert!(!same_origin(Some("flipper.example:8080"), None, local));
+    }
+
+    #[test]
+    fn same_origin_origin_test() {
+        let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert!(same_origin(Some("127.0.0.1:8080"), Some("http://127.0.0.1:8080"), local));
+        assert!(!same_origin(Some("127.0.0.1:8080"), Some("http://evil.example"), local));
+        assert!(!same_origin(Some("127.0.0.1:8080"), Some("null"), local));
+        assert!(!same_origin(Some("127.0.0.1:8080"), Some("https://127.0.0.1:8080"), local));
+    }
+}
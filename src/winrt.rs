@@ -0,0 +1,32 @@
+// Windows-only extras that go to WinRT directly, for things btleplug
+// doesn't cover.
+//
+// btleplug on Windows only knows about devices it's seen in a scan,
+// but Windows keeps its own list of paired devices. Finding the
+// Flipper there first tells us its address, so the scan can stop as
+// soon as it shows up instead of always running for 7.5 seconds.
+
+use std::error::Error;
+
+use btleplug::api::BDAddr;
+use windows::Devices::Bluetooth::BluetoothLEDevice;
+use windows::Devices::Enumeration::DeviceInformation;
+
+/// Returns the address of the first Bluetooth LE device paired with
+/// Windows whose name contains `flipper_name`, or None if none is.
+/// This blocks, so run it with spawn_blocking().
+pub fn find_paired(flipper_name: &str) -> Result<Option<BDAddr>, Box<dyn Error + Send + Sync>> {
+    let selector = BluetoothLEDevice::GetDeviceSelectorFromPairingState(true)?;
+    let devices = DeviceInformation::FindAllAsyncAqsFilter(&selector)?.get()?;
+    for info in &devices {
+        let name = info.Name()?.to_string();
+        if !name.contains(flipper_name) {
+            continue;
+        }
+        let device = BluetoothLEDevice::FromIdAsync(&info.Id()?)?.get()?;
+        let address = BDAddr::try_from(device.BluetoothAddress()?)?;
+        debug!("{:?} is paired with Windows, at {}", name, address);
+        return Ok(Some(address));
+    }
+    Ok(None)
+}